@@ -4,7 +4,7 @@
 use color_eyre::{self, eyre::Context};
 use rodio::{buffer::SamplesBuffer, OutputStream, Sink};
 use std::io::{self, Write};
-use tts::{load_language, setup_tts, synthesize};
+use tts::{list_languages, load_language, setup_tts, synthesize_streaming};
 
 fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
@@ -12,6 +12,13 @@ fn main() -> color_eyre::Result<()> {
     let stdin = io::stdin();
     let mut stdout = io::stdout();
 
+    writeln!(
+        stdout,
+        "available languages: {}",
+        list_languages()?.join(", ")
+    )
+    .wrap_err("unable to write to stdout")?;
+
     let resources = loop {
         write!(stdout, "language> ").wrap_err("unable to write to stdout")?;
         stdout.flush().wrap_err("unable to write to stdout")?;
@@ -46,13 +53,16 @@ fn main() -> color_eyre::Result<()> {
 
         let line = line.trim_end();
 
-        let waveform = synthesize(&mut engine, line)?;
+        // Start playing the first chunks while the rest are still being synthesized
+        for waveform in synthesize_streaming(&mut engine, line)? {
+            let waveform = waveform?;
 
-        sink.append(SamplesBuffer::new(
-            1,
-            waveform.sample_rate(),
-            waveform.samples(),
-        ));
+            sink.append(SamplesBuffer::new(
+                waveform.channels(),
+                waveform.sample_rate(),
+                waveform.samples(),
+            ));
+        }
 
         sink.sleep_until_end();
     }
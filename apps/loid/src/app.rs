@@ -9,28 +9,40 @@ use std::{
 };
 
 use audio::{
+    filter::Equalizer,
     output::{AudioSink, AudioSinkProgress},
-    waveform::Waveform,
+    waveform::{ChirpCurve, Waveform},
 };
 use eframe::{
     egui::{
         Button, CentralPanel, Context, RichText, ScrollArea, SidePanel, Slider, TopBottomPanel,
     },
-    epaint::Vec2,
+    epaint::{Color32, Vec2},
     epi::{App, Frame},
 };
 use instant::Instant;
 use spectrum::{WaveformSpectrum, Window};
 use tracing::warn;
 
+mod export;
+mod live;
 mod plot;
 
+use export::{export_spectrum, SpectrumFormat};
+use live::{LiveInput, LIVE_SAMPLES};
+
+/// How many samples before the window are equalized along with it, so that
+/// the filters have settled by the time they reach the window
+const EQUALIZER_PREROLL: usize = 4096;
+
 pub struct Application {
     math_elapsed: Option<Duration>,
 
     audio_sink: AudioSink,
 
     waveform: Option<Waveform<'static>>,
+    /// The input being listened to, which replaces the waveform every frame
+    live: Option<LiveInput>,
     window: Window,
 
     is_playing: Arc<AtomicBool>,
@@ -38,8 +50,11 @@ pub struct Application {
 
     follow_playback: bool,
     full_spectrum: bool,
+    /// Set to copy the spectrum to the clipboard once it is next calculated
+    copy_spectrum: Option<SpectrumFormat>,
     phase: bool,
     decibels: bool,
+    note_names: bool,
     line: bool,
     stems: bool,
 
@@ -49,6 +64,7 @@ pub struct Application {
     hop_frac: usize,
 
     shift: f64,
+    equalizer: Equalizer,
 }
 
 impl Application {
@@ -59,6 +75,7 @@ impl Application {
             audio_sink,
 
             waveform: None,
+            live: None,
 
             window: Window::Hann,
 
@@ -67,8 +84,10 @@ impl Application {
 
             follow_playback: true,
             full_spectrum: false,
+            copy_spectrum: None,
             phase: false,
             decibels: false,
+            note_names: false,
 
             // Use line plot on wasm32 platforms
             line: cfg!(target_arch = "wasm32"),
@@ -82,6 +101,7 @@ impl Application {
             hop_frac: 4,
 
             shift: 0.0,
+            equalizer: Equalizer::graphic(),
         }
     }
 }
@@ -156,22 +176,19 @@ impl Application {
         let did_queue = self.audio_sink.queue(waveform, {
             let playback_head = self.playback_head.clone();
             let is_playing = self.is_playing.clone();
-            let waveform_len = waveform.len() as f32;
+            let waveform_len = waveform.len();
 
             let once = Once::new();
 
             move |progress| {
                 match progress {
-                    AudioSinkProgress::Samples(progress) => {
+                    AudioSinkProgress::Samples(_) => {
                         once.call_once(|| {
                             is_playing.store(true, Ordering::SeqCst);
                         });
-
-                        playback_head
-                            .store((progress * waveform_len).round() as _, Ordering::SeqCst);
                     }
                     AudioSinkProgress::Finished => {
-                        playback_head.store(waveform_len as _, Ordering::SeqCst);
+                        playback_head.store(waveform_len, Ordering::SeqCst);
                         is_playing.store(false, Ordering::SeqCst);
                     }
                 };
@@ -200,16 +217,32 @@ impl App for Application {
                 eframe::egui::widgets::global_dark_light_mode_switch(ui);
                 ui.menu_button("Waveform", |ui| {
                     if ui.button("Load Sine Wave").clicked() {
+                        self.live = None;
                         self.waveform =
                             Some(Waveform::sine_wave(220.0, 0.5, Waveform::CD_SAMPLE_RATE));
 
                         ui.close_menu();
                     }
+                    if self.live.is_some() {
+                        if ui.button("Stop Listening").clicked() {
+                            self.live = None;
+
+                            ui.close_menu();
+                        }
+                    } else if ui.button("Listen to Microphone").clicked() {
+                        match LiveInput::start() {
+                            Ok(live) => self.live = Some(live),
+                            Err(err) => warn!(?err, "unable to listen to the microphone"),
+                        }
+
+                        ui.close_menu();
+                    }
                     ui.separator();
                     if ui
                         .add_enabled(self.waveform.is_some(), Button::new("Clear"))
                         .clicked()
                     {
+                        self.live = None;
                         self.waveform = None;
 
                         ui.close_menu();
@@ -266,7 +299,7 @@ impl App for Application {
                 ui.separator();
                 ui.heading("Playback");
                 ui.add_enabled_ui(
-                    self.waveform.is_some() && !self.audio_sink.playing(),
+                    self.waveform.is_some() && self.audio_sink.queue_length() == 0,
                     |ui| {
                         if ui.button("Play Original").clicked() {
                             self.play(
@@ -290,6 +323,30 @@ impl App for Application {
                     },
                 );
 
+                ui.add_enabled_ui(self.audio_sink.queue_length() > 0, |ui| {
+                    ui.horizontal(|ui| {
+                        if self.audio_sink.paused() {
+                            if ui.button("Resume").clicked() {
+                                self.audio_sink.resume();
+                            }
+                        } else if ui.button("Pause").clicked() {
+                            self.audio_sink.pause();
+                        }
+
+                        if ui.button("Stop").clicked() {
+                            self.audio_sink.stop();
+                        }
+                    });
+                });
+
+                let mut volume = self.audio_sink.volume();
+                if ui
+                    .add(Slider::new(&mut volume, 0.0..=1.0).text("Volume"))
+                    .changed()
+                {
+                    self.audio_sink.set_volume(volume);
+                }
+
                 if ui
                     .add_enabled(false, Button::new("Reconstruct Samples"))
                     .clicked()
@@ -372,6 +429,29 @@ impl App for Application {
                     ui.heading("DSP");
                     ui.label("Frequency shift");
                     ui.add(Slider::new(&mut self.shift, 0.0..=1000.0).suffix(" Hz"));
+
+                    ui.horizontal(|ui| {
+                        ui.label("Equalizer");
+                        if ui
+                            .add_enabled(!self.equalizer.is_flat(), Button::new("Flat"))
+                            .clicked()
+                        {
+                            self.equalizer.flatten();
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        for band in &mut self.equalizer.bands {
+                            ui.vertical(|ui| {
+                                ui.add(
+                                    Slider::new(&mut band.gain_db, -12.0..=12.0)
+                                        .vertical()
+                                        .show_value(false),
+                                )
+                                .on_hover_text(format!("{:+.1} dB", band.gain_db));
+                                ui.small(band_label(band.frequency));
+                            });
+                        }
+                    });
                 });
 
                 ui.separator();
@@ -380,9 +460,18 @@ impl App for Application {
                     ui.checkbox(&mut self.full_spectrum, "Show full spectrum");
                     ui.checkbox(&mut self.phase, "Show phase");
                     ui.checkbox(&mut self.decibels, "Decibels");
+                    ui.checkbox(&mut self.note_names, "Note names");
                     ui.checkbox(&mut self.line, "Line Plot");
                     ui.checkbox(&mut self.stems, "Stems");
                 });
+                ui.horizontal_wrapped(|ui| {
+                    if ui.button("Copy Spectrum as CSV").clicked() {
+                        self.copy_spectrum = Some(SpectrumFormat::Csv);
+                    }
+                    if ui.button("Copy Spectrum as JSON").clicked() {
+                        self.copy_spectrum = Some(SpectrumFormat::Json);
+                    }
+                });
 
                 ui.separator();
                 ui.heading("Debug");
@@ -390,6 +479,23 @@ impl App for Application {
                     if ui.button("Panic").clicked() {
                         panic!("User initiated panic");
                     }
+
+                    // Known sweeps across the audible range, to check windows and resampling by eye
+                    for (label, curve) in [
+                        ("Load Linear Chirp", ChirpCurve::Linear),
+                        ("Load Exponential Chirp", ChirpCurve::Exponential),
+                    ] {
+                        if ui.button(label).clicked() {
+                            self.live = None;
+                            self.waveform = Some(Waveform::chirp(
+                                20.0,
+                                20_000.0,
+                                5.0,
+                                Waveform::CD_SAMPLE_RATE,
+                                curve,
+                            ));
+                        }
+                    }
                 });
 
                 // About section
@@ -413,7 +519,25 @@ impl App for Application {
             });
         });
 
+        if let Some(live) = &self.live {
+            // Always look at the latest window of input
+            self.waveform = Some(live.waveform());
+            self.cursor = LIVE_SAMPLES.saturating_sub(self.window_width + 1);
+
+            ctx.request_repaint();
+        }
+
         if let Some(waveform) = &self.waveform {
+            if self.is_playing.load(Ordering::SeqCst) {
+                let played = (self.audio_sink.position_secs() * waveform.sample_rate() as f32)
+                    .round() as usize;
+
+                self.playback_head.store(
+                    (played * waveform.channels() as usize).min(waveform.len()),
+                    Ordering::SeqCst,
+                );
+            }
+
             let cursor = if self.follow_playback && self.is_playing.load(Ordering::SeqCst) {
                 self.playback_head
                     .load(Ordering::SeqCst)
@@ -427,20 +551,50 @@ impl App for Application {
 
             let math_start = Instant::now();
 
-            // Get the slice of the waveform to work on
-            let window_waveform = waveform.slice(cursor..(cursor + self.window_width));
+            // Get the slice of the waveform to work on, as it sounds through the equalizer
+            let equalized;
+            let window_waveform = if self.equalizer.is_flat() {
+                waveform.slice(cursor..(cursor + self.window_width))
+            } else {
+                let preroll = cursor.min(EQUALIZER_PREROLL);
+                equalized = waveform
+                    .slice((cursor - preroll)..(cursor + self.window_width))
+                    .equalize(&self.equalizer);
 
-            // Get the frequency spectrum of the waveform
-            let spectrum = window_waveform.spectrum(self.window, fft_width);
+                equalized.slice(preroll..)
+            };
 
-            // Shift the spectrum
-            let shifted_spectrum = spectrum.shift(spectrum.bucket_from_freq(self.shift));
+            // Get the frequency spectrum of the waveform, and shift it back into
+            // a waveform
+            let spectrum = window_waveform
+                .spectrum(self.window, fft_width)
+                .and_then(|spectrum| {
+                    let shifted_spectrum = spectrum.shift(spectrum.bucket_from_freq(self.shift));
+                    let reconstructed = shifted_spectrum.waveform()?;
+
+                    Ok((spectrum, reconstructed))
+                });
+            let (spectrum, reconstructed) = match spectrum {
+                Ok(spectrum) => spectrum,
+                Err(error) => {
+                    CentralPanel::default().show(ctx, |ui| {
+                        ui.centered_and_justified(|ui| {
+                            ui.heading(RichText::new(error.to_string()).color(Color32::RED));
+                        });
+                    });
+
+                    return;
+                }
+            };
 
-            let reconstructed = shifted_spectrum.waveform();
             let reconstructed = reconstructed.slice(..self.window_width);
 
             self.math_elapsed = Some(math_start.elapsed());
 
+            if let Some(format) = self.copy_spectrum.take() {
+                ctx.output().copied_text = export_spectrum(&spectrum, self.full_spectrum, format);
+            }
+
             TopBottomPanel::top("top_panel").show(ctx, |ui| {
                 ui.label(format!(
                     "Frequency Resolution: {} Hz",
@@ -483,6 +637,7 @@ impl App for Application {
                         self.full_spectrum,
                         self.phase,
                         self.decibels,
+                        self.note_names,
                     )
                 })
             });
@@ -495,3 +650,12 @@ impl App for Application {
         }
     }
 }
+
+/// A short name for the centre of an equalizer band
+fn band_label(frequency: f32) -> String {
+    if frequency >= 1000.0 {
+        format!("{}k", (frequency / 1000.0).round())
+    } else {
+        format!("{}", frequency.round())
+    }
+}
@@ -0,0 +1,51 @@
+use std::fmt::Write;
+
+use spectrum::Spectrum;
+
+/// The text formats a spectrum can be copied out as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpectrumFormat {
+    Csv,
+    Json,
+}
+
+/// Write out the frequency, amplitude and phase of every bucket, or only those
+/// up to nyquist unless `full_spectrum` is set, matching what is plotted
+pub fn export_spectrum(spectrum: &Spectrum, full_spectrum: bool, format: SpectrumFormat) -> String {
+    let rows: Box<dyn Iterator<Item = (f64, f32, f32)>> = if full_spectrum {
+        Box::new(spectrum.to_rows())
+    } else {
+        Box::new(spectrum.to_rows_real())
+    };
+
+    let mut text = String::new();
+
+    // Writing to a string can not fail
+    match format {
+        SpectrumFormat::Csv => {
+            text.push_str("frequency,amplitude,phase\n");
+
+            for (frequency, amplitude, phase) in rows {
+                let _ = writeln!(text, "{frequency},{amplitude},{phase}");
+            }
+        }
+        SpectrumFormat::Json => {
+            text.push('[');
+
+            for (n, (frequency, amplitude, phase)) in rows.enumerate() {
+                if n > 0 {
+                    text.push(',');
+                }
+
+                let _ = write!(
+                    text,
+                    r#"{{"frequency":{frequency},"amplitude":{amplitude},"phase":{phase}}}"#
+                );
+            }
+
+            text.push(']');
+        }
+    }
+
+    text
+}
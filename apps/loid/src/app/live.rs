@@ -0,0 +1,58 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use audio::{
+    input::{record_stream, Recording},
+    ring::RingWaveform,
+    waveform::Waveform,
+};
+use color_eyre::eyre::Context;
+
+/// How many of the most recent samples are kept from the input
+pub const LIVE_SAMPLES: usize = 1 << 16;
+
+/// Audio arriving from the default input device
+pub struct LiveInput {
+    samples: Arc<Mutex<RingWaveform>>,
+    sample_rate: u32,
+
+    _recording: Recording,
+}
+
+impl LiveInput {
+    pub fn start() -> color_eyre::Result<Self> {
+        // The device's rate is only known once it is recording, so the samples
+        // are labelled with it when they are taken out
+        let samples = Arc::new(Mutex::new(RingWaveform::new(
+            LIVE_SAMPLES,
+            Waveform::CD_SAMPLE_RATE,
+            1,
+        )));
+
+        let recording = record_stream(None, {
+            let samples = samples.clone();
+
+            move |chunk| lock(&samples).push(chunk.to_mono().samples())
+        })
+        .wrap_err("failed to listen to the input device")?;
+
+        Ok(Self {
+            samples,
+            sample_rate: recording.sample_rate(),
+            _recording: recording,
+        })
+    }
+
+    /// The most recent samples, labelled with the rate the device actually
+    /// records at so that frequencies come out right whatever it is
+    pub fn waveform(&self) -> Waveform<'static> {
+        let samples = lock(&self.samples).latest(LIVE_SAMPLES).into_samples();
+
+        Waveform::new_mono(samples, self.sample_rate)
+    }
+}
+
+/// A panic while pushing samples leaves nothing half written, so the buffer
+/// is still fine to use
+fn lock(samples: &Mutex<RingWaveform>) -> MutexGuard<'_, RingWaveform> {
+    samples.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -7,8 +7,18 @@ use eframe::{
     emath::Align2,
     epaint::Color32,
 };
+use music::{Accidental, MusicalNote, PianoKey, Tuning};
 use spectrum::{Spectrum, Window};
 
+/// Past this many samples, the waveform is drawn from its thumbnail
+const THUMBNAIL_POINTS: usize = 4096;
+
+/// How far below the loudest frequency, in decibels, a peak can be and still
+/// be labelled with its note
+const NOTE_PEAK_DB: f32 = -20.0;
+/// The most peaks labelled with their notes, so a noisy spectrum is not buried
+const MAX_NOTE_PEAKS: usize = 8;
+
 pub fn waveform_display(
     ui: &mut Ui,
     waveform: &Waveform,
@@ -24,12 +34,33 @@ pub fn waveform_display(
         .include_y(1.0)
         .include_y(-1.0)
         .show(ui, |ui| {
-            point_line(
-                ui,
-                "Original waveform",
-                Values::from_values_iter(waveform.time_domain().map(|(x, y)| Value::new(x, y))),
-                (line, stems),
-            );
+            if waveform.len() > THUMBNAIL_POINTS {
+                // Plotting every sample of long files is too slow, so draw the
+                // range of each bucket instead
+                let thumbnail = waveform.thumbnail(THUMBNAIL_POINTS);
+                let bucket_duration = waveform.duration() / thumbnail.len() as f32;
+
+                ui.line(
+                    Line::new(Values::from_values_iter(
+                        thumbnail
+                            .into_iter()
+                            .enumerate()
+                            .flat_map(|(n, (min, max))| {
+                                let x = n as f32 * bucket_duration;
+
+                                [Value::new(x, min), Value::new(x, max)]
+                            }),
+                    ))
+                    .name("Original waveform"),
+                );
+            } else {
+                point_line(
+                    ui,
+                    "Original waveform",
+                    Values::from_values_iter(waveform.time_domain().map(|(x, y)| Value::new(x, y))),
+                    (line, stems),
+                );
+            }
 
             // TODO:
             // ui.points(
@@ -139,6 +170,7 @@ pub fn spectrum_display(
     full_spectrum: bool,
     phase: bool,
     decibels: bool,
+    note_names: bool,
 ) {
     Plot::new("frequencies")
         .legend(Legend::default())
@@ -151,6 +183,7 @@ pub fn spectrum_display(
                 full_spectrum,
                 phase,
                 decibels,
+                note_names,
             );
 
             display_spectrum(
@@ -160,6 +193,7 @@ pub fn spectrum_display(
                 full_spectrum,
                 phase,
                 decibels,
+                note_names,
             );
         });
 }
@@ -171,21 +205,10 @@ fn display_spectrum(
     full_spectrum: bool,
     phase: bool,
     decibels: bool,
+    note_names: bool,
 ) {
     // TODO: DECIBELS
 
-    #[inline(always)]
-    fn map(
-        iterator: impl Iterator<Item = f32>,
-        freq: impl Fn(usize) -> f64,
-        db: impl Fn(f32) -> f32,
-    ) -> Vec<Bar> {
-        iterator
-            .enumerate()
-            .map(|(bucket, mag)| Bar::new(freq(bucket), db(mag) as f64))
-            .collect()
-    }
-
     let db = |mag: f32| -> f32 {
         if decibels {
             20.0 * if mag == 0.0 { 0.0 } else { mag.log10() }
@@ -194,15 +217,23 @@ fn display_spectrum(
         }
     };
 
-    let freq = |b| spectrum.freq_from_bucket(b);
-
-    let buckets = match (phase, full_spectrum) {
-        (true, true) => map(&mut spectrum.phases(), freq, db),
-        (true, false) => map(spectrum.phases_real(), freq, db),
-        (false, true) => map(spectrum.amplitudes(), freq, db),
-        (false, false) => map(spectrum.amplitudes_real(), freq, db),
+    let shown = if full_spectrum {
+        spectrum.width()
+    } else {
+        spectrum.width() / 2 + 1
     };
 
+    let buckets = spectrum
+        .iter_bins()
+        .zip(spectrum.amplitudes())
+        .take(shown)
+        .map(|((freq, complex), amplitude)| {
+            let value = if phase { complex.arg() } else { amplitude };
+
+            Bar::new(freq, db(value) as f64)
+        })
+        .collect();
+
     ui.bar_chart(
         BarChart::new(buckets)
             .width(spectrum.freq_resolution())
@@ -210,6 +241,19 @@ fn display_spectrum(
     );
 
     if !phase {
+        if note_names {
+            // Below the peaks, out of the way of the main frequency
+            for (bucket, amplitude, note) in peak_notes(spectrum) {
+                ui.text(
+                    Text::new(
+                        Value::new(spectrum.freq_from_bucket(bucket), db(amplitude)),
+                        RichText::new(note.to_string()).monospace(),
+                    )
+                    .anchor(Align2::CENTER_TOP),
+                )
+            }
+        }
+
         if let Some((bucket, max)) = spectrum.main_frequency() {
             let freq = spectrum.freq_from_bucket(bucket);
 
@@ -223,3 +267,55 @@ fn display_spectrum(
         }
     }
 }
+
+/// The buckets, amplitudes and nearest notes of the loudest peaks in the
+/// spectrum up to nyquist, loudest first
+fn peak_notes(spectrum: &Spectrum) -> Vec<(usize, f32, MusicalNote)> {
+    let amplitudes = spectrum.amplitudes_real().collect::<Vec<_>>();
+    let loudest = amplitudes.iter().copied().fold(0.0, f32::max);
+    let threshold = loudest * 10.0f32.powf(NOTE_PEAK_DB / 20.0);
+
+    let mut peaks = (1..amplitudes.len().saturating_sub(1))
+        .filter(|&bucket| {
+            let amplitude = amplitudes[bucket];
+
+            amplitude > 0.0
+                && amplitude >= threshold
+                && amplitude >= amplitudes[bucket - 1]
+                && amplitude >= amplitudes[bucket + 1]
+        })
+        .filter_map(|bucket| {
+            let freq = spectrum.freq_from_bucket(bucket) as f32;
+            let key = PianoKey::from_frequency(freq, Tuning::CONCERT)?;
+
+            Some((bucket, amplitudes[bucket], key.as_note(Accidental::Sharp)))
+        })
+        .collect::<Vec<_>>();
+
+    peaks.sort_by(|a, b| b.1.total_cmp(&a.1));
+    peaks.truncate(MAX_NOTE_PEAKS);
+
+    peaks
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+    use spectrum::{WaveformSpectrum, Window};
+
+    use super::peak_notes;
+
+    #[test]
+    fn a4_peak() {
+        let sine = Waveform::sine_wave(440.0, 0.1, Waveform::CD_SAMPLE_RATE);
+        let window = sine.slice(..4096);
+        let spectrum = window.spectrum(Window::Hann, 4096).unwrap();
+
+        let notes = peak_notes(&spectrum)
+            .into_iter()
+            .map(|(_, _, note)| note.to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(notes, ["A4"]);
+    }
+}
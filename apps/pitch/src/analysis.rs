@@ -1,19 +1,167 @@
-use std::{collections::BTreeMap, time::Duration};
+use std::{
+    collections::BTreeMap,
+    ops::{ControlFlow, Range},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
 
 use audio::waveform::Waveform;
 use eframe::epaint::{Color32, ColorImage};
-use spectrum::WaveformSpectrum;
+use rayon::prelude::*;
+use serde::{ser, Deserialize, Deserializer, Serialize, Serializer};
+use spectrum::{fft::FftError, WaveformSpectrum};
 
-use crate::key::PianoKey;
+use crate::key::{PianoKey, Tuning};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct AnalysisOptions {
     pub fft_size: u8,
 
     pub window_fraction: f32,
     pub step_fraction: f32,
 
-    pub threshold: f32,
+    pub threshold: ThresholdMode,
+
+    pub tuning: Tuning,
+
+    pub frequency_scale: FrequencyScale,
+
+    /// The width and height of the median filter run over the spectrogram
+    /// before notes are found in it, removing isolated spikes which would
+    /// otherwise become notes a single window long. Zero or one leave the
+    /// spectrogram as it is.
+    #[serde(default)]
+    pub median_filter: usize,
+
+    /// Keypresses of the same key with gaps up to this long between them are
+    /// joined, so that sustained notes are not split up by the rounding of
+    /// window starts
+    #[serde(default = "default_merge_gap_ms")]
+    pub merge_gap_ms: u64,
+    /// How the intensities of joined keypresses are combined
+    #[serde(default)]
+    pub intensity_merge: IntensityMerge,
+
+    /// When not zero, at most this many notes are found in each window by
+    /// repeatedly taking the strongest peak and removing its harmonics, rather
+    /// than taking every bucket above the threshold as a note
+    #[serde(default)]
+    pub max_polyphony: usize,
+
+    /// Take each window's spectrum in double precision, lowering the noise
+    /// floor of wide ffts so quiet notes stand out, at the cost of analysis
+    /// taking several times longer
+    #[serde(default)]
+    pub precise_fft: bool,
+}
+
+/// How the intensity of keypresses joined into one is found. Each keypress is
+/// weighted by its duration, so that joining touching keypresses gives the same
+/// intensity whatever order they are joined in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntensityMerge {
+    /// The loudest of the keypresses
+    Max,
+    /// The mean intensity over the keypresses
+    Mean,
+    /// The root mean square intensity, keeping the total energy the same
+    Energy,
+}
+
+impl Default for IntensityMerge {
+    fn default() -> Self {
+        Self::Max
+    }
+}
+
+impl IntensityMerge {
+    fn merge(self, a: KeyPress, b: KeyPress) -> f32 {
+        self.merge_by(a, b, KeyPress::intensity)
+    }
+
+    /// Combine any per keypress `value` of two keypresses in the same way
+    fn merge_by(self, a: KeyPress, b: KeyPress, value: impl Fn(&KeyPress) -> f32) -> f32 {
+        let (a_secs, b_secs) = (a.duration_secs(), b.duration_secs());
+        let total_secs = a_secs + b_secs;
+        let (a, b) = (value(&a), value(&b));
+
+        match self {
+            _ if total_secs == 0.0 => a.max(b),
+            IntensityMerge::Max => a.max(b),
+            IntensityMerge::Mean => (a * a_secs + b * b_secs) / total_secs,
+            IntensityMerge::Energy => {
+                ((a.powi(2) * a_secs + b.powi(2) * b_secs) / total_secs).sqrt()
+            }
+        }
+    }
+}
+
+/// How the rows of the spectrogram image are spaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FrequencyScale {
+    /// One row per fft bucket, from the lowest frequency down
+    Linear,
+    /// Rows spaced evenly in log-frequency across the piano's range, from the
+    /// highest frequency down to line up with the piano roll. 88 bins gives
+    /// each key a row of its own.
+    Logarithmic { bins: usize },
+}
+
+/// The amplitude a frequency must reach to count as a note being played
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ThresholdMode {
+    /// An amplitude normalized by the window length, as from
+    /// [`spectrum::Spectrum::amplitudes`]
+    Linear(f32),
+    /// Decibels relative to the loudest frequency in each window, so the same
+    /// threshold works for any fft width
+    Decibels(f32),
+    /// Decibels above the mean amplitude of each window, so that notes still
+    /// stand out in quiet passages while noisy windows need a clearer peak
+    AboveMean(f32),
+}
+
+impl ThresholdMode {
+    /// The amplitude to compare against, given every amplitude in the window
+    pub fn amplitude(&self, window: &[f32]) -> f32 {
+        match *self {
+            ThresholdMode::Linear(amplitude) => amplitude,
+            ThresholdMode::Decibels(decibels) => {
+                let peak = window.iter().copied().fold(0.0, f32::max);
+
+                peak * 10.0f32.powf(decibels / 20.0)
+            }
+            ThresholdMode::AboveMean(decibels) => {
+                let mean = window.iter().sum::<f32>() / window.len().max(1) as f32;
+
+                mean * 10.0f32.powf(decibels / 20.0)
+            }
+        }
+    }
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        Self {
+            fft_size: 14,
+            window_fraction: 0.5,
+            step_fraction: 1.0,
+            threshold: ThresholdMode::Decibels(-20.0),
+            tuning: Tuning::CONCERT,
+            frequency_scale: FrequencyScale::Linear,
+            median_filter: 0,
+            merge_gap_ms: default_merge_gap_ms(),
+            intensity_merge: IntensityMerge::Max,
+            max_polyphony: 0,
+            precise_fft: false,
+        }
+    }
+}
+
+/// Shared with options saved before the gap could be set, so that they load
+/// with the same gap as a fresh start
+fn default_merge_gap_ms() -> u64 {
+    10
 }
 
 impl AnalysisOptions {
@@ -31,61 +179,416 @@ impl AnalysisOptions {
 pub fn analyze(
     waveform: &Waveform,
     options: AnalysisOptions,
-    progress_callback: &dyn Fn(f32),
-) -> (BTreeMap<PianoKey, KeyPresses>, ColorImage) {
-    let fft_width = options.fft_width();
+    progress_callback: &(dyn Fn(f32) -> ControlFlow<()> + Sync),
+) -> Result<(BTreeMap<PianoKey, KeyPresses>, ColorImage), FftError> {
+    analyze_windows(waveform, options, progress_callback, true)
+}
+
+/// The spectrogram column and keypresses found in a single window
+struct WindowAnalysis {
+    column: Vec<Color32>,
+    keypresses: Vec<(PianoKey, KeyPress)>,
+}
+
+/// Take the spectrum of each window independently, optionally in parallel,
+/// merging them in window order so both give the same output, then find the
+/// notes in the whole spectrogram.
+///
+/// Analysis stops early if the callback breaks, keeping every window up to the
+/// first one which was skipped. An error is returned if the fft width is not
+/// supported.
+fn analyze_windows(
+    waveform: &Waveform,
+    options: AnalysisOptions,
+    progress_callback: &(dyn Fn(f32) -> ControlFlow<()> + Sync),
+    parallel: bool,
+) -> Result<(BTreeMap<PianoKey, KeyPresses>, ColorImage), FftError> {
     let window_width = options.window_width();
     let step = options.step();
 
     let windows = (0..waveform.len() - window_width)
         .step_by(step)
-        .map(|start| start..start + window_width);
-    let window_count = dbg!(windows.len());
+        .collect::<Vec<_>>();
+    let window_count = windows.len();
 
-    let seconds_per_window = window_width as f64 / waveform.sample_rate() as f64;
+    let completed = AtomicUsize::new(0);
+    let cancelled = AtomicBool::new(false);
+    let window_spectrum = |&start: &usize| {
+        if cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
 
-    let mut image = ColorImage::new([window_count, fft_width / 2], Color32::BLACK);
-    let mut keys = BTreeMap::<PianoKey, KeyPresses>::new();
+        let amplitudes = window_amplitudes(waveform, options, start..start + window_width);
+
+        let completed = completed.fetch_add(1, Ordering::Relaxed) + 1;
+        if progress_callback(completed as f32 / window_count as f32).is_break() {
+            cancelled.store(true, Ordering::Relaxed);
+        }
 
-    for (i, window) in windows.enumerate() {
-        progress_callback(i as f32 / image.width() as f32);
+        Some(amplitudes)
+    };
 
-        let waveform = waveform.slice(window);
-        let spectrum = waveform.spectrum(spectrum::Window::Hann, fft_width);
+    let spectra = if parallel {
+        windows.par_iter().map(window_spectrum).collect::<Vec<_>>()
+    } else {
+        windows.iter().map(window_spectrum).collect::<Vec<_>>()
+    };
 
-        let width = image.width();
-        // let mut max = None;
-        for (pixel, (bucket, amplitude)) in image.pixels[i..]
+    // Windows after a skipped one would leave a gap
+    let spectrogram = spectra
+        .into_iter()
+        .map_while(|amplitudes| amplitudes)
+        .collect::<Result<Vec<_>, _>>()?;
+    let window_count = spectrogram.len();
+
+    let spectrogram = if options.median_filter > 1 {
+        median_filter(&spectrogram, options.median_filter)
+    } else {
+        spectrogram
+    };
+
+    let bucket_hz = waveform.sample_rate() as f32 / options.fft_width() as f32;
+    let seconds_per_window = window_width as f64 / waveform.sample_rate() as f64;
+    let analyses = spectrogram.into_iter().enumerate().map(|(i, amplitudes)| {
+        analyze_window(amplitudes, options, i, bucket_hz, seconds_per_window)
+    });
+
+    let height = match options.frequency_scale {
+        FrequencyScale::Linear => options.fft_width() / 2,
+        FrequencyScale::Logarithmic { bins } => bins,
+    };
+
+    let mut image = ColorImage::new([window_count, height], Color32::BLACK);
+    let mut keys = BTreeMap::<PianoKey, KeyPresses>::new();
+
+    for (i, analysis) in analyses.enumerate() {
+        for (pixel, color) in image.pixels[i..]
             .iter_mut()
-            .step_by(width)
-            .zip(spectrum.amplitudes_real().enumerate())
+            .step_by(window_count)
+            .zip(analysis.column)
         {
+            *pixel = color;
+        }
+
+        for (key, keypress) in analysis.keypresses {
+            keys.entry(key).or_default().add_within(
+                keypress,
+                Duration::from_millis(options.merge_gap_ms),
+                options.intensity_merge,
+            );
+        }
+    }
+
+    Ok((keys, image))
+}
+
+/// The amplitudes of the window's spectrum from DC up to nyquist
+fn window_amplitudes(
+    waveform: &Waveform,
+    options: AnalysisOptions,
+    window: Range<usize>,
+) -> Result<Vec<f32>, FftError> {
+    let window = waveform.slice(window);
+
+    let spectrum = if options.precise_fft {
+        window.spectrum_precise(spectrum::Window::Hann, options.fft_width())?
+    } else {
+        window.spectrum(spectrum::Window::Hann, options.fft_width())?
+    };
+
+    Ok(spectrum.amplitudes_real().collect())
+}
+
+/// Find the spectrogram column and keypresses of the `i`th window from its
+/// amplitudes, each bucket of which is `bucket_hz` wide
+fn analyze_window(
+    amplitudes: Vec<f32>,
+    options: AnalysisOptions,
+    i: usize,
+    bucket_hz: f32,
+    seconds_per_window: f64,
+) -> WindowAnalysis {
+    // Half a key either side of the piano, from A0 up to C8
+    let low_hz = options.tuning.a4_hz * 2.0f32.powf(-48.5 / 12.0);
+    let high_hz = options.tuning.a4_hz * 2.0f32.powf(39.5 / 12.0);
+
+    let threshold = options.threshold.amplitude(&amplitudes);
+
+    let rows = match options.frequency_scale {
+        FrequencyScale::Linear => amplitudes.clone(),
+        FrequencyScale::Logarithmic { bins } => {
+            log_frequency_rows(&amplitudes, bucket_hz, bins, low_hz, high_hz)
+        }
+    };
+
+    let column = rows
+        .into_iter()
+        .map(|amplitude| {
             let color = colorous::VIRIDIS.eval_continuous(amplitude as f64);
-            *pixel = Color32::from_rgb(color.r, color.g, color.b);
+            Color32::from_rgb(color.r, color.g, color.b)
+        })
+        .collect();
 
-            // let max = max.get_or_insert((bucket, amplitude));
-            // if amplitude > max.1 {
-            //     *max = (bucket, amplitude)
-            // }
+    // The main lobe of the window spreads over more buckets the more it is
+    // padded
+    let lobe = (2.0 / options.window_fraction).ceil() as usize;
 
-            if amplitude < options.threshold {
-                continue;
-            }
+    let notes = if options.max_polyphony > 0 {
+        fundamentals(&amplitudes, threshold, options.max_polyphony, lobe)
+    } else {
+        amplitudes
+            .iter()
+            .enumerate()
+            // Silent windows would otherwise meet a relative threshold everywhere
+            .filter(|&(_, &amplitude)| amplitude >= threshold && amplitude != 0.0)
+            .map(|(bucket, &amplitude)| (bucket as f32, amplitude))
+            .collect()
+    };
 
-            let frequency = spectrum.freq_from_bucket(bucket) as f32;
-            let key = PianoKey::from_concert_pitch(frequency);
+    let keypresses = notes
+        .into_iter()
+        .filter_map(|(bucket, amplitude)| {
+            let key = PianoKey::from_frequency(bucket * bucket_hz, options.tuning)?;
+            let confidence = prominence(&amplitudes, bucket.round() as usize, lobe);
 
-            if let Some(key) = key {
-                keys.entry(key).or_default().add(KeyPress::new(
+            Some((
+                key,
+                KeyPress::new(
                     (i as f64 * seconds_per_window * 1000.0).round() as u64,
                     KeyDuration::from_secs_f64(seconds_per_window),
                     amplitude,
-                ));
-            }
+                )
+                .with_confidence(confidence),
+            ))
+        })
+        .collect();
+
+    WindowAnalysis { column, keypresses }
+}
+
+/// How far, in decibels, a bucket has to stand above its surroundings for a
+/// note found in it to be fully confident
+const PROMINENT_DB: f32 = 30.0;
+
+/// How clearly the bucket stands out as a peak, from 0 when it is no louder
+/// than the mean of the buckets around it up to 1 when it is [`PROMINENT_DB`]
+/// louder. The `lobe` buckets either side, which the window spreads the peak
+/// itself over, are left out of the mean, so noise which is only as loud as
+/// the rest of the spectrum is not confident however far over the threshold
+/// it is.
+fn prominence(amplitudes: &[f32], bucket: usize, lobe: usize) -> f32 {
+    let amplitude = match amplitudes.get(bucket) {
+        Some(&amplitude) if amplitude > 0.0 => amplitude,
+        _ => return 0.0,
+    };
+
+    let radius = lobe * 8;
+    let surroundings = (bucket.saturating_sub(radius)..(bucket + radius + 1).min(amplitudes.len()))
+        .filter(|&other| other.abs_diff(bucket) > lobe)
+        .map(|other| amplitudes[other])
+        .collect::<Vec<_>>();
+
+    let mean = surroundings.iter().sum::<f32>() / surroundings.len().max(1) as f32;
+    if mean == 0.0 {
+        return 1.0;
+    }
+
+    (20.0 * (amplitude / mean).log10() / PROMINENT_DB).clamp(0.0, 1.0)
+}
+
+/// The number of harmonics, counting the fundamental, removed from the
+/// spectrum along with each note found by [`fundamentals`]
+const HARMONICS: usize = 8;
+
+/// Find up to `max_notes` fundamentals in the window, as fractional buckets
+/// with their amplitudes. The strongest peak left above the threshold is taken
+/// as a harmonic of whichever of its first few subharmonics best explains the
+/// rest of the spectrum, then the main lobe, `lobe` buckets either side, of
+/// every harmonic of that fundamental is removed so they are not found again
+/// as notes of their own.
+fn fundamentals(
+    amplitudes: &[f32],
+    threshold: f32,
+    max_notes: usize,
+    lobe: usize,
+) -> Vec<(f32, f32)> {
+    let mut residual = amplitudes.to_vec();
+    let mut notes = Vec::with_capacity(max_notes);
+
+    let audible = |amplitude: f32| amplitude >= threshold && amplitude != 0.0;
+
+    while notes.len() < max_notes {
+        let peak = match (1..residual.len().saturating_sub(1))
+            .filter(|&bucket| {
+                audible(residual[bucket])
+                    && residual[bucket] >= residual[bucket - 1]
+                    && residual[bucket] >= residual[bucket + 1]
+            })
+            .max_by(|&a, &b| residual[a].total_cmp(&residual[b]))
+        {
+            Some(peak) => peak,
+            None => break,
+        };
+        let peak_bucket = interpolate_peak(&residual, peak);
+
+        // Harmonics are weighted down so a subharmonic which only lines up
+        // with some of them does not win over the true fundamental
+        let harmonic_strength = |fundamental: f32| {
+            (1..=HARMONICS)
+                .filter_map(|harmonic| {
+                    let bucket = nearest_peak(&residual, fundamental * harmonic as f32)?;
+
+                    Some(residual[bucket] / harmonic as f32)
+                })
+                .sum::<f32>()
+        };
+
+        let (fundamental, amplitude) = (1..=3)
+            .filter_map(|divisor| {
+                let bucket = nearest_peak(&residual, peak_bucket / divisor as f32)?;
+
+                audible(residual[bucket])
+                    .then(|| (interpolate_peak(&residual, bucket), residual[bucket]))
+            })
+            .map(|(fundamental, amplitude)| {
+                (fundamental, amplitude, harmonic_strength(fundamental))
+            })
+            // The first, so the peak itself, wins ties
+            .reduce(|best, candidate| {
+                if candidate.2 > best.2 {
+                    candidate
+                } else {
+                    best
+                }
+            })
+            .map(|(fundamental, amplitude, _)| (fundamental, amplitude))
+            .unwrap_or((peak_bucket, residual[peak]));
+
+        notes.push((fundamental, amplitude));
+
+        // The peak itself goes too, in case it was too far out of tune with
+        // the fundamental to be found as one of its harmonics
+        let harmonics = (1..=HARMONICS)
+            .map_while(|harmonic| nearest_peak(&residual, fundamental * harmonic as f32))
+            .collect::<Vec<_>>();
+
+        for bucket in harmonics.into_iter().chain([peak]) {
+            let lobe = bucket.saturating_sub(lobe)..(bucket + lobe + 1).min(residual.len());
+            residual[lobe].fill(0.0);
         }
     }
 
-    (keys, image)
+    notes
+}
+
+/// The loudest bucket within one of the fractional `bucket`, if it is in the
+/// spectrum
+fn nearest_peak(amplitudes: &[f32], bucket: f32) -> Option<usize> {
+    let center = bucket.round() as usize;
+    if center >= amplitudes.len() {
+        return None;
+    }
+
+    (center.saturating_sub(1)..(center + 2).min(amplitudes.len()))
+        .max_by(|&a, &b| amplitudes[a].total_cmp(&amplitudes[b]))
+}
+
+/// The fractional bucket of the peak at `bucket`, from the parabola through it
+/// and its neighbours
+fn interpolate_peak(amplitudes: &[f32], bucket: usize) -> f32 {
+    if bucket == 0 || bucket + 1 >= amplitudes.len() {
+        return bucket as f32;
+    }
+
+    let (left, center, right) = (
+        amplitudes[bucket - 1],
+        amplitudes[bucket],
+        amplitudes[bucket + 1],
+    );
+    let curvature = left - 2.0 * center + right;
+
+    if curvature == 0.0 {
+        bucket as f32
+    } else {
+        bucket as f32 + 0.5 * (left - right) / curvature
+    }
+}
+
+/// Replace every amplitude in the spectrogram, stored as columns of buckets,
+/// with the median of the `kernel` by `kernel` square around it. The square
+/// is cut short by the edges, with the upper median taken when that leaves an
+/// even number of amplitudes.
+fn median_filter(spectrogram: &[Vec<f32>], kernel: usize) -> Vec<Vec<f32>> {
+    let radius = kernel / 2;
+    let mut neighbourhood = Vec::with_capacity((radius * 2 + 1).pow(2));
+
+    spectrogram
+        .iter()
+        .enumerate()
+        .map(|(column, buckets)| {
+            let columns = &spectrogram
+                [column.saturating_sub(radius)..(column + radius + 1).min(spectrogram.len())];
+
+            (0..buckets.len())
+                .map(|bucket| {
+                    let rows = bucket.saturating_sub(radius)..bucket + radius + 1;
+
+                    neighbourhood.clear();
+                    neighbourhood.extend(columns.iter().flat_map(|column| {
+                        column[rows.start..rows.end.min(column.len())]
+                            .iter()
+                            .copied()
+                    }));
+
+                    let middle = neighbourhood.len() / 2;
+                    *neighbourhood
+                        .select_nth_unstable_by(middle, f32::total_cmp)
+                        .1
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Resample linearly spaced fft amplitudes into `bins` rows spaced evenly in
+/// log-frequency between `low_hz` and `high_hz`, from the highest row down.
+///
+/// Each row takes the loudest bucket it covers, or interpolates between the
+/// nearest buckets when it is narrower than a single bucket, as the low rows
+/// usually are.
+fn log_frequency_rows(
+    amplitudes: &[f32],
+    bucket_hz: f32,
+    bins: usize,
+    low_hz: f32,
+    high_hz: f32,
+) -> Vec<f32> {
+    let ratio = high_hz / low_hz;
+    // The fractional bucket at the lower edge of a bin
+    let edge = |bin: usize| low_hz * ratio.powf(bin as f32 / bins as f32) / bucket_hz;
+
+    (0..bins)
+        .rev()
+        .map(|bin| {
+            let (low, high) = (edge(bin), edge(bin + 1));
+
+            let covered = amplitudes
+                .iter()
+                .take(high.floor() as usize + 1)
+                .skip(low.ceil() as usize)
+                .copied()
+                .reduce(f32::max);
+
+            covered.unwrap_or_else(|| {
+                let center = (low + high) / 2.0;
+                let (below, t) = (center.floor() as usize, center.fract());
+
+                let amplitude = |bucket: usize| amplitudes.get(bucket).copied().unwrap_or(0.0);
+
+                amplitude(below) * (1.0 - t) + amplitude(below + 1) * t
+            })
+        })
+        .collect()
 }
 
 // FIXME: better data representation?
@@ -94,16 +597,40 @@ pub type KeyStart = u128;
 // The duration of the keypress
 pub type KeyDuration = Duration;
 
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct KeyPress {
+    #[serde(with = "start_millis")]
     start: KeyStart,
     info: KeyPressInfo,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
 struct KeyPressInfo {
     duration: KeyDuration,
     intensity: f32,
+    /// Notes saved before confidence was recorded are taken as certain
+    #[serde(default = "full_confidence")]
+    confidence: f32,
+}
+
+fn full_confidence() -> f32 {
+    1.0
+}
+
+/// Starts are stored as `u64` milliseconds, since not every format supports
+/// `u128`
+mod start_millis {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(start: &KeyStart, serializer: S) -> Result<S::Ok, S::Error> {
+        u64::try_from(*start)
+            .map_err(ser::Error::custom)?
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<KeyStart, D::Error> {
+        u64::deserialize(deserializer).map(KeyStart::from)
+    }
 }
 
 impl KeyPress {
@@ -117,10 +644,19 @@ impl KeyPress {
             info: KeyPressInfo {
                 duration,
                 intensity: intensity.into(),
+                confidence: full_confidence(),
             },
         }
     }
 
+    /// Set how sure the analysis was that this is a note rather than noise,
+    /// from 0 to 1
+    #[must_use = "KeyPress::with_confidence() returns a new keypress"]
+    pub fn with_confidence(mut self, confidence: f32) -> Self {
+        self.info.confidence = confidence.clamp(0.0, 1.0);
+        self
+    }
+
     pub fn start(&self) -> u128 {
         self.start
     }
@@ -144,9 +680,17 @@ impl KeyPress {
     pub fn intensity(&self) -> f32 {
         self.info.intensity
     }
+
+    /// How sure the analysis was that this is a note rather than noise, from
+    /// 0 to 1. Keypresses made any other way are fully confident.
+    pub fn confidence(&self) -> f32 {
+        self.info.confidence
+    }
 }
 
-#[derive(Debug, Default, Clone)]
+/// Stored as a list of [`KeyPress`]es
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<KeyPress>", into = "Vec<KeyPress>")]
 pub struct KeyPresses {
     key_list: BTreeMap<KeyStart, KeyPressInfo>,
 }
@@ -167,6 +711,18 @@ impl<const N: usize> From<[KeyPress; N]> for KeyPresses {
     }
 }
 
+impl From<Vec<KeyPress>> for KeyPresses {
+    fn from(vec: Vec<KeyPress>) -> Self {
+        vec.into_iter().collect()
+    }
+}
+
+impl From<KeyPresses> for Vec<KeyPress> {
+    fn from(presses: KeyPresses) -> Self {
+        presses.iter().collect()
+    }
+}
+
 impl Extend<KeyPress> for KeyPresses {
     fn extend<T: IntoIterator<Item = KeyPress>>(&mut self, iter: T) {
         for keypress in iter.into_iter() {
@@ -194,6 +750,10 @@ impl KeyPresses {
         self.key_list.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.key_list.is_empty()
+    }
+
     pub fn first(&self) -> Option<KeyPress> {
         self.iter().next()
     }
@@ -202,51 +762,711 @@ impl KeyPresses {
         self.iter().next_back()
     }
 
-    // FIXME: what do about intensity
+    /// Add `keypress`, joining it with the keypresses right before and after
+    /// it if they touch, keeping the greatest intensity
+    pub fn add(&mut self, keypress: KeyPress) {
+        self.add_within(keypress, Duration::ZERO, IntensityMerge::Max);
+    }
+
+    /// Add `keypress`, joining it with the keypresses before and after it if
+    /// the gap between them is no longer than `gap_tolerance`. A joined
+    /// keypress covers the gap, with the intensities combined by `intensity`.
     // FIXME: do at analysis time?
-    pub fn add(&mut self, mut keypress: KeyPress) {
-        // Join with the note before this
-        if let Some((
-            previous_key_start,
-            KeyPressInfo {
-                duration: previous_key_duration,
-                ..
+    pub fn add_within(
+        &mut self,
+        mut keypress: KeyPress,
+        gap_tolerance: Duration,
+        intensity: IntensityMerge,
+    ) {
+        let tolerance = gap_tolerance.as_millis();
+
+        // The gap from the end of one keypress to the start of a later one, if
+        // they do not overlap
+        let gap = |earlier: &KeyPress, later: &KeyPress| {
+            later
+                .start
+                .checked_sub(earlier.start + earlier.duration().as_millis())
+        };
+
+        // Join two keypresses, filling the gap between them
+        let join = |earlier: KeyPress, later: KeyPress, gap: u128| KeyPress {
+            start: earlier.start,
+            info: KeyPressInfo {
+                duration: earlier.duration() + Duration::from_millis(gap as u64) + later.duration(),
+                intensity: intensity.merge(earlier, later),
+                confidence: IntensityMerge::Mean.merge_by(earlier, later, KeyPress::confidence),
             },
-        )) = self.key_list.range_mut(..keypress.start).next_back()
-        {
-            // Check if the end of the previous keypress overlaps with the start of this keypress
-            if *previous_key_start + previous_key_duration.as_millis() == keypress.start {
-                // Extend the previous key's duration
-                *previous_key_duration += keypress.duration();
+        };
 
-                return;
+        // Join with the note before this
+        if let Some(previous) = self.last_before(keypress.start) {
+            if let Some(gap) = gap(&previous, &keypress).filter(|&gap| gap <= tolerance) {
+                self.key_list.remove(&previous.start);
+                keypress = join(previous, keypress, gap);
             }
         }
 
         // Join with the note after this
-        if let Some((
-            &next_key_start,
-            &KeyPressInfo {
-                duration: next_key_duration,
-                ..
-            },
-        )) = self.key_list.range(keypress.start..).next()
+        if let Some(next) = self.first_after(keypress.start) {
+            if let Some(gap) = gap(&keypress, &next).filter(|&gap| gap <= tolerance) {
+                self.key_list.remove(&next.start);
+                keypress = join(keypress, next, gap);
+            }
+        }
+
+        self.key_list.insert(keypress.start, keypress.info);
+    }
+
+    /// The last keypress starting before `start`
+    fn last_before(&self, start: KeyStart) -> Option<KeyPress> {
+        self.key_list
+            .range(..start)
+            .next_back()
+            .map(|(&start, &info)| KeyPress { start, info })
+    }
+
+    /// The first keypress starting after `start`
+    fn first_after(&self, start: KeyStart) -> Option<KeyPress> {
+        self.key_list
+            .range(start + 1..)
+            .next()
+            .map(|(&start, &info)| KeyPress { start, info })
+    }
+
+    /// Insert `keypress` as is, without joining it to the keypresses either
+    /// side, replacing any keypress with the same start
+    pub fn insert(&mut self, keypress: KeyPress) {
+        self.key_list.insert(keypress.start, keypress.info);
+    }
+
+    /// Remove the keypress starting at `start`, if there is one
+    pub fn remove(&mut self, start: KeyStart) -> Option<KeyPress> {
+        self.key_list
+            .remove(&start)
+            .map(|info| KeyPress { start, info })
+    }
+
+    /// Change how long the keypress starting at `start` lasts, returning
+    /// whether there was one
+    pub fn set_duration(&mut self, start: KeyStart, duration: KeyDuration) -> bool {
+        match self.key_list.get_mut(&start) {
+            Some(info) => {
+                info.duration = duration;
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Snap the start of every keypress to the nearest multiple of `grid_ms`.
+    /// Keypresses which land on the same start are merged, keeping the longest
+    /// duration and the highest intensity. A grid of 0ms leaves them in place.
+    #[must_use = "KeyPresses::quantize() creates new keypresses"]
+    pub fn quantize(&self, grid_ms: u64) -> KeyPresses {
+        if grid_ms == 0 {
+            return self.clone();
+        }
+
+        let grid = grid_ms as KeyStart;
+
+        self.snap(|start| (start + grid / 2) / grid * grid)
+    }
+
+    /// Snap the start of every keypress to the nearest `subdivisions`th of a
+    /// beat at `bpm`, counting beats from `first_beat_ms` so that a pickup
+    /// before the first full bar stays on the grid. Keypresses before the
+    /// first beat snap back to earlier beats, but never before the start of
+    /// the recording. Keypresses which land on the same start are merged as
+    /// with [`KeyPresses::quantize`]. Without a positive tempo or any
+    /// subdivisions there is no grid, so they are left in place.
+    #[must_use = "KeyPresses::quantize_to_beats() creates new keypresses"]
+    pub fn quantize_to_beats(&self, bpm: f32, subdivisions: u32, first_beat_ms: u64) -> KeyPresses {
+        if !(bpm.is_finite() && bpm > 0.0) || subdivisions == 0 {
+            return self.clone();
+        }
+
+        let grid_ms = 60_000.0 / (bpm as f64 * subdivisions as f64);
+        let first_beat_ms = first_beat_ms as f64;
+
+        self.snap(|start| {
+            let subdivision = ((start as f64 - first_beat_ms) / grid_ms).round();
+
+            (first_beat_ms + subdivision * grid_ms).round().max(0.0) as KeyStart
+        })
+    }
+
+    /// Move the start of every keypress with `snap`, merging those which land
+    /// on the same start by keeping the longest duration and the highest
+    /// intensity
+    fn snap(&self, snap: impl Fn(KeyStart) -> KeyStart) -> KeyPresses {
+        let mut snapped = KeyPresses::new();
+
+        for keypress in self.iter() {
+            let start = snap(keypress.start);
+
+            if let Some(info) = snapped.key_list.get_mut(&start) {
+                info.duration = info.duration.max(keypress.duration());
+                info.intensity = info.intensity.max(keypress.intensity());
+                info.confidence = info.confidence.max(keypress.confidence());
+
+                continue;
+            }
+
+            snapped.add(KeyPress {
+                start,
+                info: keypress.info,
+            });
+        }
+
+        snapped
+    }
+}
+
+/// The total energy of each pitch class across the notes, indexed by semitones
+/// above C. Each keypress contributes its duration scaled by its intensity.
+pub fn chroma(notes: &BTreeMap<PianoKey, KeyPresses>) -> [f32; 12] {
+    let mut chroma = [0.0; 12];
+
+    for (key, key_presses) in notes {
+        chroma[key.semitone_offset() as usize] += key_presses
+            .iter()
+            .map(|keypress| keypress.duration_secs() * keypress.intensity())
+            .sum::<f32>();
+    }
+
+    chroma
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+
+    use std::{collections::BTreeMap, ops::ControlFlow};
+
+    use super::{
+        analyze, analyze_windows, chroma, log_frequency_rows, median_filter, prominence,
+        AnalysisOptions, FrequencyScale, IntensityMerge, KeyDuration, KeyPress, KeyPresses,
+        ThresholdMode,
+    };
+    use crate::key::{PianoKey, Tuning};
+    use spectrum::{fft::FftError, WaveformSpectrum, Window};
+
+    #[test]
+    fn decibel_threshold_independent_of_fft_width() {
+        let sample_rate = Waveform::CD_SAMPLE_RATE;
+        let a4 = Waveform::sine_wave(440.0, 1.0, sample_rate);
+        let a5 = Waveform::sine_wave(880.0, 1.0, sample_rate).gain(0.5);
+        let chord =
+            Waveform::mix(&[&a4, &a5], &[1.0, 1.0]).expect("waveforms should share a format");
+
+        let detected_keys = |fft_size| {
+            let options = AnalysisOptions {
+                fft_size,
+                window_fraction: 1.0,
+                threshold: ThresholdMode::Decibels(-12.0),
+                merge_gap_ms: 0,
+                ..Default::default()
+            };
+
+            let (keys, _) = analyze(&chord, options, &|_| ControlFlow::Continue(())).unwrap();
+            keys.into_keys().collect::<Vec<_>>()
+        };
+
+        let expected = [PianoKey::new(49), PianoKey::new(61)]
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .expect("A4 and A5 should be piano keys");
+
+        assert_eq!(detected_keys(12), expected);
+        assert_eq!(detected_keys(13), expected);
+    }
+
+    #[test]
+    fn tuning_shifts_keys() {
+        let sample_rate = Waveform::CD_SAMPLE_RATE;
+
+        // Between G#4 and A4, closer to G#4 at concert pitch and A4 when tuned to 432Hz
+        let waveform = Waveform::sine_wave(425.0, 1.0, sample_rate);
+
+        let detected_keys = |tuning| {
+            let options = AnalysisOptions {
+                fft_size: 13,
+                window_fraction: 1.0,
+                threshold: ThresholdMode::Decibels(-3.0),
+                tuning,
+                merge_gap_ms: 0,
+                ..Default::default()
+            };
+
+            let (keys, _) = analyze(&waveform, options, &|_| ControlFlow::Continue(())).unwrap();
+            keys.into_keys().collect::<Vec<_>>()
+        };
+
+        assert_eq!(detected_keys(Tuning::CONCERT), [PianoKey::new(48).unwrap()]);
+        assert_eq!(
+            detected_keys(Tuning { a4_hz: 432.0 }),
+            [PianoKey::new(49).unwrap()]
+        );
+    }
+
+    #[test]
+    fn quantize() {
+        let presses = KeyPresses::from([
+            KeyPress::new(95u64, KeyDuration::from_millis(5), 0.5),
+            KeyPress::new(105u64, KeyDuration::from_millis(20), 0.25),
+            KeyPress::new(240u64, KeyDuration::from_millis(10), 1.0),
+        ]);
+
+        let quantized = presses.quantize(100).iter().collect::<Vec<_>>();
+
+        assert_eq!(
+            quantized,
+            [
+                KeyPress::new(100u64, KeyDuration::from_millis(20), 0.5),
+                KeyPress::new(200u64, KeyDuration::from_millis(10), 1.0),
+            ]
+        );
+
+        assert_eq!(presses.quantize(0), presses);
+    }
+
+    #[test]
+    fn quantize_to_beats() {
+        let presses = KeyPresses::from([
+            KeyPress::new(10u64, KeyDuration::from_millis(100), 0.5),
+            KeyPress::new(240u64, KeyDuration::from_millis(100), 0.25),
+            KeyPress::new(380u64, KeyDuration::from_millis(50), 1.0),
+            KeyPress::new(760u64, KeyDuration::from_millis(100), 1.0),
+        ]);
+
+        // Eighth notes at 120 BPM are 250ms apart
+        let quantized = presses
+            .quantize_to_beats(120.0, 2, 0)
+            .iter()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            quantized,
+            [
+                KeyPress::new(0u64, KeyDuration::from_millis(100), 0.5),
+                KeyPress::new(250u64, KeyDuration::from_millis(100), 0.25),
+                KeyPress::new(500u64, KeyDuration::from_millis(50), 1.0),
+                KeyPress::new(750u64, KeyDuration::from_millis(100), 1.0),
+            ]
+        );
+
+        // With the first beat 100ms in, the grid moves along with it, bringing
+        // the second and third together
+        let quantized = presses
+            .quantize_to_beats(120.0, 2, 100)
+            .iter()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            quantized,
+            [
+                KeyPress::new(100u64, KeyDuration::from_millis(100), 0.5),
+                KeyPress::new(350u64, KeyDuration::from_millis(100), 1.0),
+                KeyPress::new(850u64, KeyDuration::from_millis(100), 1.0),
+            ]
+        );
+
+        assert_eq!(presses.quantize_to_beats(0.0, 2, 0), presses);
+        assert_eq!(presses.quantize_to_beats(f32::NAN, 2, 0), presses);
+        assert_eq!(presses.quantize_to_beats(120.0, 0, 0), presses);
+    }
+
+    #[test]
+    fn chroma_folds_octaves() {
+        let notes = BTreeMap::from([
+            // C4 and C5 land in the same pitch class
+            (
+                PianoKey::new(40).unwrap(),
+                KeyPresses::from([KeyPress::new(0u64, KeyDuration::from_millis(500), 1.0)]),
+            ),
+            (
+                PianoKey::new(52).unwrap(),
+                KeyPresses::from([KeyPress::new(0u64, KeyDuration::from_millis(1000), 0.5)]),
+            ),
+            // A4
+            (
+                PianoKey::new(49).unwrap(),
+                KeyPresses::from([
+                    KeyPress::new(0u64, KeyDuration::from_millis(250), 1.0),
+                    KeyPress::new(500u64, KeyDuration::from_millis(250), 1.0),
+                ]),
+            ),
+        ]);
+
+        let chroma = chroma(&notes);
+
+        assert_eq!(chroma[0], 1.0);
+        assert_eq!(chroma[9], 0.5);
+        assert_eq!(chroma.iter().sum::<f32>(), 1.5);
+    }
+
+    #[test]
+    fn serialize_key_presses() {
+        let presses = KeyPresses::from([
+            KeyPress::new(0u64, KeyDuration::from_millis(250), 1.0),
+            KeyPress::new(500u64, KeyDuration::from_millis(1500), 0.25),
+        ]);
+
+        let serialized = ron::to_string(&presses).unwrap();
+        let deserialized: KeyPresses = ron::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, presses);
+
+        let notes = BTreeMap::from([(PianoKey::new(49).unwrap(), presses)]);
+
+        let serialized = ron::to_string(&notes).unwrap();
+        let deserialized: BTreeMap<PianoKey, KeyPresses> = ron::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, notes);
+    }
+
+    #[test]
+    fn options_saved_without_merge_gap() {
+        let serialized = ron::to_string(&AnalysisOptions::default()).unwrap();
+        assert!(serialized.contains("merge_gap_ms:10,"));
+
+        let deserialized: AnalysisOptions =
+            ron::from_str(&serialized.replace("merge_gap_ms:10,", "")).unwrap();
+        assert_eq!(deserialized, AnalysisOptions::default());
+    }
+
+    #[test]
+    fn log_frequency_spectrogram() {
+        let waveform = Waveform::sine_wave(440.0, 0.5, Waveform::CD_SAMPLE_RATE);
+
+        for bins in [88, 200] {
+            let options = AnalysisOptions {
+                fft_size: 12,
+                window_fraction: 1.0,
+                threshold: ThresholdMode::Decibels(-12.0),
+                frequency_scale: FrequencyScale::Logarithmic { bins },
+                merge_gap_ms: 0,
+                ..Default::default()
+            };
+
+            let (_, image) = analyze(&waveform, options, &|_| ControlFlow::Continue(())).unwrap();
+
+            assert_eq!(image.height(), bins);
+        }
+
+        // With a row per key, A4 lands 39 rows below C8
+        let mut amplitudes = vec![0.0; 5000];
+        amplitudes[440] = 1.0;
+        let low_hz = 27.5 * 2.0f32.powf(-1.0 / 24.0);
+        let high_hz = 4186.009 * 2.0f32.powf(1.0 / 24.0);
+
+        let rows = log_frequency_rows(&amplitudes, 1.0, 88, low_hz, high_hz);
+
+        assert_eq!(rows.len(), 88);
+        assert_eq!(rows.iter().position(|&row| row == 1.0), Some(39));
+    }
+
+    #[test]
+    fn parallel_matches_serial() {
+        let sample_rate = Waveform::CD_SAMPLE_RATE;
+        let a4 = Waveform::sine_wave(440.0, 1.0, sample_rate);
+        let e5 = Waveform::sine_wave(659.25, 1.0, sample_rate).gain(0.5);
+        let chord =
+            Waveform::mix(&[&a4, &e5], &[1.0, 1.0]).expect("waveforms should share a format");
+
+        let options = AnalysisOptions {
+            fft_size: 11,
+            window_fraction: 1.0,
+            step_fraction: 0.5,
+            frequency_scale: FrequencyScale::Logarithmic { bins: 88 },
+            merge_gap_ms: 0,
+            ..Default::default()
+        };
+
+        let (serial_keys, serial_image) =
+            analyze_windows(&chord, options, &|_| ControlFlow::Continue(()), false).unwrap();
+        let (parallel_keys, parallel_image) =
+            analyze_windows(&chord, options, &|_| ControlFlow::Continue(()), true).unwrap();
+
+        assert!(!serial_keys.is_empty());
+        assert_eq!(serial_keys.len(), parallel_keys.len());
+        for ((serial_key, serial), (parallel_key, parallel)) in
+            serial_keys.iter().zip(&parallel_keys)
         {
-            // Check if the end of this keypress overlaps with the start of the next keypress
-            if keypress.start + keypress.duration().as_millis() == next_key_start {
-                // Extend this key's duration
-                keypress.info.duration += next_key_duration;
+            assert_eq!(serial_key, parallel_key);
+            assert!(serial.iter().eq(parallel.iter()));
+        }
+
+        assert_eq!(serial_image.size, parallel_image.size);
+        assert_eq!(serial_image.pixels, parallel_image.pixels);
+    }
+
+    #[test]
+    fn cancel_analysis() {
+        let waveform = Waveform::sine_wave(440.0, 2.0, Waveform::CD_SAMPLE_RATE);
+        let options = AnalysisOptions {
+            fft_size: 10,
+            window_fraction: 1.0,
+            step_fraction: 0.25,
+            merge_gap_ms: 0,
+            ..Default::default()
+        };
+
+        let (_, full) = analyze(&waveform, options, &|_| ControlFlow::Continue(())).unwrap();
+
+        // Serially, only the window which broke is kept
+        let (_, image) =
+            analyze_windows(&waveform, options, &|_| ControlFlow::Break(()), false).unwrap();
+        assert_eq!(image.width(), 1);
+
+        // In parallel, windows already being analyzed are kept as well
+        let (_, image) = analyze(&waveform, options, &|_| ControlFlow::Break(())).unwrap();
+        assert!(image.width() < full.width());
+    }
 
-                // Remove the note after this
-                self.key_list.remove(&next_key_start);
+    #[test]
+    fn unsupported_fft_width() {
+        let waveform = Waveform::sine_wave(440.0, 1.0, Waveform::CD_SAMPLE_RATE);
+        let options = AnalysisOptions {
+            fft_size: 15,
+            merge_gap_ms: 0,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            analyze(&waveform, options, &|_| ControlFlow::Continue(())).err(),
+            Some(FftError::UnsupportedWidth(1 << 15))
+        );
+
+        // Any power of two works in double precision
+        let options = AnalysisOptions {
+            precise_fft: true,
+            ..options
+        };
+        assert!(analyze(&waveform, options, &|_| ControlFlow::Continue(())).is_ok());
+    }
+
+    #[test]
+    fn median_filter_removes_spikes() {
+        let mut spectrogram = vec![vec![0.0; 5]; 5];
+        spectrogram[2][2] = 1.0;
+
+        assert_eq!(median_filter(&spectrogram, 3), vec![vec![0.0; 5]; 5]);
+
+        // A note held across windows survives, even at the edges
+        for column in &mut spectrogram {
+            column[0] = 0.5;
+        }
+        let filtered = median_filter(&spectrogram, 3);
+        assert!(filtered
+            .iter()
+            .all(|column| column == &[0.5, 0.0, 0.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn adaptive_threshold_follows_fade() {
+        // Fading out exponentially by 40dB
+        let sample_rate = Waveform::CD_SAMPLE_RATE;
+        let sine = Waveform::sine_wave(440.0, 4.0, sample_rate);
+        let len = sine.len() as f32;
+        let fading = Waveform::new_mono(
+            sine.samples_iter()
+                .enumerate()
+                .map(|(n, sample)| sample * 10f32.powf(-2.0 * n as f32 / len))
+                .collect(),
+            sample_rate,
+        );
+
+        let a4 = PianoKey::new(49).unwrap();
+        let held_until = |threshold| {
+            let options = AnalysisOptions {
+                fft_size: 11,
+                window_fraction: 1.0,
+                threshold,
+                merge_gap_ms: 0,
+                ..Default::default()
+            };
+
+            let (keys, _) = analyze(&fading, options, &|_| ControlFlow::Continue(())).unwrap();
+            let last_held = keys.get(&a4).map_or(0.0, |presses| {
+                presses
+                    .iter()
+                    .map(|press| press.start_secs() + press.duration().as_secs_f32())
+                    .fold(0.0, f32::max)
+            });
+
+            last_held / fading.duration()
+        };
+
+        // Relative to each window, the note is found right to the end
+        assert!(held_until(ThresholdMode::Decibels(-12.0)) > 0.95);
+        assert!(held_until(ThresholdMode::AboveMean(20.0)) > 0.95);
+
+        // while a fixed amplitude loses it once it gets quiet
+        assert!(held_until(ThresholdMode::Linear(0.05)) < 0.5);
+    }
+
+    #[test]
+    fn merge_gaps() {
+        let press = |start: u64, millis, intensity: f32| {
+            KeyPress::new(start, KeyDuration::from_millis(millis), intensity)
+        };
+        let tolerance = KeyDuration::from_millis(5);
+
+        // Touching keypresses always join, in either order
+        for order in [[0, 1], [1, 0]] {
+            let halves = [press(0, 100, 0.5), press(100, 50, 1.0)];
+
+            let mut presses = KeyPresses::new();
+            for i in order {
+                presses.add(halves[i]);
             }
+
+            assert_eq!(presses.iter().collect::<Vec<_>>(), [press(0, 150, 1.0)]);
         }
 
-        self.key_list.insert(keypress.start, keypress.info);
+        // Gaps within the tolerance are filled
+        let mut presses = KeyPresses::new();
+        presses.add_within(press(0, 100, 1.0), tolerance, IntensityMerge::Max);
+        presses.add_within(press(105, 100, 0.5), tolerance, IntensityMerge::Max);
+        assert_eq!(presses.iter().collect::<Vec<_>>(), [press(0, 205, 1.0)]);
+
+        // and one bridging two keypresses joins all three
+        presses.add_within(press(400, 100, 0.25), tolerance, IntensityMerge::Max);
+        presses.add_within(press(207, 190, 0.75), tolerance, IntensityMerge::Max);
+        assert_eq!(presses.iter().collect::<Vec<_>>(), [press(0, 500, 1.0)]);
+
+        // while longer gaps are left alone
+        let mut presses = KeyPresses::new();
+        presses.add_within(press(0, 100, 1.0), tolerance, IntensityMerge::Max);
+        presses.add_within(press(106, 100, 1.0), tolerance, IntensityMerge::Max);
+        presses.add(press(300, 100, 1.0));
+        presses.add(press(401, 100, 1.0));
+        assert_eq!(presses.len(), 4);
+    }
+
+    #[test]
+    fn merged_intensity() {
+        let presses = [
+            KeyPress::new(0u64, KeyDuration::from_millis(100), 1.0),
+            KeyPress::new(100u64, KeyDuration::from_millis(100), 0.5),
+            KeyPress::new(200u64, KeyDuration::from_millis(200), 0.25),
+        ];
+
+        for (merge, expected) in [
+            (IntensityMerge::Max, 1.0),
+            (IntensityMerge::Mean, 0.5),
+            (IntensityMerge::Energy, 0.34375f32.sqrt()),
+        ] {
+            // The same whichever order they are joined in
+            for order in [[0, 1, 2], [2, 1, 0], [0, 2, 1]] {
+                let mut merged = KeyPresses::new();
+                for i in order {
+                    merged.add_within(presses[i], KeyDuration::ZERO, merge);
+                }
+
+                assert_eq!(merged.len(), 1);
+                let intensity = merged.first().unwrap().intensity();
+                assert!(
+                    (intensity - expected).abs() < 1e-5,
+                    "{merge:?} of {order:?} was {intensity}"
+                );
+            }
+        }
     }
 
-    // FIXME: Does not verify duration
-    pub fn remove(&mut self, keypress: &KeyPress) {
-        self.key_list.remove(&keypress.start);
+    #[test]
+    fn polyphonic_chord() {
+        let sample_rate = Waveform::CD_SAMPLE_RATE;
+
+        // C4, E4 and G4, each with a couple of quieter harmonics
+        let harmonics = [261.63, 329.63, 392.0]
+            .into_iter()
+            .flat_map(|fundamental| {
+                [(1.0, 1.0), (2.0, 0.5), (3.0, 0.25)].map(|(harmonic, gain)| {
+                    Waveform::sine_wave(fundamental * harmonic, 1.0, sample_rate).gain(gain)
+                })
+            })
+            .collect::<Vec<_>>();
+        let chord = Waveform::mix(&harmonics.iter().collect::<Vec<_>>(), &[1.0; 9])
+            .expect("waveforms should share a format");
+
+        let detected_keys = |max_polyphony| {
+            let options = AnalysisOptions {
+                fft_size: 13,
+                window_fraction: 1.0,
+                threshold: ThresholdMode::Decibels(-30.0),
+                merge_gap_ms: 0,
+                max_polyphony,
+                ..Default::default()
+            };
+
+            let (keys, _) = analyze(&chord, options, &|_| ControlFlow::Continue(())).unwrap();
+
+            let mut held = keys
+                .into_iter()
+                .map(|(key, presses)| {
+                    let held = presses
+                        .iter()
+                        .map(|press| press.duration())
+                        .sum::<KeyDuration>();
+
+                    (key, held)
+                })
+                .collect::<Vec<_>>();
+            held.sort_by_key(|&(_, held)| std::cmp::Reverse(held));
+
+            held.into_iter().map(|(key, _)| key).collect::<Vec<_>>()
+        };
+
+        let triad =
+            [40, 44, 47].map(|key| PianoKey::new(key).expect("C4, E4 and G4 should be piano keys"));
+
+        // Every harmonic, and the leakage around it, becomes a note of its own
+        assert!(detected_keys(0).len() > 3);
+
+        let mut polyphonic = detected_keys(3);
+        assert_eq!(polyphonic.len(), 3);
+        polyphonic.sort();
+        assert_eq!(polyphonic, triad);
+    }
+
+    #[test]
+    fn confidence() {
+        let sample_rate = Waveform::CD_SAMPLE_RATE;
+
+        let tone = Waveform::sine_wave(440.0, 1.0, sample_rate);
+        let options = AnalysisOptions {
+            fft_size: 13,
+            window_fraction: 1.0,
+            threshold: ThresholdMode::Decibels(-3.0),
+            merge_gap_ms: 0,
+            ..Default::default()
+        };
+
+        let (keys, _) = analyze(&tone, options, &|_| ControlFlow::Continue(())).unwrap();
+        assert!(!keys.is_empty());
+
+        for keypress in keys.values().flat_map(KeyPresses::iter) {
+            assert!(keypress.confidence() > 0.9, "{keypress:?}");
+        }
+
+        // The loudest bucket of noise is only a little louder than the rest
+        let noise = Waveform::white_noise(0.1, sample_rate, 7);
+        let amplitudes = noise
+            .slice(..4096)
+            .spectrum(Window::Hann, 4096)
+            .unwrap()
+            .amplitudes_real()
+            .collect::<Vec<_>>();
+        let loudest = (0..amplitudes.len())
+            .max_by(|&a, &b| amplitudes[a].total_cmp(&amplitudes[b]))
+            .unwrap();
+
+        let confidence = prominence(&amplitudes, loudest, 2);
+        assert!(confidence < 0.5, "{confidence}");
+
+        // Made by hand, and read from before confidence was recorded
+        assert_eq!(
+            KeyPress::new(0u64, KeyDuration::from_millis(10), 1.0).confidence(),
+            1.0
+        );
     }
 }
@@ -1,17 +1,23 @@
 use std::{
     collections::BTreeMap,
-    path::PathBuf,
-    sync::{atomic::Ordering, Arc},
+    mem,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     thread,
     time::Duration,
 };
 
 use atomic::Atomic;
 use audio::waveform::Waveform;
+use color_eyre::eyre::{bail, eyre, WrapErr};
 use eframe::{
     egui::{
-        Button, CentralPanel, Context, Layout, ProgressBar, RichText, Slider, TextFormat,
-        TopBottomPanel, Ui, Visuals, Window,
+        Button, CentralPanel, ComboBox, Context, Key, Layout, ProgressBar, RichText,
+        SelectableLabel, Slider, TextFormat, TopBottomPanel, Ui, Visuals, Window,
     },
     emath::{Align, Align2},
     epaint::{text::LayoutJob, Color32, TextureHandle, Vec2},
@@ -20,42 +26,94 @@ use eframe::{
 use once_cell::sync::Lazy;
 use parking_lot::{RwLock, RwLockReadGuard};
 use ritelinked::LinkedHashSet;
+use spectrum::onset;
 use static_assertions::const_assert;
 
 use crate::{
-    analysis::{analyze, AnalysisOptions, KeyPress, KeyPresses},
-    decode::AudioDecoder,
-    key::{Accidental, PianoKey},
-    midi::{MidiPlayer, SongProgress},
-    piano_roll::PianoRoll,
+    analysis::{
+        analyze, chroma, AnalysisOptions, FrequencyScale, IntensityMerge, KeyPress, KeyPresses,
+        ThresholdMode,
+    },
+    csv_file::export_csv,
+    decode::{AudioDecoder, AudioMetadata, ChannelMode, TrackInfo},
+    history::History,
+    key::{detect_key, Accidental, MusicalNote, PianoKey, Scale},
+    metronome::Metronome,
+    midi::{MidiPlayer, SongProgress, VelocityCurve},
+    midi_file::{export_midi, import_midi},
+    piano_roll::{zoom_to_fit, Notes, PianoRoll, PitchGrid},
+    session::{Session, SourceStatus},
+    synth::export_synthesized,
+    tuner::Tuner,
     ui_error::UiError,
 };
 
+/// The storage key for the notes of the last analysis
+pub const NOTES_KEY: &str = "notes";
+
+/// The width and hop, in samples, of the windows onsets are found in for
+/// estimating the tempo
+const ONSET_WIDTH: usize = 2048;
+const ONSET_HOP: usize = 512;
+
+/// Shown in place of the notes when nothing has been analyzed
+static NO_NOTES: Lazy<BTreeMap<PianoKey, KeyPresses>> = Lazy::new(BTreeMap::new);
+
 pub struct Application {
     recently_opened_files: LinkedHashSet<PathBuf>,
+    opened_file: Option<OpenedFile>,
 
     // FIXME: fix this abomination
     seconds_per_width: f32,
     key_height: f32,
     preference: Accidental,
+    scale: Option<Scale>,
     spectrogram: bool,
+    pitch_grid: PitchGrid,
+    follow_playback: bool,
+    edit_notes: bool,
+    /// Whether the notes were edited by hand since they were analyzed, restored
+    /// or saved
+    notes_edited: bool,
+    history: History,
+    /// The size of the piano roll last frame, for zooming to fit
+    piano_roll_size: Vec2,
+    /// Whether the piano roll is following this playback, until scrolled by hand
+    following: bool,
 
     // FIXME: RWLock really useful at all?
     waveform: Arc<RwLock<Option<Waveform<'static>>>>,
+    metadata: Arc<RwLock<Option<AudioMetadata>>>,
     analysis: Arc<RwLock<Option<AudioAnalysis>>>,
     analysis_options: AnalysisOptions,
     status: Arc<Atomic<TaskProgress>>,
+    /// Bumped whenever a background task starts, so any older task still
+    /// running knows to give up
+    task_generation: Arc<AtomicUsize>,
 
     midi: MidiPlayer,
     current_song: SongProgress,
+    tuner: Tuner,
+    metronome: Metronome,
 
     // Error reporting
     previous_error: Option<Box<dyn UiError>>,
+    task_error: Arc<RwLock<Option<Box<dyn UiError + Send + Sync>>>>,
+}
+
+/// The file most recently opened, and which of its tracks was chosen
+struct OpenedFile {
+    path: PathBuf,
+    tracks: Vec<TrackInfo>,
+    track_id: u32,
 }
 
 struct AudioAnalysis {
     notes: BTreeMap<PianoKey, KeyPresses>,
     spectrum: Option<TextureHandle>,
+    /// The estimated beats per minute, if the notes came from audio with a
+    /// clear enough beat
+    tempo: Option<f32>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -71,8 +129,55 @@ pub enum TaskProgress {
 const_assert!(Atomic::<TaskProgress>::is_lock_free());
 
 impl Application {
-    pub fn new(recently_opened_files: LinkedHashSet<PathBuf>) -> Self {
-        let test_pattern = PianoKey::all()
+    /// Create the application, showing the `notes` persisted from last time or
+    /// a test pattern if there are none
+    pub fn new(
+        recently_opened_files: LinkedHashSet<PathBuf>,
+        notes: Option<BTreeMap<PianoKey, KeyPresses>>,
+    ) -> Self {
+        let notes = notes.unwrap_or_else(Self::test_pattern);
+
+        Self {
+            previous_error: None,
+            task_error: Default::default(),
+
+            recently_opened_files,
+            opened_file: None,
+
+            midi: MidiPlayer::new(crate::NAME),
+            current_song: SongProgress::new(),
+            tuner: Tuner::default(),
+            metronome: Metronome::default(),
+
+            seconds_per_width: 30.0,
+            key_height: 10.0,
+            preference: Accidental::Flat,
+            scale: None,
+            spectrogram: true,
+            pitch_grid: PitchGrid::Off,
+            follow_playback: true,
+            edit_notes: false,
+            notes_edited: false,
+            history: History::default(),
+            piano_roll_size: Vec2::ZERO,
+            following: true,
+
+            analysis_options: AnalysisOptions::default(),
+            analysis: Arc::new(RwLock::new(Some(AudioAnalysis {
+                notes,
+                spectrum: None,
+                tempo: None,
+            }))),
+            waveform: Default::default(),
+            metadata: Default::default(),
+            status: Arc::new(Atomic::new(TaskProgress::None)),
+            task_generation: Default::default(),
+        }
+    }
+
+    /// A pattern across every key, shown before anything has been analyzed
+    fn test_pattern() -> BTreeMap<PianoKey, KeyPresses> {
+        PianoKey::all()
             .enumerate()
             .map(|(index, key)| {
                 let duration = Duration::from_secs_f32(0.1);
@@ -91,51 +196,128 @@ impl Application {
                     ]),
                 )
             })
-            .collect();
+            .collect()
+    }
 
-        Self {
-            previous_error: None,
+    fn save_session(&self, path: &Path) -> color_eyre::Result<()> {
+        let source = match &self.opened_file {
+            Some(opened_file) => opened_file.path.clone(),
+            None => bail!("no file is open to save a session of"),
+        };
+        let notes = match self.analysis.read().as_ref() {
+            Some(analysis) => analysis.notes.clone(),
+            None => bail!("the open file has not been analyzed"),
+        };
+
+        Session::new(source, self.analysis_options, notes)?.save(path)
+    }
 
-            recently_opened_files,
+    /// Show the notes of a saved session, without analyzing its source again.
+    /// The source is only checked, so any waveform from before is unloaded.
+    fn restore_session(&mut self, session: Session) {
+        // Cancel anything still decoding or analyzing
+        self.start_task();
+        self.status.store(TaskProgress::None, Ordering::SeqCst);
+
+        match session.source_status() {
+            SourceStatus::Unchanged => {}
+            SourceStatus::Changed => {
+                self.previous_error = Some(Box::new(eyre!(
+                    "{} has changed since the session was saved, so the notes may not match it",
+                    session.source.display()
+                )))
+            }
+            SourceStatus::Missing => {
+                self.previous_error = Some(Box::new(eyre!(
+                    "{} no longer exists, only the saved notes were loaded",
+                    session.source.display()
+                )))
+            }
+        }
 
-            midi: MidiPlayer::new(crate::NAME),
-            current_song: SongProgress::new(),
+        *self.waveform.write() = None;
+        *self.metadata.write() = None;
+        self.opened_file = None;
 
-            seconds_per_width: 30.0,
-            key_height: 10.0,
-            preference: Accidental::Flat,
-            spectrogram: true,
+        self.analysis_options = session.options;
+        *self.analysis.write() = Some(AudioAnalysis {
+            notes: session.notes,
+            spectrum: None,
+            tempo: None,
+        });
+        self.history.clear();
+    }
 
-            analysis_options: AnalysisOptions {
-                threshold: 100.0,
-                fft_size: 14,
-                window_fraction: 0.5,
-                step_fraction: 1.0,
-            },
-            analysis: Arc::new(RwLock::new(Some(AudioAnalysis {
-                notes: test_pattern,
-                spectrum: None,
-            }))),
-            waveform: Default::default(),
-            status: Arc::new(Atomic::new(TaskProgress::None)),
+    /// Undo the last edit made to the notes by hand
+    fn undo(&mut self) {
+        if let Some(analysis) = self.analysis.write().as_mut() {
+            if self.history.undo(&mut analysis.notes) {
+                self.notes_edited = true;
+            }
+        }
+    }
+
+    /// Redo the last edit to the notes that was undone
+    fn redo(&mut self) {
+        if let Some(analysis) = self.analysis.write().as_mut() {
+            if self.history.redo(&mut analysis.notes) {
+                self.notes_edited = true;
+            }
+        }
+    }
+
+    /// Start a new background task, cancelling any which is still running.
+    /// Returns a closure which breaks once the task has been superseded.
+    fn start_task(&self) -> impl Fn() -> ControlFlow<()> {
+        let task_generation = self.task_generation.clone();
+        let generation = task_generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+        move || {
+            if task_generation.load(Ordering::SeqCst) == generation {
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
         }
     }
 
-    fn open_file(&mut self, path: PathBuf, ctx: Context) {
-        if let Err(error) = self.open_file_inner(path, ctx) {
+    /// Open the first supported track of a file, or the track with the id
+    /// `track_id`
+    fn open_file(&mut self, path: PathBuf, track_id: Option<u32>, ctx: Context) {
+        self.history.clear();
+
+        if let Err(error) = self.open_file_inner(path, track_id, ctx) {
             self.previous_error = Some(error);
         }
     }
 
-    fn open_file_inner(&mut self, path: PathBuf, ctx: Context) -> Result<(), Box<dyn UiError>> {
-        let (decoder, path) = AudioDecoder::create_for_file(path)?;
+    fn open_file_inner(
+        &mut self,
+        path: PathBuf,
+        track_id: Option<u32>,
+        ctx: Context,
+    ) -> Result<(), Box<dyn UiError>> {
+        let (decoder, path) = match track_id {
+            Some(track_id) => AudioDecoder::create_for_file_with_track(path, track_id)?,
+            None => AudioDecoder::create_for_file(path)?,
+        };
+
+        self.opened_file = Some(OpenedFile {
+            path: path.clone(),
+            tracks: decoder.tracks(),
+            track_id: decoder.track_id(),
+        });
 
         // Add to recently opened files if decoder created successfully
         self.recently_opened_files.insert(path);
 
+        let current = self.start_task();
         let status = self.status.clone();
         let waveform = self.waveform.clone();
+        let metadata = self.metadata.clone();
         let analysis = self.analysis.clone();
+        let task_error = self.task_error.clone();
+        let new_metadata = decoder.metadata();
 
         thread::Builder::new()
             .name("file-decode".to_string())
@@ -143,12 +325,35 @@ impl Application {
                 status.store(TaskProgress::Decoding(0.0), Ordering::SeqCst);
                 ctx.request_repaint();
 
-                let new_waveform = decoder.decode(&|progress| {
+                let decoder = decoder.skip_corrupt_packets(true);
+                let new_waveform = decoder.decode(ChannelMode::Mono, &|progress| {
+                    current()?;
+
                     status.store(TaskProgress::Decoding(progress), Ordering::SeqCst);
                     ctx.request_repaint();
+
+                    ControlFlow::Continue(())
                 });
 
+                // A newer file has been opened, so this one is no longer wanted
+                if current().is_break() {
+                    return;
+                }
+
+                let new_waveform = match new_waveform {
+                    Ok(new_waveform) => new_waveform,
+                    Err(error) => {
+                        *task_error.write() = Some(Box::new(error));
+
+                        status.store(TaskProgress::None, Ordering::SeqCst);
+                        ctx.request_repaint();
+
+                        return;
+                    }
+                };
+
                 *waveform.write() = Some(new_waveform);
+                *metadata.write() = Some(new_metadata);
                 *analysis.write() = None;
 
                 status.store(TaskProgress::None, Ordering::SeqCst);
@@ -160,9 +365,11 @@ impl Application {
     }
 
     fn analyze_waveform(&self, ctx: Context) {
+        let current = self.start_task();
         let status = self.status.clone();
         let waveform = self.waveform.clone();
         let analysis = self.analysis.clone();
+        let task_error = self.task_error.clone();
         let analysis_options = self.analysis_options;
 
         thread::Builder::new()
@@ -181,11 +388,45 @@ impl Application {
                     }
                 };
 
-                let (notes, image) = analyze(waveform, analysis_options, &|progress| {
+                let analyzed = analyze(waveform, analysis_options, &|progress| {
+                    current()?;
+
                     status.store(TaskProgress::Analyzing(progress), Ordering::SeqCst);
                     ctx.request_repaint();
+
+                    ControlFlow::Continue(())
                 });
 
+                // Superseded by another task, which now owns the status
+                if current().is_break() {
+                    return;
+                }
+
+                let (notes, image) = match analyzed.wrap_err("unable to analyze the waveform") {
+                    Ok(analyzed) => analyzed,
+                    Err(error) => {
+                        *task_error.write() = Some(Box::new(error));
+
+                        status.store(TaskProgress::None, Ordering::SeqCst);
+                        ctx.request_repaint();
+
+                        return;
+                    }
+                };
+
+                let tempo = match onset::spectral_flux(waveform, ONSET_WIDTH, ONSET_HOP) {
+                    Ok(onsets) => onset::estimate_tempo(
+                        &onsets,
+                        waveform.sample_rate() as f32 / ONSET_HOP as f32,
+                    ),
+                    Err(error) => {
+                        tracing::warn!("unable to estimate the tempo: {error}");
+
+                        0.0
+                    }
+                };
+                let tempo = (tempo > 0.0).then_some(tempo);
+
                 status.store(TaskProgress::GeneratingSpectrogram, Ordering::SeqCst);
                 ctx.request_repaint();
                 // FIXME: is the above code useful? the context stays locked the whole time the
@@ -208,7 +449,11 @@ impl Application {
                         Some(ctx.load_texture("fft-spectrum", image))
                     };
 
-                *analysis.write() = Some(AudioAnalysis { notes, spectrum });
+                *analysis.write() = Some(AudioAnalysis {
+                    notes,
+                    spectrum,
+                    tempo,
+                });
 
                 status.store(TaskProgress::None, Ordering::SeqCst);
                 ctx.request_repaint();
@@ -257,6 +502,7 @@ impl Application {
                 dropped_file
                     .path
                     .expect("drag and drop not supported on web platform yet"),
+                None,
                 ui.ctx().clone(),
             )
         }
@@ -265,6 +511,25 @@ impl Application {
 
 impl App for Application {
     fn update(&mut self, ctx: &Context, frame: &mut epi::Frame) {
+        if let Some(error) = self.task_error.write().take() {
+            self.previous_error = Some(error);
+        }
+
+        let (undo, redo) = {
+            let input = ctx.input();
+
+            (
+                input.modifiers.command && input.key_pressed(Key::Z),
+                input.modifiers.command && input.key_pressed(Key::Y),
+            )
+        };
+        if undo {
+            self.undo();
+        }
+        if redo {
+            self.redo();
+        }
+
         if let Some(error) = self.previous_error.take() {
             Window::new("Error")
                 .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
@@ -318,7 +583,7 @@ impl App for Application {
                         ui.close_menu();
 
                         if let Some(path) = rfd::FileDialog::new().pick_file() {
-                            self.open_file(path, ctx.clone());
+                            self.open_file(path, None, ctx.clone());
                         }
                     }
                     ui.add_enabled_ui(!self.recently_opened_files.is_empty(), |ui| {
@@ -369,7 +634,7 @@ impl App for Application {
 
                             // Delay file open until all files have been put on screen.
                             if let Some(selected_file) = selected_file {
-                                self.open_file(selected_file, ctx.clone());
+                                self.open_file(selected_file, None, ctx.clone());
                             }
 
                             ui.separator();
@@ -379,6 +644,132 @@ impl App for Application {
                             }
                         });
                     });
+
+                    ui.separator();
+
+                    if ui.button("Open Session…").clicked() {
+                        ui.close_menu();
+
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("Pitch Session", &[Session::EXTENSION])
+                            .pick_file()
+                        {
+                            match Session::load(&path) {
+                                Ok(session) => self.restore_session(session),
+                                Err(error) => self.previous_error = Some(Box::new(error)),
+                            }
+                        }
+                    }
+
+                    let can_save_session =
+                        self.opened_file.is_some() && self.analysis.read().is_some();
+                    ui.add_enabled_ui(can_save_session, |ui| {
+                        if ui.button("Save Session…").clicked() {
+                            ui.close_menu();
+
+                            if let Some(path) = rfd::FileDialog::new()
+                                .add_filter("Pitch Session", &[Session::EXTENSION])
+                                .save_file()
+                            {
+                                match self.save_session(&path) {
+                                    Ok(()) => self.notes_edited = false,
+                                    Err(error) => self.previous_error = Some(Box::new(error)),
+                                }
+                            }
+                        }
+                    });
+
+                    ui.separator();
+
+                    if ui.button("Import MIDI…").clicked() {
+                        ui.close_menu();
+
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("MIDI", &["mid", "midi"])
+                            .pick_file()
+                        {
+                            match import_midi(&path) {
+                                Ok(notes) => {
+                                    *self.analysis.write() = Some(AudioAnalysis {
+                                        notes,
+                                        spectrum: None,
+                                        tempo: None,
+                                    });
+                                    self.history.clear();
+                                }
+                                Err(error) => self.previous_error = Some(Box::new(error)),
+                            }
+                        }
+                    }
+
+                    ui.add_enabled_ui(self.analysis.read().is_some(), |ui| {
+                        if ui.button("Export MIDI…").clicked() {
+                            ui.close_menu();
+
+                            if let Some(path) = rfd::FileDialog::new()
+                                .add_filter("MIDI", &["mid", "midi"])
+                                .save_file()
+                            {
+                                if let Some(analysis) = self.analysis.read().as_ref() {
+                                    if let Err(error) = export_midi(&analysis.notes, &path) {
+                                        self.previous_error = Some(Box::new(error));
+                                    }
+                                }
+                            }
+                        }
+
+                        if ui.button("Export CSV…").clicked() {
+                            ui.close_menu();
+
+                            if let Some(path) = rfd::FileDialog::new()
+                                .add_filter("CSV", &["csv"])
+                                .save_file()
+                            {
+                                if let Some(analysis) = self.analysis.read().as_ref() {
+                                    if let Err(error) =
+                                        export_csv(&analysis.notes, self.preference, &path)
+                                    {
+                                        self.previous_error = Some(Box::new(error));
+                                    }
+                                }
+                            }
+                        }
+
+                        if ui.button("Export Synthesized WAV…").clicked() {
+                            ui.close_menu();
+
+                            if let Some(path) = rfd::FileDialog::new()
+                                .add_filter("WAV", &["wav"])
+                                .save_file()
+                            {
+                                if let Some(analysis) = self.analysis.read().as_ref() {
+                                    if let Err(error) = export_synthesized(
+                                        &analysis.notes,
+                                        self.analysis_options.tuning,
+                                        &path,
+                                    ) {
+                                        self.previous_error = Some(Box::new(error));
+                                    }
+                                }
+                            }
+                        }
+                    });
+                });
+                ui.menu_button("Edit", |ui| {
+                    if ui
+                        .add_enabled(self.history.can_undo(), Button::new("Undo (Ctrl+Z)"))
+                        .clicked()
+                    {
+                        ui.close_menu();
+                        self.undo();
+                    }
+                    if ui
+                        .add_enabled(self.history.can_redo(), Button::new("Redo (Ctrl+Y)"))
+                        .clicked()
+                    {
+                        ui.close_menu();
+                        self.redo();
+                    }
                 });
                 ui.menu_button("View", |ui| {
                     ui.menu_button("Accidental Preference", |ui| {
@@ -388,8 +779,91 @@ impl App for Application {
 
                         ui.separator();
 
-                        // TODO: Scales
-                        ui.add_enabled_ui(false, |ui| ui.menu_button("Scale", |_ui| {}))
+                        ui.menu_button("Scale", |ui| {
+                            if ui.radio(self.scale.is_none(), "None").clicked() {
+                                ui.close_menu();
+
+                                self.scale = None;
+                            }
+
+                            let modes: [(&str, fn(MusicalNote) -> Scale); 2] =
+                                [("Major", Scale::major), ("Minor", Scale::minor)];
+
+                            for (mode, scale) in modes {
+                                ui.menu_button(mode, |ui| {
+                                    // One octave of roots, starting from C4
+                                    for key in (40..52).filter_map(PianoKey::new) {
+                                        let root = key.as_note(self.preference);
+                                        let scale = scale(root);
+
+                                        let label = match root.accidental() {
+                                            Some(accidental) => {
+                                                format!("{}{accidental} {mode}", root.letter())
+                                            }
+                                            None => format!("{} {mode}", root.letter()),
+                                        };
+
+                                        if ui.radio(self.scale == Some(scale), label).clicked() {
+                                            ui.close_menu();
+
+                                            self.scale = Some(scale);
+                                        }
+                                    }
+                                });
+                            }
+                        });
+                    });
+
+                    ui.checkbox(&mut self.tuner.open, "Tuner");
+                    ui.checkbox(&mut self.metronome.open, "Metronome");
+
+                    ui.menu_button("MIDI Output", |ui| {
+                        let ports = self.midi.available_ports();
+
+                        if ports.is_empty() {
+                            ui.label("No MIDI outputs available");
+                        }
+
+                        for (index, port) in ports.into_iter().enumerate() {
+                            let selected = self.midi.selected_port() == Some(index);
+
+                            if ui.radio(selected, port).clicked() {
+                                ui.close_menu();
+
+                                if let Err(error) = self.midi.connect_to(index) {
+                                    self.previous_error = Some(Box::new(error));
+                                }
+                            }
+                        }
+                    });
+
+                    ui.menu_button("MIDI Instrument", |ui| {
+                        // General MIDI patches are numbered from 1 for people
+                        let mut patch = self.midi.program() + 1;
+
+                        ui.add(Slider::new(&mut patch, 1..=128).text("General MIDI Patch"));
+
+                        self.midi.set_program(patch - 1);
+                    });
+
+                    ui.menu_button("MIDI Pitch Bend Range", |ui| {
+                        let mut range = self.midi.pitch_bend_range();
+
+                        ui.add(
+                            Slider::new(&mut range, MidiPlayer::PITCH_BEND_RANGE).text("Semitones"),
+                        );
+
+                        self.midi.set_pitch_bend_range(range);
+                    });
+
+                    ui.menu_button("MIDI Velocity Curve", |ui| {
+                        let mut curve = self.midi.velocity_curve();
+
+                        ui.selectable_value(&mut curve, VelocityCurve::Linear, "Linear");
+                        ui.selectable_value(&mut curve, VelocityCurve::Power(0.5), "Soft");
+                        ui.selectable_value(&mut curve, VelocityCurve::Power(2.0), "Hard");
+
+                        self.midi.set_velocity_curve(curve);
                     });
 
                     ui.menu_button("Theme", |ui| {
@@ -417,6 +891,24 @@ impl App for Application {
             });
         });
 
+        if let Err(error) = self.tuner.show(
+            ctx,
+            &self.midi,
+            self.analysis_options.tuning,
+            self.preference,
+        ) {
+            self.previous_error = Some(Box::new(error));
+        }
+
+        let tempo = self
+            .analysis
+            .read()
+            .as_ref()
+            .and_then(|analysis| analysis.tempo);
+        if let Err(error) = self.metronome.show(ctx, &self.midi, tempo) {
+            self.previous_error = Some(Box::new(error));
+        }
+
         CentralPanel::default().show(ctx, |ui| {
             let analysis = self.analysis.clone();
 
@@ -428,7 +920,75 @@ impl App for Application {
 
                         if ui.button("Unload").clicked() {
                             *self.waveform.write() = None;
+                            *self.metadata.write() = None;
+                            self.opened_file = None;
                             *self.analysis.write() = None;
+                            self.history.clear();
+                        }
+
+                        let mut selected_track = None;
+                        if let Some(opened_file) = &self.opened_file {
+                            if opened_file.tracks.len() > 1 {
+                                let track_name = |track: &TrackInfo| {
+                                    format!(
+                                        "#{} ({})",
+                                        track.id,
+                                        track.codec.unwrap_or("unsupported")
+                                    )
+                                };
+
+                                let current = opened_file
+                                    .tracks
+                                    .iter()
+                                    .find(|track| track.id == opened_file.track_id)
+                                    .map(track_name)
+                                    .unwrap_or_default();
+
+                                ComboBox::from_label("Track")
+                                    .selected_text(current)
+                                    .show_ui(ui, |ui| {
+                                        for track in &opened_file.tracks {
+                                            let mut label = track_name(track);
+                                            if let (Some(channels), Some(sample_rate)) =
+                                                (track.channels, track.sample_rate)
+                                            {
+                                                label +=
+                                                    &format!(", {channels} ch, {sample_rate} Hz");
+                                            }
+
+                                            if ui
+                                                .add_enabled(
+                                                    track.codec.is_some(),
+                                                    SelectableLabel::new(
+                                                        track.id == opened_file.track_id,
+                                                        label,
+                                                    ),
+                                                )
+                                                .clicked()
+                                            {
+                                                selected_track =
+                                                    Some((opened_file.path.clone(), track.id));
+                                            }
+                                        }
+                                    });
+                            }
+                        }
+
+                        // Decode the newly chosen track in place of the current one
+                        if let Some((path, track_id)) = selected_track {
+                            self.open_file(path, Some(track_id), ui.ctx().clone());
+                        }
+
+                        if let Some(metadata) = self.metadata.read().as_ref() {
+                            for (label, value) in [
+                                ("Title", &metadata.title),
+                                ("Artist", &metadata.artist),
+                                ("Album", &metadata.album),
+                            ] {
+                                if let Some(value) = value {
+                                    ui.label(format!("{label}: {value}"));
+                                }
+                            }
                         }
 
                         let waveform = self.waveform.read();
@@ -500,15 +1060,108 @@ impl App for Application {
                             ));
                         });
 
+                        ui.horizontal(|ui| {
+                            let threshold = &mut self.analysis_options.threshold;
+
+                            for (label, default) in [
+                                ("Linear", ThresholdMode::Linear(0.01)),
+                                ("Decibels", ThresholdMode::Decibels(-20.0)),
+                                ("Above Mean", ThresholdMode::AboveMean(20.0)),
+                            ] {
+                                let selected = mem::discriminant(threshold)
+                                    == mem::discriminant(&default);
+
+                                if ui.radio(selected, label).clicked() && !selected {
+                                    *threshold = default;
+                                }
+                            }
+                        });
+
+                        match &mut self.analysis_options.threshold {
+                            ThresholdMode::Linear(amplitude) => ui.add(
+                                Slider::new(amplitude, 0.0..=0.5)
+                                    .logarithmic(true)
+                                    .text("Note threshold"),
+                            ),
+                            ThresholdMode::Decibels(decibels) => ui.add(
+                                Slider::new(decibels, -60.0..=0.0)
+                                    .text("Note threshold")
+                                    .suffix(" dB"),
+                            ),
+                            ThresholdMode::AboveMean(decibels) => ui.add(
+                                Slider::new(decibels, 0.0..=60.0)
+                                    .text("Note threshold")
+                                    .suffix(" dB"),
+                            ),
+                        };
+
+                        ui.add(
+                            Slider::new(&mut self.analysis_options.median_filter, 0..=7)
+                                .text("Median Filter")
+                                .suffix(" px"),
+                        )
+                        .on_hover_text("Remove isolated spikes from the spectrogram, 0 is off");
+
+                        ui.add(
+                            Slider::new(&mut self.analysis_options.merge_gap_ms, 0..=100)
+                                .text("Merge Gaps")
+                                .suffix(" ms"),
+                        )
+                        .on_hover_text("Join notes of the same key with gaps up to this long");
+
+                        ui.horizontal(|ui| {
+                            let merge = &mut self.analysis_options.intensity_merge;
+
+                            ui.label("Joined Intensity");
+                            ui.selectable_value(merge, IntensityMerge::Max, "Max");
+                            ui.selectable_value(merge, IntensityMerge::Mean, "Mean");
+                            ui.selectable_value(merge, IntensityMerge::Energy, "Energy");
+                        });
+
                         ui.add(
-                            Slider::new(&mut self.analysis_options.threshold, 0.0..=1000.0)
-                                .text("Note threshold"),
+                            Slider::new(&mut self.analysis_options.max_polyphony, 0..=10)
+                                .text("Max Polyphony"),
+                        )
+                        .on_hover_text(
+                            "Find at most this many notes at once, ignoring their harmonics, or \
+                             every note above the threshold when zero",
                         );
 
+                        ui.checkbox(&mut self.analysis_options.precise_fft, "Precise FFT")
+                            .on_hover_text(
+                                "Take spectra in double precision, finding quieter notes but \
+                                 analyzing several times slower",
+                            );
+
+                        ui.add(
+                            Slider::new(&mut self.analysis_options.tuning.a4_hz, 400.0..=480.0)
+                                .text("A4 Tuning")
+                                .suffix(" Hz"),
+                        );
+
+                        ui.horizontal(|ui| {
+                            let scale = &mut self.analysis_options.frequency_scale;
+
+                            ui.label("Spectrogram rows");
+                            ui.radio_value(scale, FrequencyScale::Linear, "Linear");
+                            ui.radio_value(
+                                scale,
+                                FrequencyScale::Logarithmic { bins: 88 },
+                                "Per key",
+                            );
+                            ui.radio_value(
+                                scale,
+                                FrequencyScale::Logarithmic { bins: 88 * 4 },
+                                "Logarithmic",
+                            );
+                        });
+
                         drop(waveform);
 
                         if ui.button("Analyze").clicked() {
                             self.analyze_waveform(ui.ctx().clone());
+                            self.notes_edited = false;
+                            self.history.clear();
                         }
                     });
 
@@ -520,19 +1173,19 @@ impl App for Application {
 
                             if ui.button("Unload").clicked() {
                                 *analysis.write() = None;
+                                self.history.clear();
                                 if let Some(progress) = self.current_song.upgrade() {
                                     progress.cancel();
                                 }
                             }
 
+                            // Read before the notes keep the lock held
+                            let tempo = analysis.read().as_ref().and_then(|analysis| analysis.tempo);
                             let notes = RwLockReadGuard::map(analysis.read(), |analysis| {
-                                static EMPTY: Lazy<BTreeMap<PianoKey, KeyPresses>> =
-                                    Lazy::new(BTreeMap::new);
-
                                 analysis
                                     .as_ref()
                                     .map(|analysis| &analysis.notes)
-                                    .unwrap_or(&EMPTY)
+                                    .unwrap_or(&NO_NOTES)
                             });
 
                             let notes_count = notes
@@ -548,7 +1201,25 @@ impl App for Application {
                                 ));
                                 ui.label(format!("{:.2}s", progress.time()));
                             } else {
-                                ui.label(format!("Loaded {} notes", notes_count));
+                                if self.notes_edited {
+                                    ui.label(format!("Loaded {} notes (edited)", notes_count));
+                                } else {
+                                    ui.label(format!("Loaded {} notes", notes_count));
+                                }
+                            }
+
+                            if let Some(tempo) = tempo {
+                                ui.label(format!("≈{tempo:.0} BPM"));
+                            }
+
+                            if let Some(key) = detect_key(&chroma(&notes)) {
+                                ui.horizontal(|ui| {
+                                    ui.label(format!("Detected key: {key}"));
+
+                                    if ui.small_button("Show Scale").clicked() {
+                                        self.scale = Some(key.scale());
+                                    }
+                                });
                             }
 
                             ui.horizontal(|ui| match self.current_song.upgrade() {
@@ -561,6 +1232,7 @@ impl App for Application {
                                     if ui.button("Play Notes").clicked() {
                                         self.current_song =
                                             self.midi.play_song(&notes, ctx.clone());
+                                        self.following = self.follow_playback;
                                     }
                                 }
                             });
@@ -572,10 +1244,42 @@ impl App for Application {
                     ui.vertical(|ui| {
                         ui.heading("Visualization");
                         ui.checkbox(&mut self.spectrogram, "Show Spectrogram");
+                        ui.horizontal(|ui| {
+                            ui.label("Pitch Grid");
+                            ui.selectable_value(&mut self.pitch_grid, PitchGrid::Off, "Off");
+                            ui.selectable_value(
+                                &mut self.pitch_grid,
+                                PitchGrid::ConcertPitch,
+                                "Concert Pitch",
+                            )
+                            .on_hover_text("A line at the A440 pitch of every key");
+                            ui.selectable_value(&mut self.pitch_grid, PitchGrid::Cents(25), "25¢");
+                            ui.selectable_value(&mut self.pitch_grid, PitchGrid::Cents(10), "10¢");
+                        });
+                        if ui
+                            .checkbox(&mut self.follow_playback, "Follow Playback")
+                            .changed()
+                        {
+                            self.following = self.follow_playback;
+                        }
+                        ui.checkbox(&mut self.edit_notes, "Edit Notes")
+                            .on_hover_text(
+                                "Click to add notes, drag over them to select them, right click or press \
+                                 delete to remove them and drag their ends to resize them",
+                            );
                         ui.add(
                             Slider::new(&mut self.seconds_per_width, 1.0..=100.0).text("Scale X"),
                         );
                         ui.add(Slider::new(&mut self.key_height, 1.0..=100.0).text("Scale Y"));
+
+                        if ui.button("Fit").clicked() {
+                            if let Some((seconds_per_width, key_height)) =
+                                zoom_to_fit(&notes, self.piano_roll_size)
+                            {
+                                self.seconds_per_width = seconds_per_width.clamp(1.0, 100.0);
+                                self.key_height = key_height.clamp(1.0, 100.0);
+                            }
+                        }
                     });
 
                     notes
@@ -583,24 +1287,44 @@ impl App for Application {
                 .inner;
 
             {
-                let analysis = self.analysis.read();
-                let spectrum = if self.spectrogram {
-                    analysis
-                        .as_ref()
-                        .and_then(|analysis| analysis.spectrum.as_ref())
-                } else {
-                    None
+                // Editing needs the notes borrowed mutably
+                drop(notes);
+                let mut analysis = self.analysis.write();
+
+                let (notes, spectrum): (Notes, _) = match analysis.as_mut() {
+                    Some(AudioAnalysis {
+                        notes, spectrum, ..
+                    }) => (
+                        if self.edit_notes {
+                            notes.into()
+                        } else {
+                            (&*notes).into()
+                        },
+                        spectrum.as_ref().filter(|_| self.spectrogram),
+                    ),
+                    None => ((&*NO_NOTES).into(), None),
                 };
 
-                ui.add(PianoRoll::new(
-                    &self.midi,
-                    self.preference,
-                    self.current_song.upgrade().map(|progress| progress.time()),
-                    self.key_height,
-                    self.seconds_per_width,
-                    &notes,
-                    spectrum,
-                ));
+                let response = ui.add(
+                    PianoRoll::new(
+                        &self.midi,
+                        self.preference,
+                        self.scale,
+                        self.current_song.upgrade().map(|progress| progress.time()),
+                        self.key_height,
+                        self.seconds_per_width,
+                        notes,
+                        spectrum,
+                    )
+                    .follow_cursor(&mut self.following)
+                    .history(&mut self.history)
+                    .pitch_grid(self.pitch_grid, self.analysis_options.tuning),
+                );
+
+                self.piano_roll_size = response.rect.size();
+                if response.changed() {
+                    self.notes_edited = true;
+                }
             }
 
             self.detect_files_being_dropped(ui);
@@ -609,6 +1333,15 @@ impl App for Application {
 
     fn save(&mut self, storage: &mut dyn Storage) {
         epi::set_value(storage, APP_KEY, &self.recently_opened_files);
+        epi::set_value(
+            storage,
+            NOTES_KEY,
+            &self
+                .analysis
+                .read()
+                .as_ref()
+                .map(|analysis| &analysis.notes),
+        );
     }
 
     fn persist_native_window(&self) -> bool {
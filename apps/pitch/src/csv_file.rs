@@ -0,0 +1,89 @@
+use std::{collections::BTreeMap, fmt::Write, fs, path::Path};
+
+use color_eyre::eyre::WrapErr;
+
+use crate::{
+    analysis::KeyPresses,
+    key::{Accidental, PianoKey},
+};
+
+const HEADER: &str = "note,midi,start_secs,duration_secs,intensity";
+
+/// Write one row per keypress, spelling notes with `preference`
+pub fn export_csv(
+    notes: &BTreeMap<PianoKey, KeyPresses>,
+    preference: Accidental,
+    path: &Path,
+) -> color_eyre::Result<()> {
+    fs::write(path, to_csv(notes, preference))
+        .wrap_err_with(|| format!("unable to write csv file {}", path.display()))
+}
+
+/// The keypresses as csv, ordered by when they start and then from the lowest
+/// key up
+pub fn to_csv(notes: &BTreeMap<PianoKey, KeyPresses>, preference: Accidental) -> String {
+    let mut rows = notes
+        .iter()
+        .flat_map(|(&key, presses)| presses.iter().map(move |press| (press.start(), key, press)))
+        .collect::<Vec<_>>();
+    rows.sort_by_key(|&(start, key, _)| (start, key));
+
+    let mut csv = String::from(HEADER);
+    csv.push('\n');
+
+    for (_, key, press) in rows {
+        // Writing to a string can not fail
+        let _ = writeln!(
+            csv,
+            "{},{},{:.3},{:.3},{}",
+            key.as_note(preference),
+            key.midi_number(),
+            press.start_secs(),
+            press.duration_secs(),
+            press.intensity()
+        );
+    }
+
+    csv
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use super::to_csv;
+    use crate::{
+        analysis::{KeyPress, KeyPresses},
+        key::{Accidental, PianoKey},
+    };
+
+    #[test]
+    fn two_notes() {
+        let notes = BTreeMap::from([
+            (
+                PianoKey::new(50).unwrap(),
+                KeyPresses::from([KeyPress::new(250u64, Duration::from_millis(1500), 0.5)]),
+            ),
+            (
+                PianoKey::new(40).unwrap(),
+                KeyPresses::from([KeyPress::new(1000u64, Duration::from_millis(500), 1.0)]),
+            ),
+        ]);
+
+        assert_eq!(
+            to_csv(&notes, Accidental::Flat),
+            "note,midi,start_secs,duration_secs,intensity\n\
+             Bb4,70,0.250,1.500,0.5\n\
+             C4,60,1.000,0.500,1\n"
+        );
+        assert!(to_csv(&notes, Accidental::Sharp).contains("A#4,70,"));
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(
+            to_csv(&BTreeMap::new(), Accidental::Sharp),
+            "note,midi,start_secs,duration_secs,intensity\n"
+        );
+    }
+}
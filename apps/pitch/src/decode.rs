@@ -1,4 +1,10 @@
-use std::{fs::File, io, path::PathBuf};
+use std::{
+    fs::File,
+    io,
+    ops::{ControlFlow, Range},
+    path::PathBuf,
+    time::Duration,
+};
 
 use audio::waveform::Waveform;
 use eframe::{
@@ -6,14 +12,15 @@ use eframe::{
     epaint::Color32,
 };
 use symphonia::core::{
-    audio::SampleBuffer,
-    codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL},
-    formats::{FormatOptions, FormatReader},
+    audio::{SampleBuffer, SignalSpec},
+    codecs::{CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
     io::MediaSourceStream,
-    meta::MetadataOptions,
+    meta::{MetadataOptions, StandardTagKey, Tag},
     probe::Hint,
 };
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::ui_error::UiError;
 
@@ -22,6 +29,7 @@ pub enum CreateDecoderError {
     OpenFile(PathBuf, io::Error),
     UnsupportedAudioFormat,
     NoSupportedAudioTrack,
+    NoSuchTrack(u32),
     UnknownDuration,
     UnknownCodec,
 }
@@ -66,6 +74,13 @@ impl UiError for CreateDecoderError {
                         .color(Color32::RED),
                 );
             }
+            CreateDecoderError::NoSuchTrack(track_id) => {
+                ui.label(
+                    RichText::new(format!("File contains no track with id {track_id}"))
+                        .heading()
+                        .color(Color32::RED),
+                );
+            }
             CreateDecoderError::UnknownDuration => {
                 ui.label(
                     RichText::new("Unable to know duration of file")
@@ -84,16 +99,138 @@ impl UiError for CreateDecoderError {
     }
 }
 
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The next packet could not be read from the file
+    ReadPacket(SymphoniaError),
+    /// A packet could not be decoded into samples
+    DecodeSamples(SymphoniaError),
+    /// The file ended before every frame of the track was read
+    Truncated { expected_frames: u64, frames: u64 },
+    /// The channel asked for is not one of the track's
+    NoSuchChannel { requested: u16, available: u16 },
+    /// The track does not say how many frames are in each second, so a time
+    /// range can not be found in it
+    UnknownSampleRate,
+}
+
+impl From<DecodeError> for Box<dyn UiError> {
+    fn from(error: DecodeError) -> Self {
+        Box::new(error) as _
+    }
+}
+
+impl UiError for DecodeError {
+    fn ui_error(&self, ui: &mut Ui) {
+        let (heading, details) = match self {
+            DecodeError::ReadPacket(error) => ("Unable to read audio file", error.to_string()),
+            DecodeError::DecodeSamples(error) => ("Unable to decode audio", error.to_string()),
+            DecodeError::Truncated {
+                expected_frames,
+                frames,
+            } => (
+                "Audio file is truncated",
+                format!("only {frames} of {expected_frames} frames could be read"),
+            ),
+            DecodeError::NoSuchChannel {
+                requested,
+                available,
+            } => (
+                "Unable to decode channel",
+                format!("channel {requested} does not exist in a track with {available} channels"),
+            ),
+            DecodeError::UnknownSampleRate => (
+                "Unable to decode part of audio",
+                "the track has an unknown sample rate".to_string(),
+            ),
+        };
+
+        ui.label(RichText::new(heading).heading().color(Color32::RED));
+        ui.label(details);
+    }
+}
+
+/// Descriptive information about an audio file, where the file provides it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AudioMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+impl AudioMetadata {
+    /// Fill in any fields found in `tags`, replacing those already read
+    fn read_tags(&mut self, tags: &[Tag]) {
+        for tag in tags {
+            let field = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => &mut self.title,
+                Some(StandardTagKey::Artist) => &mut self.artist,
+                Some(StandardTagKey::Album) => &mut self.album,
+                _ => continue,
+            };
+
+            *field = Some(tag.value.to_string());
+        }
+    }
+}
+
+/// A track within an audio file, which may or may not be decodable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackInfo {
+    pub id: u32,
+    /// The short name of the codec, if it is one which can be decoded
+    pub codec: Option<&'static str>,
+    pub channels: Option<u16>,
+    pub sample_rate: Option<u32>,
+}
+
+impl TrackInfo {
+    fn new(id: u32, codec_params: &CodecParameters) -> Self {
+        Self {
+            id,
+            codec: symphonia::default::get_codecs()
+                .get_codec(codec_params.codec)
+                .map(|descriptor| descriptor.short_name),
+            // Some containers only describe the layout of the channels
+            channels: codec_params
+                .channels
+                .or_else(|| Some(codec_params.channel_layout?.into_channels()))
+                .map(|channels| channels.count() as u16),
+            sample_rate: codec_params.sample_rate,
+        }
+    }
+}
+
 pub struct AudioDecoder {
     decoder: Box<dyn Decoder>,
     format: Box<dyn FormatReader>,
+    metadata: AudioMetadata,
     track_id: u32,
     track_frames: u64,
+    skip_corrupt_packets: bool,
 }
 
 impl AudioDecoder {
-    // TODO: make last lint global?
+    /// Open a file for decoding its first supported audio track
     pub fn create_for_file(path: PathBuf) -> Result<(AudioDecoder, PathBuf), CreateDecoderError> {
+        Self::open(path, None)
+    }
+
+    /// Open a file for decoding the track with the id `track_id`, as listed by
+    /// [`AudioDecoder::tracks`]
+    pub fn create_for_file_with_track(
+        path: PathBuf,
+        track_id: u32,
+    ) -> Result<(AudioDecoder, PathBuf), CreateDecoderError> {
+        Self::open(path, Some(track_id))
+    }
+
+    // TODO: make last lint global?
+    fn open(
+        path: PathBuf,
+        track_id: Option<u32>,
+    ) -> Result<(AudioDecoder, PathBuf), CreateDecoderError> {
         // Verify file
         // path.extension()
         let file = match File::open(&path) {
@@ -117,21 +254,44 @@ impl AudioDecoder {
             )
             .map_err(|_| CreateDecoderError::UnsupportedAudioFormat)?;
 
-        dbg!(probe.metadata.get());
-        let format = probe.format;
+        let mut metadata = AudioMetadata::default();
 
-        // TODO: track selection
-        let track = format
-            .tracks()
-            .iter()
-            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-            .ok_or(CreateDecoderError::NoSupportedAudioTrack)?;
+        // Tags found while probing, such as ID3, come before those in the container itself
+        if let Some(probed) = probe.metadata.get() {
+            if let Some(revision) = probed.current() {
+                metadata.read_tags(revision.tags());
+            }
+        }
+
+        let mut format = probe.format;
+
+        if let Some(revision) = format.metadata().current() {
+            metadata.read_tags(revision.tags());
+        }
+
+        let track = match track_id {
+            Some(track_id) => format
+                .tracks()
+                .iter()
+                .find(|t| t.id == track_id)
+                .ok_or(CreateDecoderError::NoSuchTrack(track_id))?,
+            None => format
+                .tracks()
+                .iter()
+                .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+                .ok_or(CreateDecoderError::NoSupportedAudioTrack)?,
+        };
 
         let track_frames = track
             .codec_params
             .n_frames
             .ok_or(CreateDecoderError::UnknownDuration)?;
 
+        metadata.duration = track
+            .codec_params
+            .sample_rate
+            .map(|sample_rate| Duration::from_secs_f64(track_frames as f64 / sample_rate as f64));
+
         let decoder = symphonia::default::get_codecs()
             .make(&track.codec_params, &DecoderOptions::default())
             .map_err(|_| CreateDecoderError::UnknownCodec)?;
@@ -142,15 +302,117 @@ impl AudioDecoder {
                 track_frames,
                 decoder,
                 format,
+                metadata,
+                skip_corrupt_packets: false,
             },
             path,
         ))
     }
 }
 
+/// How the channels of a decoded track are combined into the resulting waveform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelMode {
+    /// Average all channels into one
+    Mono,
+    /// Keep only the given channel
+    Channel(u16),
+    /// Keep every channel, interleaved
+    All,
+}
+
 impl AudioDecoder {
-    // TODO: channel select/multi channel
-    pub fn decode(mut self, progress_callback: &dyn Fn(f32)) -> Waveform<'static> {
+    /// Every track in the file, including those which can not be decoded
+    pub fn tracks(&self) -> Vec<TrackInfo> {
+        self.format
+            .tracks()
+            .iter()
+            .map(|track| TrackInfo::new(track.id, &track.codec_params))
+            .collect()
+    }
+
+    /// The id of the track being decoded
+    pub fn track_id(&self) -> u32 {
+        self.track_id
+    }
+
+    /// The title, artist, album and duration of the file, where known
+    pub fn metadata(&self) -> AudioMetadata {
+        self.metadata.clone()
+    }
+
+    /// Skip over packets which fail to decode because of invalid data, instead
+    /// of failing the whole decode. The skipped packets are left out of the
+    /// waveform, so it will be shorter than the track.
+    pub fn skip_corrupt_packets(mut self, skip: bool) -> Self {
+        self.skip_corrupt_packets = skip;
+        self
+    }
+
+    /// Decode the whole track, reporting progress as each packet is read.
+    /// Decoding stops early if the callback breaks, returning everything
+    /// decoded up until then.
+    pub fn decode(
+        self,
+        channel_mode: ChannelMode,
+        progress_callback: &dyn Fn(f32) -> ControlFlow<()>,
+    ) -> Result<Waveform<'static>, DecodeError> {
+        let frames = 0..self.track_frames;
+
+        self.decode_frames(channel_mode, frames, progress_callback)
+    }
+
+    /// Decode only the part of the track from `start` until `end`, seeking past
+    /// everything before it where the format allows.
+    pub fn decode_range(
+        self,
+        start: Duration,
+        end: Duration,
+        channel_mode: ChannelMode,
+        progress_callback: &dyn Fn(f32) -> ControlFlow<()>,
+    ) -> Result<Waveform<'static>, DecodeError> {
+        let sample_rate = self
+            .decoder
+            .codec_params()
+            .sample_rate
+            .ok_or(DecodeError::UnknownSampleRate)?;
+
+        let frame = |time: Duration| (time.as_secs_f64() * sample_rate as f64).round() as u64;
+        let frames = frame(start)..frame(end);
+
+        self.decode_frames(channel_mode, frames, progress_callback)
+    }
+
+    /// Decode the frames of the track within `frames`.
+    ///
+    /// Timestamps of audio tracks count frames at the track's sample rate, so
+    /// they are compared directly against the range.
+    fn decode_frames(
+        mut self,
+        channel_mode: ChannelMode,
+        frames: Range<u64>,
+        progress_callback: &dyn Fn(f32) -> ControlFlow<()>,
+    ) -> Result<Waveform<'static>, DecodeError> {
+        if frames.start > 0 {
+            let seek_to = SeekTo::TimeStamp {
+                ts: frames.start,
+                track_id: self.track_id,
+            };
+
+            match self.format.seek(SeekMode::Accurate, seek_to) {
+                Ok(seeked_to) => {
+                    info!(ts = seeked_to.actual_ts, "Seeked to start of range");
+                    self.decoder.reset();
+                }
+                // Packets before the range are decoded and then discarded instead
+                Err(err) => warn!(%err, "unable to seek, decoding from the start"),
+            }
+        }
+
+        let range_frames = frames.end.saturating_sub(frames.start).max(1);
+
+        let mut cancelled = false;
+        let mut skipped = false;
         let mut spec = None;
         let mut sample_buf = None;
         let mut samples = Vec::new();
@@ -167,19 +429,22 @@ impl AudioDecoder {
                 //     // for chained OGG physical streams.
                 //     unimplemented!();
                 // }
-                Err(symphonia::core::errors::Error::IoError(e))
-                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
-                {
+                Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
                     info!("Reached end of file");
                     break;
                 }
                 Err(err) => {
                     // A unrecoverable error occured, halt decoding.
-                    panic!("{}", err);
+                    return Err(DecodeError::ReadPacket(err));
                 }
             };
 
-            progress_callback(packet.ts() as f32 / self.track_frames as f32);
+            let progress = packet.ts().saturating_sub(frames.start) as f32 / range_frames as f32;
+            if progress_callback(progress).is_break() {
+                info!("Decoding cancelled");
+                cancelled = true;
+                break;
+            }
 
             // Consume any new metadata that has been read since the last packet.
             while !self.format.metadata().is_latest() {
@@ -195,43 +460,552 @@ impl AudioDecoder {
                 continue;
             }
 
+            // Everything that is wanted has been decoded
+            if packet.ts() >= frames.end {
+                break;
+            }
+
             // Decode the packet into audio samples.
             match self.decoder.decode(&packet) {
                 Ok(decoded) => {
                     let spec = spec.get_or_insert(*decoded.spec());
 
+                    // Only keep the frames of the packet within the range
+                    let packet_frames = decoded.frames() as u64;
+                    let first = frames.start.saturating_sub(packet.ts()).min(packet_frames);
+                    let last = frames.end.saturating_sub(packet.ts()).min(packet_frames);
+
                     let sample_buf = sample_buf.get_or_insert_with(|| {
                         SampleBuffer::<f32>::new(decoded.capacity() as u64, *spec)
                     });
 
-                    sample_buf.copy_planar_ref(decoded);
+                    sample_buf.copy_interleaved_ref(decoded);
 
-                    samples.extend_from_slice(
-                        &sample_buf.samples()[..sample_buf.len() / spec.channels.count()],
-                    );
+                    let channels = spec.channels.count();
+                    let interleaved =
+                        &sample_buf.samples()[first as usize * channels..last as usize * channels];
+
+                    match channel_mode {
+                        ChannelMode::Mono => samples.extend(
+                            interleaved
+                                .chunks_exact(channels)
+                                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+                        ),
+                        ChannelMode::Channel(channel) => {
+                            if channel as usize >= channels {
+                                return Err(DecodeError::NoSuchChannel {
+                                    requested: channel,
+                                    available: channels as u16,
+                                });
+                            }
+
+                            samples.extend(
+                                interleaved
+                                    .iter()
+                                    .skip(channel as usize)
+                                    .step_by(channels)
+                                    .copied(),
+                            )
+                        }
+                        ChannelMode::All => samples.extend_from_slice(interleaved),
+                    }
+                }
+                Err(err @ (SymphoniaError::IoError(_) | SymphoniaError::DecodeError(_)))
+                    if self.skip_corrupt_packets =>
+                {
+                    // The packet failed to decode due to an IO error or invalid data, skip the packet.
+                    warn!(%err, ts = packet.ts(), "skipping corrupt packet");
+                    skipped = true;
+                    continue;
                 }
-                // Err(symphonia::core::errors::Error::IoError(_)) => {
-                //     // The packet failed to decode due to an IO error, skip the packet.
-                //     continue;
-                // }
-                // Err(symphonia::core::errors::Error::DecodeError(_)) => {
-                //     // The packet failed to decode due to invalid data, skip the packet.
-                //     continue;
-                // }
                 Err(err) => {
                     // An unrecoverable error occurred, halt decoding.
-                    panic!("{}", err);
+                    return Err(DecodeError::DecodeSamples(err));
                 }
             }
         }
 
-        let spec = spec.expect("encountered no packets");
+        // Nothing was decoded, so fall back to what the track claims to be
+        let params = self.decoder.codec_params();
+        let spec = spec.or_else(|| Some(SignalSpec::new(params.sample_rate?, params.channels?)));
+
+        let waveform = match spec {
+            Some(spec) => {
+                let channels = match channel_mode {
+                    ChannelMode::Mono | ChannelMode::Channel(_) => 1,
+                    ChannelMode::All => spec.channels.count() as u16,
+                };
+
+                Waveform::new(samples, spec.rate, channels)
+            }
+            None => Waveform::new_mono(samples, Waveform::CD_SAMPLE_RATE),
+        };
+
+        // Cancelled or skipped decodes are expected to come up short
+        let expected_frames = frames
+            .end
+            .min(self.track_frames)
+            .saturating_sub(frames.start);
+        if !cancelled && !skipped && (waveform.frames() as u64) < expected_frames {
+            return Err(DecodeError::Truncated {
+                expected_frames,
+                frames: waveform.frames() as u64,
+            });
+        }
+
+        Ok(waveform)
+    }
+}
 
-        let waveform = Waveform::new(samples, spec.rate);
+#[cfg(test)]
+mod test {
+    use std::{cell::Cell, fs, ops::ControlFlow, path::PathBuf, time::Duration};
 
-        // Sanity check
-        debug_assert_eq!(waveform.len() as u64, self.track_frames);
+    use super::{AudioDecoder, AudioMetadata, ChannelMode, CreateDecoderError, DecodeError};
+
+    /// Write a 16-bit PCM wav file with the given interleaved samples
+    fn write_wav(name: &str, channels: u16, sample_rate: u32, samples: &[i16]) -> PathBuf {
+        write_wav_with_chunks(name, channels, sample_rate, samples, &[])
+    }
+
+    /// Write a 16-bit PCM wav file, with `chunks` placed between the format and
+    /// data chunks
+    fn write_wav_with_chunks(
+        name: &str,
+        channels: u16,
+        sample_rate: u32,
+        samples: &[i16],
+        chunks: &[u8],
+    ) -> PathBuf {
+        let data_len = (samples.len() * 2) as u32;
+        let block_align = channels * 2;
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + chunks.len() as u32 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        bytes.extend_from_slice(&block_align.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(chunks);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, bytes).expect("unable to write test wav file");
+
+        path
+    }
+
+    /// Write a matroska file with a track of 16-bit FLAC audio for each of
+    /// `tracks`, given as its channel count and interleaved samples. Every
+    /// track is a single FLAC frame, so must hold from 16 to 256 frames.
+    fn write_mka(name: &str, sample_rate: u32, tracks: &[(u8, &[i16])]) -> PathBuf {
+        let uint = |id, value: u64| ebml_element(id, &value.to_be_bytes());
+        let float = |id, value: f64| ebml_element(id, &value.to_be_bytes());
+
+        let frames = tracks
+            .iter()
+            .map(|&(channels, samples)| samples.len() as u64 / channels as u64)
+            .max()
+            .unwrap_or(0);
+
+        // Timestamps count frames, so the duration is the length of the tracks
+        let info = [
+            uint(0x2AD7B1, 1_000_000_000 / sample_rate as u64),
+            float(0x4489, frames as f64),
+            ebml_element(0x4D80, b"speaky"),
+            ebml_element(0x5741, b"speaky"),
+        ]
+        .concat();
+
+        let mut entries = Vec::new();
+        let mut cluster = uint(0xE7, 0);
+        for (number, &(channels, samples)) in (1..).zip(tracks) {
+            let track_frames = samples.len() as u64 / channels as u64;
+
+            let mut stream_info = Vec::new();
+            stream_info.extend_from_slice(&(track_frames as u16).to_be_bytes());
+            stream_info.extend_from_slice(&(track_frames as u16).to_be_bytes());
+            // Unknown frame sizes
+            stream_info.extend_from_slice(&[0; 6]);
+            stream_info.extend_from_slice(
+                &((u64::from(sample_rate) << 44)
+                    | (u64::from(channels - 1) << 41)
+                    | (15 << 36)
+                    | track_frames)
+                    .to_be_bytes(),
+            );
+            // Unknown MD5
+            stream_info.extend_from_slice(&[0; 16]);
+
+            // The stream marker and the last, and only, metadata block
+            let mut codec_private = b"fLaC\x80".to_vec();
+            codec_private.extend_from_slice(&(stream_info.len() as u32).to_be_bytes()[1..]);
+            codec_private.extend_from_slice(&stream_info);
+
+            let audio = [
+                float(0xB5, sample_rate as f64),
+                uint(0x9F, channels as u64),
+                uint(0x6264, 16),
+            ]
+            .concat();
+
+            entries.extend(ebml_element(
+                0xAE,
+                &[
+                    uint(0xD7, number),
+                    uint(0x73C5, number),
+                    uint(0x83, 2),
+                    ebml_element(0x86, b"A_FLAC"),
+                    ebml_element(0x63A2, &codec_private),
+                    ebml_element(0xE1, &audio),
+                ]
+                .concat(),
+            ));
+
+            // A keyframe at the start of the cluster, without lacing
+            let mut block = vec![0x80 | number as u8, 0, 0, 0x80];
+            block.extend(flac_frame(channels, samples));
+            cluster.extend(ebml_element(0xA3, &block));
+        }
+
+        let header = [
+            uint(0x4286, 1),
+            uint(0x42F7, 1),
+            uint(0x42F2, 4),
+            uint(0x42F3, 8),
+            ebml_element(0x4282, b"matroska"),
+            uint(0x4287, 4),
+            uint(0x4285, 2),
+        ]
+        .concat();
+        let segment = [
+            ebml_element(0x1549A966, &info),
+            ebml_element(0x1654AE6B, &entries),
+            ebml_element(0x1F43B675, &cluster),
+        ]
+        .concat();
+
+        let mut bytes = ebml_element(0x1A45DFA3, &header);
+        bytes.extend(ebml_element(0x18538067, &segment));
+
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, bytes).expect("unable to write test mka file");
+
+        path
+    }
+
+    /// An EBML element, with its size always written in eight bytes
+    fn ebml_element(id: u32, data: &[u8]) -> Vec<u8> {
+        let mut element = id
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&byte| byte == 0)
+            .collect::<Vec<_>>();
+        element.push(0x01);
+        element.extend_from_slice(&(data.len() as u64).to_be_bytes()[1..]);
+        element.extend_from_slice(data);
+
+        element
+    }
+
+    /// A FLAC frame storing the interleaved samples verbatim, taking its sample
+    /// rate from the stream info
+    fn flac_frame(channels: u8, samples: &[i16]) -> Vec<u8> {
+        let frames = samples.len() / channels as usize;
+
+        let mut frame = vec![
+            // Sync code, with a fixed block size
+            0xFF,
+            0xF8,
+            // Block size from the end of the header
+            0b0110_0000,
+            // Independent channels of 16 bits each
+            ((channels - 1) << 4) | 0b1000,
+            // Frame number
+            0,
+            (frames - 1) as u8,
+        ];
+        frame.push(crc8(&frame));
+
+        for channel in 0..channels as usize {
+            // Verbatim subframe, without wasted bits
+            frame.push(0b0000_0010);
+
+            for sample in samples.iter().skip(channel).step_by(channels as usize) {
+                frame.extend_from_slice(&sample.to_be_bytes());
+            }
+        }
+
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+
+        frame
+    }
+
+    fn crc8(bytes: &[u8]) -> u8 {
+        bytes.iter().fold(0, |crc, &byte| {
+            (0..8).fold(crc ^ byte, |crc, _| {
+                if crc & 0x80 != 0 {
+                    (crc << 1) ^ 0x07
+                } else {
+                    crc << 1
+                }
+            })
+        })
+    }
+
+    fn crc16(bytes: &[u8]) -> u16 {
+        bytes.iter().fold(0, |crc, &byte| {
+            (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+                if crc & 0x8000 != 0 {
+                    (crc << 1) ^ 0x8005
+                } else {
+                    crc << 1
+                }
+            })
+        })
+    }
+
+    fn decode(path: PathBuf, channel_mode: ChannelMode) -> Vec<f32> {
+        let (decoder, _) = AudioDecoder::create_for_file(path).expect("unable to create decoder");
+
+        decoder
+            .decode(channel_mode, &|_| ControlFlow::Continue(()))
+            .expect("unable to decode test wav file")
+            .into_samples()
+    }
+
+    #[test]
+    fn decode_stereo() {
+        let half = i16::MAX / 2 + 1;
+        let path = write_wav(
+            "pitch-decode-stereo.wav",
+            2,
+            8000,
+            &[half, -half, 0, half, -half, 0],
+        );
+
+        assert_eq!(
+            decode(path.clone(), ChannelMode::Channel(0)),
+            [0.5, 0.0, -0.5]
+        );
+        assert_eq!(
+            decode(path.clone(), ChannelMode::Channel(1)),
+            [-0.5, 0.5, 0.0]
+        );
+        assert_eq!(decode(path.clone(), ChannelMode::Mono), [0.0, 0.25, -0.25]);
+        assert_eq!(
+            decode(path.clone(), ChannelMode::All),
+            [0.5, -0.5, 0.0, 0.5, -0.5, 0.0]
+        );
+
+        let (decoder, _) =
+            AudioDecoder::create_for_file(path.clone()).expect("unable to create decoder");
+        assert!(matches!(
+            decoder.decode(ChannelMode::Channel(2), &|_| ControlFlow::Continue(())),
+            Err(DecodeError::NoSuchChannel {
+                requested: 2,
+                available: 2
+            })
+        ));
+
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn cancel_decoding() {
+        let samples = (0..100_000).map(|n| (n % 1000) as i16).collect::<Vec<_>>();
+        let path = write_wav("pitch-decode-cancel.wav", 1, 8000, &samples);
+
+        let (decoder, _) =
+            AudioDecoder::create_for_file(path.clone()).expect("unable to create decoder");
+
+        let calls = Cell::new(0);
+        let waveform = decoder
+            .decode(ChannelMode::Mono, &|_| {
+                calls.set(calls.get() + 1);
+
+                if calls.get() > 1 {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })
+            .expect("cancelling should not be an error");
+
+        assert_eq!(calls.get(), 2);
+        assert!(!waveform.is_empty());
+        assert!(waveform.len() < samples.len());
+
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn truncated_file() {
+        let samples = (0..100_000).map(|n| (n % 1000) as i16).collect::<Vec<_>>();
+        let path = write_wav("pitch-decode-truncated.wav", 1, 8000, &samples);
+
+        // Cut off the second half of the samples, leaving the header intact
+        let mut bytes = fs::read(&path).expect("unable to read test wav file");
+        bytes.truncate(bytes.len() - samples.len());
+        fs::write(&path, bytes).expect("unable to truncate test wav file");
+
+        let (decoder, _) =
+            AudioDecoder::create_for_file(path.clone()).expect("unable to create decoder");
+
+        assert!(decoder
+            .decode(ChannelMode::Mono, &|_| ControlFlow::Continue(()))
+            .is_err());
+
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn metadata() {
+        // A LIST chunk holding a single INFO title, of even length so it needs no padding
+        let title = b"Test Title";
+        let mut chunks = Vec::new();
+        chunks.extend_from_slice(b"LIST");
+        chunks.extend_from_slice(&(4 + 8 + title.len() as u32).to_le_bytes());
+        chunks.extend_from_slice(b"INFOINAM");
+        chunks.extend_from_slice(&(title.len() as u32).to_le_bytes());
+        chunks.extend_from_slice(title);
+
+        let tagged =
+            write_wav_with_chunks("pitch-decode-metadata.wav", 1, 8000, &[0; 8000], &chunks);
+        let (decoder, _) =
+            AudioDecoder::create_for_file(tagged.clone()).expect("unable to create decoder");
+
+        let metadata = decoder.metadata();
+        assert_eq!(metadata.title.as_deref(), Some("Test Title"));
+        assert_eq!(metadata.artist, None);
+        assert_eq!(metadata.duration, Some(Duration::from_secs(1)));
+
+        // No tags at all
+        let untagged = write_wav("pitch-decode-no-metadata.wav", 1, 8000, &[0; 4000]);
+        let (decoder, _) =
+            AudioDecoder::create_for_file(untagged.clone()).expect("unable to create decoder");
+
+        assert_eq!(
+            decoder.metadata(),
+            AudioMetadata {
+                duration: Some(Duration::from_millis(500)),
+                ..Default::default()
+            }
+        );
+
+        fs::remove_file(tagged).ok();
+        fs::remove_file(untagged).ok();
+    }
+
+    #[test]
+    fn tracks() {
+        let path = write_wav("pitch-decode-tracks.wav", 2, 8000, &[0; 16]);
+
+        let (decoder, _) =
+            AudioDecoder::create_for_file(path.clone()).expect("unable to create decoder");
+        let tracks = decoder.tracks();
+
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].id, decoder.track_id());
+        assert_eq!(tracks[0].channels, Some(2));
+        assert_eq!(tracks[0].sample_rate, Some(8000));
+        assert!(tracks[0].codec.is_some());
+
+        // Selecting the same track explicitly
+        let (decoder, _) = AudioDecoder::create_for_file_with_track(path.clone(), tracks[0].id)
+            .expect("unable to create decoder");
+        assert_eq!(decoder.track_id(), tracks[0].id);
+
+        assert!(matches!(
+            AudioDecoder::create_for_file_with_track(path.clone(), tracks[0].id + 1),
+            Err(CreateDecoderError::NoSuchTrack(_))
+        ));
+
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn multiple_tracks() {
+        // A stereo track followed by a mono one, as with a stereo and mono pair
+        let stereo = (0..64).map(|n| n * 100).collect::<Vec<_>>();
+        let mono = (0..32).map(|n| -n * 100).collect::<Vec<_>>();
+        let path = write_mka(
+            "pitch-decode-multiple-tracks.mka",
+            8000,
+            &[(2, &stereo[..]), (1, &mono[..])],
+        );
+
+        let (decoder, _) =
+            AudioDecoder::create_for_file(path.clone()).expect("unable to create decoder");
+        let tracks = decoder.tracks();
+
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(
+            tracks.iter().map(|track| track.id).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert_eq!(tracks[0].channels, Some(2));
+        assert_eq!(tracks[1].channels, Some(1));
+        assert!(tracks
+            .iter()
+            .all(|track| track.codec.is_some() && track.sample_rate == Some(8000)));
+
+        // The first supported track is decoded by default
+        assert_eq!(decoder.track_id(), tracks[0].id);
+
+        let (decoder, _) = AudioDecoder::create_for_file_with_track(path.clone(), tracks[1].id)
+            .expect("unable to create decoder");
+        assert_eq!(decoder.track_id(), tracks[1].id);
+
+        let waveform = decoder
+            .decode(ChannelMode::All, &|_| ControlFlow::Continue(()))
+            .expect("unable to decode the second track");
+
+        assert_eq!(waveform.channels(), 1);
+        assert_eq!(
+            waveform.into_samples(),
+            mono.iter()
+                .map(|&sample| sample as f32 / 32768.0)
+                .collect::<Vec<_>>()
+        );
+
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn decode_range() {
+        // Three seconds of a sawtooth
+        let samples = (0..24_000).map(|n| (n % 1000) as i16).collect::<Vec<_>>();
+        let path = write_wav("pitch-decode-range.wav", 1, 8000, &samples);
+
+        let (decoder, _) =
+            AudioDecoder::create_for_file(path.clone()).expect("unable to create decoder");
+
+        let waveform = decoder
+            .decode_range(
+                Duration::from_secs(1),
+                Duration::from_secs(2),
+                ChannelMode::Mono,
+                &|_| ControlFlow::Continue(()),
+            )
+            .expect("unable to decode the middle of the test wav file");
+
+        let middle = samples[8000..16000]
+            .iter()
+            .map(|&sample| sample as f32 / 32768.0)
+            .collect::<Vec<_>>();
+        assert_eq!(waveform.into_samples(), middle);
 
-        waveform
+        fs::remove_file(path).ok();
     }
 }
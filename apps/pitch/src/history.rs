@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    analysis::{KeyPress, KeyPresses, KeyStart},
+    key::PianoKey,
+    piano_roll::NoteEdit,
+};
+
+/// What an edit did to the keypresses of a single key, which is enough to
+/// undo or redo it without keeping a copy of every note
+#[derive(Debug, Clone, PartialEq)]
+struct Change {
+    key: PianoKey,
+    removed: Vec<KeyPress>,
+    inserted: Vec<KeyPress>,
+}
+
+impl Change {
+    /// Make `edit` to `keys`, recording the keypresses it removed and inserted
+    fn make(edit: &NoteEdit, keys: &mut BTreeMap<PianoKey, KeyPresses>) -> Self {
+        let key = edit.key();
+        let before = keys.get(&key).cloned().unwrap_or_default();
+
+        edit.apply(keys);
+
+        let after = keys.get(&key).cloned().unwrap_or_default();
+
+        Self {
+            key,
+            removed: difference(&before, &after),
+            inserted: difference(&after, &before),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.inserted.is_empty()
+    }
+
+    fn undo(&self, keys: &mut BTreeMap<PianoKey, KeyPresses>) {
+        swap(keys, self.key, &self.inserted, &self.removed);
+    }
+
+    fn redo(&self, keys: &mut BTreeMap<PianoKey, KeyPresses>) {
+        swap(keys, self.key, &self.removed, &self.inserted);
+    }
+}
+
+/// The keypresses in `a` which are not in `b`
+fn difference(a: &KeyPresses, b: &KeyPresses) -> Vec<KeyPress> {
+    a.iter()
+        .filter(|keypress| !b.iter().any(|other| other == *keypress))
+        .collect()
+}
+
+/// Replace the keypresses `old` of `key` with `new`, leaving keys without any
+/// keypresses out of the map
+fn swap(
+    keys: &mut BTreeMap<PianoKey, KeyPresses>,
+    key: PianoKey,
+    old: &[KeyPress],
+    new: &[KeyPress],
+) {
+    let presses = keys.entry(key).or_default();
+
+    for keypress in old {
+        presses.remove(keypress.start());
+    }
+    for &keypress in new {
+        presses.insert(keypress);
+    }
+
+    if presses.is_empty() {
+        keys.remove(&key);
+    }
+}
+
+/// The edits made to the notes by hand, so they can be undone and redone. Each
+/// step holds every change made by the edits made together.
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Vec<Change>>,
+    redo: Vec<Vec<Change>>,
+    /// The note being resized by the last edit, so that dragging its end
+    /// becomes a single step
+    resizing: Option<(PianoKey, KeyStart)>,
+}
+
+impl History {
+    /// Make every one of `edits` to `keys` as a single step, remembering how
+    /// to undo them
+    pub fn edit_all(&mut self, edits: &[NoteEdit], keys: &mut BTreeMap<PianoKey, KeyPresses>) {
+        let changes = edits
+            .iter()
+            .map(|edit| Change::make(edit, keys))
+            .filter(|change| !change.is_empty())
+            .collect::<Vec<_>>();
+        if changes.is_empty() {
+            return;
+        }
+
+        let resizing = match edits {
+            [NoteEdit::Resize { key, start, .. }] => Some((*key, *start)),
+            _ => None,
+        };
+
+        match (self.undo.last_mut().map(Vec::as_mut_slice), &changes[..]) {
+            // Keep resizing the same note as one step
+            (Some([last]), [change]) if resizing.is_some() && resizing == self.resizing => {
+                last.inserted = change.inserted.clone();
+            }
+            _ => self.undo.push(changes),
+        }
+
+        self.resizing = resizing;
+        self.redo.clear();
+    }
+
+    /// Undo the last step, returning whether there was one
+    pub fn undo(&mut self, keys: &mut BTreeMap<PianoKey, KeyPresses>) -> bool {
+        self.resizing = None;
+
+        match self.undo.pop() {
+            Some(changes) => {
+                for change in changes.iter().rev() {
+                    change.undo(keys);
+                }
+                self.redo.push(changes);
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Redo the last undone step, returning whether there was one
+    pub fn redo(&mut self, keys: &mut BTreeMap<PianoKey, KeyPresses>) -> bool {
+        self.resizing = None;
+
+        match self.redo.pop() {
+            Some(changes) => {
+                for change in &changes {
+                    change.redo(keys);
+                }
+                self.undo.push(changes);
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Forget every edit, for when the notes are replaced
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use super::History;
+    use crate::{
+        analysis::{KeyPress, KeyPresses},
+        key::PianoKey,
+        piano_roll::NoteEdit,
+    };
+
+    #[test]
+    fn undo_insertion() {
+        let key = PianoKey::new(49).unwrap();
+        let original = BTreeMap::from([(
+            key,
+            KeyPresses::from([
+                KeyPress::new(0u64, Duration::from_millis(500), 1.0),
+                KeyPress::new(1000u64, Duration::from_millis(500), 0.5),
+            ]),
+        )]);
+
+        let mut notes = original.clone();
+        let mut history = History::default();
+
+        // Joins with the keypress after it
+        let insertion = NoteEdit::Add {
+            key,
+            keypress: KeyPress::new(600u64, Duration::from_millis(400), 1.0),
+        };
+        let joined = KeyPress::new(600u64, Duration::from_millis(900), 1.0);
+
+        history.edit_all(&[insertion], &mut notes);
+        assert_eq!(notes[&key].last(), Some(joined));
+
+        assert!(history.undo(&mut notes));
+        assert_eq!(notes, original);
+        assert!(!history.undo(&mut notes));
+
+        assert!(history.redo(&mut notes));
+        assert_eq!(notes[&key].last(), Some(joined));
+        assert!(!history.redo(&mut notes));
+
+        // Undoing the only note of a key leaves the key out entirely
+        let other = PianoKey::new(40).unwrap();
+        history.edit_all(
+            &[NoteEdit::Add {
+                key: other,
+                keypress: KeyPress::new(0u64, Duration::from_millis(250), 1.0),
+            }],
+            &mut notes,
+        );
+        history.undo(&mut notes);
+        assert!(!notes.contains_key(&other));
+    }
+
+    #[test]
+    fn resizing_is_one_step() {
+        let key = PianoKey::new(49).unwrap();
+        let original = BTreeMap::from([(
+            key,
+            KeyPresses::from([KeyPress::new(0u64, Duration::from_millis(500), 1.0)]),
+        )]);
+
+        let mut notes = original.clone();
+        let mut history = History::default();
+
+        for millis in [600, 700, 800] {
+            history.edit_all(
+                &[NoteEdit::Resize {
+                    key,
+                    start: 0,
+                    duration: Duration::from_millis(millis),
+                }],
+                &mut notes,
+            );
+        }
+        assert_eq!(
+            notes[&key].first().map(|keypress| keypress.duration()),
+            Some(Duration::from_millis(800))
+        );
+
+        assert!(history.undo(&mut notes));
+        assert_eq!(notes, original);
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn removals_together_are_one_step() {
+        let (c4, a4) = (PianoKey::new(40).unwrap(), PianoKey::new(49).unwrap());
+        let original = BTreeMap::from([
+            (
+                c4,
+                KeyPresses::from([KeyPress::new(0u64, Duration::from_millis(500), 1.0)]),
+            ),
+            (
+                a4,
+                KeyPresses::from([
+                    KeyPress::new(0u64, Duration::from_millis(250), 1.0),
+                    KeyPress::new(1000u64, Duration::from_millis(250), 1.0),
+                ]),
+            ),
+        ]);
+
+        let mut notes = original.clone();
+        let mut history = History::default();
+
+        history.edit_all(
+            &[
+                NoteEdit::Remove { key: c4, start: 0 },
+                NoteEdit::Remove { key: a4, start: 0 },
+                NoteEdit::Remove {
+                    key: a4,
+                    start: 1000,
+                },
+            ],
+            &mut notes,
+        );
+        assert!(notes.is_empty());
+
+        assert!(history.undo(&mut notes));
+        assert_eq!(notes, original);
+        assert!(!history.can_undo());
+    }
+}
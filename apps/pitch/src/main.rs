@@ -8,14 +8,21 @@ use ritelinked::LinkedHashSet;
 use tracing::info;
 use util::install_tracing;
 
-use crate::app::Application;
+use crate::app::{Application, NOTES_KEY};
 
 mod analysis;
 mod app;
+mod csv_file;
 mod decode;
+mod history;
 mod key;
+mod metronome;
 mod midi;
+mod midi_file;
 mod piano_roll;
+mod session;
+mod synth;
+mod tuner;
 mod ui_error;
 
 pub const NAME: &str = "Pitch";
@@ -31,13 +38,16 @@ pub fn main() -> color_eyre::Result<()> {
         NAME,
         NativeOptions::default(),
         Box::new(|cc| {
-            let recently_opened_files = if let Some(storage) = cc.storage {
-                eframe::get_value(storage, APP_KEY).unwrap_or_default()
+            let (recently_opened_files, notes) = if let Some(storage) = cc.storage {
+                (
+                    eframe::get_value(storage, APP_KEY).unwrap_or_default(),
+                    eframe::get_value(storage, NOTES_KEY).flatten(),
+                )
             } else {
-                LinkedHashSet::new()
+                (LinkedHashSet::new(), None)
             };
 
-            Box::new(Application::new(recently_opened_files))
+            Box::new(Application::new(recently_opened_files, notes))
         }),
     )
 }
@@ -0,0 +1,251 @@
+use std::{f32::consts, sync::Weak};
+
+use audio::{output::AudioSink, waveform::Waveform};
+use color_eyre::eyre::Context as _;
+use eframe::egui::{Context, DragValue, Slider, Window};
+use spectrum::onset::{MAX_BPM, MIN_BPM};
+
+use crate::midi::{MidiPlayer, SongProgress};
+
+/// How long each click rings for
+const CLICK_SECS: f32 = 0.03;
+
+/// The pitch of the clicks, and of the accented first beat of each bar
+const CLICK_HZ: f32 = 1_000.0;
+const ACCENT_HZ: f32 = 1_500.0;
+
+/// How loud the clicks are relative to an accented one
+const CLICK_GAIN: f32 = 0.6;
+
+/// Where the clicks go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetronomeOutput {
+    Speakers,
+    /// Wood blocks on the General MIDI percussion channel
+    Midi,
+}
+
+/// Clicks along at a steady tempo while open, to record along to or to check
+/// a tempo estimate by ear
+pub struct Metronome {
+    pub open: bool,
+
+    pub bpm: f32,
+    pub beats_per_bar: u8,
+    /// Whether the first beat of each bar is higher and louder than the rest
+    pub accent: bool,
+    pub output: MetronomeOutput,
+
+    /// The sink looping the click track, while playing through the speakers
+    sink: Option<AudioSink>,
+    midi_clicks: SongProgress,
+}
+
+impl Default for Metronome {
+    fn default() -> Self {
+        Self {
+            open: false,
+            bpm: 120.0,
+            beats_per_bar: 4,
+            accent: true,
+            output: MetronomeOutput::Speakers,
+            sink: None,
+            midi_clicks: Weak::new(),
+        }
+    }
+}
+
+impl Metronome {
+    /// The sample each beat of `bars` starts on, and whether it is accented
+    fn beats(&self, bars: usize, sample_rate: u32) -> impl Iterator<Item = (usize, bool)> {
+        let beats_per_bar = self.beats_per_bar.max(1) as usize;
+        let samples_per_beat = 60.0 * sample_rate as f64 / self.bpm as f64;
+        let accent = self.accent;
+
+        (0..bars * beats_per_bar).map(move |beat| {
+            (
+                (beat as f64 * samples_per_beat).round() as usize,
+                accent && beat % beats_per_bar == 0,
+            )
+        })
+    }
+
+    /// A short decaying tone burst on every beat of `bars`, with silence
+    /// between them. Beats land on the nearest sample, so they do not drift
+    /// when a beat is not a whole number of samples long.
+    pub fn click_track(&self, bars: usize, sample_rate: u32) -> Waveform<'static> {
+        let beats = bars * self.beats_per_bar.max(1) as usize;
+        let length = (beats as f64 * 60.0 * sample_rate as f64 / self.bpm as f64).round() as usize;
+        let click_length = (CLICK_SECS * sample_rate as f32).round() as usize;
+
+        let mut samples = vec![0.0; length];
+
+        for (start, accented) in self.beats(bars, sample_rate) {
+            let (frequency, gain) = if accented {
+                (ACCENT_HZ, 1.0)
+            } else {
+                (CLICK_HZ, CLICK_GAIN)
+            };
+
+            for (n, sample) in samples
+                .iter_mut()
+                .skip(start)
+                .take(click_length)
+                .enumerate()
+            {
+                let t = n as f32 / sample_rate as f32;
+
+                // Starting at its peak for a sharp attack, and dying away
+                // to almost nothing by the end of the click
+                *sample =
+                    gain * (-5.0 * t / CLICK_SECS).exp() * (consts::TAU * frequency * t).cos();
+            }
+        }
+
+        Waveform::new_mono(samples, sample_rate)
+    }
+
+    fn is_playing(&self) -> bool {
+        self.sink.is_some() || self.midi_clicks.upgrade().is_some()
+    }
+
+    fn start(&mut self, midi: &MidiPlayer, ctx: Context) -> color_eyre::Result<()> {
+        match self.output {
+            MetronomeOutput::Speakers => {
+                let sink = AudioSink::new().wrap_err("failed to play the metronome")?;
+
+                // A single bar repeats without a gap for as long as it plays
+                sink.queue_looping(&self.click_track(1, Waveform::CD_SAMPLE_RATE), |_| {});
+
+                self.sink = Some(sink);
+            }
+            MetronomeOutput::Midi => {
+                self.midi_clicks =
+                    midi.play_metronome(self.bpm, self.beats_per_bar, self.accent, ctx);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.sink = None;
+
+        if let Some(progress) = self.midi_clicks.upgrade() {
+            progress.cancel();
+        }
+    }
+
+    /// Show the metronome window if it is open, stopping the clicks once it
+    /// is closed. The tempo found in the analysis can be picked up from
+    /// `detected_tempo`.
+    pub fn show(
+        &mut self,
+        ctx: &Context,
+        midi: &MidiPlayer,
+        detected_tempo: Option<f32>,
+    ) -> color_eyre::Result<()> {
+        if !self.open {
+            self.stop();
+
+            return Ok(());
+        }
+
+        let mut open = self.open;
+        let mut started = Ok(());
+
+        Window::new("Metronome")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let playing = self.is_playing();
+
+                ui.add_enabled_ui(!playing, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            DragValue::new(&mut self.bpm)
+                                .clamp_range(MIN_BPM..=MAX_BPM)
+                                .suffix(" BPM"),
+                        );
+
+                        if let Some(tempo) = detected_tempo {
+                            if ui.small_button(format!("Use ≈{tempo:.0}")).clicked() {
+                                self.bpm = tempo.round();
+                            }
+                        }
+                    });
+                    ui.add(Slider::new(&mut self.beats_per_bar, 1..=12).text("Beats per bar"));
+                    ui.checkbox(&mut self.accent, "Accent the first beat");
+
+                    ui.horizontal(|ui| {
+                        ui.label("Output");
+                        ui.selectable_value(
+                            &mut self.output,
+                            MetronomeOutput::Speakers,
+                            "Speakers",
+                        );
+                        ui.selectable_value(&mut self.output, MetronomeOutput::Midi, "MIDI");
+                    });
+                });
+
+                if playing {
+                    if ui.button("Stop").clicked() {
+                        self.stop();
+                    }
+                } else if ui.button("Start").clicked() {
+                    started = self.start(midi, ctx.clone());
+                }
+            });
+
+        self.open = open;
+
+        started
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Metronome, CLICK_GAIN, CLICK_SECS};
+
+    #[test]
+    fn clicks_on_the_beat() {
+        let sample_rate = 8000;
+        let click_length = (CLICK_SECS * sample_rate as f32).round() as usize;
+
+        // Each beat is 5333⅓ samples long, so they land on the nearest sample
+        let starts = [0, 5333, 10667, 16000, 21333, 26667, 32000, 37333];
+
+        for accent in [true, false] {
+            let metronome = Metronome {
+                bpm: 90.0,
+                beats_per_bar: 4,
+                accent,
+                ..Default::default()
+            };
+
+            let clicks = metronome.click_track(2, sample_rate);
+            let samples = clicks.samples();
+            assert_eq!(clicks.len(), 42667);
+
+            for (beat, &start) in starts.iter().enumerate() {
+                let gain = if accent && beat % 4 == 0 {
+                    1.0
+                } else {
+                    CLICK_GAIN
+                };
+
+                assert_eq!(samples[start], gain, "beat {beat}");
+            }
+
+            // Silent everywhere but the clicks
+            for (n, &sample) in samples.iter().enumerate() {
+                if !starts
+                    .iter()
+                    .any(|&start| (start..start + click_length).contains(&n))
+                {
+                    assert_eq!(sample, 0.0, "sample {n}");
+                }
+            }
+        }
+    }
+}
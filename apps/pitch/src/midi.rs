@@ -1,5 +1,6 @@
 use std::{
     collections::{BTreeMap, HashSet},
+    ops::RangeInclusive,
     sync::{
         atomic::{AtomicBool, AtomicUsize},
         Arc, Weak,
@@ -11,48 +12,155 @@ use std::{
 use async_executor::Executor;
 use async_io::Timer;
 use atomic::{Atomic, Ordering};
+use color_eyre::eyre::eyre;
 use eframe::egui::Context;
 use flume::{Receiver, RecvError, Sender};
 use futures_lite::future;
 use midir::{MidiOutput, MidiOutputConnection};
-use tracing::{debug, info};
+use spectrum::onset::{MAX_BPM, MIN_BPM};
+use tracing::{debug, info, warn};
 
 use crate::{
     analysis::{KeyPress, KeyPresses},
-    key::PianoKey,
+    key::{PianoKey, Tuning},
 };
 
 pub struct MidiPlayer {
+    name: String,
+    selected_port: Option<usize>,
+    velocity_curve: VelocityCurve,
+    program: u8,
+    pitch_bend_range: f32,
     sender: Sender<MidiThreadCommand>,
     executor: Arc<Executor<'static>>,
 }
 
 pub enum MidiConnection {
-    Disconnected { output: MidiOutput },
-    Connected { connection: MidiOutputConnection },
+    Disconnected {
+        output: MidiOutput,
+    },
+    Connected {
+        connection: MidiOutputConnection,
+    },
+    #[cfg(test)]
+    Mock {
+        port: Option<usize>,
+        sent: Vec<MidiCommand>,
+    },
+}
+
+impl MidiConnection {
+    fn is_connected(&self) -> bool {
+        match self {
+            MidiConnection::Disconnected { .. } => false,
+            MidiConnection::Connected { .. } => true,
+            #[cfg(test)]
+            MidiConnection::Mock { port, .. } => port.is_some(),
+        }
+    }
+
+    fn send(&mut self, command: MidiCommand) {
+        match self {
+            MidiConnection::Disconnected { .. } => {
+                info!(?command, "Midi disconnected.. ignoring command");
+            }
+            MidiConnection::Connected { connection } => {
+                connection.send(command.to_bytes().as_slice()).unwrap();
+            }
+            #[cfg(test)]
+            MidiConnection::Mock { sent, .. } => sent.push(command),
+        }
+    }
+
+    /// Disconnect from the current port, if any, and connect to the port at
+    /// `index`, along with whether the new port could be connected to
+    fn connect(self, index: usize) -> (Self, color_eyre::Result<()>) {
+        let output = match self {
+            MidiConnection::Disconnected { output } => output,
+            MidiConnection::Connected { connection } => connection.close(),
+            #[cfg(test)]
+            MidiConnection::Mock { sent, .. } => {
+                return (
+                    MidiConnection::Mock {
+                        port: Some(index),
+                        sent,
+                    },
+                    Ok(()),
+                )
+            }
+        };
+
+        let port = match output.ports().get(index) {
+            Some(port) => port.clone(),
+            None => {
+                warn!(index, "No midi output port at index");
+
+                return (
+                    MidiConnection::Disconnected { output },
+                    Err(eyre!("there is no midi output port {index}")),
+                );
+            }
+        };
+
+        match output.connect(&port, MidiPlayer::CONN_NAME) {
+            Ok(connection) => {
+                debug!(index, "Connected to midi output port");
+
+                (MidiConnection::Connected { connection }, Ok(()))
+            }
+            Err(error) => {
+                warn!(%error, index, "Unable to connect to midi output port");
+
+                let report = eyre!("unable to connect to midi output port {index}: {error}");
+
+                (
+                    MidiConnection::Disconnected {
+                        output: error.into_inner(),
+                    },
+                    Err(report),
+                )
+            }
+        }
+    }
 }
 
 impl MidiPlayer {
     const CONN_NAME: &'static str = "piano-roll";
 
+    /// The General MIDI percussion the metronome clicks with, a high wood
+    /// block on accented beats and a low one on the rest
+    const ACCENT_DRUM: u8 = 76;
+    const CLICK_DRUM: u8 = 77;
+
+    /// How long each metronome click is held for
+    const CLICK_DURATION: Duration = Duration::from_millis(50);
+
+    /// The bend ranges, in semitones, that devices can be set to
+    pub const PITCH_BEND_RANGE: RangeInclusive<f32> = 1.0..=24.0;
+
     pub fn new(name: &str) -> Self {
         let midi_output = MidiOutput::new(name).expect("unable to enumerate midi devices");
 
-        // TODO: expose and implement selection
-        let connection = match midi_output.ports().as_slice() {
+        let (connection, selected_port) = match midi_output.ports().as_slice() {
             // Connect if there is only one port available
             [port] => {
                 let port_name = midi_output.port_name(port).unwrap();
 
                 debug!(%port_name, "Connecting to the only available output port");
 
-                MidiConnection::Connected {
-                    connection: midi_output.connect(port, Self::CONN_NAME).unwrap(),
-                }
+                (
+                    MidiConnection::Connected {
+                        connection: midi_output.connect(port, Self::CONN_NAME).unwrap(),
+                    },
+                    Some(0),
+                )
             }
-            _ => MidiConnection::Disconnected {
-                output: midi_output,
-            },
+            _ => (
+                MidiConnection::Disconnected {
+                    output: midi_output,
+                },
+                None,
+            ),
         };
 
         let (sender, recv) = flume::unbounded();
@@ -72,30 +180,156 @@ impl MidiPlayer {
 
         executor.spawn(midi_thread(connection, recv)).detach();
 
-        Self { sender, executor }
+        Self {
+            name: name.to_string(),
+            selected_port,
+            velocity_curve: VelocityCurve::Linear,
+            program: 0,
+            pitch_bend_range: 2.0,
+            sender,
+            executor,
+        }
+    }
+
+    /// The names of the midi output ports, in the order used by [`MidiPlayer::connect_to`]
+    pub fn available_ports(&self) -> Vec<String> {
+        let midi_output = match MidiOutput::new(&self.name) {
+            Ok(midi_output) => midi_output,
+            Err(error) => {
+                warn!(%error, "Unable to enumerate midi devices");
+
+                return Vec::new();
+            }
+        };
+
+        midi_output
+            .ports()
+            .iter()
+            .map(|port| {
+                midi_output
+                    .port_name(port)
+                    .unwrap_or_else(|_| "Unknown".to_string())
+            })
+            .collect()
+    }
+
+    /// The index of the output port currently connected to
+    pub fn selected_port(&self) -> Option<usize> {
+        self.selected_port
+    }
+
+    /// Switch to the output port at `index`, silencing any notes on the current
+    /// port. If the new port can not be connected to, no port is left selected.
+    pub fn connect_to(&mut self, index: usize) -> color_eyre::Result<()> {
+        let (reply, connected) = flume::bounded(1);
+
+        self.sender
+            .send(MidiThreadCommand::Connect(index, reply))
+            .unwrap();
+
+        let connected = connected
+            .recv()
+            .unwrap_or_else(|_| Err(eyre!("the midi thread has stopped")));
+        self.selected_port = connected.is_ok().then_some(index);
+
+        connected
+    }
+
+    pub fn velocity_curve(&self) -> VelocityCurve {
+        self.velocity_curve
+    }
+
+    pub fn set_velocity_curve(&mut self, velocity_curve: VelocityCurve) {
+        self.velocity_curve = velocity_curve;
+    }
+
+    /// The velocity to play a keypress at, relative to the loudest keypress of its song
+    pub fn velocity(&self, intensity: f32, max_intensity: f32) -> u8 {
+        let normalized = if max_intensity > 0.0 {
+            intensity / max_intensity
+        } else {
+            1.0
+        };
+
+        self.velocity_curve.velocity(normalized)
+    }
+
+    /// The General MIDI patch songs are played with, counting from zero
+    pub fn program(&self) -> u8 {
+        self.program
+    }
+
+    pub fn set_program(&mut self, program: u8) {
+        self.program = program & 0b01111111;
+    }
+
+    /// Send `command` to the current port, behind any notes already queued
+    fn send(&self, command: MidiCommand) {
+        self.sender.send(MidiThreadCommand::Send(command)).unwrap();
+    }
+
+    pub fn play_piano(&self, key: PianoKey, duration: Duration, velocity: u8) {
+        // Undo any bend left behind by a microtonal note
+        self.send(MidiCommand::PitchBendChange(MidiCommand::PITCH_BEND_CENTER));
+
+        self.play_note(key, duration, velocity);
     }
 
-    pub fn play_piano(&self, key: PianoKey, duration: Duration) {
+    fn play_note(&self, key: PianoKey, duration: Duration, velocity: u8) {
         self.sender
             .send(MidiThreadCommand::PlayNote(
                 MidiNote::from_piano_key(key),
+                velocity,
                 duration,
             ))
             .unwrap();
     }
 
+    /// How many semitones either side of a note the receiving device bends over
+    pub fn pitch_bend_range(&self) -> f32 {
+        self.pitch_bend_range
+    }
+
+    /// Set the bend range to match the receiving device, kept within
+    /// [`MidiPlayer::PITCH_BEND_RANGE`]
+    pub fn set_pitch_bend_range(&mut self, semitones: f32) {
+        let (min, max) = Self::PITCH_BEND_RANGE.into_inner();
+
+        // A NaN range falls back to the smallest
+        self.pitch_bend_range = semitones.max(min).min(max);
+    }
+
+    /// Play `key` bent by `cents`, to reproduce frequencies between the keys.
+    ///
+    /// Pitch bend applies to the whole channel, so it also bends any notes
+    /// already sounding, until the next note played without a bend.
+    pub fn play_microtonal(&self, key: PianoKey, cents: f32, duration: Duration) {
+        self.send(MidiCommand::pitch_bend_cents(cents, self.pitch_bend_range));
+        self.play_note(key, duration, 0b01111111);
+    }
+
     #[must_use]
     pub fn play_song(&self, notes: &BTreeMap<PianoKey, KeyPresses>, ctx: Context) -> SongProgress {
+        // Make sure the song plays on the chosen instrument, at the pitch of
+        // its keys
+        self.send(MidiCommand::ProgramChange(self.program));
+        self.send(MidiCommand::PitchBendChange(MidiCommand::PITCH_BEND_CENTER));
+
         let song_start = Instant::now();
         let sender = self.sender.clone();
+        let max_intensity = max_intensity(notes);
 
-        let mut deadlines = BTreeMap::<Instant, Vec<(PianoKey, KeyPress)>>::new();
+        let mut deadlines = BTreeMap::<Instant, Vec<(PianoKey, KeyPress, u8)>>::new();
         for (key, key_presses) in notes {
             for key_press in key_presses.iter() {
                 deadlines
                     .entry(song_start + Duration::from_secs_f32(key_press.start_secs()))
                     .or_default()
-                    .push((*key, key_press))
+                    .push((
+                        *key,
+                        key_press,
+                        self.velocity(key_press.intensity(), max_intensity),
+                    ))
             }
         }
 
@@ -115,10 +349,11 @@ impl MidiPlayer {
                         break;
                     }
 
-                    for (key, key_press) in keys {
+                    for (key, key_press, velocity) in keys {
                         sender
                             .send(MidiThreadCommand::PlayNote(
                                 MidiNote::from_piano_key(*key),
+                                *velocity,
                                 key_press.duration(),
                             ))
                             .unwrap();
@@ -137,6 +372,110 @@ impl MidiPlayer {
 
         weak
     }
+
+    /// Click every beat at `bpm` on the percussion channel until cancelled,
+    /// accenting the first of every `beats_per_bar` if `accent` is set. The
+    /// progress counts the clicks played. The tempo is clamped between
+    /// [`MIN_BPM`] and [`MAX_BPM`].
+    #[must_use]
+    pub fn play_metronome(
+        &self,
+        bpm: f32,
+        beats_per_bar: u8,
+        accent: bool,
+        ctx: Context,
+    ) -> SongProgress {
+        let start = Instant::now();
+        let bpm = if bpm.is_nan() {
+            MIN_BPM
+        } else {
+            bpm.clamp(MIN_BPM, MAX_BPM)
+        };
+        let beat = Duration::from_secs_f32(60.0 / bpm);
+        let beats_per_bar = beats_per_bar.max(1) as u32;
+        let sender = self.sender.clone();
+
+        let progress = Arc::new(SongProgressInner {
+            time: Atomic::new(0.0),
+            notes: AtomicUsize::new(0),
+            cancel: AtomicBool::new(false),
+        });
+        let weak = Arc::downgrade(&progress);
+
+        self.executor
+            .spawn(async move {
+                for beat_number in 0.. {
+                    // Counted from the start, so that late wakeups do not add up
+                    let deadline = start + beat * beat_number;
+                    Timer::at(deadline).await;
+
+                    if progress.cancel.load(Ordering::SeqCst) {
+                        break;
+                    }
+
+                    let (drum, velocity) = if accent && beat_number % beats_per_bar == 0 {
+                        (Self::ACCENT_DRUM, 0b01111111)
+                    } else {
+                        (Self::CLICK_DRUM, 100)
+                    };
+                    let drum = MidiNote::new(drum);
+
+                    sender
+                        .send(MidiThreadCommand::Send(MidiCommand::PercussionNoteOn(
+                            drum, velocity,
+                        )))
+                        .unwrap();
+
+                    progress.notes.fetch_add(1, Ordering::SeqCst);
+                    progress
+                        .time
+                        .store((deadline - start).as_secs_f32(), Ordering::SeqCst);
+                    ctx.request_repaint();
+
+                    Timer::after(Self::CLICK_DURATION).await;
+                    sender
+                        .send(MidiThreadCommand::Send(MidiCommand::PercussionNoteOff(
+                            drum, 0b01111111,
+                        )))
+                        .unwrap();
+                }
+            })
+            .detach();
+
+        weak
+    }
+}
+
+/// The loudest intensity of any keypress, to normalize the rest against
+pub fn max_intensity(notes: &BTreeMap<PianoKey, KeyPresses>) -> f32 {
+    notes
+        .values()
+        .flat_map(KeyPresses::iter)
+        .map(|key_press| key_press.intensity())
+        .fold(0.0, f32::max)
+}
+
+/// How normalized intensities in `0.0..=1.0` map onto midi velocities
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VelocityCurve {
+    Linear,
+    /// Raise the normalized intensity to a power, below 1.0 brings up quiet
+    /// notes and above 1.0 pushes them down
+    Power(f32),
+}
+
+impl VelocityCurve {
+    /// The velocity from 1 to 127, so that even the quietest notes sound
+    pub fn velocity(self, normalized_intensity: f32) -> u8 {
+        let normalized_intensity = normalized_intensity.clamp(0.0, 1.0);
+
+        let shaped = match self {
+            VelocityCurve::Linear => normalized_intensity,
+            VelocityCurve::Power(exponent) => normalized_intensity.powf(exponent),
+        };
+
+        (shaped * 127.0).round().clamp(1.0, 127.0) as u8
+    }
 }
 
 pub type SongProgress = Weak<SongProgressInner>;
@@ -199,52 +538,82 @@ async fn midi_thread(mut connection: MidiConnection, thread_commands: Receiver<M
         // Poll both futures
         match future::or(commands_fut, deadline_timer).await {
             MidiAction::ChannelClosed => return,
-            MidiAction::NewCommand(MidiThreadCommand::PlayNote(note, duration)) => {
-                match &mut connection {
-                    MidiConnection::Disconnected { .. } => {
-                        info!(?note, ?duration, "Midi disconnected.. ignoring note");
-                    }
-                    MidiConnection::Connected { connection } => {
-                        connection
-                            .send(MidiCommand::NoteOn(note, 0b01111111).to_bytes().as_slice())
-                            .unwrap();
-
-                        let deadline = Instant::now() + duration;
-
-                        // Add the key to the deadlines
-                        note_off_deadlines.entry(deadline).or_default().insert(note);
-
-                        // Remove any previous deadlines
-                        if let Some((&instant, _)) = note_off_deadlines
-                            .range(..deadline)
-                            .find(|(_, notes)| notes.contains(&note))
-                        {
-                            note_off_deadlines.entry(instant).or_default().remove(&note);
-                        }
-                    }
-                }
+            MidiAction::NewCommand(command) => {
+                connection = handle_command(connection, &mut note_off_deadlines, command);
             }
-            MidiAction::NoteOffWake(deadline, notes) => match &mut connection {
-                MidiConnection::Disconnected { .. } => {
+            MidiAction::NoteOffWake(deadline, notes) => {
+                note_off_deadlines.remove(&deadline);
+
+                if !connection.is_connected() {
                     info!(?notes, "Midi disconnected.. ignoring note off");
+                    continue;
                 }
-                MidiConnection::Connected { connection } => {
-                    note_off_deadlines.remove(&deadline);
 
-                    for note in notes {
-                        connection
-                            .send(MidiCommand::NoteOff(note, 0b01111111).to_bytes().as_slice())
-                            .unwrap();
-                    }
+                for note in notes {
+                    connection.send(MidiCommand::NoteOff(note, 0b01111111));
                 }
-            },
+            }
+        }
+    }
+}
+
+fn handle_command(
+    mut connection: MidiConnection,
+    note_off_deadlines: &mut BTreeMap<Instant, HashSet<MidiNote>>,
+    command: MidiThreadCommand,
+) -> MidiConnection {
+    match command {
+        MidiThreadCommand::PlayNote(note, velocity, duration) => {
+            if !connection.is_connected() {
+                info!(?note, ?duration, "Midi disconnected.. ignoring note");
+
+                return connection;
+            }
+
+            connection.send(MidiCommand::NoteOn(note, velocity));
+
+            let deadline = Instant::now() + duration;
+
+            // Add the key to the deadlines
+            note_off_deadlines.entry(deadline).or_default().insert(note);
+
+            // Remove any previous deadlines
+            if let Some((&instant, _)) = note_off_deadlines
+                .range(..deadline)
+                .find(|(_, notes)| notes.contains(&note))
+            {
+                note_off_deadlines.entry(instant).or_default().remove(&note);
+            }
+
+            connection
+        }
+        MidiThreadCommand::Send(command) => {
+            connection.send(command);
+
+            connection
+        }
+        MidiThreadCommand::Connect(index, reply) => {
+            // Stop any sounding notes from hanging on the old port
+            if connection.is_connected() {
+                connection.send(MidiCommand::AllSoundOff);
+            }
+            note_off_deadlines.clear();
+
+            let (connection, connected) = connection.connect(index);
+
+            // The player may have stopped waiting for the reply
+            let _ = reply.send(connected);
+
+            connection
         }
     }
 }
 
 #[derive(Debug)]
 pub enum MidiThreadCommand {
-    PlayNote(MidiNote, Duration),
+    PlayNote(MidiNote, u8, Duration), // 7 bit velocity
+    Send(MidiCommand),
+    Connect(usize, Sender<color_eyre::Result<()>>),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -252,21 +621,60 @@ pub enum MidiCommand {
     NoteOn(MidiNote, u8),  // 7 bit velocity
     NoteOff(MidiNote, u8), // 7 bit velocity
     AllSoundOff,
-    PitchBendChange(u16), // 14 bit
+    PitchBendChange(u16),  // 14 bit
+    ControlChange(u8, u8), // 7 bit controller, 7 bit value
+    ProgramChange(u8),     // 7 bit General MIDI patch
+    /// Notes on the General MIDI percussion channel, where each note is a
+    /// different drum
+    PercussionNoteOn(MidiNote, u8), // 7 bit velocity
+    PercussionNoteOff(MidiNote, u8), // 7 bit velocity
 }
 
 impl MidiCommand {
-    pub fn to_bytes(self) -> [u8; 3] {
+    const SUSTAIN_PEDAL: u8 = 64;
+
+    /// The 14 bit pitch bend value which leaves notes unchanged
+    pub const PITCH_BEND_CENTER: u16 = 0x2000;
+
+    /// Bend by `cents`, where the full 14 bit range of the bend spans
+    /// `range_semitones` either side of the note
+    pub fn pitch_bend_cents(cents: f32, range_semitones: f32) -> Self {
+        let offset = cents / (range_semitones * 100.0) * Self::PITCH_BEND_CENTER as f32;
+
+        MidiCommand::PitchBendChange(
+            (Self::PITCH_BEND_CENTER as f32 + offset)
+                .round()
+                .clamp(0.0, 0x3FFF as f32) as u16,
+        )
+    }
+
+    /// Press or release the sustain pedal
+    pub fn sustain(on: bool) -> Self {
+        MidiCommand::ControlChange(Self::SUSTAIN_PEDAL, if on { 0b01111111 } else { 0 })
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
         #[allow(clippy::unusual_byte_groupings)]
         match self {
-            MidiCommand::NoteOn(note, velocity) => [0b1001_0000, note.as_u8(), velocity],
-            MidiCommand::NoteOff(note, velocity) => [0b1000_0000, note.as_u8(), velocity],
-            MidiCommand::AllSoundOff => [0b1011_0000, 0b0_111_1000, 0b0_000_0000],
-            MidiCommand::PitchBendChange(change) => [
+            MidiCommand::NoteOn(note, velocity) => vec![0b1001_0000, note.as_u8(), velocity],
+            MidiCommand::NoteOff(note, velocity) => vec![0b1000_0000, note.as_u8(), velocity],
+            MidiCommand::AllSoundOff => vec![0b1011_0000, 0b0_111_1000, 0b0_000_0000],
+            MidiCommand::PitchBendChange(change) => vec![
                 0b1110_0000,
                 0b01111111 & (change as u8),        // 7 LSB
                 0b01111111 & ((change >> 7) as u8), // 7 MSB
             ],
+            MidiCommand::ControlChange(controller, value) => {
+                vec![0b1011_0000, 0b01111111 & controller, 0b01111111 & value]
+            }
+            MidiCommand::ProgramChange(program) => vec![0b1100_0000, 0b01111111 & program],
+            // Channel 10, counting from one
+            MidiCommand::PercussionNoteOn(note, velocity) => {
+                vec![0b1001_1001, note.as_u8(), velocity]
+            }
+            MidiCommand::PercussionNoteOff(note, velocity) => {
+                vec![0b1000_1001, note.as_u8(), velocity]
+            }
         }
     }
 }
@@ -282,10 +690,179 @@ impl MidiNote {
     }
 
     pub fn from_piano_key(key: PianoKey) -> Self {
-        Self::new(key.number() + 20)
+        Self::new(key.midi_number())
+    }
+
+    /// The nearest note to `freq` with A4 tuned to `tuning`
+    pub fn from_frequency(freq: f32, tuning: Tuning) -> Option<Self> {
+        PianoKey::from_frequency(freq, tuning).map(Self::from_piano_key)
+    }
+
+    pub fn as_piano_key(&self) -> PianoKey {
+        PianoKey::from_midi(self.0).expect("midi notes should always be in the keys' range")
     }
 
     pub const fn as_u8(&self) -> u8 {
         self.0
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use super::{
+        handle_command, max_intensity, MidiCommand, MidiConnection, MidiNote, MidiThreadCommand,
+        VelocityCurve,
+    };
+    use crate::{
+        analysis::{KeyPress, KeyPresses},
+        key::{PianoKey, Tuning},
+    };
+
+    #[test]
+    fn reconnect_silences_sounding_notes() {
+        let mut deadlines = BTreeMap::new();
+        let note = MidiNote::new(60);
+
+        let mut connection = MidiConnection::Mock {
+            port: None,
+            sent: Vec::new(),
+        };
+
+        // Notes are dropped until connected
+        connection = handle_command(
+            connection,
+            &mut deadlines,
+            MidiThreadCommand::PlayNote(note, 100, Duration::from_secs(1)),
+        );
+        assert!(deadlines.is_empty());
+
+        let (reply, connected) = flume::bounded(1);
+        connection = handle_command(
+            connection,
+            &mut deadlines,
+            MidiThreadCommand::Connect(1, reply),
+        );
+        assert!(matches!(connected.try_recv(), Ok(Ok(()))));
+
+        connection = handle_command(
+            connection,
+            &mut deadlines,
+            MidiThreadCommand::PlayNote(note, 100, Duration::from_secs(1)),
+        );
+        assert_eq!(deadlines.len(), 1);
+
+        let (reply, _) = flume::bounded(1);
+        connection = handle_command(
+            connection,
+            &mut deadlines,
+            MidiThreadCommand::Connect(0, reply),
+        );
+        assert!(deadlines.is_empty());
+
+        match connection {
+            MidiConnection::Mock { port, sent } => {
+                assert_eq!(port, Some(0));
+                assert_eq!(
+                    sent,
+                    [MidiCommand::NoteOn(note, 100), MidiCommand::AllSoundOff]
+                );
+            }
+            _ => unreachable!("the connection should still be mocked"),
+        }
+    }
+
+    #[test]
+    fn velocity_from_intensity() {
+        let notes = BTreeMap::from([
+            (
+                PianoKey::new(40).unwrap(),
+                KeyPresses::from([KeyPress::new(0u64, Duration::from_millis(100), 2.0)]),
+            ),
+            (
+                PianoKey::new(44).unwrap(),
+                KeyPresses::from([KeyPress::new(0u64, Duration::from_millis(100), 1.0)]),
+            ),
+        ]);
+
+        let max_intensity = max_intensity(&notes);
+        assert_eq!(max_intensity, 2.0);
+
+        let loud = VelocityCurve::Linear.velocity(2.0 / max_intensity);
+        let quiet = VelocityCurve::Linear.velocity(1.0 / max_intensity);
+
+        assert_eq!(loud, 127);
+        assert_eq!(quiet, 64);
+
+        // Curves keep the loudest note at full velocity while reshaping the rest
+        assert_eq!(VelocityCurve::Power(0.5).velocity(1.0), 127);
+        assert!(VelocityCurve::Power(0.5).velocity(0.5) > quiet);
+        assert_eq!(VelocityCurve::Linear.velocity(0.0), 1);
+    }
+
+    #[test]
+    fn command_bytes() {
+        assert_eq!(MidiCommand::ProgramChange(5).to_bytes(), [0b1100_0000, 5]);
+        assert_eq!(
+            MidiCommand::sustain(true).to_bytes(),
+            [0b1011_0000, 64, 127]
+        );
+        assert_eq!(MidiCommand::sustain(false).to_bytes(), [0b1011_0000, 64, 0]);
+        assert_eq!(
+            MidiCommand::PercussionNoteOn(MidiNote::new(76), 100).to_bytes(),
+            [0b1001_1001, 76, 100]
+        );
+        assert_eq!(
+            MidiCommand::PercussionNoteOff(MidiNote::new(76), 0).to_bytes(),
+            [0b1000_1001, 76, 0]
+        );
+    }
+
+    #[test]
+    fn pitch_bend_from_cents() {
+        assert_eq!(
+            MidiCommand::pitch_bend_cents(0.0, 2.0),
+            MidiCommand::PitchBendChange(0x2000)
+        );
+        assert_eq!(
+            MidiCommand::pitch_bend_cents(100.0, 2.0),
+            MidiCommand::PitchBendChange(0x3000)
+        );
+        assert_eq!(
+            MidiCommand::pitch_bend_cents(-200.0, 2.0),
+            MidiCommand::PitchBendChange(0)
+        );
+        // Bending past the range saturates at the largest 14 bit value
+        assert_eq!(
+            MidiCommand::pitch_bend_cents(300.0, 2.0),
+            MidiCommand::PitchBendChange(0x3FFF)
+        );
+    }
+
+    #[test]
+    fn full_note_range() {
+        let lowest = MidiNote::new(0);
+        let highest = MidiNote::new(127);
+
+        assert_eq!(
+            MidiNote::from_frequency(8.176, Tuning::CONCERT),
+            Some(lowest)
+        );
+        assert_eq!(
+            MidiNote::from_frequency(12543.854, Tuning::CONCERT),
+            Some(highest)
+        );
+        assert_eq!(
+            MidiNote::from_frequency(440.0, Tuning::CONCERT),
+            Some(MidiNote::new(69))
+        );
+        assert_eq!(MidiNote::from_frequency(4.0, Tuning::CONCERT), None);
+
+        // Both ends survive the trip through a key
+        for note in [lowest, highest] {
+            assert_eq!(MidiNote::from_piano_key(note.as_piano_key()), note);
+            assert!(!note.as_piano_key().is_on_piano());
+        }
+    }
+}
@@ -0,0 +1,482 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, ensure, eyre, WrapErr};
+
+use crate::{
+    analysis::{KeyPress, KeyPresses},
+    key::PianoKey,
+    midi::MidiNote,
+};
+
+/// Ticks per quarter note used when exporting. Together with the tempo this
+/// makes every tick exactly one millisecond.
+const EXPORT_DIVISION: u16 = 1000;
+/// Microseconds per quarter note used when exporting
+const EXPORT_TEMPO: u32 = 1_000_000;
+/// Microseconds per quarter note until a file sets its own tempo
+const DEFAULT_TEMPO: u32 = 500_000;
+
+const EXPORT_VELOCITY: u8 = 0b01111111;
+
+/// Write the notes to a single track, type 0 standard midi file
+pub fn export_midi(notes: &BTreeMap<PianoKey, KeyPresses>, path: &Path) -> color_eyre::Result<()> {
+    fs::write(path, to_midi_bytes(notes))
+        .wrap_err_with(|| format!("unable to write midi file {}", path.display()))
+}
+
+pub fn to_midi_bytes(notes: &BTreeMap<PianoKey, KeyPresses>) -> Vec<u8> {
+    // (tick, is note on, note), sorting note offs before note ons on the same tick
+    let mut events = notes
+        .iter()
+        .flat_map(|(&key, presses)| {
+            let note = MidiNote::from_piano_key(key);
+
+            presses.iter().flat_map(move |press| {
+                let start = press.start() as u64;
+                let end = start + press.duration().as_millis() as u64;
+
+                [(start, true, note), (end, false, note)]
+            })
+        })
+        .collect::<Vec<_>>();
+    events.sort();
+
+    let mut track = Vec::new();
+
+    // Tempo meta event
+    write_variable_length(&mut track, 0);
+    track.extend_from_slice(&[0xFF, 0x51, 0x03]);
+    track.extend_from_slice(&EXPORT_TEMPO.to_be_bytes()[1..]);
+
+    let mut previous_tick = 0;
+    for (tick, is_note_on, note) in events {
+        write_variable_length(&mut track, (tick - previous_tick) as u32);
+        previous_tick = tick;
+
+        if is_note_on {
+            track.extend_from_slice(&[0x90, note.as_u8(), EXPORT_VELOCITY]);
+        } else {
+            track.extend_from_slice(&[0x80, note.as_u8(), 0]);
+        }
+    }
+
+    // End of track meta event
+    write_variable_length(&mut track, 0);
+    track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+
+    let mut bytes = Vec::with_capacity(22 + track.len());
+    bytes.extend_from_slice(b"MThd");
+    bytes.extend_from_slice(&6u32.to_be_bytes());
+    bytes.extend_from_slice(&0u16.to_be_bytes());
+    bytes.extend_from_slice(&1u16.to_be_bytes());
+    bytes.extend_from_slice(&EXPORT_DIVISION.to_be_bytes());
+    bytes.extend_from_slice(b"MTrk");
+    bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&track);
+
+    bytes
+}
+
+fn write_variable_length(bytes: &mut Vec<u8>, mut value: u32) {
+    let mut groups = vec![(value & 0x7F) as u8];
+    value >>= 7;
+
+    while value > 0 {
+        groups.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+
+    bytes.extend(groups.into_iter().rev());
+}
+
+/// Read the notes from every track of a standard midi file
+pub fn import_midi(path: &Path) -> color_eyre::Result<BTreeMap<PianoKey, KeyPresses>> {
+    let bytes =
+        fs::read(path).wrap_err_with(|| format!("unable to read midi file {}", path.display()))?;
+
+    from_midi_bytes(&bytes).wrap_err_with(|| format!("invalid midi file {}", path.display()))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Event {
+    Tempo(u32),
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    NoteOff { channel: u8, note: u8 },
+}
+
+pub fn from_midi_bytes(bytes: &[u8]) -> color_eyre::Result<BTreeMap<PianoKey, KeyPresses>> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    ensure!(reader.take(4)? == b"MThd", "missing midi header");
+    let header_length = reader.u32()? as usize;
+    ensure!(header_length >= 6, "midi header is too short");
+
+    let _format = reader.u16()?;
+    let tracks = reader.u16()?;
+    let division = reader.u16()?;
+    reader.take(header_length - 6)?;
+
+    ensure!(division & 0x7FFF != 0, "midi file has a time division of 0");
+
+    // SMPTE timing, negative frames per second and ticks per frame
+    let smpte = division & 0x8000 != 0;
+    let frames_per_second = -i16::from((division >> 8) as u8 as i8);
+    let ticks_per_frame = division & 0xFF;
+    if smpte {
+        ensure!(
+            [24, 25, 29, 30].contains(&frames_per_second),
+            "midi file has an unsupported SMPTE frame rate of {frames_per_second}"
+        );
+        ensure!(
+            ticks_per_frame != 0,
+            "midi file has 0 ticks per SMPTE frame"
+        );
+    }
+
+    // (tick, order in file, event) from every track, so tempo changes in one
+    // track apply to the notes in all of them
+    let mut events = Vec::new();
+
+    for _ in 0..tracks {
+        // Skip over any unknown chunks
+        let track = loop {
+            let id = reader.take(4)?;
+            let length = reader.u32()? as usize;
+            let chunk = reader.take(length)?;
+
+            if id == b"MTrk" {
+                break chunk;
+            }
+        };
+
+        read_track(track, &mut events)?;
+    }
+
+    events.sort_by_key(|&(tick, order, _)| (tick, order));
+
+    let ticks_to_micros = |tick_delta: u64, tempo: u32| -> f64 {
+        if smpte {
+            tick_delta as f64 * 1_000_000.0 / (frames_per_second as f64 * ticks_per_frame as f64)
+        } else {
+            tick_delta as f64 * tempo as f64 / division as f64
+        }
+    };
+
+    let mut tempo = DEFAULT_TEMPO;
+    let mut previous_tick = 0;
+    let mut micros = 0.0;
+
+    let mut held = HashMap::<(u8, u8), (f64, u8)>::new();
+    let mut notes = BTreeMap::<PianoKey, KeyPresses>::new();
+
+    for (tick, _, event) in events {
+        micros += ticks_to_micros(tick - previous_tick, tempo);
+        previous_tick = tick;
+
+        match event {
+            Event::Tempo(new_tempo) => tempo = new_tempo,
+            Event::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => {
+                held.entry((channel, note)).or_insert((micros, velocity));
+            }
+            Event::NoteOff { channel, note } => {
+                let (start, velocity) = match held.remove(&(channel, note)) {
+                    Some(held) => held,
+                    None => continue,
+                };
+
+                let key = MidiNote::new(note).as_piano_key();
+
+                notes.entry(key).or_default().add(KeyPress::new(
+                    (start / 1000.0).round() as u64,
+                    Duration::from_millis(((micros - start) / 1000.0).round() as u64),
+                    velocity as f32 / 127.0,
+                ));
+            }
+        }
+    }
+
+    Ok(notes)
+}
+
+fn read_track(track: &[u8], events: &mut Vec<(u64, usize, Event)>) -> color_eyre::Result<()> {
+    let mut reader = Reader {
+        bytes: track,
+        position: 0,
+    };
+
+    let mut tick = 0;
+    let mut running_status = None;
+
+    while !reader.is_empty() {
+        tick += reader.variable_length()? as u64;
+
+        let mut status = reader.u8()?;
+
+        match status {
+            // Meta event
+            0xFF => {
+                let kind = reader.u8()?;
+                let length = reader.variable_length()? as usize;
+                let data = reader.take(length)?;
+
+                match (kind, data) {
+                    (0x2F, _) => break,
+                    (0x51, &[a, b, c]) => {
+                        let tempo = u32::from_be_bytes([0, a, b, c]);
+
+                        events.push((tick, events.len(), Event::Tempo(tempo)));
+                    }
+                    _ => {}
+                }
+
+                continue;
+            }
+            // System exclusive
+            0xF0 | 0xF7 => {
+                let length = reader.variable_length()? as usize;
+                reader.take(length)?;
+
+                continue;
+            }
+            // Running status, reuse the previous status with this as the first data byte
+            0x00..=0x7F => {
+                status = running_status.ok_or_else(|| eyre!("data byte without a status"))?;
+                reader.position -= 1;
+            }
+            0x80..=0xEF => running_status = Some(status),
+            _ => bail!("unsupported midi status byte {status:#04X}"),
+        }
+
+        let channel = status & 0x0F;
+
+        match status & 0xF0 {
+            0x80 => {
+                let note = reader.u8()? & 0x7F;
+                reader.u8()?;
+
+                events.push((tick, events.len(), Event::NoteOff { channel, note }));
+            }
+            0x90 => {
+                let note = reader.u8()? & 0x7F;
+                let velocity = reader.u8()?;
+
+                // A note on with no velocity is the same as a note off
+                let event = if velocity == 0 {
+                    Event::NoteOff { channel, note }
+                } else {
+                    Event::NoteOn {
+                        channel,
+                        note,
+                        velocity,
+                    }
+                };
+
+                events.push((tick, events.len(), event));
+            }
+            0xC0 | 0xD0 => {
+                reader.u8()?;
+            }
+            _ => {
+                reader.take(2)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    position: usize,
+}
+
+impl<'b> Reader<'b> {
+    fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn take(&mut self, length: usize) -> color_eyre::Result<&'b [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + length)
+            .ok_or_else(|| eyre!("unexpected end of midi data"))?;
+        self.position += length;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> color_eyre::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> color_eyre::Result<u16> {
+        let bytes = self.take(2)?;
+
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> color_eyre::Result<u32> {
+        let bytes = self.take(4)?;
+
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn variable_length(&mut self) -> color_eyre::Result<u32> {
+        let mut value = 0u32;
+
+        // Variable length quantities are at most 4 bytes long
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        bail!("variable length quantity is longer than 4 bytes")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use super::{from_midi_bytes, to_midi_bytes, write_variable_length};
+    use crate::{
+        analysis::{KeyPress, KeyPresses},
+        key::PianoKey,
+    };
+
+    #[test]
+    fn variable_length() {
+        for (value, expected) in [
+            (0, &[0x00][..]),
+            (0x7F, &[0x7F][..]),
+            (0x80, &[0x81, 0x00][..]),
+            (0x3FFF, &[0xFF, 0x7F][..]),
+            (0x0FFF_FFFF, &[0xFF, 0xFF, 0xFF, 0x7F][..]),
+        ] {
+            let mut bytes = Vec::new();
+            write_variable_length(&mut bytes, value);
+
+            assert_eq!(bytes, expected);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let notes = BTreeMap::from([
+            (
+                PianoKey::new(40).unwrap(),
+                KeyPresses::from([
+                    KeyPress::new(0u64, Duration::from_millis(250), 1.0),
+                    KeyPress::new(500u64, Duration::from_millis(250), 1.0),
+                ]),
+            ),
+            (
+                PianoKey::new(44).unwrap(),
+                KeyPresses::from([KeyPress::new(250u64, Duration::from_millis(1000), 1.0)]),
+            ),
+            (
+                PianoKey::new(88).unwrap(),
+                KeyPresses::from([KeyPress::new(1234u64, Duration::from_millis(5), 1.0)]),
+            ),
+        ]);
+
+        let imported = from_midi_bytes(&to_midi_bytes(&notes)).unwrap();
+
+        assert_eq!(imported.len(), notes.len());
+        for ((key, presses), (imported_key, imported_presses)) in notes.iter().zip(&imported) {
+            assert_eq!(key, imported_key);
+            assert!(presses.iter().eq(imported_presses.iter()));
+        }
+    }
+
+    #[test]
+    fn running_status_and_extreme_notes() {
+        #[rustfmt::skip]
+        let track = [
+            // Note on for middle C, then a note below the piano using running status
+            0x00, 0x90, 60, 100,
+            0x00, 0, 100,
+            // Note offs as note ons with no velocity, a quarter note later
+            0x83, 0x60, 60, 0,
+            0x00, 0, 0,
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"MThd");
+        bytes.extend_from_slice(&[0, 0, 0, 6, 0, 0, 0, 1, 0x01, 0xE0]);
+        bytes.extend_from_slice(b"MTrk");
+        bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&track);
+
+        let imported = from_midi_bytes(&bytes).unwrap();
+
+        // 480 ticks per quarter at the default 120bpm
+        let c4 = PianoKey::new(40).unwrap();
+        let c_minus_1 = PianoKey::from_midi(0).unwrap();
+        assert_eq!(imported.keys().collect::<Vec<_>>(), [&c_minus_1, &c4]);
+
+        for key in [c_minus_1, c4] {
+            assert_eq!(
+                imported[&key].iter().collect::<Vec<_>>(),
+                [KeyPress::new(
+                    0u64,
+                    Duration::from_millis(500),
+                    100.0 / 127.0
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn smpte_timing() {
+        #[rustfmt::skip]
+        let track = [
+            // Middle C held for 250 ticks
+            0x00, 0x90, 60, 127,
+            0x81, 0x7A, 0x80, 60, 0,
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+
+        let midi_bytes = |division: u16| {
+            let mut bytes = Vec::new();
+            bytes.extend_from_slice(b"MThd");
+            bytes.extend_from_slice(&[0, 0, 0, 6, 0, 0, 0, 1]);
+            bytes.extend_from_slice(&division.to_be_bytes());
+            bytes.extend_from_slice(b"MTrk");
+            bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&track);
+
+            bytes
+        };
+
+        // 25 frames per second of 40 ticks each, so a tick is a millisecond
+        let imported = from_midi_bytes(&midi_bytes(0xE728)).unwrap();
+        assert_eq!(
+            imported[&PianoKey::new(40).unwrap()]
+                .iter()
+                .collect::<Vec<_>>(),
+            [KeyPress::new(0u64, Duration::from_millis(250), 1.0)]
+        );
+
+        // -128 frames per second, which must not overflow when negated
+        assert!(from_midi_bytes(&midi_bytes(0x8028)).is_err());
+        // An unsupported frame rate
+        assert!(from_midi_bytes(&midi_bytes(0xE928)).is_err());
+        // No ticks in each frame
+        assert!(from_midi_bytes(&midi_bytes(0xE700)).is_err());
+    }
+}
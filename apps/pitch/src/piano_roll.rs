@@ -1,7 +1,14 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    mem,
+    ops::{Range, RangeInclusive},
+    sync::Arc,
+    time::Duration,
+};
 
 use eframe::{
-    egui::{Frame, Id, Response, ScrollArea, Sense, TextFormat, Ui, Widget},
+    egui::{CursorIcon, Frame, Id, Key, Response, ScrollArea, Sense, TextFormat, Ui, Widget},
     emath::{Align, Align2},
     epaint::{
         text::LayoutJob, Color32, FontId, Fonts, Galley, Pos2, Rect, Rounding, Shape, Stroke,
@@ -10,50 +17,311 @@ use eframe::{
 };
 
 use crate::{
-    analysis::KeyPresses,
-    key::{Accidental, MusicalNote, PianoKey},
-    midi::MidiPlayer,
+    analysis::{KeyPress, KeyPresses, KeyStart},
+    history::History,
+    key::{cents_between, Accidental, MusicalNote, PianoKey, Scale, Tuning},
+    midi::{max_intensity, MidiPlayer},
 };
 
-pub struct PianoRoll<'player, 'keys, 'spectrum> {
+pub struct PianoRoll<'player, 'keys, 'spectrum, 'follow> {
     // TODO: scales?
     preference: Accidental,
+    scale: Option<Scale>,
 
     key_height: f32,
     seconds_per_width: f32, // TODO: less jank
 
     cursor: Option<f32>,
+    /// Whether to keep the cursor in view, which is turned off once the user
+    /// scrolls by hand
+    follow_cursor: Option<&'follow mut bool>,
 
     midi: &'player MidiPlayer,
 
-    keys: &'keys BTreeMap<PianoKey, KeyPresses>,
+    keys: Notes<'keys>,
+    /// Edits made while drawing, applied to the notes once they are drawn
+    edits: RefCell<Vec<NoteEdit>>,
+    /// Where edits are recorded to be undone, if anywhere
+    history: Option<&'keys mut History>,
+    /// The notes selected for deletion, kept in egui's memory between frames
+    selection: BTreeSet<(PianoKey, KeyStart)>,
     spectrum: Option<&'spectrum TextureHandle>,
+
+    pitch_grid: PitchGrid,
+    /// The tuning of the keys in each row, which the pitch grid is drawn
+    /// against
+    tuning: Tuning,
+}
+
+/// Faint lines across the rows of a [`PianoRoll`], to see how far notes found
+/// between keys are out of tune
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PitchGrid {
+    Off,
+    /// A line at the concert pitch of each key, which is off the middle of its
+    /// row when the keys are tuned some other way
+    ConcertPitch,
+    /// Lines every this many cents within each key, with the key's exact
+    /// frequency in the middle of its row
+    Cents(u8),
+}
+
+impl PitchGrid {
+    /// The frequencies of the lines drawn across the row of `key`, stopping
+    /// short of the edges of the row half a semitone either side
+    fn frequencies(&self, key: PianoKey, tuning: Tuning) -> Vec<f32> {
+        match *self {
+            PitchGrid::Off => Vec::new(),
+            PitchGrid::ConcertPitch => vec![key.concert_pitch()],
+            PitchGrid::Cents(step) => {
+                let step = step.max(1) as i32;
+                let lines = 49 / step;
+
+                (-lines..=lines)
+                    .map(|line| key.frequency(tuning) * 2.0f32.powf((line * step) as f32 / 1200.0))
+                    .collect()
+            }
+        }
+    }
+
+    /// Whether the lines are far enough apart with rows `key_height` high to
+    /// be worth drawing, rather than crowding the rows
+    fn is_visible(&self, key_height: f32) -> bool {
+        match *self {
+            PitchGrid::Off => false,
+            PitchGrid::ConcertPitch => key_height >= MIN_GRID_SPACING,
+            PitchGrid::Cents(step) => key_height * step.max(1) as f32 / 100.0 >= MIN_GRID_SPACING,
+        }
+    }
+}
+
+/// The notes shown in a [`PianoRoll`], which can only be edited when borrowed
+/// mutably
+pub enum Notes<'keys> {
+    ReadOnly(&'keys BTreeMap<PianoKey, KeyPresses>),
+    Editable(&'keys mut BTreeMap<PianoKey, KeyPresses>),
+}
+
+impl<'keys> From<&'keys BTreeMap<PianoKey, KeyPresses>> for Notes<'keys> {
+    fn from(keys: &'keys BTreeMap<PianoKey, KeyPresses>) -> Self {
+        Self::ReadOnly(keys)
+    }
+}
+
+impl<'keys> From<&'keys mut BTreeMap<PianoKey, KeyPresses>> for Notes<'keys> {
+    fn from(keys: &'keys mut BTreeMap<PianoKey, KeyPresses>) -> Self {
+        Self::Editable(keys)
+    }
+}
+
+/// A change made to the notes by hand in the piano roll
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoteEdit {
+    Add {
+        key: PianoKey,
+        keypress: KeyPress,
+    },
+    Remove {
+        key: PianoKey,
+        start: KeyStart,
+    },
+    Resize {
+        key: PianoKey,
+        start: KeyStart,
+        duration: Duration,
+    },
+}
+
+impl NoteEdit {
+    /// The key whose notes this edit changes
+    pub fn key(&self) -> PianoKey {
+        match *self {
+            NoteEdit::Add { key, .. }
+            | NoteEdit::Remove { key, .. }
+            | NoteEdit::Resize { key, .. } => key,
+        }
+    }
+
+    pub fn apply(&self, keys: &mut BTreeMap<PianoKey, KeyPresses>) {
+        match *self {
+            NoteEdit::Add { key, keypress } => keys.entry(key).or_default().add(keypress),
+            NoteEdit::Remove { key, start } => {
+                if let Some(presses) = keys.get_mut(&key) {
+                    presses.remove(start);
+
+                    if presses.is_empty() {
+                        keys.remove(&key);
+                    }
+                }
+            }
+            NoteEdit::Resize {
+                key,
+                start,
+                duration,
+            } => {
+                if let Some(presses) = keys.get_mut(&key) {
+                    presses.set_duration(start, duration);
+                }
+            }
+        }
+    }
+}
+
+/// How long a note added by clicking lasts
+const NEW_NOTE_DURATION: Duration = Duration::from_millis(250);
+
+/// The shortest a note can be resized to
+const MIN_NOTE_DURATION: Duration = Duration::from_millis(10);
+
+/// How close to the end of a note the pointer has to be to resize it
+const END_GRAB_DISTANCE: f32 = 4.0;
+
+/// The closest the lines of a [`PitchGrid`] are drawn together
+const MIN_GRID_SPACING: f32 = 4.0;
+
+/// The time in seconds and the row, counting down from the top, at `offset`
+/// from the top left of the grid
+fn grid_position(offset: Vec2, seconds_per_width: f32, key_height: f32) -> Option<(f32, usize)> {
+    (offset.x >= 0.0 && offset.y >= 0.0).then_some((
+        offset.x / seconds_per_width,
+        (offset.y / key_height) as usize,
+    ))
+}
+
+/// How far down from the top of the grid `frequency` is drawn, when the top row
+/// is `highest` and every row is a key of `tuning`. The exact frequency of a
+/// key is in the middle of its row.
+fn frequency_y(frequency: f32, tuning: Tuning, highest: PianoKey, key_height: f32) -> f32 {
+    let semitones_below = cents_between(frequency, highest.frequency(tuning)) / 100.0;
+
+    (semitones_below + 0.5) * key_height
+}
+
+/// Whether `pointer` is over the end of the note drawn in `note`, where
+/// dragging resizes it
+fn grabs_end(note: Rect, pointer: Pos2) -> bool {
+    (note.right() - pointer.x).abs() <= END_GRAB_DISTANCE && note.y_range().contains(&pointer.y)
+}
+
+/// Every note of a key within `pitches` sounding at some point during `times`
+/// in seconds
+fn notes_in(
+    keys: &BTreeMap<PianoKey, KeyPresses>,
+    times: Range<f32>,
+    pitches: RangeInclusive<PianoKey>,
+) -> BTreeSet<(PianoKey, KeyStart)> {
+    keys.range(pitches)
+        .flat_map(|(&key, presses)| {
+            presses
+                .iter()
+                .filter(|keypress| {
+                    keypress.start_secs() < times.end && keypress.end_secs() > times.start
+                })
+                .map(move |keypress| (key, keypress.start()))
+        })
+        .collect()
+}
+
+/// The keypress sounding at `time` in seconds
+fn note_at(presses: &KeyPresses, time: f32) -> Option<KeyPress> {
+    presses
+        .iter()
+        .find(|keypress| (keypress.start_secs()..keypress.end_secs()).contains(&time))
 }
 
-impl<'player, 'keys, 'spectrum> PianoRoll<'player, 'keys, 'spectrum> {
+impl<'player, 'keys, 'spectrum, 'follow> PianoRoll<'player, 'keys, 'spectrum, 'follow> {
     // TODO: builder
     pub fn new(
         midi: &'player MidiPlayer,
         preference: Accidental,
+        scale: Option<Scale>,
         cursor: Option<f32>,
         key_height: f32,
         seconds_per_width: f32,
-        keys: &'keys BTreeMap<PianoKey, KeyPresses>,
+        keys: impl Into<Notes<'keys>>,
         spectrum: Option<&'spectrum TextureHandle>,
     ) -> Self {
         Self {
             key_height,
-            keys,
+            keys: keys.into(),
+            edits: Default::default(),
+            history: None,
+            selection: BTreeSet::new(),
             midi,
             preference,
+            scale,
             seconds_per_width,
             cursor,
+            follow_cursor: None,
             spectrum,
+            pitch_grid: PitchGrid::Off,
+            tuning: Tuning::CONCERT,
         }
     }
+
+    /// Draw `grid` over the rows of keys tuned to `tuning`
+    pub fn pitch_grid(mut self, grid: PitchGrid, tuning: Tuning) -> Self {
+        self.pitch_grid = grid;
+        self.tuning = tuning;
+        self
+    }
+
+    /// Record every edit to the notes in `history`, so they can be undone
+    pub fn history(mut self, history: &'keys mut History) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Scroll to keep the cursor in view while `follow` is set, clearing it
+    /// if the user scrolls by hand
+    pub fn follow_cursor(mut self, follow: &'follow mut bool) -> Self {
+        self.follow_cursor = Some(follow);
+        self
+    }
 }
 
-impl PianoRoll<'_, '_, '_> {
+impl PianoRoll<'_, '_, '_, '_> {
+    fn notes(&self) -> &BTreeMap<PianoKey, KeyPresses> {
+        match &self.keys {
+            Notes::ReadOnly(keys) => keys,
+            Notes::Editable(keys) => keys,
+        }
+    }
+
+    fn is_editable(&self) -> bool {
+        matches!(self.keys, Notes::Editable(_))
+    }
+
+    /// The lowest and highest keys given a row, which covers the whole piano
+    /// and any notes beyond it
+    fn key_range(&self) -> (PianoKey, PianoKey) {
+        let mut piano = PianoKey::all();
+        let (highest, lowest) = (piano.next().unwrap(), piano.next_back().unwrap());
+
+        let mut keys = self.notes().keys().copied();
+
+        (
+            keys.next().map_or(lowest, |key| key.min(lowest)),
+            keys.next_back().map_or(highest, |key| key.max(highest)),
+        )
+    }
+
+    /// The keys given a row, from the top row down
+    fn rows(&self) -> impl Iterator<Item = PianoKey> {
+        let (lowest, highest) = self.key_range();
+
+        PianoKey::all_midi()
+            .skip_while(move |&key| key > highest)
+            .take_while(move |&key| key >= lowest)
+    }
+
+    /// The row that `key` is drawn in
+    fn row(&self, key: PianoKey) -> usize {
+        let (_, highest) = self.key_range();
+
+        (highest.midi_number() - key.midi_number()) as usize
+    }
+
     fn layout_key(fonts: &Fonts, note: &MusicalNote, height: f32) -> Arc<Galley> {
         let mut job = LayoutJob::default();
 
@@ -106,7 +374,7 @@ impl PianoRoll<'_, '_, '_> {
         top_left: Pos2,
         allocated_space: &'s mut Vec2,
     ) -> impl Iterator<Item = Shape> + 's {
-        PianoKey::all().enumerate().map(move |(row, key)| {
+        self.rows().enumerate().map(move |(row, key)| {
             let y = row as f32 * self.key_height;
             // The top left of this key's row
             let top_left = top_left + Vec2::new(0.0, y);
@@ -133,7 +401,10 @@ impl PianoRoll<'_, '_, '_> {
                     let galley = Self::layout_key(&ui.fonts(), &note, 20.0);
                     ui.label(galley);
 
-                    ui.label(format!("Key #{}", key.number()));
+                    if key.is_on_piano() {
+                        ui.label(format!("Key #{}", key.number()));
+                    }
+                    ui.label(format!("MIDI note {}", key.midi_number()));
                 });
 
             if response.hovered() {
@@ -151,7 +422,7 @@ impl PianoRoll<'_, '_, '_> {
         margin: Vec2,
         size: Vec2,
     ) -> impl Iterator<Item = Shape> + '_ {
-        PianoKey::all().enumerate().flat_map(move |(row, key)| {
+        self.rows().enumerate().flat_map(move |(row, key)| {
             let y = row as f32 * self.key_height;
 
             let top_left = Pos2::new(0.0, y) + drawing_window.min.to_vec2() + margin;
@@ -159,15 +430,19 @@ impl PianoRoll<'_, '_, '_> {
             let rect = Rect::from_min_size(top_left, Vec2::new(size.x, self.key_height));
 
             [
-                Shape::rect_filled(
-                    rect,
-                    Rounding::none(),
+                Shape::rect_filled(rect, Rounding::none(), {
+                    let color = if self.scale.map_or(false, |scale| scale.contains(key)) {
+                        Color32::LIGHT_BLUE
+                    } else {
+                        Color32::WHITE
+                    };
+
                     if key.is_white() {
-                        Color32::WHITE.linear_multiply(0.5)
+                        color.linear_multiply(0.5)
                     } else {
-                        Color32::WHITE.linear_multiply(0.05)
-                    },
-                ),
+                        color.linear_multiply(0.05)
+                    }
+                }),
                 // TODO: make it look better
                 Shape::rect_stroke(
                     rect,
@@ -178,6 +453,33 @@ impl PianoRoll<'_, '_, '_> {
         })
     }
 
+    fn draw_pitch_grid(
+        &self,
+        drawing_window: Rect,
+        margin: Vec2,
+        size: Vec2,
+    ) -> impl Iterator<Item = Shape> + '_ {
+        let (_, highest) = self.key_range();
+        let offset = margin + drawing_window.min.to_vec2();
+
+        // Too many lines would only cover up the rows
+        let visible = self.pitch_grid.is_visible(self.key_height);
+
+        self.rows().filter(move |_| visible).flat_map(move |key| {
+            self.pitch_grid
+                .frequencies(key, self.tuning)
+                .into_iter()
+                .map(move |frequency| {
+                    let y = frequency_y(frequency, self.tuning, highest, self.key_height);
+
+                    Shape::line_segment(
+                        [Pos2::new(0.0, y) + offset, Pos2::new(size.x, y) + offset],
+                        Stroke::new(1.0, Color32::GRAY.linear_multiply(0.25)),
+                    )
+                })
+        })
+    }
+
     // TODO: CULLING
     fn draw_notes<'s>(
         &'s self,
@@ -185,8 +487,8 @@ impl PianoRoll<'_, '_, '_> {
         drawing_window: Rect,
         margin: Vec2,
     ) -> impl Iterator<Item = Shape> + 's {
-        self.keys.iter().flat_map(move |(&key, key_presses)| {
-            let y = (PianoKey::all().len() as u8 - key.number()) as f32 * self.key_height;
+        self.notes().iter().flat_map(move |(&key, key_presses)| {
+            let y = self.row(key) as f32 * self.key_height;
 
             key_presses.iter().flat_map(move |keypress| {
                 let rect = Rect::from_min_size(
@@ -218,22 +520,49 @@ impl PianoRoll<'_, '_, '_> {
                             keypress.duration_secs()
                         ));
                         ui.label(format!("Intensity: {}", keypress.intensity()));
+                        ui.label(format!("Confidence: {:.0}%", keypress.confidence() * 100.0));
                     });
 
                 if response.clicked() {
-                    self.midi.play_piano(key, keypress.duration())
+                    self.midi.play_piano(
+                        key,
+                        keypress.duration(),
+                        self.midi
+                            .velocity(keypress.intensity(), max_intensity(self.notes())),
+                    )
+                }
+
+                if self.is_editable() && response.secondary_clicked() {
+                    self.edits.borrow_mut().push(NoteEdit::Remove {
+                        key,
+                        start: keypress.start(),
+                    });
+                }
+
+                if self.is_editable() {
+                    self.resize_note(
+                        ui,
+                        &response,
+                        rect,
+                        drawing_window.min + margin,
+                        key,
+                        keypress,
+                    );
                 }
 
                 [
                     Shape::rect_filled(
                         rect,
                         Rounding::same(2.0),
-                        if self.cursor >= Some(keypress.start_secs()) {
+                        if self.selection.contains(&(key, keypress.start())) {
+                            Color32::GOLD
+                        } else if self.cursor >= Some(keypress.start_secs()) {
                             Color32::GREEN
                         } else if response.hovered() {
                             Color32::LIGHT_RED
                         } else {
-                            Color32::RED
+                            // Fade out notes which may just be noise
+                            Color32::RED.linear_multiply(0.25 + 0.75 * keypress.confidence())
                         },
                     ),
                     Shape::rect_stroke(rect, Rounding::same(2.0), Stroke::new(2.0, Color32::KHAKI)),
@@ -242,6 +571,166 @@ impl PianoRoll<'_, '_, '_> {
         })
     }
 
+    /// Drag the end of the note drawn in `rect` to change its duration
+    fn resize_note(
+        &self,
+        ui: &Ui,
+        response: &Response,
+        rect: Rect,
+        origin: Pos2,
+        key: PianoKey,
+        keypress: KeyPress,
+    ) {
+        // Notes keep their id while resizing, since their start does not move
+        let resizing = response.id.with("resizing");
+
+        if response.drag_started() {
+            let grabbed = response
+                .interact_pointer_pos()
+                .map_or(false, |pointer| grabs_end(rect, pointer));
+
+            ui.memory().data.insert_temp(resizing, grabbed);
+        }
+
+        let grabbed = ui.memory().data.get_temp::<bool>(resizing).unwrap_or(false);
+
+        if grabbed
+            || response
+                .hover_pos()
+                .map_or(false, |pointer| grabs_end(rect, pointer))
+        {
+            ui.output().cursor_icon = CursorIcon::ResizeHorizontal;
+        }
+
+        if grabbed && response.dragged() {
+            if let Some(pointer) = response.interact_pointer_pos() {
+                let end = (pointer.x - origin.x) / self.seconds_per_width;
+                let duration = Duration::from_secs_f32((end - keypress.start_secs()).max(0.0))
+                    .max(MIN_NOTE_DURATION);
+
+                self.edits.borrow_mut().push(NoteEdit::Resize {
+                    key,
+                    start: keypress.start(),
+                    duration,
+                });
+            }
+        }
+    }
+
+    /// Add a note to the empty space under a click on the grid
+    fn add_note(&self, response: &Response, origin: Pos2) {
+        let position = response
+            .interact_pointer_pos()
+            .and_then(|pointer| {
+                grid_position(pointer - origin, self.seconds_per_width, self.key_height)
+            })
+            .and_then(|(time, row)| Some((time, self.rows().nth(row)?)));
+
+        if let Some((time, key)) = position {
+            let occupied = self
+                .notes()
+                .get(&key)
+                .and_then(|presses| note_at(presses, time))
+                .is_some();
+
+            if !occupied {
+                self.edits.borrow_mut().push(NoteEdit::Add {
+                    key,
+                    keypress: KeyPress::new(
+                        (time * 1000.0).round() as u64,
+                        NEW_NOTE_DURATION,
+                        max_intensity(self.notes()).max(f32::EPSILON),
+                    ),
+                });
+            }
+        }
+    }
+
+    /// Drag over empty space to select every note touched by the region
+    fn select_region(&mut self, ui: &Ui, response: &Response, origin: Pos2) {
+        let drag_start = Id::new("piano_roll_region");
+
+        if response.drag_started() {
+            if let Some(start) = ui.input().pointer.press_origin() {
+                ui.memory().data.insert_temp(drag_start, start);
+            }
+        }
+
+        let region = match (
+            ui.memory().data.get_temp::<Pos2>(drag_start),
+            ui.input().pointer.interact_pos(),
+        ) {
+            (Some(start), Some(end)) if response.dragged() || response.drag_released() => {
+                Rect::from_two_pos(start, end)
+            }
+            _ => return,
+        };
+
+        if response.dragged() {
+            ui.painter().rect(
+                region,
+                Rounding::same(2.0),
+                Color32::GOLD.linear_multiply(0.1),
+                Stroke::new(1.0, Color32::GOLD),
+            );
+        }
+
+        if response.drag_released() {
+            ui.memory().data.remove::<Pos2>(drag_start);
+
+            let region = region.translate(-origin.to_vec2());
+            let (lowest, _) = self.key_range();
+            let top = self
+                .rows()
+                .nth((region.min.y.max(0.0) / self.key_height) as usize);
+            let bottom = self
+                .rows()
+                .nth((region.max.y.max(0.0) / self.key_height) as usize)
+                .unwrap_or(lowest);
+
+            self.selection = match top {
+                Some(top) => notes_in(
+                    self.notes(),
+                    region.min.x / self.seconds_per_width..region.max.x / self.seconds_per_width,
+                    bottom..=top,
+                ),
+                None => BTreeSet::new(),
+            };
+        }
+    }
+
+    /// Remove every selected note
+    fn delete_selection(&mut self) {
+        let selection = mem::take(&mut self.selection);
+
+        self.edits.borrow_mut().extend(
+            selection
+                .into_iter()
+                .map(|(key, start)| NoteEdit::Remove { key, start }),
+        );
+    }
+
+    /// Apply the edits made while drawing, returning whether there were any
+    fn apply_edits(&mut self) -> bool {
+        let edits = self.edits.take();
+
+        match &mut self.keys {
+            Notes::Editable(keys) => {
+                match &mut self.history {
+                    Some(history) => history.edit_all(&edits, keys),
+                    None => {
+                        for edit in &edits {
+                            edit.apply(keys);
+                        }
+                    }
+                }
+
+                !edits.is_empty()
+            }
+            Notes::ReadOnly(_) => false,
+        }
+    }
+
     fn draw_time_ui<'s>(
         &'s self,
         ui: &'s Ui,
@@ -287,54 +776,75 @@ impl PianoRoll<'_, '_, '_> {
     }
 }
 
-impl PianoRoll<'_, '_, '_> {}
+impl PianoRoll<'_, '_, '_, '_> {}
+
+/// The horizontal scale in points per second and the key height which frame
+/// every one of `keys` within `viewport`, or [`None`] if there are no notes to
+/// frame
+pub fn zoom_to_fit(keys: &BTreeMap<PianoKey, KeyPresses>, viewport: Vec2) -> Option<(f32, f32)> {
+    let end = keys
+        .values()
+        .filter_map(KeyPresses::last)
+        .map(|keypress| keypress.end_secs())
+        .reduce(f32::max)?;
+    if end <= 0.0 {
+        return None;
+    }
 
-impl Widget for PianoRoll<'_, '_, '_> {
-    fn ui(self, ui: &mut Ui) -> Response {
-        Frame::canvas(ui.style())
+    let (lowest, highest) = (keys.keys().next()?, keys.keys().next_back()?);
+    let rows = (highest.midi_number() - lowest.midi_number()) as f32 + 1.0;
+
+    Some((viewport.x / end, viewport.y / rows))
+}
+
+impl Widget for PianoRoll<'_, '_, '_, '_> {
+    fn ui(mut self, ui: &mut Ui) -> Response {
+        let follow_cursor = self.follow_cursor.take();
+        let mut changed = false;
+
+        let selection_id = Id::new("piano_roll_selection");
+        self.selection = ui.memory().data.get_temp(selection_id).unwrap_or_default();
+
+        let mut response = Frame::canvas(ui.style())
             .show(ui, |ui| {
                 ScrollArea::both().show(ui, |ui| {
                     let drawing_window = ui.available_rect_before_wrap();
 
-                    // let size = {
-                    //     let piano_height = self.key_height * PianoKey::all().len() as f32;
-                    //     // Fill avaliable space
-                    //     let piano_height = piano_height.max(drawing_window.height());
-
-                    //     let alloc_width = self
-                    //         .keys
-                    //         .values()
-                    //         .filter_map(|set| {
-                    //             set.last()
-                    //                 .map(|keypress| keypress.end_secs() * self.seconds_per_width)
-                    //         })
-                    //         .reduce(f32::max)
-                    //         .unwrap_or_default()
-                    //         .max(drawing_window.width());
+                    let size = {
+                        let height = self.key_height * self.rows().count() as f32;
 
-                    //     Vec2::new(alloc_width, alloc_height)
-                    // };
+                        // Fill the available space when the song is shorter
+                        let end = self
+                            .notes()
+                            .values()
+                            .filter_map(KeyPresses::last)
+                            .map(|keypress| keypress.end_secs())
+                            .chain(self.cursor)
+                            .fold(0.0, f32::max);
+                        let width = (end * self.seconds_per_width).max(drawing_window.width());
 
-                    let rect = Rect::from_min_size(drawing_window.min, size);
+                        Vec2::new(width, height)
+                    };
 
                     let time_text_size = 15.0;
 
                     let (shapes, margin) = {
                         let mut shapes = Vec::new();
 
-                        let mut left_margin = 0.0;
+                        let mut label_space = Vec2::ZERO;
 
+                        // The labels line up with the rows, below the time labels
                         shapes.extend(self.draw_key_text_ui(
                             ui,
-                            drawing_window,
-                            time_text_size,
-                            &mut left_margin,
+                            drawing_window.min + Vec2::new(0.0, time_text_size),
+                            &mut label_space,
                         ));
 
                         // TODO: padding around text relative to text size (em)
-                        let margin = Vec2::new(left_margin + 5.0, time_text_size);
+                        let margin = Vec2::new(label_space.x + 5.0, time_text_size);
 
                         shapes.extend(self.draw_key_lines_ui(drawing_window, margin, size));
+                        shapes.extend(self.draw_pitch_grid(drawing_window, margin, size));
                         shapes.extend(self.draw_time_ui(ui, drawing_window, margin, size));
 
                         shapes.extend(self.draw_notes(ui, drawing_window, margin));
@@ -356,13 +866,60 @@ impl Widget for PianoRoll<'_, '_, '_> {
 
                     ui.painter().extend(shapes);
 
-                    ui.allocate_rect(
+                    if let Some(follow) = follow_cursor {
+                        // Scrolling by hand takes over from following the cursor
+                        if ui.rect_contains_pointer(ui.clip_rect())
+                            && ui.input().scroll_delta != Vec2::ZERO
+                        {
+                            *follow = false;
+                        }
+
+                        if let (true, Some(time)) = (*follow, self.cursor) {
+                            let x = drawing_window.min.x + margin.x + time * self.seconds_per_width;
+
+                            ui.scroll_to_rect(
+                                Rect::from_x_y_ranges(x..=x, ui.clip_rect().y_range()),
+                                Some(Align::Center),
+                            );
+                        }
+                    }
+
+                    let response = ui.allocate_rect(
                         Rect::from_min_size(drawing_window.min, size + margin),
                         Sense::click_and_drag(),
-                    )
+                    );
+
+                    if self.is_editable() {
+                        // A click clears any selection before adding notes
+                        if response.clicked() && !self.selection.is_empty() {
+                            self.selection.clear();
+                        } else if response.clicked() {
+                            self.add_note(&response, drawing_window.min + margin);
+                        }
+
+                        self.select_region(ui, &response, drawing_window.min + margin);
+
+                        if ui.input().key_pressed(Key::Delete) {
+                            self.delete_selection();
+                        }
+                    }
+                    changed = self.apply_edits();
+
+                    response
                 });
             })
-            .response
+            .response;
+
+        ui.memory()
+            .data
+            .insert_temp(selection_id, mem::take(&mut self.selection));
+
+        // Let the host know that the notes were edited
+        if changed {
+            response.mark_changed();
+        }
+
+        response
     }
 }
 
@@ -374,3 +931,172 @@ struct PianoRollPainter {
 impl PianoRollPainter {}
 
 // TODO: functions to calculate positions taking into account scaling and all that shit fuck :)
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        time::Duration,
+    };
+
+    use eframe::epaint::{Pos2, Rect, Vec2};
+
+    use super::{
+        frequency_y, grabs_end, grid_position, note_at, notes_in, zoom_to_fit, NoteEdit, PitchGrid,
+    };
+    use crate::{
+        analysis::{KeyPress, KeyPresses},
+        key::{PianoKey, Tuning},
+    };
+
+    #[test]
+    fn fit_note_span() {
+        let notes = BTreeMap::from([
+            (
+                PianoKey::new(40).unwrap(),
+                KeyPresses::from([KeyPress::new(0u64, Duration::from_millis(500), 1.0)]),
+            ),
+            (
+                PianoKey::new(49).unwrap(),
+                KeyPresses::from([
+                    KeyPress::new(1000u64, Duration::from_millis(500), 1.0),
+                    KeyPress::new(3000u64, Duration::from_millis(1000), 1.0),
+                ]),
+            ),
+        ]);
+
+        // Four seconds of notes across ten keys
+        assert_eq!(
+            zoom_to_fit(&notes, Vec2::new(800.0, 200.0)),
+            Some((200.0, 20.0))
+        );
+
+        assert_eq!(zoom_to_fit(&BTreeMap::new(), Vec2::new(800.0, 200.0)), None);
+    }
+
+    #[test]
+    fn hit_testing() {
+        // 100 points per second and 10 point high rows
+        assert_eq!(
+            grid_position(Vec2::new(250.0, 35.0), 100.0, 10.0),
+            Some((2.5, 3))
+        );
+        assert_eq!(
+            grid_position(Vec2::new(0.0, 0.0), 100.0, 10.0),
+            Some((0.0, 0))
+        );
+        assert_eq!(grid_position(Vec2::new(-1.0, 35.0), 100.0, 10.0), None);
+        assert_eq!(grid_position(Vec2::new(250.0, -1.0), 100.0, 10.0), None);
+
+        let note = Rect::from_min_max(Pos2::new(100.0, 0.0), Pos2::new(200.0, 10.0));
+        assert!(grabs_end(note, Pos2::new(198.0, 5.0)));
+        assert!(grabs_end(note, Pos2::new(203.0, 5.0)));
+        assert!(!grabs_end(note, Pos2::new(150.0, 5.0)));
+        assert!(!grabs_end(note, Pos2::new(200.0, 15.0)));
+
+        let presses = KeyPresses::from([
+            KeyPress::new(500u64, Duration::from_millis(500), 1.0),
+            KeyPress::new(2000u64, Duration::from_millis(250), 1.0),
+        ]);
+        assert_eq!(note_at(&presses, 0.75), presses.first());
+        assert_eq!(note_at(&presses, 2.1), presses.last());
+        assert_eq!(note_at(&presses, 1.0), None);
+        assert_eq!(note_at(&presses, 0.25), None);
+    }
+
+    #[test]
+    fn pitch_grid_position() {
+        // 39 rows from C8 down to A4, each 10 points high
+        let c8 = PianoKey::new(88).unwrap();
+        let a4 = PianoKey::new(49).unwrap();
+        let y = |frequency, tuning| frequency_y(frequency, tuning, c8, 10.0);
+
+        assert!((y(440.0, Tuning::CONCERT) - 395.0).abs() < 1e-2);
+        assert!((y(c8.concert_pitch(), Tuning::CONCERT) - 5.0).abs() < 1e-2);
+
+        // A quarter tone sharp sits on the edge between A4 and the key above
+        let quarter_tone = 440.0 * 2.0f32.powf(0.5 / 12.0);
+        assert!((y(quarter_tone, Tuning::CONCERT) - 390.0).abs() < 1e-2);
+
+        // Concert A is about 32 cents sharp of an A tuned to 432Hz
+        let low_tuning = Tuning { a4_hz: 432.0 };
+        let concert = PitchGrid::ConcertPitch.frequencies(a4, low_tuning);
+        assert_eq!(concert, [440.0]);
+        assert!((y(concert[0], low_tuning) - 391.823).abs() < 1e-2);
+
+        let cents = PitchGrid::Cents(25).frequencies(a4, low_tuning);
+        assert_eq!(cents.len(), 3);
+        assert!((y(cents[1], low_tuning) - 395.0).abs() < 1e-2);
+        assert!((y(cents[2], low_tuning) - 392.5).abs() < 1e-2);
+        assert_eq!(PitchGrid::Cents(10).frequencies(a4, low_tuning).len(), 9);
+
+        // Lines closer together than a few points are left out
+        assert!(PitchGrid::Cents(25).is_visible(20.0));
+        assert!(!PitchGrid::Cents(25).is_visible(10.0));
+        assert!(PitchGrid::ConcertPitch.is_visible(10.0));
+        assert!(!PitchGrid::Off.is_visible(100.0));
+    }
+
+    #[test]
+    fn region_selection() {
+        let (c4, e4, g4) = (
+            PianoKey::new(40).unwrap(),
+            PianoKey::new(44).unwrap(),
+            PianoKey::new(47).unwrap(),
+        );
+        let notes = BTreeMap::from([
+            (
+                c4,
+                KeyPresses::from([
+                    KeyPress::new(0u64, Duration::from_millis(500), 1.0),
+                    KeyPress::new(2000u64, Duration::from_millis(500), 1.0),
+                ]),
+            ),
+            (
+                e4,
+                KeyPresses::from([KeyPress::new(900u64, Duration::from_millis(200), 1.0)]),
+            ),
+            (
+                g4,
+                KeyPresses::from([KeyPress::new(1000u64, Duration::from_millis(500), 1.0)]),
+            ),
+        ]);
+
+        // Touches the end of the first C and the whole E, but not the G above
+        assert_eq!(
+            notes_in(&notes, 0.4..1.5, c4..=e4),
+            BTreeSet::from([(c4, 0), (e4, 900)])
+        );
+
+        // Notes only touching the edges of the region are left out
+        assert_eq!(notes_in(&notes, 0.5..2.0, c4..=c4), BTreeSet::new());
+
+        assert_eq!(notes_in(&notes, 0.0..10.0, c4..=g4).len(), 4);
+    }
+
+    #[test]
+    fn apply_edits() {
+        let key = PianoKey::new(49).unwrap();
+        let mut notes = BTreeMap::new();
+
+        NoteEdit::Add {
+            key,
+            keypress: KeyPress::new(1000u64, Duration::from_millis(250), 1.0),
+        }
+        .apply(&mut notes);
+        NoteEdit::Resize {
+            key,
+            start: 1000,
+            duration: Duration::from_millis(750),
+        }
+        .apply(&mut notes);
+
+        assert_eq!(
+            notes,
+            BTreeMap::from([(
+                key,
+                KeyPresses::from([KeyPress::new(1000u64, Duration::from_millis(750), 1.0)])
+            )])
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use color_eyre::eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analysis::{AnalysisOptions, KeyPresses},
+    key::PianoKey,
+};
+
+/// An analyzed file, saved so its notes can be reopened without analyzing it
+/// again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub source: PathBuf,
+    /// The size and modification time of the source when it was analyzed, to
+    /// tell if it has since changed
+    source_len: u64,
+    source_modified: Option<SystemTime>,
+
+    pub options: AnalysisOptions,
+    pub notes: BTreeMap<PianoKey, KeyPresses>,
+}
+
+/// How the source file of a session compares to when it was saved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceStatus {
+    Unchanged,
+    Changed,
+    Missing,
+}
+
+impl Session {
+    pub const EXTENSION: &'static str = "pitchsession";
+
+    pub fn new(
+        source: PathBuf,
+        options: AnalysisOptions,
+        notes: BTreeMap<PianoKey, KeyPresses>,
+    ) -> color_eyre::Result<Self> {
+        let metadata = fs::metadata(&source)
+            .wrap_err_with(|| format!("unable to read source file {}", source.display()))?;
+
+        Ok(Self {
+            source,
+            source_len: metadata.len(),
+            source_modified: metadata.modified().ok(),
+            options,
+            notes,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> color_eyre::Result<()> {
+        let session = ron::to_string(self).wrap_err("unable to serialize session")?;
+
+        fs::write(path, session)
+            .wrap_err_with(|| format!("unable to write session file {}", path.display()))
+    }
+
+    pub fn load(path: &Path) -> color_eyre::Result<Self> {
+        let session = fs::read_to_string(path)
+            .wrap_err_with(|| format!("unable to read session file {}", path.display()))?;
+
+        ron::from_str(&session).wrap_err_with(|| format!("invalid session file {}", path.display()))
+    }
+
+    pub fn source_status(&self) -> SourceStatus {
+        let metadata = match fs::metadata(&self.source) {
+            Ok(metadata) => metadata,
+            Err(_) => return SourceStatus::Missing,
+        };
+
+        // Only compare modification times where both are known
+        let modified = match (self.source_modified, metadata.modified()) {
+            (Some(saved), Ok(current)) => saved != current,
+            _ => false,
+        };
+
+        if modified || metadata.len() != self.source_len {
+            SourceStatus::Changed
+        } else {
+            SourceStatus::Unchanged
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, fs, time::Duration};
+
+    use super::{Session, SourceStatus};
+    use crate::{
+        analysis::{AnalysisOptions, FrequencyScale, KeyPress, KeyPresses, ThresholdMode},
+        key::{PianoKey, Tuning},
+    };
+
+    #[test]
+    fn save_then_load() {
+        let directory = std::env::temp_dir();
+        let source = directory.join("pitch-session-source.wav");
+        let path = directory.join("pitch-session.pitchsession");
+
+        fs::write(&source, b"not really audio").unwrap();
+
+        let notes = BTreeMap::from([
+            (
+                PianoKey::new(40).unwrap(),
+                KeyPresses::from([KeyPress::new(0u64, Duration::from_millis(500), 1.0)]),
+            ),
+            (
+                PianoKey::from_midi(0).unwrap(),
+                KeyPresses::from([
+                    KeyPress::new(250u64, Duration::from_millis(100), 0.5),
+                    KeyPress::new(1000u64, Duration::from_millis(100), 2.0),
+                ]),
+            ),
+        ]);
+        let options = AnalysisOptions {
+            fft_size: 12,
+            step_fraction: 0.25,
+            threshold: ThresholdMode::Decibels(-30.0),
+            tuning: Tuning { a4_hz: 432.0 },
+            frequency_scale: FrequencyScale::Logarithmic { bins: 352 },
+            merge_gap_ms: 0,
+            ..Default::default()
+        };
+
+        let session = Session::new(source.clone(), options, notes.clone()).unwrap();
+        session.save(&path).unwrap();
+
+        let loaded = Session::load(&path).unwrap();
+        assert_eq!(loaded.notes, notes);
+        assert_eq!(loaded, session);
+        assert_eq!(loaded.source_status(), SourceStatus::Unchanged);
+
+        fs::write(&source, b"different audio entirely").unwrap();
+        assert_eq!(loaded.source_status(), SourceStatus::Changed);
+
+        fs::remove_file(&source).unwrap();
+        assert_eq!(loaded.source_status(), SourceStatus::Missing);
+
+        fs::remove_file(path).ok();
+    }
+}
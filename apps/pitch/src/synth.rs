@@ -0,0 +1,117 @@
+use std::{collections::BTreeMap, f64::consts, path::Path};
+
+use audio::waveform::Waveform;
+use color_eyre::eyre::WrapErr;
+
+use crate::{
+    analysis::KeyPresses,
+    key::{PianoKey, Tuning},
+    midi::max_intensity,
+};
+
+/// How long each note takes to fade in and out, so that its edges do not click
+const FADE_SECS: f32 = 0.005;
+
+/// Write the notes as a wav file of sine waves, to hear how closely they match
+/// the audio they were found in
+pub fn export_synthesized(
+    notes: &BTreeMap<PianoKey, KeyPresses>,
+    tuning: Tuning,
+    path: &Path,
+) -> color_eyre::Result<()> {
+    synthesize_notes(notes, Waveform::CD_SAMPLE_RATE, tuning)
+        .write_wav_path(path)
+        .wrap_err_with(|| format!("unable to write wav file {}", path.display()))
+}
+
+/// Render every keypress as a sine wave at its key's frequency, faded in and
+/// out at its edges, and as loud as its intensity relative to the loudest
+/// keypress. Overlapping keypresses are summed, and the result is scaled back
+/// down if that would clip.
+pub fn synthesize_notes(
+    notes: &BTreeMap<PianoKey, KeyPresses>,
+    sample_rate: u32,
+    tuning: Tuning,
+) -> Waveform<'static> {
+    let sample_rate_f = sample_rate as f32;
+    let to_sample = |secs: f32| (secs * sample_rate_f).round() as usize;
+
+    let end = notes
+        .values()
+        .flat_map(KeyPresses::iter)
+        .map(|keypress| to_sample(keypress.end_secs()))
+        .max()
+        .unwrap_or(0);
+    let mut samples = vec![0.0; end];
+
+    let loudest = max_intensity(notes);
+    if loudest <= 0.0 {
+        return Waveform::new_mono(samples, sample_rate);
+    }
+
+    for (key, presses) in notes {
+        // Radians each sample, in double precision so long notes stay in tune
+        let step = consts::TAU * key.frequency(tuning) as f64 / sample_rate as f64;
+
+        for keypress in presses.iter() {
+            let start = to_sample(keypress.start_secs());
+            let length = to_sample(keypress.duration_secs());
+            let fade = to_sample(FADE_SECS).min(length / 2).max(1);
+            let amplitude = keypress.intensity() / loudest;
+
+            for (n, sample) in samples.iter_mut().skip(start).take(length).enumerate() {
+                let edge = n.min(length - 1 - n);
+                let envelope = (edge as f32 / fade as f32).min(1.0);
+
+                *sample += amplitude * envelope * (step * n as f64).sin() as f32;
+            }
+        }
+    }
+
+    let synthesized = Waveform::new_mono(samples, sample_rate);
+    if synthesized.peak() > 1.0 {
+        synthesized.normalize_peak(1.0)
+    } else {
+        synthesized
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use audio::waveform::Waveform;
+    use spectrum::{WaveformSpectrum, Window};
+
+    use super::synthesize_notes;
+    use crate::{
+        analysis::{KeyPress, KeyPresses},
+        key::{PianoKey, Tuning},
+    };
+
+    #[test]
+    fn a4() {
+        let notes = BTreeMap::from([(
+            PianoKey::new(49).unwrap(),
+            KeyPresses::from([KeyPress::new(100u64, Duration::from_millis(500), 0.5)]),
+        )]);
+
+        let synthesized = synthesize_notes(&notes, Waveform::CD_SAMPLE_RATE, Tuning::CONCERT);
+        assert_eq!(
+            synthesized.len(),
+            Waveform::CD_SAMPLE_RATE as usize * 6 / 10
+        );
+        assert_eq!(synthesized.samples()[..4410], [0.0; 4410]);
+        assert!((synthesized.peak() - 1.0).abs() < 1e-3);
+
+        let note = synthesized.slice(4410..4410 + 8192);
+        let spectrum = note.spectrum(Window::Hann, 8192).unwrap();
+        let (bucket, _) = spectrum.main_frequency().unwrap();
+        let frequency = spectrum.freq_from_bucket(bucket);
+
+        assert!(
+            (frequency - 440.0).abs() < spectrum.freq_resolution(),
+            "{frequency}Hz"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use std::{mem, sync::Arc, time::Duration};
+
+use audio::{
+    input::{record_stream, Recording},
+    waveform::Waveform,
+};
+use color_eyre::eyre::Context as _;
+use eframe::{
+    egui::{Button, Context, RichText, Sense, Ui, Window},
+    emath::Align2,
+    epaint::{Color32, FontId, Stroke, Vec2},
+};
+use parking_lot::RwLock;
+use spectrum::pitch::{detect_pitch_yin, YIN_THRESHOLD};
+
+use crate::{
+    key::{cents_between, nearest_note, Accidental, PianoKey, Tuning},
+    midi::MidiPlayer,
+};
+
+/// The length of audio the pitch is detected over, long enough to hold two
+/// periods of the lowest note on a bass guitar
+const WINDOW_SECS: f32 = 0.05;
+
+/// Input quieter than this is treated as silence instead of searched for a pitch
+const SILENCE_RMS: f32 = 0.01;
+
+/// How close to a note the pitch has to be to count as in tune
+const IN_TUNE_CENTS: f32 = 5.0;
+
+/// How long the pitch heard is played back for over midi
+const PLAYBACK_DURATION: Duration = Duration::from_secs(1);
+
+/// Listens to the default input device while open, showing how far the pitch
+/// heard is from the nearest note
+#[derive(Default)]
+pub struct Tuner {
+    pub open: bool,
+
+    recording: Option<Recording>,
+    /// The pitch of the most recent window of input, if there was one
+    pitch: Arc<RwLock<Option<f32>>>,
+}
+
+impl Tuner {
+    fn start(&mut self, ctx: Context) -> color_eyre::Result<()> {
+        let pitch = self.pitch.clone();
+        let mut buffer = Vec::new();
+
+        let recording = record_stream(None, move |chunk| {
+            let mono = chunk.to_mono();
+            let window = (WINDOW_SECS * mono.sample_rate() as f32) as usize;
+
+            buffer.extend_from_slice(mono.samples());
+            if buffer.len() < window {
+                return;
+            }
+
+            // Only the most recent window matters
+            buffer.drain(..buffer.len() - window);
+            let window = Waveform::new_mono(mem::take(&mut buffer), mono.sample_rate());
+
+            *pitch.write() = if window.rms() < SILENCE_RMS {
+                None
+            } else {
+                detect_pitch_yin(&window, YIN_THRESHOLD)
+            };
+
+            ctx.request_repaint();
+        })
+        .wrap_err("failed to start listening for the tuner")?;
+
+        self.recording = Some(recording);
+
+        Ok(())
+    }
+
+    /// Show the tuner window if it is open, listening for as long as it stays
+    /// open. The pitch heard can be played back on `midi`, bent between keys.
+    pub fn show(
+        &mut self,
+        ctx: &Context,
+        midi: &MidiPlayer,
+        tuning: Tuning,
+        preference: Accidental,
+    ) -> color_eyre::Result<()> {
+        if !self.open {
+            self.recording = None;
+            *self.pitch.write() = None;
+
+            return Ok(());
+        }
+
+        if self.recording.is_none() {
+            if let Err(error) = self.start(ctx.clone()) {
+                self.open = false;
+
+                return Err(error);
+            }
+        }
+
+        let pitch = *self.pitch.read();
+
+        Window::new("Tuner")
+            .open(&mut self.open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                needle(ui, pitch, tuning, preference);
+
+                // Midi devices play at concert pitch whatever the tuning
+                let heard = pitch.and_then(|pitch| {
+                    let key = PianoKey::from_frequency(pitch, Tuning::CONCERT)?;
+
+                    Some((key, cents_between(key.frequency(Tuning::CONCERT), pitch)))
+                });
+
+                if ui
+                    .add_enabled(heard.is_some(), Button::new("Play over MIDI"))
+                    .on_hover_text("Play the pitch heard, between the keys if it falls there")
+                    .clicked()
+                {
+                    if let Some((key, cents)) = heard {
+                        midi.play_microtonal(key, cents, PLAYBACK_DURATION);
+                    }
+                }
+            });
+
+        Ok(())
+    }
+}
+
+/// A needle swinging either side of the centre by the cents the pitch is off
+/// its nearest note, which turns green once in tune
+fn needle(ui: &mut Ui, pitch: Option<f32>, tuning: Tuning, preference: Accidental) {
+    let nearest = pitch.and_then(|pitch| Some((pitch, nearest_note(pitch, tuning, preference)?)));
+
+    let (response, painter) = ui.allocate_painter(Vec2::new(300.0, 120.0), Sense::hover());
+    let rect = response.rect;
+
+    let (note, cents) = match nearest {
+        Some((_, (note, cents))) => (note.to_string(), cents),
+        None => ("no pitch".to_owned(), 0.0),
+    };
+
+    let color = match nearest {
+        Some(_) if cents.abs() <= IN_TUNE_CENTS => Color32::GREEN,
+        Some(_) => Color32::RED,
+        None => Color32::GRAY,
+    };
+
+    painter.text(
+        rect.center_top(),
+        Align2::CENTER_TOP,
+        note,
+        FontId::proportional(32.0),
+        color,
+    );
+
+    // The scale spans half a semitone either way
+    let base = rect.center_bottom();
+    let length = rect.height() * 0.6;
+    let angle = cents / 50.0 * std::f32::consts::FRAC_PI_4;
+
+    painter.line_segment(
+        [base, base - Vec2::new(0.0, length)],
+        Stroke::new(1.0, Color32::DARK_GRAY),
+    );
+    painter.line_segment(
+        [base, base + Vec2::new(angle.sin(), -angle.cos()) * length],
+        Stroke::new(3.0, color),
+    );
+
+    ui.horizontal(|ui| match nearest {
+        Some((pitch, _)) => {
+            ui.label(RichText::new(format!("{pitch:.1} Hz")).monospace());
+            ui.label(RichText::new(format!("{cents:+.0} cents")).monospace());
+        }
+        None => {
+            ui.label("Play a note");
+        }
+    });
+}
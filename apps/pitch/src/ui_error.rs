@@ -1,5 +1,23 @@
-use eframe::egui::Ui;
+use color_eyre::Report;
+use eframe::{
+    egui::{RichText, Ui},
+    epaint::Color32,
+};
 
 pub trait UiError {
     fn ui_error(&self, ui: &mut Ui);
 }
+
+impl UiError for Report {
+    fn ui_error(&self, ui: &mut Ui) {
+        ui.label(
+            RichText::new(self.to_string())
+                .heading()
+                .color(Color32::RED),
+        );
+
+        for cause in self.chain().skip(1) {
+            ui.label(cause.to_string());
+        }
+    }
+}
@@ -0,0 +1,496 @@
+use std::f32::consts;
+
+use color_eyre::eyre::ensure;
+
+use crate::{
+    filter::{Biquad, BiquadChain},
+    waveform::Waveform,
+};
+
+/// The largest feedback an echo will use, anything at or above 1.0 would never decay
+pub const MAX_ECHO_FEEDBACK: f32 = 0.99;
+
+/// The level, relative to the first repeat, at which an echo's tail is cut off
+const ECHO_TAIL_THRESHOLD: f32 = 0.001;
+
+/// Turns down the level of anything louder than `threshold_db`, following the
+/// peak level of the waveform with an envelope which rises over `attack_secs`
+/// and falls over `release_secs`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Compressor {
+    /// The level in dBFS above which the gain is reduced
+    pub threshold_db: f32,
+    /// How many dB over the threshold going in become one dB over coming out,
+    /// with [`f32::INFINITY`] never letting the level past the threshold
+    pub ratio: f32,
+    pub attack_secs: f32,
+    pub release_secs: f32,
+}
+
+impl Compressor {
+    /// A compressor with an infinite ratio and an instant attack, so that no
+    /// sample ever goes above `threshold_db`. Limiting at 0dB stops a waveform
+    /// clipping, such as after a [`Waveform::mix`].
+    pub fn limiter(threshold_db: f32, release_secs: f32) -> Self {
+        Self {
+            threshold_db,
+            ratio: f32::INFINITY,
+            attack_secs: 0.0,
+            release_secs,
+        }
+    }
+
+    /// The change in dB for a signal at `level_db`
+    fn gain_db(&self, level_db: f32) -> f32 {
+        let over = level_db - self.threshold_db;
+
+        if over > 0.0 {
+            -over * (1.0 - self.ratio.recip())
+        } else {
+            0.0
+        }
+    }
+}
+
+/// How far below the threshold an open noise gate has to fall before closing,
+/// so that a level hovering around the threshold does not open and close it
+/// over and over
+const GATE_HYSTERESIS_DB: f32 = 6.0;
+
+/// The centres of the lowest and highest bands of a vocoder, spanning what
+/// matters for making out speech
+const VOCODER_RANGE: (f32, f32) = (100.0, 8_000.0);
+
+/// How quickly each band of a vocoder follows the level of the modulator, fast
+/// enough to keep up with syllables without tracking individual cycles
+const VOCODER_ATTACK_SECS: f32 = 0.005;
+const VOCODER_RELEASE_SECS: f32 = 0.02;
+
+/// Smooths a level so that it rises over the attack time and falls over the
+/// release time
+#[derive(Debug, Clone, Copy)]
+struct EnvelopeFollower {
+    attack: f32,
+    release: f32,
+    envelope: f32,
+}
+
+impl EnvelopeFollower {
+    fn new(attack_secs: f32, release_secs: f32, sample_rate: u32) -> Self {
+        Self {
+            attack: smoothing(attack_secs, sample_rate),
+            release: smoothing(release_secs, sample_rate),
+            envelope: 0.0,
+        }
+    }
+
+    /// Move the envelope toward the next `level`, returning where it ends up
+    fn follow(&mut self, level: f32) -> f32 {
+        let smoothing = if level > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope = smoothing * self.envelope + (1.0 - smoothing) * level;
+
+        self.envelope
+    }
+}
+
+/// How much of the envelope is kept each sample for it to move most of the
+/// way to the level over `secs`, or none of it if instant
+fn smoothing(secs: f32, sample_rate: u32) -> f32 {
+    (-1.0 / (secs * sample_rate as f32)).exp()
+}
+
+/// The loudest sample in a frame, which all of its channels follow
+fn frame_peak(frame: &[f32]) -> f32 {
+    frame
+        .iter()
+        .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+}
+
+impl Waveform<'_> {
+    /// Mix in repeats of the waveform every `delay_secs`, each scaled by `feedback`
+    /// relative to the last. `mix` blends from only the dry signal at `0.0` to
+    /// only the echoes at `1.0`.
+    ///
+    /// `feedback` is clamped to within [`MAX_ECHO_FEEDBACK`] of zero so the
+    /// repeats always die away, and the waveform is extended until they fall
+    /// 60dB below the first repeat.
+    #[must_use = "Waveform::echo() does not modify the provided waveform"]
+    pub fn echo(&self, delay_secs: f32, feedback: f32, mix: f32) -> Waveform<'static> {
+        let delay_frames = (delay_secs * self.sample_rate() as f32).round() as usize;
+        assert!(delay_frames > 0, "echo delay must be at least one frame");
+
+        let feedback = feedback.clamp(-MAX_ECHO_FEEDBACK, MAX_ECHO_FEEDBACK);
+
+        let repeats = if feedback == 0.0 {
+            1
+        } else {
+            (ECHO_TAIL_THRESHOLD.ln() / feedback.abs().ln()).ceil() as usize
+        };
+
+        // Interleaved, so a delay in samples always lands on the same channel
+        let delay = delay_frames * self.channels() as usize;
+        let len = self.len() + delay * repeats;
+
+        let mut delay_line = Vec::with_capacity(len);
+        let mut samples = Vec::with_capacity(len);
+
+        for n in 0..len {
+            let dry = self.samples().get(n).copied().unwrap_or(0.0);
+            let wet = n.checked_sub(delay).map_or(0.0, |n| delay_line[n]);
+
+            delay_line.push(dry + feedback * wet);
+            samples.push((1.0 - mix) * dry + mix * wet);
+        }
+
+        Waveform::new(samples, self.sample_rate(), self.channels())
+    }
+
+    /// Reduce the dynamic range with `compressor`. Every channel of a frame
+    /// gets the same gain, following whichever is loudest, so that the stereo
+    /// image does not shift.
+    #[must_use = "Waveform::compress() does not modify the provided waveform"]
+    pub fn compress(&self, compressor: &Compressor) -> Waveform<'static> {
+        let mut follower = EnvelopeFollower::new(
+            compressor.attack_secs,
+            compressor.release_secs,
+            self.sample_rate(),
+        );
+
+        let samples = self
+            .samples()
+            .chunks(self.channels() as usize)
+            .flat_map(|frame| {
+                let envelope = follower.follow(frame_peak(frame));
+
+                let gain = 10f32.powf(compressor.gain_db(20.0 * envelope.log10()) / 20.0);
+
+                frame.iter().map(move |sample| sample * gain)
+            })
+            .collect();
+
+        Waveform::new(samples, self.sample_rate(), self.channels())
+    }
+
+    /// Silence the waveform wherever its envelope falls below `threshold_db`,
+    /// such as the quiet between words. The gate opens over `attack_secs` and
+    /// closes over `release_secs`, and only closes once the level is a few dB
+    /// under the threshold, so that it does not chatter on borderline regions.
+    #[must_use = "Waveform::noise_gate() does not modify the provided waveform"]
+    pub fn noise_gate(
+        &self,
+        threshold_db: f32,
+        attack_secs: f32,
+        release_secs: f32,
+    ) -> Waveform<'static> {
+        let mut level = EnvelopeFollower::new(attack_secs, release_secs, self.sample_rate());
+        let mut gain = EnvelopeFollower::new(attack_secs, release_secs, self.sample_rate());
+        let mut open = false;
+
+        let samples = self
+            .samples()
+            .chunks(self.channels() as usize)
+            .flat_map(|frame| {
+                let level_db = 20.0 * level.follow(frame_peak(frame)).log10();
+
+                open = if open {
+                    level_db > threshold_db - GATE_HYSTERESIS_DB
+                } else {
+                    level_db > threshold_db
+                };
+
+                let gain = gain.follow(if open { 1.0 } else { 0.0 });
+
+                frame.iter().map(move |sample| sample * gain)
+            })
+            .collect();
+
+        Waveform::new(samples, self.sample_rate(), self.channels())
+    }
+}
+
+impl Waveform<'_> {
+    /// Multiply the waveform by a sine wave at `carrier_hz`, which replaces
+    /// every frequency with its sum and difference with the carrier
+    #[must_use = "Waveform::ring_modulate() does not modify the provided waveform"]
+    pub fn ring_modulate(&self, carrier_hz: f32) -> Waveform<'static> {
+        let channels = self.channels() as usize;
+        let sample_rate = self.sample_rate() as f32;
+
+        Waveform::new(
+            self.samples_iter()
+                .enumerate()
+                .map(|(n, sample)| {
+                    let time = (n / channels) as f32 / sample_rate;
+
+                    sample * (carrier_hz * consts::TAU * time).sin()
+                })
+                .collect(),
+            self.sample_rate(),
+            self.channels(),
+        )
+    }
+
+    /// Impose the spectral envelope of this waveform onto `carrier` with a
+    /// channel vocoder. Both are split into `bands` band passes spaced evenly
+    /// in pitch, and each band of the carrier is scaled by the level of the
+    /// same band of this waveform, so the carrier keeps its pitch while taking
+    /// on the shape of, say, speech.
+    ///
+    /// The result is mono and as long as the shorter of the two waveforms,
+    /// which must share a sample rate.
+    pub fn vocode(
+        &self,
+        carrier: &Waveform,
+        bands: usize,
+    ) -> color_eyre::Result<Waveform<'static>> {
+        ensure!(
+            self.sample_rate() == carrier.sample_rate(),
+            "can not vocode a {} Hz carrier with a {} Hz waveform",
+            carrier.sample_rate(),
+            self.sample_rate()
+        );
+        ensure!(bands > 0, "a vocoder needs at least one band");
+
+        let sample_rate = self.sample_rate();
+        let modulator = self.to_mono();
+        let carrier = carrier.to_mono();
+        let len = modulator.len().min(carrier.len());
+
+        let (lowest, highest) = VOCODER_RANGE;
+        let highest = highest.min(0.45 * sample_rate as f32);
+
+        // Each band spans the same ratio of frequencies, reaching halfway in
+        // pitch to its neighbours
+        let ratio = (highest / lowest).powf(1.0 / bands.saturating_sub(1).max(1) as f32);
+        let q = ratio.sqrt() / (ratio - 1.0);
+
+        let mut samples = vec![0.0; len];
+
+        for band in 0..bands {
+            let center = lowest * ratio.powi(band as i32);
+
+            // Two in series for steeper sides, so that the bands overlap less
+            let band_pass = Biquad::band_pass(center, q, sample_rate);
+            let chain = BiquadChain::new().with(band_pass).with(band_pass);
+
+            let mut follower =
+                EnvelopeFollower::new(VOCODER_ATTACK_SECS, VOCODER_RELEASE_SECS, sample_rate);
+
+            let modulator = modulator.slice(..len).filter_chain(&chain);
+            let carrier = carrier.slice(..len).filter_chain(&chain);
+
+            for ((sample, modulator), carrier) in samples
+                .iter_mut()
+                .zip(modulator.samples_iter())
+                .zip(carrier.samples_iter())
+            {
+                *sample += carrier * follower.follow(modulator.abs());
+            }
+        }
+
+        Ok(Waveform::new_mono(samples, sample_rate))
+    }
+
+    /// Quantize every sample to `bits` of resolution and hold each frame for
+    /// `downsample_factor` frames, as if played back on hardware with a lower
+    /// bit depth and sample rate. The sample rate itself is left unchanged, so
+    /// it plays at the same speed, with everything above the reduced nyquist
+    /// aliasing back down.
+    #[must_use = "Waveform::bitcrush() does not modify the provided waveform"]
+    pub fn bitcrush(&self, bits: u8, downsample_factor: usize) -> Waveform<'static> {
+        assert!(bits > 0, "bitcrushing needs at least one bit");
+        assert!(
+            downsample_factor > 0,
+            "bitcrushing needs a downsample factor of at least one"
+        );
+
+        // Levels centred within equal steps across -1.0..=1.0, so one bit
+        // leaves one level either side of zero
+        let levels = 2f32.powi(bits.into());
+        let step = 2.0 / levels;
+        let quantize = |sample: f32| {
+            let level = ((sample.clamp(-1.0, 1.0) + 1.0) / step)
+                .floor()
+                .min(levels - 1.0);
+
+            -1.0 + (level + 0.5) * step
+        };
+
+        let channels = self.channels() as usize;
+        let held = downsample_factor * channels;
+
+        Waveform::new(
+            (0..self.len())
+                .map(|n| quantize(self.samples()[n / held * held + n % channels]))
+                .collect(),
+            self.sample_rate(),
+            self.channels(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts;
+
+    use super::Compressor;
+    use crate::waveform::Waveform;
+
+    #[test]
+    fn echo_repeats_decay() {
+        let mut impulse = vec![0.0; 10];
+        impulse[0] = 1.0;
+        let impulse = Waveform::new_mono(impulse, 1_000);
+
+        let echo = impulse.echo(0.1, 0.5, 0.5);
+
+        // Ten halvings fall below the tail threshold
+        assert_eq!(echo.len(), 10 + 100 * 10);
+
+        for (n, &sample) in echo.samples().iter().enumerate() {
+            let expected = match n {
+                0 => 0.5,
+                n if n % 100 == 0 => 0.5 * 0.5f32.powi(n as i32 / 100 - 1),
+                _ => 0.0,
+            };
+
+            assert!((sample - expected).abs() < 1e-6, "sample {n} was {sample}");
+        }
+    }
+
+    #[test]
+    fn echo_feedback_clamped() {
+        let impulse = Waveform::new(vec![1.0, -1.0], 1_000, 2);
+
+        let echo = impulse.echo(0.01, 2.0, 1.0);
+
+        // Each repeat of the left channel is quieter than the one before
+        let repeats = echo
+            .channel(0)
+            .samples()
+            .iter()
+            .copied()
+            .skip(10)
+            .step_by(10)
+            .collect::<Vec<_>>();
+
+        assert!(repeats.windows(2).all(|pair| pair[1].abs() < pair[0].abs()));
+        assert!(repeats.last().map_or(false, |last| last.abs() < 0.01));
+    }
+
+    #[test]
+    fn compress_loud_passages() -> color_eyre::Result<()> {
+        let sample_rate = Waveform::CD_SAMPLE_RATE;
+        let quiet = Waveform::sine_wave(100.0, 0.5, sample_rate).gain(0.1);
+        let loud = Waveform::sine_wave(100.0, 0.5, sample_rate);
+        let speech = Waveform::concat(&[&quiet, &loud])?;
+
+        let compressor = Compressor {
+            threshold_db: -12.0,
+            ratio: 4.0,
+            attack_secs: 0.001,
+            release_secs: 0.05,
+        };
+        let compressed = speech.compress(&compressor);
+
+        // Under the threshold nothing changes
+        assert_eq!(compressed.slice(..quiet.len()).samples(), quiet.samples());
+
+        // 12dB over the threshold comes out 3dB over it, once the envelope
+        // has caught up
+        let settled = compressed.slice(quiet.len() + 4_410..).peak();
+        let expected = 10f32.powf(-9.0 / 20.0);
+        assert!((settled - expected).abs() < 0.02, "peak was {settled}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn limiter_prevents_clipping() {
+        let sine = Waveform::sine_wave(440.0, 0.5, Waveform::CD_SAMPLE_RATE);
+        let clipping = Waveform::new_mono(
+            sine.samples_iter().map(|sample| sample * 2.0).collect(),
+            sine.sample_rate(),
+        );
+
+        let limited = clipping.compress(&Compressor::limiter(0.0, 0.05));
+
+        assert!(limited.peak() <= 1.0 + 1e-4, "peak was {}", limited.peak());
+        assert!(limited.peak() > 0.9);
+    }
+
+    #[test]
+    fn noise_gate_silences_quiet() -> color_eyre::Result<()> {
+        let sample_rate = Waveform::CD_SAMPLE_RATE;
+        let loud = Waveform::sine_wave(100.0, 0.5, sample_rate);
+        let hiss = Waveform::white_noise(0.5, sample_rate, 0).gain(0.001);
+        let speech = Waveform::concat(&[&loud, &hiss, &loud])?;
+
+        let gated = speech.noise_gate(-40.0, 0.001, 0.02);
+
+        // Once the envelope has fallen and the gate has had time to close the
+        // hiss is gone
+        let closed = gated.slice(loud.len() + 13_230..loud.len() + hiss.len());
+        assert!(closed.peak() < 1e-5, "hiss peaked at {}", closed.peak());
+
+        // and once it has opened again the loud sections are untouched
+        for start in [441, loud.len() + hiss.len() + 441] {
+            let opened = gated.slice(start..start + loud.len() - 441);
+            let original = speech.slice(start..start + loud.len() - 441);
+
+            assert!(opened
+                .samples_iter()
+                .zip(original.samples_iter())
+                .all(|(gated, original)| (gated - original).abs() < 1e-3));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn ring_modulation_sum_and_difference() {
+        let sample_rate = Waveform::CD_SAMPLE_RATE;
+        let tone = Waveform::sine_wave(440.0, 0.1, sample_rate);
+
+        let modulated = tone.ring_modulate(100.0);
+
+        // sin(a)sin(b) = (cos(a - b) - cos(a + b)) / 2
+        for (n, sample) in modulated.samples_iter().enumerate() {
+            let time = n as f32 / sample_rate as f32;
+            let expected =
+                ((340.0 * consts::TAU * time).cos() - (540.0 * consts::TAU * time).cos()) / 2.0;
+
+            assert!((sample - expected).abs() < 1e-3, "sample {n} was {sample}");
+        }
+    }
+
+    #[test]
+    fn vocoder_mismatched_rates() {
+        let modulator = Waveform::white_noise(0.1, 44_100, 0);
+        let carrier = Waveform::sawtooth_wave(110.0, 0.1, 48_000, true);
+
+        assert!(modulator.vocode(&carrier, 16).is_err());
+        assert!(modulator.vocode(&modulator, 0).is_err());
+    }
+
+    #[test]
+    fn bitcrush() {
+        let sine = Waveform::sine_wave(440.0, 0.1, Waveform::CD_SAMPLE_RATE);
+
+        let mut one_bit = sine.bitcrush(1, 1).into_samples();
+        one_bit.sort_by(f32::total_cmp);
+        one_bit.dedup();
+        assert_eq!(one_bit, [-0.5, 0.5]);
+
+        // Each frame is held for the following ones, per channel
+        let stereo = Waveform::new(vec![0.1, -0.1, 0.6, -0.6, 0.9, -0.9], 8, 2);
+        assert_eq!(
+            stereo.bitcrush(2, 2).samples(),
+            [0.25, -0.25, 0.25, -0.25, 0.75, -0.75]
+        );
+    }
+}
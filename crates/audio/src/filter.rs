@@ -0,0 +1,475 @@
+use std::f32::consts;
+
+use crate::waveform::Waveform;
+
+/// A second order IIR filter, using the coefficients from Robert
+/// Bristow-Johnson's audio EQ cookbook
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    pub fn low_pass(cutoff: f32, q: f32, sample_rate: u32) -> Self {
+        let (cos, alpha) = Self::intermediates(cutoff, q, sample_rate);
+
+        Self::normalized(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn high_pass(cutoff: f32, q: f32, sample_rate: u32) -> Self {
+        let (cos, alpha) = Self::intermediates(cutoff, q, sample_rate);
+
+        Self::normalized(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// A band pass with a peak gain of 0dB at `center`
+    pub fn band_pass(center: f32, q: f32, sample_rate: u32) -> Self {
+        let (cos, alpha) = Self::intermediates(center, q, sample_rate);
+
+        Self::normalized([alpha, 0.0, -alpha], [1.0 + alpha, -2.0 * cos, 1.0 - alpha])
+    }
+
+    /// A bell raising frequencies around `center` by `gain_db`, or lowering
+    /// them if negative, leaving those far from it unchanged
+    pub fn peaking(center: f32, q: f32, gain_db: f32, sample_rate: u32) -> Self {
+        let (cos, alpha) = Self::intermediates(center, q, sample_rate);
+        let a = 10f32.powf(gain_db / 40.0);
+
+        Self::normalized(
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        )
+    }
+
+    /// A shelf raising frequencies above `frequency` by `gain_db`, or
+    /// lowering them if negative
+    pub fn high_shelf(frequency: f32, q: f32, gain_db: f32, sample_rate: u32) -> Self {
+        let (cos, alpha) = Self::intermediates(frequency, q, sample_rate);
+        let a = 10f32.powf(gain_db / 40.0);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        Self::normalized(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ],
+        )
+    }
+
+    /// The cosine of the angular frequency and the cookbook's alpha
+    fn intermediates(frequency: f32, q: f32, sample_rate: u32) -> (f32, f32) {
+        let omega = consts::TAU * frequency / sample_rate as f32;
+        let (sin, cos) = omega.sin_cos();
+
+        (cos, sin / (2.0 * q))
+    }
+
+    /// Divide all coefficients through by a0
+    fn normalized([b0, b1, b2]: [f32; 3], [a0, a1, a2]: [f32; 3]) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    fn process(&self, state: &mut BiquadState, input: f32) -> f32 {
+        let output = self.b0 * input + self.b1 * state.x1 + self.b2 * state.x2
+            - self.a1 * state.y1
+            - self.a2 * state.y2;
+
+        state.x2 = state.x1;
+        state.x1 = input;
+        state.y2 = state.y1;
+        state.y1 = output;
+
+        output
+    }
+}
+
+/// Biquads applied one after another
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BiquadChain {
+    filters: Vec<Biquad>,
+}
+
+impl BiquadChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn with(mut self, biquad: Biquad) -> Self {
+        self.push(biquad);
+        self
+    }
+
+    pub fn push(&mut self, biquad: Biquad) {
+        self.filters.push(biquad);
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// The A-weighting curve of IEC 61672, approximating how loud quiet sounds
+    /// seem at each frequency. Its poles are paired into biquads, which are
+    /// only accurate up to around 10kHz and leave the curve 2dB below 0dB at
+    /// 1kHz, see [`Waveform::a_weighted`].
+    pub fn a_weighting(sample_rate: u32) -> Self {
+        // The real poles of the analog filter in Hz, each highpass having
+        // two zeros at DC
+        let (f1, f2, f3, f4): (f32, f32, f32, f32) = (20.6, 107.7, 737.9, 12194.0);
+
+        // Two poles at the same frequency make a biquad with a Q of a half,
+        // and the two in the middle share one
+        let (f23, q23) = ((f2 * f3).sqrt(), (f2 * f3).sqrt() / (f2 + f3));
+
+        Self::new()
+            .with(Biquad::high_pass(f1, 0.5, sample_rate))
+            .with(Biquad::high_pass(f23, q23, sample_rate))
+            // Kept below nyquist for low sample rates
+            .with(Biquad::low_pass(
+                f4.min(0.45 * sample_rate as f32),
+                0.5,
+                sample_rate,
+            ))
+    }
+
+    /// The K-weighting of ITU-R BS.1770, a shelf modelling the head followed
+    /// by a highpass dropping the lowest frequencies
+    pub fn k_weighting(sample_rate: u32) -> Self {
+        Self::new()
+            .with(Biquad::high_shelf(
+                1681.97,
+                consts::FRAC_1_SQRT_2,
+                4.0,
+                sample_rate,
+            ))
+            .with(Biquad::high_pass(38.13, 0.5, sample_rate))
+    }
+}
+
+impl FromIterator<Biquad> for BiquadChain {
+    fn from_iter<T: IntoIterator<Item = Biquad>>(iter: T) -> Self {
+        Self {
+            filters: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// A single band of an [`Equalizer`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EqualizerBand {
+    pub frequency: f32,
+    pub q: f32,
+    pub gain_db: f32,
+}
+
+/// A bank of peaking filters, each raising or lowering the frequencies around
+/// its band
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Equalizer {
+    pub bands: Vec<EqualizerBand>,
+}
+
+impl Equalizer {
+    /// The ten octave bands of a graphic equalizer, from 31.25Hz to 16kHz,
+    /// all starting out flat
+    pub fn graphic() -> Self {
+        Self {
+            bands: (0..10)
+                .map(|octave| EqualizerBand {
+                    frequency: 31.25 * 2f32.powi(octave),
+                    // About an octave wide
+                    q: consts::SQRT_2,
+                    gain_db: 0.0,
+                })
+                .collect(),
+        }
+    }
+
+    /// If no band changes the level at all
+    pub fn is_flat(&self) -> bool {
+        self.bands.iter().all(|band| band.gain_db == 0.0)
+    }
+
+    /// Put every band back to 0dB
+    pub fn flatten(&mut self) {
+        for band in &mut self.bands {
+            band.gain_db = 0.0;
+        }
+    }
+
+    /// The filters for the bands which change the level and lie below
+    /// nyquist at `sample_rate`
+    pub fn chain(&self, sample_rate: u32) -> BiquadChain {
+        self.bands
+            .iter()
+            .filter(|band| band.gain_db != 0.0 && band.frequency < sample_rate as f32 / 2.0)
+            .map(|band| Biquad::peaking(band.frequency, band.q, band.gain_db, sample_rate))
+            .collect()
+    }
+}
+
+/// The previous two inputs and outputs of a biquad
+#[derive(Debug, Default, Clone, Copy)]
+struct BiquadState {
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Waveform<'_> {
+    /// Run the whole waveform through the filter, keeping separate state for each channel
+    #[must_use = "Waveform::filter() does not modify the provided waveform"]
+    pub fn filter(&self, biquad: &Biquad) -> Waveform<'static> {
+        let channels = self.channels() as usize;
+        let mut states = vec![BiquadState::default(); channels];
+
+        Waveform::new(
+            self.samples_iter()
+                .enumerate()
+                .map(|(n, sample)| biquad.process(&mut states[n % channels], sample))
+                .collect(),
+            self.sample_rate(),
+            self.channels(),
+        )
+    }
+
+    /// Run the whole waveform through each filter in series, keeping separate
+    /// state for each filter and channel
+    #[must_use = "Waveform::filter_chain() does not modify the provided waveform"]
+    pub fn filter_chain(&self, chain: &BiquadChain) -> Waveform<'static> {
+        let channels = self.channels() as usize;
+        let mut states = vec![BiquadState::default(); channels * chain.len()];
+
+        Waveform::new(
+            self.samples_iter()
+                .enumerate()
+                .map(|(n, sample)| {
+                    let channel = n % channels;
+
+                    chain
+                        .filters
+                        .iter()
+                        .zip(states.chunks_exact_mut(channels))
+                        .fold(sample, |sample, (biquad, states)| {
+                            biquad.process(&mut states[channel], sample)
+                        })
+                })
+                .collect(),
+            self.sample_rate(),
+            self.channels(),
+        )
+    }
+
+    /// Run the waveform through each band of `equalizer`
+    #[must_use = "Waveform::equalize() does not modify the provided waveform"]
+    pub fn equalize(&self, equalizer: &Equalizer) -> Waveform<'static> {
+        self.filter_chain(&equalizer.chain(self.sample_rate()))
+    }
+
+    /// Apply the A-weighting curve, normalized to leave 1kHz unchanged
+    #[must_use = "Waveform::a_weighted() does not modify the provided waveform"]
+    pub fn a_weighted(&self) -> Waveform<'static> {
+        const GAIN_AT_1KHZ_DB: f32 = -2.0;
+
+        self.filter_chain(&BiquadChain::a_weighting(self.sample_rate()))
+            .gain(10f32.powf(-GAIN_AT_1KHZ_DB / 20.0))
+    }
+
+    /// The integrated loudness in LUFS, measured much like ITU-R BS.1770 by
+    /// gating overlapping 400ms blocks of the K-weighted waveform. Every
+    /// channel is weighted the same, and the biquads are close to but not
+    /// exactly the filters of the standard, so this is only an approximation
+    /// for comparing levels. Silence is negative infinity.
+    pub fn loudness_lufs(&self) -> f32 {
+        const BLOCK_SECS: f32 = 0.4;
+        const ABSOLUTE_GATE_LUFS: f32 = -70.0;
+        const RELATIVE_GATE_LU: f32 = -10.0;
+
+        let loudness = |mean_square: f32| -0.691 + 10.0 * mean_square.log10();
+        let mean = |blocks: &[f32]| blocks.iter().sum::<f32>() / blocks.len() as f32;
+
+        if self.is_empty() {
+            return f32::NEG_INFINITY;
+        }
+
+        let weighted = self.filter_chain(&BiquadChain::k_weighting(self.sample_rate()));
+        let channels = self.channels() as usize;
+
+        // Blocks overlap by three quarters, with waveforms shorter than a
+        // block measured as a single block
+        let block = ((BLOCK_SECS * self.sample_rate() as f32) as usize).clamp(1, self.frames());
+        let hop = (block / 4).max(1);
+
+        // The mean square of each block, summed over channels
+        let blocks = (0..=self.frames().saturating_sub(block))
+            .step_by(hop)
+            .map(|start| weighted.slice(start * channels..(start + block) * channels))
+            .map(|block| {
+                block
+                    .samples_iter()
+                    .map(|sample| sample * sample)
+                    .sum::<f32>()
+                    / block.frames() as f32
+            })
+            .filter(|&mean_square| loudness(mean_square) > ABSOLUTE_GATE_LUFS)
+            .collect::<Vec<_>>();
+
+        if blocks.is_empty() {
+            return f32::NEG_INFINITY;
+        }
+
+        let relative_gate = loudness(mean(&blocks)) + RELATIVE_GATE_LU;
+        let gated = blocks
+            .into_iter()
+            .filter(|&mean_square| loudness(mean_square) > relative_gate)
+            .collect::<Vec<_>>();
+
+        loudness(mean(&gated))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts;
+
+    use super::{Biquad, BiquadChain, Equalizer};
+    use crate::waveform::Waveform;
+
+    /// The change in level through the filter, ignoring the initial transient
+    fn attenuation_db(biquad: &Biquad, frequency: f32) -> f32 {
+        let sine = Waveform::sine_wave(frequency, 1.0, Waveform::CD_SAMPLE_RATE);
+        let filtered = sine.filter(biquad);
+
+        let settled = 4_410..;
+        20.0 * (filtered.slice(settled.clone()).rms() / sine.slice(settled).rms()).log10()
+    }
+
+    #[test]
+    fn high_pass() {
+        let high_pass = Biquad::high_pass(1_000.0, consts::FRAC_1_SQRT_2, Waveform::CD_SAMPLE_RATE);
+
+        // A second order filter falls off at 40dB per decade
+        assert!((attenuation_db(&high_pass, 100.0) + 40.0).abs() < 1.0);
+        assert!(attenuation_db(&high_pass, 10_000.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn low_pass() {
+        let low_pass = Biquad::low_pass(1_000.0, consts::FRAC_1_SQRT_2, Waveform::CD_SAMPLE_RATE);
+
+        assert!(attenuation_db(&low_pass, 100.0).abs() < 0.5);
+        assert!(attenuation_db(&low_pass, 10_000.0) < -35.0);
+    }
+
+    #[test]
+    fn band_pass() {
+        let band_pass = Biquad::band_pass(440.0, 5.0, Waveform::CD_SAMPLE_RATE);
+
+        assert!(attenuation_db(&band_pass, 440.0).abs() < 0.5);
+        assert!(attenuation_db(&band_pass, 880.0) < -12.0);
+    }
+
+    #[test]
+    fn peaking() {
+        let peaking = Biquad::peaking(1_000.0, 2.0, 6.0, Waveform::CD_SAMPLE_RATE);
+
+        assert!((attenuation_db(&peaking, 1_000.0) - 6.0).abs() < 0.5);
+        assert!(attenuation_db(&peaking, 100.0).abs() < 0.5);
+        assert!(attenuation_db(&peaking, 10_000.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn flat_equalizer() {
+        let mut equalizer = Equalizer::graphic();
+        assert!(equalizer.is_flat() && equalizer.chain(Waveform::CD_SAMPLE_RATE).is_empty());
+
+        equalizer.bands[5].gain_db = 3.0;
+        equalizer.bands[9].gain_db = 3.0;
+        // 16kHz is above nyquist at 16kHz
+        assert_eq!(equalizer.chain(16_000).len(), 1);
+
+        equalizer.flatten();
+        assert!(equalizer.is_flat());
+    }
+
+    #[test]
+    fn a_weighting() {
+        let level_db = |frequency| {
+            let sine = Waveform::sine_wave(frequency, 1.0, Waveform::CD_SAMPLE_RATE);
+            let weighted = sine.a_weighted();
+
+            let settled = 4_410..;
+            20.0 * (weighted.slice(settled.clone()).rms() / sine.slice(settled).rms()).log10()
+        };
+
+        assert!(level_db(1_000.0).abs() < 0.5);
+        // The standard curve is -19.1dB at 100Hz
+        assert!((level_db(100.0) + 19.1).abs() < 1.0);
+    }
+
+    #[test]
+    fn loudness() -> color_eyre::Result<()> {
+        // A full scale sine at 1kHz is -3LUFS
+        let sine = Waveform::sine_wave(1_000.0, 2.0, Waveform::CD_SAMPLE_RATE);
+        assert!((sine.loudness_lufs() + 3.0).abs() < 0.5);
+
+        // Halving the level takes 6dB off
+        let difference = sine.loudness_lufs() - sine.gain(0.5).loudness_lufs();
+        assert!((difference - 6.02).abs() < 0.1);
+
+        // Quiet passages are gated out instead of dragging the loudness down
+        let silence = Waveform::new_mono(vec![0.0; 44_100], Waveform::CD_SAMPLE_RATE);
+        assert_eq!(silence.loudness_lufs(), f32::NEG_INFINITY);
+        let gapped = Waveform::concat(&[&sine, &silence])?;
+        assert!((gapped.loudness_lufs() - sine.loudness_lufs()).abs() < 0.5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn chain() {
+        let sample_rate = Waveform::CD_SAMPLE_RATE;
+        let band_pass = Biquad::band_pass(440.0, 5.0, sample_rate);
+
+        let chain = BiquadChain::new().with(band_pass).with(band_pass);
+
+        let sine = Waveform::sine_wave(880.0, 1.0, sample_rate);
+        let settled = 4_410..;
+
+        // Two filters in series attenuate twice as much as one
+        let once = sine.filter(&band_pass).slice(settled.clone()).rms();
+        let twice = sine.filter_chain(&chain).slice(settled.clone()).rms();
+        let sine = sine.slice(settled).rms();
+
+        let once_db = 20.0 * (once / sine).log10();
+        let twice_db = 20.0 * (twice / sine).log10();
+        assert!((twice_db - 2.0 * once_db).abs() < 0.5);
+    }
+}
@@ -1,23 +1,83 @@
+use std::{
+    fmt::{self, Debug},
+    sync::{atomic::Ordering, mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+use atomic::Atomic;
 use color_eyre::eyre::{Context, ContextCompat};
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
-    SampleRate, StreamConfig, StreamError,
+    Device, Stream, StreamConfig, StreamError,
 };
-use tracing::error;
+use tracing::{error, warn};
 
-pub fn read_one_second() -> color_eyre::Result<(Vec<f32>, SampleRate)> {
+use crate::waveform::Waveform;
+
+/// The names of the available input devices. Devices which can not be
+/// enumerated are logged and left out.
+pub fn list_input_devices() -> Vec<String> {
     let host = cpal::default_host();
 
-    let input_device = host
-        .default_input_device()
-        .wrap_err("failed to get the default input device")?;
+    let input_devices = match host.input_devices() {
+        Ok(input_devices) => input_devices,
+        Err(err) => {
+            warn!(%err, "failed to enumerate the input devices");
+
+            return Vec::new();
+        }
+    };
 
-    let config: StreamConfig = input_device
+    input_devices
+        .filter_map(|device| match device.name() {
+            Ok(name) => Some(name),
+            Err(err) => {
+                warn!(%err, "failed to get the name of an input device");
+
+                None
+            }
+        })
+        .collect()
+}
+
+/// Find the input device called `name`, or the default one
+fn input_device(name: Option<&str>) -> color_eyre::Result<Device> {
+    let host = cpal::default_host();
+
+    match name {
+        Some(name) => host
+            .input_devices()
+            .wrap_err("failed to enumerate the input devices")?
+            .find(|device| device.name().map_or(false, |device| device == name))
+            .wrap_err_with(|| format!("no input device named {name:?}")),
+        None => host
+            .default_input_device()
+            .wrap_err("failed to get the default input device"),
+    }
+}
+
+fn input_config(device: &Device) -> color_eyre::Result<StreamConfig> {
+    Ok(device
         .default_input_config()
         .wrap_err("failed to get default input config")?
-        .into();
+        .into())
+}
 
-    let (send, recv) = std::sync::mpsc::channel();
+/// Record `duration` of audio from the input device called `device`, or the
+/// default input device if there is no name.
+///
+/// A zero duration returns straight away with an empty mono waveform, without
+/// opening any device.
+pub fn record(duration: Duration, device: Option<&str>) -> color_eyre::Result<Waveform<'static>> {
+    if duration.is_zero() {
+        return Ok(Waveform::new_mono(Vec::new(), Waveform::CD_SAMPLE_RATE));
+    }
+
+    let input_device = input_device(device)?;
+    let config = input_config(&input_device)?;
+
+    let (send, recv) = mpsc::channel();
 
     let input_stream = input_device
         .build_input_stream(
@@ -33,16 +93,157 @@ pub fn read_one_second() -> color_eyre::Result<(Vec<f32>, SampleRate)> {
 
     input_stream.play()?;
 
-    std::thread::sleep(std::time::Duration::from_secs(1));
+    thread::sleep(duration);
 
     drop(input_stream);
 
-    Ok((
-        recv.iter()
-            .flatten()
-            .step_by(config.channels as usize)
-            .map(|x| x * 10.0)
-            .collect::<Vec<_>>(),
-        config.sample_rate,
+    let frames = (duration.as_secs_f64() * config.sample_rate.0 as f64).round() as usize;
+
+    let mut samples = recv.iter().flatten().collect::<Vec<_>>();
+    samples.truncate(frames * config.channels as usize);
+
+    Ok(Waveform::new(
+        samples,
+        config.sample_rate.0,
+        config.channels,
     ))
 }
+
+/// The levels of the most recent block of audio, shared between an input
+/// callback and whatever draws them
+#[derive(Debug, Default)]
+pub struct LevelMeter {
+    peak: Atomic<f32>,
+    rms: Atomic<f32>,
+}
+
+impl LevelMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Measure a new block of audio, across all of its channels
+    pub fn update(&self, block: &Waveform) {
+        self.peak.store(block.peak(), Ordering::Relaxed);
+        self.rms.store(block.rms(), Ordering::Relaxed);
+    }
+
+    /// The largest absolute sample in the last block
+    pub fn peak(&self) -> f32 {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// The root mean square of the last block
+    pub fn rms(&self) -> f32 {
+        self.rms.load(Ordering::Relaxed)
+    }
+}
+
+/// An ongoing recording, which stops once dropped
+pub struct Recording {
+    config: StreamConfig,
+    level_meter: Arc<LevelMeter>,
+
+    _input_stream: Stream,
+}
+
+impl Debug for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recording")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Recording {
+    pub fn sample_rate(&self) -> u32 {
+        self.config.sample_rate.0
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.config.channels
+    }
+
+    /// The levels of the audio being recorded, updated as each chunk arrives
+    pub fn level_meter(&self) -> Arc<LevelMeter> {
+        self.level_meter.clone()
+    }
+}
+
+/// Continuously record from the input device called `device`, or the default
+/// input device if there is no name, passing each chunk of audio to `callback`
+/// as it arrives until the returned [`Recording`] is dropped.
+pub fn record_stream(
+    device: Option<&str>,
+    mut callback: impl FnMut(Waveform<'static>) + Send + 'static,
+) -> color_eyre::Result<Recording> {
+    let input_device = input_device(device)?;
+    let config = input_config(&input_device)?;
+
+    let level_meter = Arc::new(LevelMeter::new());
+
+    let input_stream = input_device
+        .build_input_stream(
+            &config,
+            {
+                let config = config.clone();
+                let level_meter = level_meter.clone();
+
+                move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                    // Samples arrive interleaved across every channel
+                    let chunk = Waveform::new(data.to_vec(), config.sample_rate.0, config.channels);
+
+                    level_meter.update(&chunk);
+                    callback(chunk);
+                }
+            },
+            |err: StreamError| {
+                error!(%err, "an error occurred on the input stream");
+            },
+        )
+        .wrap_err("failed to build input stream")?;
+
+    input_stream
+        .play()
+        .wrap_err("failed to start the input stream")?;
+
+    Ok(Recording {
+        config,
+        level_meter,
+        _input_stream: input_stream,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{record, LevelMeter};
+    use crate::waveform::Waveform;
+
+    #[test]
+    fn record_nothing() -> color_eyre::Result<()> {
+        let start = Instant::now();
+
+        let waveform = record(Duration::ZERO, None)?;
+
+        assert!(waveform.is_empty());
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        Ok(())
+    }
+
+    #[test]
+    fn level_meter() {
+        let meter = LevelMeter::new();
+        assert_eq!((meter.peak(), meter.rms()), (0.0, 0.0));
+
+        // A quiet left channel and a loud right channel
+        meter.update(&Waveform::new(vec![0.5, -1.0, -0.5, 1.0], 48_000, 2));
+        assert_eq!(meter.peak(), 1.0);
+        assert!((meter.rms() - 0.625f32.sqrt()).abs() < 1e-6);
+
+        meter.update(&Waveform::new_mono(vec![0.0; 4], 48_000));
+        assert_eq!((meter.peak(), meter.rms()), (0.0, 0.0));
+    }
+}
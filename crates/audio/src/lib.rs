@@ -12,6 +12,10 @@ pub mod input;
 #[cfg(feature = "io")]
 pub mod output;
 
+pub mod effect;
+pub mod filter;
+pub mod ring;
+pub mod wav;
 pub mod waveform;
 
 #[cfg(feature = "cpal")]
@@ -1,21 +1,21 @@
 use std::{
     fmt::{self, Debug},
-    iter,
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        mpsc::{self, Sender, TryRecvError},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender, TryRecvError},
         Arc, Once,
     },
 };
 
+use atomic::Atomic;
 use color_eyre::eyre::{Context, ContextCompat};
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
-    Stream, StreamConfig,
+    Device, SampleFormat, Stream, StreamConfig,
 };
-use tracing::{debug, error, trace};
+use tracing::{debug, error, trace, warn};
 
-use crate::waveform::Waveform;
+use crate::waveform::{ResampleQuality, Waveform};
 
 #[derive(Debug, Clone, Copy)]
 pub enum AudioSinkProgress {
@@ -25,19 +25,56 @@ pub enum AudioSinkProgress {
 
 type AudioSinkCallback = Box<dyn Fn(AudioSinkProgress) + Send>;
 
+/// A waveform waiting in the sink's queue
+struct QueuedWaveform {
+    waveform: Waveform<'static>,
+    callback: AudioSinkCallback,
+    /// Repeat the waveform until the sink is stopped
+    looping: bool,
+}
+
 pub struct AudioSink {
     // FIXME: channels are broken on web assembly due to lack of condvar support.
     // TODO: use a mutex instead
-    samples_sender: Sender<(Waveform<'static>, AudioSinkCallback)>,
+    samples_sender: Sender<QueuedWaveform>,
     config: StreamConfig,
+    /// The interpolation used for waveforms which are not already at the
+    /// output's sample rate
+    resample_quality: ResampleQuality,
 
-    queue_length: Arc<AtomicUsize>,
+    transport: Arc<Transport>,
 
     // Field (drop) ordering here is very important, the sender must be dropped
     // before the stream can be dropped to prevent deadlocking
     _output_stream: Stream,
 }
 
+/// The loudest volume a sink can be set to, a gain of 12dB
+pub const MAX_VOLUME: f32 = 4.0;
+
+/// State shared between the sink and its output callback
+#[derive(Debug)]
+struct Transport {
+    queue_length: AtomicUsize,
+    paused: AtomicBool,
+    stop_requested: AtomicBool,
+    volume: Atomic<f32>,
+    /// Frames of the current waveform which have been output
+    position: AtomicUsize,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Self {
+            queue_length: AtomicUsize::new(0),
+            paused: AtomicBool::new(false),
+            stop_requested: AtomicBool::new(false),
+            volume: Atomic::new(1.0),
+            position: AtomicUsize::new(0),
+        }
+    }
+}
+
 impl Debug for AudioSink {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("AudioSink").finish()
@@ -45,6 +82,7 @@ impl Debug for AudioSink {
 }
 
 impl AudioSink {
+    /// Play through the default output device
     pub fn new() -> color_eyre::Result<Self> {
         let host = cpal::default_host();
 
@@ -52,141 +90,622 @@ impl AudioSink {
             .default_output_device()
             .wrap_err("no default output device")?;
 
-        let config: StreamConfig = output_device
-            .default_output_config()
-            .wrap_err("no default output config")?
-            .into();
+        Self::from_device(output_device)
+    }
 
-        let (samples_sender, samples_receiver) =
-            mpsc::channel::<(Waveform<'static>, AudioSinkCallback)>();
+    /// Play through the output device called `name`, as listed by
+    /// [`AudioSink::list_output_devices`]
+    pub fn with_device(name: &str) -> color_eyre::Result<Self> {
+        let host = cpal::default_host();
 
-        let queue_length = Arc::new(AtomicUsize::new(0));
+        let mut output_devices = host
+            .output_devices()
+            .wrap_err("failed to enumerate the output devices")?;
 
-        let output_stream = output_device
-            .build_output_stream(
-                &config,
-                {
-                    // Mutable closure state
-                    let mut starting_samples = 0;
-                    let mut working_samples = Vec::new();
-                    let mut working_callback: AudioSinkCallback = Box::new(|_| {}); // TODO: Option?
-
-                    // Immutable closure state
-                    let config = config.clone();
-                    let queue_length = queue_length.clone();
-
-                    let mut playing = false;
-
-                    // TODO: clean up this closure
-                    move |data: &mut [f32], _info| {
-                        if working_samples.is_empty() {
-                            if playing {
-                                queue_length.fetch_update(
-                                    Ordering::SeqCst,
-                                    Ordering::SeqCst,
-                                    |queue_length| Some(queue_length.saturating_sub(1))
-                                ).ok();
-                                working_callback(AudioSinkProgress::Finished);
-                            }
-
-                            playing = false;
-
-                            match samples_receiver.try_recv() {
-                                Ok((new_samples, new_callback)) => {
-                                    assert_eq!(new_samples.sample_rate(), config.sample_rate.0);
-
-                                    trace!("Received {} new samples", new_samples.len());
-
-                                    working_samples = new_samples.as_samples();
-                                    working_callback = new_callback;
-                                    starting_samples = working_samples.len();
-                                },
-                                Err(e) => {
-                                    data.fill(0.0);
-
-                                    match e {
-                                        TryRecvError::Empty => std::hint::spin_loop(),
-                                        TryRecvError::Disconnected => {
-                                            static ONCE: Once = Once::new();
-
-                                            ONCE.call_once(|| {
-                                                debug!("Sample channel has hung up, looping until the stream closes");
-                                            });
-                                        },
-                                    }
-
-                                    return;
-                                },
-                            }
-                        }
-                        playing = true;
+        let output_device = output_devices
+            .find(|device| device.name().map_or(false, |device| device == name))
+            .wrap_err_with(|| format!("no output device named {name:?}"))?;
 
-                        // Run the callback
-                        working_callback(AudioSinkProgress::Samples((starting_samples - working_samples.len()) as f32 / starting_samples as f32));
+        Self::from_device(output_device)
+    }
 
-                        // Happy path if one channel
-                        if config.channels == 1 {
-                            let length = data.len().min(working_samples.len());
+    /// The names of the available output devices. Devices which can not be
+    /// enumerated are logged and left out.
+    pub fn list_output_devices() -> Vec<String> {
+        let host = cpal::default_host();
 
-                            data.copy_from_slice(&working_samples[..length]);
+        let output_devices = match host.output_devices() {
+            Ok(output_devices) => output_devices,
+            Err(err) => {
+                warn!(%err, "failed to enumerate the output devices");
+
+                return Vec::new();
+            }
+        };
+
+        output_devices
+            .filter_map(|device| match device.name() {
+                Ok(name) => Some(name),
+                Err(err) => {
+                    warn!(%err, "failed to get the name of an output device");
+
+                    None
+                }
+            })
+            .collect()
+    }
 
-                            // Remove the copied samples
-                            working_samples.drain(..length);
+    fn from_device(output_device: Device) -> color_eyre::Result<Self> {
+        let name = output_device
+            .name()
+            .unwrap_or_else(|_| "unknown device".to_string());
 
-                            return;
-                        }
+        let default_config = output_device
+            .default_output_config()
+            .wrap_err_with(|| format!("no default output config for {name}"))?;
+
+        // The output callback writes f32 samples, so prefer the default config
+        // but fall back to an f32 one at the same sample rate
+        let config: StreamConfig = if default_config.sample_format() == SampleFormat::F32 {
+            default_config.into()
+        } else {
+            output_device
+                .supported_output_configs()
+                .wrap_err_with(|| format!("failed to get the supported configs for {name}"))?
+                .find(|config| {
+                    config.sample_format() == SampleFormat::F32
+                        && (config.min_sample_rate()..=config.max_sample_rate())
+                            .contains(&default_config.sample_rate())
+                })
+                .wrap_err_with(|| {
+                    format!(
+                        "{name} does not support f32 samples at {}Hz",
+                        default_config.sample_rate().0
+                    )
+                })?
+                .with_sample_rate(default_config.sample_rate())
+                .into()
+        };
+
+        debug!(%name, ?config, "opening output device");
+
+        let (samples_sender, samples_receiver) = mpsc::channel::<QueuedWaveform>();
+
+        let transport = Arc::new(Transport::default());
 
-                        // Normal path for multi-channel
-                        let windows = data.chunks_exact_mut(config.channels.into());
-                        let length = windows.len().min(working_samples.len());
-                        let drain = working_samples.drain(..length);
+        let output_stream = output_device
+            .build_output_stream(
+                &config,
+                {
+                    let mut output =
+                        OutputState::new(samples_receiver, config.clone(), transport.clone());
 
-                        for (frame, value) in windows.zip(drain.chain(iter::repeat(0.0))) {
-                            for sample in frame {
-                                *sample = value;
-                            }
-                        }
-                    }
+                    move |data: &mut [f32], _info| output.fill(data)
                 },
                 |err| {
                     error!(%err, "an error occurred on the output stream");
                 },
             )
-            .wrap_err("failed to build output stream")?;
+            .wrap_err_with(|| format!("failed to build an output stream for {name}"))?;
 
         output_stream
             .play()
             .wrap_err("failed to start the output stream")?;
 
         Ok(Self {
-            queue_length,
+            transport,
             _output_stream: output_stream,
             samples_sender,
             config,
+            resample_quality: ResampleQuality::Sinc(16),
         })
     }
 
+    /// The number of waveforms playing or waiting to be played, including
+    /// while paused
     pub fn queue_length(&self) -> usize {
-        self.queue_length.load(Ordering::SeqCst)
+        self.transport.queue_length.load(Ordering::SeqCst)
     }
 
+    /// Check if there is a waveform being played, which is not the case while paused
     pub fn playing(&self) -> bool {
-        self.queue_length() >= 1
+        self.queue_length() >= 1 && !self.paused()
+    }
+
+    pub fn paused(&self) -> bool {
+        self.transport.paused.load(Ordering::SeqCst)
+    }
+
+    /// Output silence, holding the position in the current waveform until resumed
+    pub fn pause(&self) {
+        self.transport.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.transport.paused.store(false, Ordering::SeqCst);
+    }
+
+    /// Discard the current waveform and everything queued behind it, finishing
+    /// each of their callbacks. This also resumes a paused sink.
+    pub fn stop(&self) {
+        self.transport.stop_requested.store(true, Ordering::SeqCst);
+        self.resume();
+    }
+
+    /// The number of samples per channel of the current waveform which have
+    /// been played, at the output's sample rate
+    pub fn position_samples(&self) -> usize {
+        self.transport.position.load(Ordering::SeqCst)
+    }
+
+    /// How far through the current waveform playback is
+    pub fn position_secs(&self) -> f32 {
+        self.position_samples() as f32 / self.config.sample_rate.0 as f32
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.transport.volume.load(Ordering::SeqCst)
+    }
+
+    /// Scale every sample played by `volume`, clamped between silence at `0.0`
+    /// and [`MAX_VOLUME`]. Volumes above `1.0` amplify the waveforms, which will
+    /// clip any samples pushed outside of -1.0 to 1.0.
+    pub fn set_volume(&self, volume: f32) {
+        // Also replaces NaN with silence
+        let volume = volume.max(0.0).min(MAX_VOLUME);
+
+        self.transport.volume.store(volume, Ordering::SeqCst);
     }
 
+    /// Play `waveform` once every waveform queued before it has finished
     pub fn queue(
         &self,
         waveform: &Waveform<'_>,
         callback: impl Fn(AudioSinkProgress) + Send + 'static,
     ) -> bool {
-        let resampled_waveform = waveform.resample(self.config.sample_rate.0);
+        self.send(waveform, Box::new(callback), false)
+    }
 
-        let send_result = self
-            .samples_sender
-            .send((resampled_waveform, Box::new(callback)));
+    /// Play `waveform` once every waveform queued before it has finished, then
+    /// repeat it without any gap until the sink is stopped. The callback's
+    /// progress starts over with each repeat, and it is only finished by
+    /// [`AudioSink::stop`].
+    pub fn queue_looping(
+        &self,
+        waveform: &Waveform<'_>,
+        callback: impl Fn(AudioSinkProgress) + Send + 'static,
+    ) -> bool {
+        self.send(waveform, Box::new(callback), true)
+    }
 
-        self.queue_length.fetch_add(1, Ordering::SeqCst);
+    fn send(&self, waveform: &Waveform<'_>, callback: AudioSinkCallback, looping: bool) -> bool {
+        let sample_rate = self.config.sample_rate.0;
+
+        let mono;
+        let waveform = if waveform.channels() == 1 || waveform.channels() == self.config.channels {
+            waveform
+        } else {
+            // Downmix sources that can not be mapped onto the output channels
+            mono = waveform.to_mono();
+            &mono
+        };
+
+        let resampled_waveform = if waveform.sample_rate() == sample_rate {
+            waveform.to_owned()
+        } else {
+            waveform.resample_with(sample_rate, self.resample_quality)
+        };
+
+        // Count the waveform before sending it, so the output callback can not
+        // finish it first
+        self.transport.queue_length.fetch_add(1, Ordering::SeqCst);
+
+        let send_result = self.samples_sender.send(QueuedWaveform {
+            waveform: resampled_waveform,
+            callback,
+            looping,
+        });
+
+        if send_result.is_err() {
+            self.transport.queue_length.fetch_sub(1, Ordering::SeqCst);
+        }
 
         send_result.is_ok()
     }
 }
+
+/// The state of the output stream's callback
+struct OutputState {
+    samples_receiver: Receiver<QueuedWaveform>,
+    config: StreamConfig,
+    transport: Arc<Transport>,
+
+    starting_samples: usize,
+    working_samples: Vec<f32>,
+    working_channels: u16,
+    working_callback: Option<AudioSinkCallback>,
+    /// A copy of the working waveform to start over from, if it is looping
+    looping_samples: Option<Vec<f32>>,
+}
+
+impl OutputState {
+    fn new(
+        samples_receiver: Receiver<QueuedWaveform>,
+        config: StreamConfig,
+        transport: Arc<Transport>,
+    ) -> Self {
+        Self {
+            samples_receiver,
+            config,
+            transport,
+            starting_samples: 0,
+            working_samples: Vec::new(),
+            working_channels: 1,
+            working_callback: None,
+            looping_samples: None,
+        }
+    }
+
+    /// Finish the working waveform, if there is one
+    fn finish(&mut self) {
+        if let Some(callback) = self.working_callback.take() {
+            self.working_samples.clear();
+            self.looping_samples = None;
+            self.transport
+                .queue_length
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queue_length| {
+                    Some(queue_length.saturating_sub(1))
+                })
+                .ok();
+
+            callback(AudioSinkProgress::Finished);
+        }
+    }
+
+    /// Start the working waveform over if it is looping and has run out
+    fn rewind(&mut self) -> bool {
+        match &self.looping_samples {
+            Some(samples) if self.working_samples.is_empty() && !samples.is_empty() => {
+                self.working_samples.extend_from_slice(samples);
+                self.transport.position.store(0, Ordering::SeqCst);
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn fill(&mut self, data: &mut [f32]) {
+        if self.transport.stop_requested.swap(false, Ordering::SeqCst) {
+            self.finish();
+
+            // Drain everything that was queued behind the working waveform
+            while let Ok(queued) = self.samples_receiver.try_recv() {
+                self.working_callback = Some(queued.callback);
+                self.finish();
+            }
+        }
+
+        if self.transport.paused.load(Ordering::SeqCst) {
+            data.fill(0.0);
+
+            return;
+        }
+
+        if self.working_samples.is_empty() && !self.rewind() {
+            self.finish();
+
+            match self.samples_receiver.try_recv() {
+                Ok(queued) => {
+                    let new_samples = queued.waveform;
+                    assert_eq!(new_samples.sample_rate(), self.config.sample_rate.0);
+
+                    trace!("Received {} new samples", new_samples.len());
+
+                    self.working_channels = new_samples.channels();
+                    self.working_samples = new_samples.as_samples();
+                    self.working_callback = Some(queued.callback);
+                    self.starting_samples = self.working_samples.len();
+                    self.looping_samples = queued.looping.then(|| self.working_samples.clone());
+                    self.transport.position.store(0, Ordering::SeqCst);
+                }
+                Err(e) => {
+                    data.fill(0.0);
+
+                    match e {
+                        TryRecvError::Empty => std::hint::spin_loop(),
+                        TryRecvError::Disconnected => {
+                            static ONCE: Once = Once::new();
+
+                            ONCE.call_once(|| {
+                                debug!(
+                                    "Sample channel has hung up, looping until the stream closes"
+                                );
+                            });
+                        }
+                    }
+
+                    return;
+                }
+            }
+        }
+
+        // Run the callback
+        if let Some(callback) = &self.working_callback {
+            callback(AudioSinkProgress::Samples(
+                (self.starting_samples - self.working_samples.len()) as f32
+                    / self.starting_samples as f32,
+            ));
+        }
+
+        let volume = self.transport.volume.load(Ordering::SeqCst);
+
+        let mut written = 0;
+        loop {
+            let wrote = self.write(&mut data[written..], volume);
+            written += wrote;
+
+            // Looping waveforms carry straight on from their start
+            if wrote == 0 || written == data.len() || !self.rewind() {
+                break;
+            }
+        }
+
+        data[written..].fill(0.0);
+    }
+
+    /// Write as much of the working waveform as fits into `data`, returning the
+    /// number of output samples written
+    fn write(&mut self, data: &mut [f32], volume: f32) -> usize {
+        // Happy path if the source channels line up with the output
+        if self.working_channels == self.config.channels {
+            let length = data.len().min(self.working_samples.len());
+
+            for (output, sample) in data.iter_mut().zip(&self.working_samples[..length]) {
+                *output = sample * volume;
+            }
+
+            // Remove the copied samples
+            self.working_samples.drain(..length);
+            self.transport
+                .position
+                .fetch_add(length / self.working_channels as usize, Ordering::SeqCst);
+
+            return length;
+        }
+
+        // Mono source, copied to every output channel
+        let channels = self.config.channels as usize;
+        let windows = data.chunks_exact_mut(channels);
+        let length = windows.len().min(self.working_samples.len());
+        self.transport.position.fetch_add(length, Ordering::SeqCst);
+
+        for (frame, value) in windows.zip(self.working_samples.drain(..length)) {
+            for sample in frame {
+                *sample = value * volume;
+            }
+        }
+
+        length * channels
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    };
+
+    use cpal::{BufferSize, SampleRate, StreamConfig};
+
+    use super::{AudioSink, AudioSinkProgress, OutputState, QueuedWaveform, Transport};
+    use crate::waveform::Waveform;
+
+    #[test]
+    fn pause_resume_stop() {
+        let (sender, receiver) = mpsc::channel::<QueuedWaveform>();
+        let transport = Arc::new(Transport::default());
+        let config = StreamConfig {
+            channels: 1,
+            sample_rate: SampleRate(10),
+            buffer_size: BufferSize::Default,
+        };
+
+        let mut output = OutputState::new(receiver, config, transport.clone());
+
+        let finished = Arc::new(AtomicUsize::new(0));
+        let queue = |samples: Vec<f32>| {
+            let finished = finished.clone();
+
+            transport.queue_length.fetch_add(1, Ordering::SeqCst);
+            assert!(sender
+                .send(QueuedWaveform {
+                    waveform: Waveform::new_mono(samples, 10),
+                    callback: Box::new(move |progress| {
+                        if let AudioSinkProgress::Finished = progress {
+                            finished.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }),
+                    looping: false,
+                })
+                .is_ok());
+        };
+
+        let mut data = [0.0; 2];
+
+        queue(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        output.fill(&mut data);
+        assert_eq!(data, [1.0, 2.0]);
+
+        // Silence while paused, without losing the position
+        transport.paused.store(true, Ordering::SeqCst);
+        output.fill(&mut data);
+        assert_eq!(data, [0.0, 0.0]);
+        output.fill(&mut data);
+        assert_eq!(data, [0.0, 0.0]);
+        assert_eq!(transport.queue_length.load(Ordering::SeqCst), 1);
+
+        transport.paused.store(false, Ordering::SeqCst);
+        output.fill(&mut data);
+        assert_eq!(data, [3.0, 4.0]);
+
+        // Stopping discards the rest of the waveform and everything queued
+        queue(vec![7.0, 8.0]);
+        assert_eq!(transport.queue_length.load(Ordering::SeqCst), 2);
+
+        transport.stop_requested.store(true, Ordering::SeqCst);
+        output.fill(&mut data);
+        assert_eq!(data, [0.0, 0.0]);
+        assert_eq!(transport.queue_length.load(Ordering::SeqCst), 0);
+        assert_eq!(finished.load(Ordering::SeqCst), 2);
+        assert!(!transport.stop_requested.load(Ordering::SeqCst));
+
+        // Playback carries on normally afterwards
+        queue(vec![9.0]);
+        output.fill(&mut data);
+        assert_eq!(data, [9.0, 0.0]);
+        output.fill(&mut data);
+        assert_eq!(finished.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn volume() {
+        let (sender, receiver) = mpsc::channel::<QueuedWaveform>();
+        let transport = Arc::new(Transport::default());
+        let config = StreamConfig {
+            channels: 2,
+            sample_rate: SampleRate(10),
+            buffer_size: BufferSize::Default,
+        };
+
+        let mut output = OutputState::new(receiver, config, transport.clone());
+
+        transport.volume.store(0.5, Ordering::SeqCst);
+
+        let mut data = [0.0; 4];
+        for waveform in [
+            Waveform::new(vec![1.0, -0.5, 0.25, 1.0], 10, 2),
+            // Mono waveforms are copied to both channels
+            Waveform::new_mono(vec![1.0, -0.5], 10),
+        ] {
+            assert!(sender
+                .send(QueuedWaveform {
+                    waveform,
+                    callback: Box::new(|_| {}),
+                    looping: false,
+                })
+                .is_ok());
+        }
+
+        output.fill(&mut data);
+        assert_eq!(data, [0.5, -0.25, 0.125, 0.5]);
+
+        output.fill(&mut data);
+        assert_eq!(data, [0.5, 0.5, -0.25, -0.25]);
+    }
+
+    #[test]
+    fn position() {
+        let (sender, receiver) = mpsc::channel::<QueuedWaveform>();
+        let transport = Arc::new(Transport::default());
+        let config = StreamConfig {
+            channels: 2,
+            sample_rate: SampleRate(10),
+            buffer_size: BufferSize::Default,
+        };
+
+        let mut output = OutputState::new(receiver, config, transport.clone());
+        let position = || transport.position.load(Ordering::SeqCst);
+
+        for waveform in [
+            Waveform::new(vec![0.0; 10], 10, 2),
+            Waveform::new_mono(vec![0.0; 3], 10),
+        ] {
+            assert!(sender
+                .send(QueuedWaveform {
+                    waveform,
+                    callback: Box::new(|_| {}),
+                    looping: false,
+                })
+                .is_ok());
+        }
+
+        // Two stereo frames per callback
+        let mut data = [0.0; 4];
+
+        output.fill(&mut data);
+        assert_eq!(position(), 2);
+        output.fill(&mut data);
+        assert_eq!(position(), 4);
+        output.fill(&mut data);
+        assert_eq!(position(), 5);
+
+        // Starting the mono waveform resets the count
+        output.fill(&mut data);
+        assert_eq!(position(), 2);
+        output.fill(&mut data);
+        assert_eq!(position(), 3);
+    }
+
+    #[test]
+    fn list_output_devices() {
+        // There may not be any devices, but listing them should never panic
+        let devices: Vec<String> = AudioSink::list_output_devices();
+
+        drop(devices);
+    }
+
+    #[test]
+    fn looping() {
+        let (sender, receiver) = mpsc::channel::<QueuedWaveform>();
+        let transport = Arc::new(Transport::default());
+        let config = StreamConfig {
+            channels: 1,
+            sample_rate: SampleRate(10),
+            buffer_size: BufferSize::Default,
+        };
+
+        let mut output = OutputState::new(receiver, config, transport.clone());
+
+        let finished = Arc::new(AtomicUsize::new(0));
+        transport.queue_length.fetch_add(1, Ordering::SeqCst);
+        assert!(sender
+            .send(QueuedWaveform {
+                waveform: Waveform::new_mono(vec![1.0, 2.0, 3.0], 10),
+                callback: Box::new({
+                    let finished = finished.clone();
+
+                    move |progress| {
+                        if let AudioSinkProgress::Finished = progress {
+                            finished.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }),
+                looping: true,
+            })
+            .is_ok());
+
+        // Wraps around without a gap, even in the middle of a buffer
+        let mut data = [0.0; 4];
+        output.fill(&mut data);
+        assert_eq!(data, [1.0, 2.0, 3.0, 1.0]);
+        output.fill(&mut data);
+        assert_eq!(data, [2.0, 3.0, 1.0, 2.0]);
+        output.fill(&mut data);
+        assert_eq!(data, [3.0, 1.0, 2.0, 3.0]);
+
+        // Ending exactly on the clip's end still starts it over
+        output.fill(&mut data);
+        assert_eq!(data, [1.0, 2.0, 3.0, 1.0]);
+
+        assert_eq!(finished.load(Ordering::SeqCst), 0);
+        assert_eq!(transport.queue_length.load(Ordering::SeqCst), 1);
+
+        transport.stop_requested.store(true, Ordering::SeqCst);
+        output.fill(&mut data);
+        assert_eq!(data, [0.0; 4]);
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+        assert_eq!(transport.queue_length.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::waveform::Waveform;
+
+/// A fixed capacity of the most recent samples from a stream of audio, such
+/// as the chunks arriving from an input device. Pushing samples once full
+/// overwrites the oldest ones, so the buffer never grows or reallocates.
+#[derive(Debug, Clone)]
+pub struct RingWaveform {
+    samples: Vec<f32>,
+    /// Where the next sample is written, which is also the oldest sample
+    next: usize,
+
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl RingWaveform {
+    /// A ring of `capacity` interleaved samples, starting out silent. The
+    /// capacity must be a whole number of frames.
+    pub fn new(capacity: usize, sample_rate: u32, channels: u16) -> Self {
+        assert_ne!(capacity, 0, "a ring waveform must hold at least one sample");
+        assert_ne!(
+            channels, 0,
+            "a ring waveform must have at least one channel"
+        );
+        assert_eq!(
+            capacity % channels as usize,
+            0,
+            "a ring waveform of {capacity} samples does not hold whole frames of {channels} channels"
+        );
+
+        Self {
+            samples: vec![0.0; capacity],
+            next: 0,
+            sample_rate,
+            channels,
+        }
+    }
+
+    /// A ring of interleaved samples lasting `duration` seconds
+    pub fn with_duration(duration: f32, sample_rate: u32, channels: u16) -> Self {
+        let frames = (duration * sample_rate as f32).round() as usize;
+
+        Self::new(frames.max(1) * channels as usize, sample_rate, channels)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Append interleaved `samples`, overwriting the oldest samples once the
+    /// ring is full. Only the end of a push larger than the whole ring is kept.
+    pub fn push(&mut self, samples: &[f32]) {
+        let capacity = self.capacity();
+        let samples = &samples[samples.len().saturating_sub(capacity)..];
+
+        // Copy in at most two runs, either side of the end of the buffer
+        let first = samples.len().min(capacity - self.next);
+        self.samples[self.next..self.next + first].copy_from_slice(&samples[..first]);
+        self.samples[..samples.len() - first].copy_from_slice(&samples[first..]);
+
+        self.next = (self.next + samples.len()) % capacity;
+    }
+
+    /// Copy out the `n` most recent samples from oldest to newest, or all of
+    /// them if there are fewer than `n`. For multichannel rings `n` is rounded
+    /// down to a whole number of frames.
+    pub fn latest(&self, n: usize) -> Waveform<'static> {
+        let capacity = self.capacity();
+        let n = n.min(capacity);
+        let n = n - n % self.channels as usize;
+        let start = (self.next + capacity - n) % capacity;
+
+        let samples = if start + n <= capacity {
+            self.samples[start..start + n].to_vec()
+        } else {
+            [
+                &self.samples[start..],
+                &self.samples[..start + n - capacity],
+            ]
+            .concat()
+        };
+
+        Waveform::new(samples, self.sample_rate, self.channels)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RingWaveform;
+
+    #[test]
+    fn overwrite_when_full() {
+        let mut ring = RingWaveform::new(4, 8, 1);
+        assert_eq!(ring.latest(4).samples(), [0.0; 4]);
+
+        ring.push(&[1.0, 2.0, 3.0]);
+        assert_eq!(ring.latest(2).samples(), [2.0, 3.0]);
+
+        // Wraps around over the oldest samples
+        ring.push(&[4.0, 5.0, 6.0]);
+        assert_eq!(ring.latest(4).samples(), [3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(ring.latest(10).samples(), [3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(ring.latest(3).samples(), [4.0, 5.0, 6.0]);
+
+        // More than fits at once only keeps the end
+        ring.push(&[7.0, 8.0, 9.0, 10.0, 11.0]);
+        assert_eq!(ring.latest(4).samples(), [8.0, 9.0, 10.0, 11.0]);
+        assert!(ring.latest(0).is_empty());
+
+        let latest = ring.latest(4);
+        assert_eq!((latest.sample_rate(), latest.channels()), (8, 1));
+    }
+
+    #[test]
+    fn many_small_pushes() {
+        let mut ring = RingWaveform::with_duration(0.5, 10, 2);
+        assert_eq!(ring.capacity(), 10);
+
+        for frame in 0..23 {
+            ring.push(&[frame as f32, -(frame as f32)]);
+        }
+
+        assert_eq!(
+            ring.latest(6).samples(),
+            [20.0, -20.0, 21.0, -21.0, 22.0, -22.0]
+        );
+    }
+
+    #[test]
+    fn stereo_past_capacity() {
+        let mut ring = RingWaveform::new(6, 8, 2);
+
+        ring.push(&[1.0, -1.0, 2.0, -2.0]);
+        ring.push(&[3.0, -3.0, 4.0, -4.0, 5.0, -5.0]);
+        assert_eq!(ring.latest(6).samples(), [3.0, -3.0, 4.0, -4.0, 5.0, -5.0]);
+
+        // Partial frames are left off the front
+        assert_eq!(ring.latest(5).samples(), [4.0, -4.0, 5.0, -5.0]);
+        assert_eq!(ring.latest(7).samples(), [3.0, -3.0, 4.0, -4.0, 5.0, -5.0]);
+        assert!(ring.latest(1).is_empty());
+
+        let latest = ring.latest(4);
+        assert_eq!((latest.frames(), latest.channels()), (2, 2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn partial_frame_capacity() {
+        RingWaveform::new(5, 8, 2);
+    }
+}
@@ -0,0 +1,474 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use color_eyre::eyre::{bail, ensure, eyre, Context};
+
+use crate::waveform::Waveform;
+
+const FORMAT_PCM: u16 = 0x0001;
+const FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// The sample encoding used when writing a WAV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WavSampleFormat {
+    #[default]
+    Pcm16,
+    Float32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct WavFormat {
+    format: u16,
+    channels: u16,
+    sample_rate: u32,
+    /// The bytes in each frame, which may pad samples out past their bit depth
+    block_align: u16,
+    bits_per_sample: u16,
+}
+
+impl Waveform<'static> {
+    /// Load a PCM or floating point WAV file, keeping all of its channels
+    pub fn from_wav_path(path: impl AsRef<Path>) -> color_eyre::Result<Self> {
+        let path = path.as_ref();
+
+        let file = File::open(path)
+            .wrap_err_with(|| format!("unable to open wav file {}", path.display()))?;
+
+        Self::from_wav_reader(BufReader::new(file))
+    }
+
+    /// Read a PCM or floating point WAV stream, keeping all of its channels
+    pub fn from_wav_reader(mut reader: impl Read + Seek) -> color_eyre::Result<Self> {
+        let mut header = [0; 12];
+        reader
+            .read_exact(&mut header)
+            .wrap_err("unable to read the riff header")?;
+
+        ensure!(&header[0..4] == b"RIFF", "file is not a riff file");
+        ensure!(
+            &header[8..12] == b"WAVE",
+            "riff file does not contain wave data"
+        );
+
+        let mut format = None;
+
+        loop {
+            let mut chunk_header = [0; 8];
+            reader
+                .read_exact(&mut chunk_header)
+                .wrap_err("wav file ended before the data chunk")?;
+
+            let id = &chunk_header[0..4];
+            let len = u32::from_le_bytes([
+                chunk_header[4],
+                chunk_header[5],
+                chunk_header[6],
+                chunk_header[7],
+            ]);
+
+            match id {
+                b"fmt " => {
+                    let chunk =
+                        read_chunk(&mut reader, len).wrap_err("unable to read the format chunk")?;
+
+                    format = Some(parse_format(&chunk)?);
+                }
+                b"data" => {
+                    let format = match format {
+                        Some(format) => format,
+                        None => bail!("wav data chunk came before the format chunk"),
+                    };
+
+                    let data =
+                        read_chunk(&mut reader, len).wrap_err("unable to read the data chunk")?;
+
+                    return Ok(Waveform::new(
+                        decode_samples(format, &data)?,
+                        format.sample_rate,
+                        format.channels,
+                    ));
+                }
+                _ => {
+                    // Chunks are padded to an even length
+                    let skip = len as i64 + (len % 2) as i64;
+
+                    reader
+                        .seek(SeekFrom::Current(skip))
+                        .wrap_err("unable to skip unknown wav chunk")?;
+                }
+            }
+        }
+    }
+}
+
+impl Waveform<'_> {
+    /// Save as a 16-bit PCM WAV file
+    pub fn write_wav_path(&self, path: impl AsRef<Path>) -> color_eyre::Result<()> {
+        self.write_wav_path_with(path, WavSampleFormat::default())
+    }
+
+    pub fn write_wav_path_with(
+        &self,
+        path: impl AsRef<Path>,
+        sample_format: WavSampleFormat,
+    ) -> color_eyre::Result<()> {
+        let path = path.as_ref();
+
+        let file = File::create(path)
+            .wrap_err_with(|| format!("unable to create wav file {}", path.display()))?;
+
+        self.write_wav_with(BufWriter::new(file), sample_format)
+    }
+
+    /// Write as a 16-bit PCM WAV stream
+    pub fn write_wav(&self, writer: impl Write + Seek) -> color_eyre::Result<()> {
+        self.write_wav_with(writer, WavSampleFormat::default())
+    }
+
+    pub fn write_wav_with(
+        &self,
+        mut writer: impl Write + Seek,
+        sample_format: WavSampleFormat,
+    ) -> color_eyre::Result<()> {
+        let (format, bits_per_sample) = match sample_format {
+            WavSampleFormat::Pcm16 => (FORMAT_PCM, 16u16),
+            WavSampleFormat::Float32 => (FORMAT_IEEE_FLOAT, 32u16),
+        };
+
+        let block_align =
+            u16::try_from(u32::from(self.channels()) * u32::from(bits_per_sample) / 8)
+                .wrap_err("waveform has too many channels to fit in a wav file")?;
+        let byte_rate = self
+            .sample_rate()
+            .checked_mul(u32::from(block_align))
+            .ok_or_else(|| eyre!("waveform sample rate is too high to fit in a wav file"))?;
+        let data_len = u32::try_from(self.len() * (bits_per_sample / 8) as usize)
+            .wrap_err("waveform is too long to fit in a wav file")?;
+        let riff_len = data_len
+            .checked_add(36)
+            .ok_or_else(|| eyre!("waveform is too long to fit in a wav file"))?;
+
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&riff_len.to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&format.to_le_bytes());
+        header.extend_from_slice(&self.channels().to_le_bytes());
+        header.extend_from_slice(&self.sample_rate().to_le_bytes());
+        header.extend_from_slice(&byte_rate.to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&bits_per_sample.to_le_bytes());
+
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_len.to_le_bytes());
+
+        writer
+            .write_all(&header)
+            .wrap_err("unable to write the wav header")?;
+
+        for sample in self.samples_iter() {
+            match sample_format {
+                WavSampleFormat::Pcm16 => {
+                    let sample = (sample * 32_768.0).round().clamp(-32_768.0, 32_767.0) as i16;
+
+                    writer.write_all(&sample.to_le_bytes())
+                }
+                WavSampleFormat::Float32 => writer.write_all(&sample.to_le_bytes()),
+            }
+            .wrap_err("unable to write wav samples")?;
+        }
+
+        writer.flush().wrap_err("unable to flush the wav writer")
+    }
+}
+
+/// Read a whole chunk, only allocating as much as the stream actually holds
+/// rather than trusting the length in its header
+fn read_chunk(reader: impl Read, len: u32) -> color_eyre::Result<Vec<u8>> {
+    let mut chunk = Vec::new();
+    reader.take(u64::from(len)).read_to_end(&mut chunk)?;
+
+    ensure!(
+        chunk.len() as u64 == u64::from(len),
+        "wav chunk declared {len} bytes but only {} remained",
+        chunk.len()
+    );
+
+    Ok(chunk)
+}
+
+fn parse_format(chunk: &[u8]) -> color_eyre::Result<WavFormat> {
+    ensure!(chunk.len() >= 16, "wav format chunk is too short");
+
+    let u16_at = |offset: usize| u16::from_le_bytes([chunk[offset], chunk[offset + 1]]);
+    let u32_at = |offset: usize| {
+        u32::from_le_bytes([
+            chunk[offset],
+            chunk[offset + 1],
+            chunk[offset + 2],
+            chunk[offset + 3],
+        ])
+    };
+
+    let mut format = u16_at(0);
+
+    // The real format is the start of the sub-format guid
+    if format == FORMAT_EXTENSIBLE {
+        ensure!(
+            chunk.len() >= 26,
+            "extensible wav format chunk is too short"
+        );
+
+        format = u16_at(24);
+    }
+
+    let format = WavFormat {
+        format,
+        channels: u16_at(2),
+        sample_rate: u32_at(4),
+        block_align: u16_at(12),
+        bits_per_sample: u16_at(14),
+    };
+
+    ensure!(format.channels > 0, "wav file has no channels");
+    ensure!(format.bits_per_sample > 0, "wav file has empty samples");
+    ensure!(
+        format.block_align % format.channels == 0
+            && u32::from(format.block_align / format.channels)
+                >= u32::from(format.bits_per_sample).div_ceil(8),
+        "wav block alignment of {} bytes does not fit {} channels of {} bit samples",
+        format.block_align,
+        format.channels,
+        format.bits_per_sample
+    );
+
+    Ok(format)
+}
+
+fn decode_samples(format: WavFormat, data: &[u8]) -> color_eyre::Result<Vec<f32>> {
+    let bytes_per_sample = usize::from(format.bits_per_sample).div_ceil(8);
+    let frame_len = format.block_align as usize;
+    let container_len = frame_len / format.channels as usize;
+
+    // Ignore any trailing partial frame
+    let data = &data[..data.len() - data.len() % frame_len];
+
+    // Samples are left justified in their containers, so any padding comes
+    // before the sample's little endian bytes
+    let samples = data
+        .chunks_exact(container_len)
+        .map(|container| &container[container_len - bytes_per_sample..]);
+
+    Ok(match (format.format, format.bits_per_sample) {
+        (FORMAT_PCM, 8) => samples
+            .map(|bytes| (bytes[0] as f32 - 128.0) / 128.0)
+            .collect(),
+        (FORMAT_PCM, 16) => samples
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32_768.0)
+            .collect(),
+        (FORMAT_PCM, 24) => samples
+            .map(|bytes| {
+                // Shift into the top of an i32 to sign extend
+                (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f32 / 8_388_608.0
+            })
+            .collect(),
+        (FORMAT_PCM, 32) => samples
+            .map(|bytes| {
+                i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32
+                    / 2_147_483_648.0
+            })
+            .collect(),
+        (FORMAT_IEEE_FLOAT, 32) => samples
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect(),
+        (FORMAT_IEEE_FLOAT, 64) => samples
+            .map(|bytes| {
+                f64::from_le_bytes([
+                    bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
+                ]) as f32
+            })
+            .collect(),
+        (format, bits) => bail!("unsupported wav format {format:#06x} with {bits} bits per sample"),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::WavSampleFormat;
+    use crate::waveform::Waveform;
+
+    fn wav_bytes(format: u16, channels: u16, bits: u16, data: &[u8]) -> Vec<u8> {
+        let sample_rate = 8_000u32;
+        let block_align = channels * bits / 8;
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(4 + 24 + 12 + 8 + data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&format.to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        bytes.extend_from_slice(&block_align.to_le_bytes());
+        bytes.extend_from_slice(&bits.to_le_bytes());
+
+        // An unknown chunk with an odd length, which must be skipped with its padding
+        bytes.extend_from_slice(b"junk");
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+
+        bytes
+    }
+
+    #[test]
+    fn pcm_16_stereo() -> color_eyre::Result<()> {
+        let data = [16_384i16, -16_384, 0, i16::MIN]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        let waveform = Waveform::from_wav_reader(Cursor::new(wav_bytes(1, 2, 16, &data)))?;
+
+        assert_eq!(waveform.sample_rate(), 8_000);
+        assert_eq!(waveform.channels(), 2);
+        assert_eq!(waveform.samples(), &[0.5, -0.5, 0.0, -1.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn float_32_mono() -> color_eyre::Result<()> {
+        let data = [0.25f32, -0.75]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        let waveform = Waveform::from_wav_reader(Cursor::new(wav_bytes(3, 1, 32, &data)))?;
+
+        assert_eq!(waveform.channels(), 1);
+        assert_eq!(waveform.samples(), &[0.25, -0.75]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() -> color_eyre::Result<()> {
+        let sine = Waveform::sine_wave(440.0, 0.1, Waveform::CD_SAMPLE_RATE);
+
+        let mut pcm = Cursor::new(Vec::new());
+        sine.write_wav(&mut pcm)?;
+        pcm.set_position(0);
+
+        let read = Waveform::from_wav_reader(pcm)?;
+        assert_eq!(read.sample_rate(), sine.sample_rate());
+        assert_eq!(read.channels(), 1);
+        assert_eq!(read.len(), sine.len());
+
+        // Within the 16 bit quantization error
+        for (read, original) in read.samples_iter().zip(sine.samples_iter()) {
+            assert!((read - original).abs() <= 1.0 / 32_768.0);
+        }
+
+        let mut float = Cursor::new(Vec::new());
+        sine.write_wav_with(&mut float, WavSampleFormat::Float32)?;
+        float.set_position(0);
+
+        assert_eq!(Waveform::from_wav_reader(float)?.samples(), sine.samples());
+
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip_stereo() -> color_eyre::Result<()> {
+        let stereo = Waveform::new(vec![0.5, -0.5, 0.25, -0.25], 16_000, 2);
+
+        let mut wav = Cursor::new(Vec::new());
+        stereo.write_wav(&mut wav)?;
+        wav.set_position(0);
+
+        let read = Waveform::from_wav_reader(wav)?;
+        assert_eq!(read.channels(), 2);
+        assert_eq!(read.sample_rate(), 16_000);
+        assert_eq!(read.samples(), stereo.samples());
+
+        Ok(())
+    }
+
+    #[test]
+    fn pcm_24_padded_to_32() -> color_eyre::Result<()> {
+        // 24 bit samples in the top of 4 byte containers
+        let data = [0x40_0000i32, -0x40_0000, 0, -0x80_0000]
+            .iter()
+            .flat_map(|sample| (sample << 8).to_le_bytes())
+            .collect::<Vec<_>>();
+
+        let mut bytes = wav_bytes(1, 2, 24, &data);
+        // Each frame holds two 4 byte containers
+        bytes[32..34].copy_from_slice(&8u16.to_le_bytes());
+
+        let waveform = Waveform::from_wav_reader(Cursor::new(bytes))?;
+
+        assert_eq!(waveform.channels(), 2);
+        assert_eq!(waveform.samples(), &[0.5, -0.5, 0.0, -1.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn block_align_too_small() {
+        let mut bytes = wav_bytes(1, 2, 16, &[0; 8]);
+        // Too small to hold two 16 bit samples
+        bytes[32..34].copy_from_slice(&3u16.to_le_bytes());
+
+        assert!(Waveform::from_wav_reader(Cursor::new(bytes)).is_err());
+
+        let mut bytes = wav_bytes(1, 2, 16, &[0; 8]);
+        // Wide enough for the bytes per sample to overflow a u16
+        bytes[34..36].copy_from_slice(&u16::MAX.to_le_bytes());
+
+        assert!(Waveform::from_wav_reader(Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn not_a_wav_file() {
+        let waveform = Waveform::from_wav_reader(Cursor::new(b"RIFF\0\0\0\0AVI LIST".to_vec()));
+
+        assert!(waveform.is_err());
+    }
+
+    #[test]
+    fn truncated_data() {
+        let mut bytes = wav_bytes(1, 1, 16, &[0; 4]);
+
+        // Claim far more data than there is
+        let len = bytes.len();
+        bytes[len - 8..len - 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        assert!(Waveform::from_wav_reader(Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn too_many_channels() {
+        // Enough 32 bit channels for the block alignment to overflow a u16
+        let waveform = Waveform::new(vec![0.0; 20_000], 8_000, 20_000);
+
+        let written = waveform.write_wav_with(Cursor::new(Vec::new()), WavSampleFormat::Float32);
+        assert!(written.is_err());
+    }
+}
@@ -1,23 +1,77 @@
-use std::{borrow::Cow, f32::consts, slice::SliceIndex};
+use std::{borrow::Cow, f32::consts, ops::Range, slice::SliceIndex};
 
+use color_eyre::eyre::{bail, ensure};
 use lerp::Lerp;
 
+/// The shape of the gain ramp used when fading
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FadeCurve {
+    Linear,
+    /// Keeps the summed power constant when crossfading two signals
+    EqualPower,
+}
+
+impl FadeCurve {
+    /// The gain at `t` in the range `0.0..=1.0` through a fade in
+    pub fn gain(self, t: f32) -> f32 {
+        match self {
+            FadeCurve::Linear => t,
+            FadeCurve::EqualPower => (t * consts::FRAC_PI_2).sin(),
+        }
+    }
+}
+
+/// How the frequency of a [`Waveform::chirp`] moves from start to end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChirpCurve {
+    /// The same number of hertz every second
+    Linear,
+    /// The same number of octaves every second
+    Exponential,
+}
+
+/// The interpolation used by [`Waveform::resample_with`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Linear interpolation between neighboring samples. Fast, but aliases
+    /// badly when downsampling.
+    Linear,
+    /// Windowed sinc interpolation with the given number of zero crossings on
+    /// each side of the kernel. Low pass filters below the lower of the two
+    /// nyquist frequencies, so downsampling does not alias.
+    Sinc(usize),
+}
+
+/// A run of samples, interleaved by frame when there is more than one channel
 #[derive(Debug)]
 pub struct Waveform<'s> {
     samples: Cow<'s, [f32]>,
     sample_rate: u32,
+    channels: u16,
 }
 
 impl Waveform<'static> {
     pub const CD_SAMPLE_RATE: u32 = 44_100;
 
-    pub fn new(samples: Vec<f32>, sample_rate: u32) -> Self {
+    pub fn new(samples: Vec<f32>, sample_rate: u32, channels: u16) -> Self {
+        assert_ne!(channels, 0, "a waveform must have at least one channel");
+        assert_eq!(
+            samples.len() % channels as usize,
+            0,
+            "interleaved samples must contain whole frames"
+        );
+
         Self {
             samples: Cow::Owned(samples),
             sample_rate,
+            channels,
         }
     }
 
+    pub fn new_mono(samples: Vec<f32>, sample_rate: u32) -> Self {
+        Self::new(samples, sample_rate, 1)
+    }
+
     pub fn sine_wave(frequency: f32, duration: f32, sample_rate: u32) -> Self {
         let samples_len = (duration * sample_rate as f32).round() as u32;
 
@@ -28,7 +82,306 @@ impl Waveform<'static> {
         Self {
             samples,
             sample_rate,
+            channels: 1,
+        }
+    }
+
+    /// A square wave between `-1.0` and `1.0`, starting high. A band limited
+    /// wave is summed from only the harmonics below nyquist, so it does not
+    /// alias.
+    pub fn square_wave(
+        frequency: f32,
+        duration: f32,
+        sample_rate: u32,
+        band_limited: bool,
+    ) -> Self {
+        if band_limited {
+            // Odd harmonics, falling off with their number
+            Self::additive(frequency, duration, sample_rate, |harmonic| {
+                (harmonic % 2 == 1).then_some(4.0 / (consts::PI * harmonic as f32))
+            })
+        } else {
+            Self::periodic(frequency, duration, sample_rate, |phase| {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            })
+        }
+    }
+
+    /// A sawtooth wave rising from `-1.0` to `1.0`, starting from zero. A band
+    /// limited wave is summed from only the harmonics below nyquist, so it
+    /// does not alias.
+    pub fn sawtooth_wave(
+        frequency: f32,
+        duration: f32,
+        sample_rate: u32,
+        band_limited: bool,
+    ) -> Self {
+        if band_limited {
+            // Every harmonic, falling off with its number and alternating in sign
+            Self::additive(frequency, duration, sample_rate, |harmonic| {
+                let sign = if harmonic % 2 == 1 { 1.0 } else { -1.0 };
+
+                Some(sign * 2.0 / (consts::PI * harmonic as f32))
+            })
+        } else {
+            Self::periodic(frequency, duration, sample_rate, |phase| {
+                2.0 * ((phase + 0.5) % 1.0) - 1.0
+            })
+        }
+    }
+
+    /// A triangle wave between `-1.0` and `1.0`, starting from zero and rising.
+    /// A band limited wave is summed from only the harmonics below nyquist, so
+    /// it does not alias.
+    pub fn triangle_wave(
+        frequency: f32,
+        duration: f32,
+        sample_rate: u32,
+        band_limited: bool,
+    ) -> Self {
+        if band_limited {
+            // Odd harmonics, falling off with their number squared and alternating in sign
+            Self::additive(frequency, duration, sample_rate, |harmonic| {
+                let sign = if harmonic % 4 == 1 { 1.0 } else { -1.0 };
+
+                (harmonic % 2 == 1).then_some(sign * 8.0 / (consts::PI * harmonic as f32).powi(2))
+            })
+        } else {
+            Self::periodic(frequency, duration, sample_rate, |phase| {
+                1.0 - 4.0 * (((phase + 0.25) % 1.0) - 0.5).abs()
+            })
+        }
+    }
+
+    /// Uniformly distributed noise between `-1.0` and `1.0`. The same seed
+    /// always produces the same noise.
+    pub fn white_noise(duration: f32, sample_rate: u32, seed: u64) -> Self {
+        let samples_len = (duration * sample_rate as f32).round() as usize;
+        let mut noise = NoiseSource::new(seed);
+
+        Self::new_mono(
+            (0..samples_len).map(|_| noise.next_sample()).collect(),
+            sample_rate,
+        )
+    }
+
+    /// Noise with equal power in every octave, falling off by 3 dB per octave,
+    /// mostly within `-1.0` to `1.0`. The same seed always produces the same
+    /// noise.
+    pub fn pink_noise(duration: f32, sample_rate: u32, seed: u64) -> Self {
+        let samples_len = (duration * sample_rate as f32).round() as usize;
+        let mut noise = NoiseSource::new(seed);
+
+        // Paul Kellet's filter, accurate to within 0.05 dB above 9.2 Hz at 44.1 kHz
+        let mut b = [0.0f32; 7];
+        let samples = (0..samples_len)
+            .map(|_| {
+                let white = noise.next_sample();
+
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.1538520;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+
+                pink * 0.11
+            })
+            .collect();
+
+        Self::new_mono(samples, sample_rate)
+    }
+
+    /// A unit sine sweeping from `start_frequency` to `end_frequency` over
+    /// `duration` seconds. The phase is integrated from the frequency, so the
+    /// sweep is continuous.
+    pub fn chirp(
+        start_frequency: f32,
+        end_frequency: f32,
+        duration: f32,
+        sample_rate: u32,
+        curve: ChirpCurve,
+    ) -> Self {
+        let samples_len = (duration * sample_rate as f32).round() as u32;
+
+        if curve == ChirpCurve::Exponential {
+            assert!(
+                start_frequency > 0.0 && end_frequency > 0.0,
+                "an exponential chirp can only sweep between positive frequencies"
+            );
+        }
+
+        let (start_frequency, end_frequency) = (start_frequency as f64, end_frequency as f64);
+        let duration = duration as f64;
+        let octaves_per_second = (end_frequency / start_frequency).log2() / duration;
+
+        // Cycles completed by `t` seconds in
+        let cycles = |t: f64| match curve {
+            ChirpCurve::Exponential if start_frequency != end_frequency => {
+                start_frequency * ((octaves_per_second * t).exp2() - 1.0)
+                    / (octaves_per_second * std::f64::consts::LN_2)
+            }
+            // Without a change in frequency both curves are the same constant tone
+            ChirpCurve::Linear | ChirpCurve::Exponential => {
+                start_frequency * t + (end_frequency - start_frequency) * t * t / (2.0 * duration)
+            }
+        };
+
+        let samples = (0..samples_len)
+            .map(|n| {
+                let cycles = cycles(n as f64 / sample_rate as f64);
+
+                // Only the fraction of a cycle matters, keeping precision for long sweeps
+                (cycles.fract() * std::f64::consts::TAU).sin() as f32
+            })
+            .collect();
+
+        Self::new_mono(samples, sample_rate)
+    }
+
+    /// A mono waveform from a function of the phase through each period, from
+    /// `0.0` up to `1.0`
+    fn periodic(
+        frequency: f32,
+        duration: f32,
+        sample_rate: u32,
+        wave: impl Fn(f32) -> f32,
+    ) -> Self {
+        let samples_len = (duration * sample_rate as f32).round() as u32;
+
+        let samples = (0..samples_len)
+            .map(|n| wave((frequency * n as f32 / sample_rate as f32).fract()))
+            .collect();
+
+        Self::new_mono(samples, sample_rate)
+    }
+
+    /// A mono waveform summed from sines at each harmonic of `frequency` below
+    /// nyquist, with the amplitude given for each harmonic number
+    fn additive(
+        frequency: f32,
+        duration: f32,
+        sample_rate: u32,
+        amplitude: impl Fn(u32) -> Option<f32>,
+    ) -> Self {
+        let samples_len = (duration * sample_rate as f32).round() as u32;
+        let nyquist = sample_rate as f32 / 2.0;
+
+        let harmonics = (1..)
+            .take_while(|&harmonic| frequency * (harmonic as f32) < nyquist)
+            .filter_map(|harmonic| Some((harmonic as f32, amplitude(harmonic)?)))
+            .collect::<Vec<_>>();
+
+        let samples = (0..samples_len)
+            .map(|n| {
+                let phase = frequency * consts::TAU * (n as f32 / sample_rate as f32);
+
+                harmonics
+                    .iter()
+                    .map(|&(harmonic, amplitude)| amplitude * (harmonic * phase).sin())
+                    .sum()
+            })
+            .collect();
+
+        Self::new_mono(samples, sample_rate)
+    }
+
+    /// Sum the waveforms sample by sample, scaling each by its gain. Shorter
+    /// waveforms are padded with silence to the length of the longest.
+    pub fn mix(waveforms: &[&Waveform], gains: &[f32]) -> color_eyre::Result<Self> {
+        ensure!(
+            waveforms.len() == gains.len(),
+            "{} gains were provided for {} waveforms",
+            gains.len(),
+            waveforms.len()
+        );
+
+        let (sample_rate, channels) = Self::shared_format(waveforms)?;
+
+        let len = waveforms.iter().map(|w| w.len()).max().unwrap_or_default();
+        let mut samples = vec![0.0; len];
+
+        for (waveform, gain) in waveforms.iter().zip(gains) {
+            for (mixed, sample) in samples.iter_mut().zip(waveform.samples_iter()) {
+                *mixed += sample * gain;
+            }
         }
+
+        Ok(Self::new(samples, sample_rate, channels))
+    }
+
+    /// Join the waveforms end to end
+    pub fn concat(waveforms: &[&Waveform]) -> color_eyre::Result<Self> {
+        Self::concat_crossfade(waveforms, 0.0)
+    }
+
+    /// Join the waveforms end to end, overlapping each boundary by
+    /// `crossfade_secs` with equal-power ramps. The overlap is clamped to the
+    /// length of the waveforms on either side of it.
+    pub fn concat_crossfade(
+        waveforms: &[&Waveform],
+        crossfade_secs: f32,
+    ) -> color_eyre::Result<Self> {
+        let (sample_rate, channels) = Self::shared_format(waveforms)?;
+        let channels_len = channels as usize;
+
+        let crossfade_frames = (crossfade_secs * sample_rate as f32).round() as usize;
+
+        let mut samples = Vec::with_capacity(waveforms.iter().map(|w| w.len()).sum());
+
+        for waveform in waveforms {
+            let overlap_frames = crossfade_frames
+                .min(samples.len() / channels_len)
+                .min(waveform.frames());
+            let overlap = overlap_frames * channels_len;
+
+            let start = samples.len() - overlap;
+            for (n, (mixed, sample)) in samples[start..]
+                .iter_mut()
+                .zip(waveform.samples_iter())
+                .enumerate()
+            {
+                let t = (n / channels_len) as f32 / overlap_frames as f32;
+
+                *mixed = *mixed * FadeCurve::EqualPower.gain(1.0 - t)
+                    + sample * FadeCurve::EqualPower.gain(t);
+            }
+
+            samples.extend(waveform.samples_iter().skip(overlap));
+        }
+
+        Ok(Self::new(samples, sample_rate, channels))
+    }
+
+    /// The sample rate and channel count shared by all of the waveforms
+    fn shared_format(waveforms: &[&Waveform]) -> color_eyre::Result<(u32, u16)> {
+        let (first, rest) = match waveforms {
+            [first, rest @ ..] => (first, rest),
+            [] => bail!("no waveforms were provided"),
+        };
+
+        for waveform in rest {
+            ensure!(
+                waveform.sample_rate == first.sample_rate,
+                "can not combine a {} Hz waveform with a {} Hz waveform",
+                waveform.sample_rate,
+                first.sample_rate
+            );
+            ensure!(
+                waveform.channels == first.channels,
+                "can not combine a waveform with {} channels with one with {} channels",
+                waveform.channels,
+                first.channels
+            );
+        }
+
+        Ok((first.sample_rate, first.channels))
     }
 
     pub fn as_samples(self) -> Vec<f32> {
@@ -41,7 +394,24 @@ impl Waveform<'static> {
 
 #[cfg(test)]
 mod test {
-    use super::Waveform;
+    use std::f32::consts;
+
+    use super::{ChirpCurve, FadeCurve, ResampleQuality, Waveform};
+
+    /// The amplitude of a single frequency, through a discrete fourier transform at that frequency
+    fn magnitude_at(waveform: &Waveform, frequency: f32) -> f32 {
+        let (re, im) = waveform
+            .samples_iter()
+            .enumerate()
+            .map(|(n, sample)| {
+                let phase = consts::TAU * frequency * waveform.time_from_sample(n);
+
+                (sample * phase.cos(), -sample * phase.sin())
+            })
+            .fold((0.0, 0.0), |(re, im), (x, y)| (re + x, im + y));
+
+        2.0 * (re * re + im * im).sqrt() / waveform.len() as f32
+    }
 
     #[test]
     fn as_samples() {
@@ -49,6 +419,506 @@ mod test {
 
         assert_eq!(waveform.len(), waveform.as_samples().len());
     }
+
+    #[test]
+    fn periodic_generators() {
+        // Four samples per period land on each quarter of the wave
+        let square = Waveform::square_wave(2.0, 1.0, 8, false);
+        assert_eq!(
+            square.samples(),
+            [1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, -1.0]
+        );
+
+        let sawtooth = Waveform::sawtooth_wave(2.0, 1.0, 8, false);
+        assert_eq!(
+            sawtooth.samples(),
+            [0.0, 0.5, -1.0, -0.5, 0.0, 0.5, -1.0, -0.5]
+        );
+
+        let triangle = Waveform::triangle_wave(2.0, 1.0, 8, false);
+        assert_eq!(
+            triangle.samples(),
+            [0.0, 1.0, 0.0, -1.0, 0.0, 1.0, 0.0, -1.0]
+        );
+
+        // Band limited waves keep roughly the same shape, overshooting a little
+        for wave in [
+            Waveform::square_wave,
+            Waveform::sawtooth_wave,
+            Waveform::triangle_wave,
+        ] {
+            let band_limited = wave(100.0, 0.1, Waveform::CD_SAMPLE_RATE, true);
+
+            assert_eq!(band_limited.len(), 4410);
+            assert!(band_limited.peak() > 0.9 && band_limited.peak() < 1.25);
+        }
+    }
+
+    #[test]
+    fn continuous_chirp() {
+        let sample_rate = 8000;
+
+        for curve in [ChirpCurve::Linear, ChirpCurve::Exponential] {
+            let chirp = Waveform::chirp(100.0, 3000.0, 1.0, sample_rate, curve);
+
+            assert_eq!(chirp.len(), 8000);
+            assert_eq!(chirp.samples()[0], 0.0);
+
+            // A click would jump further than even the highest frequency can in a sample
+            let max_step = consts::TAU * 3000.0 / sample_rate as f32;
+            for pair in chirp.samples().windows(2) {
+                assert!((pair[1] - pair[0]).abs() <= max_step + 1e-3);
+            }
+        }
+
+        // A chirp that does not sweep is just a sine
+        let steady = Waveform::chirp(440.0, 440.0, 0.1, sample_rate, ChirpCurve::Exponential);
+        let sine = Waveform::sine_wave(440.0, 0.1, sample_rate);
+        for (chirp, sine) in steady.samples_iter().zip(sine.samples_iter()) {
+            assert!((chirp - sine).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn envelope() {
+        let sample_rate = Waveform::CD_SAMPLE_RATE;
+
+        // A sine fading in from silence over a second
+        let sine = Waveform::sine_wave(440.0, 1.0, sample_rate);
+        let len = sine.len();
+        let ramp = Waveform::new_mono(
+            sine.samples_iter()
+                .enumerate()
+                .map(|(n, sample)| sample * n as f32 / len as f32)
+                .collect(),
+            sample_rate,
+        );
+
+        let envelope = ramp.envelope(0.05, 0.025);
+
+        // Windows start every 1103 frames, so the last only covers the final 1083 frames
+        assert_eq!(envelope.len(), 40);
+        assert!(envelope[0] < 0.05);
+        assert!(envelope.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(envelope[39] > 0.65 && envelope[39] < 1.0 / 2.0f32.sqrt());
+
+        let peaks = ramp.peak_envelope(0.05, 0.025);
+        assert_eq!(peaks.len(), 40);
+        assert!(peaks.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        // Channels are measured together
+        let stereo = Waveform::new(vec![1.0, 0.0, 1.0, 0.0, 0.0, 0.0], 4, 2);
+        assert_eq!(stereo.peak_envelope(0.5, 0.5), [1.0, 0.0]);
+    }
+
+    #[test]
+    fn zero_crossing_rate() {
+        let sine = Waveform::sine_wave(100.0, 1.0, 8000);
+
+        // Crosses every half period after starting at zero
+        assert_eq!(
+            (sine.zero_crossing_rate(0..sine.len()) * 8000.0).round(),
+            199.0
+        );
+        assert_eq!(
+            (sine.zero_crossing_rate(0..sine.len() / 2) * 4000.0).round(),
+            99.0
+        );
+
+        // Touching zero without changing sign does not count
+        let touching = Waveform::new_mono(vec![1.0, 0.0, 0.5, 0.0, 0.0, -1.0], 6);
+        assert_eq!(touching.zero_crossing_rate(0..6), 1.0 / 6.0);
+
+        assert_eq!(sine.zero_crossing_rate(10..10), 0.0);
+        assert_eq!(sine.zero_crossing_rate(8000..9000), 0.0);
+
+        // Stereo is measured on the downmix over a window of frames, rather
+        // than comparing the left and right samples of each frame
+        let stereo = Waveform::new(
+            sine.samples_iter()
+                .flat_map(|sample| [sample, -sample * 0.5])
+                .collect(),
+            8000,
+            2,
+        );
+        assert_eq!(
+            stereo.zero_crossing_rate(0..stereo.frames()),
+            sine.zero_crossing_rate(0..sine.len())
+        );
+        assert_eq!(stereo.zero_crossing_rate(8000..9000), 0.0);
+    }
+
+    #[test]
+    fn thumbnail() {
+        let noise = Waveform::white_noise(1.0, Waveform::CD_SAMPLE_RATE, 3);
+        let thumbnail = noise.thumbnail(1000);
+
+        assert!(thumbnail.len() <= 1000);
+
+        let min = noise.samples_iter().fold(f32::INFINITY, f32::min);
+        let max = noise.samples_iter().fold(f32::NEG_INFINITY, f32::max);
+        assert_eq!(
+            thumbnail
+                .iter()
+                .map(|&(min, _)| min)
+                .fold(f32::INFINITY, f32::min),
+            min
+        );
+        assert_eq!(
+            thumbnail
+                .iter()
+                .map(|&(_, max)| max)
+                .fold(f32::NEG_INFINITY, f32::max),
+            max
+        );
+        assert!(thumbnail.iter().all(|(min, max)| min <= max));
+
+        // Short waveforms keep every sample
+        let short = Waveform::new_mono(vec![0.5, -0.25, 1.0], 4);
+        assert_eq!(
+            short.thumbnail(10),
+            [(0.5, 0.5), (-0.25, -0.25), (1.0, 1.0)]
+        );
+        assert!(short.thumbnail(0).is_empty());
+    }
+
+    #[test]
+    fn seeded_noise() {
+        for noise in [Waveform::white_noise, Waveform::pink_noise] {
+            let first = noise(0.5, Waveform::CD_SAMPLE_RATE, 42);
+            let again = noise(0.5, Waveform::CD_SAMPLE_RATE, 42);
+            let other = noise(0.5, Waveform::CD_SAMPLE_RATE, 7);
+
+            assert_eq!(first.len(), 22050);
+            assert_eq!(first.samples(), again.samples());
+            assert_ne!(first.samples(), other.samples());
+        }
+
+        let white = Waveform::white_noise(1.0, Waveform::CD_SAMPLE_RATE, 0);
+        assert!(white.peak() <= 1.0);
+        assert!(white.samples_iter().sum::<f32>().abs() / (white.len() as f32) < 0.01);
+    }
+
+    #[test]
+    fn interleaved_channels() {
+        let waveform = Waveform::new(vec![1.0, -1.0, 0.5, 0.0, 0.25, 0.75], 8, 2);
+
+        assert_eq!(waveform.channels(), 2);
+        assert_eq!(waveform.frames(), 3);
+        assert_eq!(waveform.duration(), 3.0 / 8.0);
+
+        let left = waveform.channel(0);
+        assert_eq!(left.channels(), 1);
+        assert_eq!(left.sample_rate(), 8);
+        assert_eq!(left.samples(), &[1.0, 0.5, 0.25]);
+
+        assert_eq!(waveform.channel(1).samples(), &[-1.0, 0.0, 0.75]);
+
+        assert_eq!(waveform.to_mono().samples(), &[0.0, 0.25, 0.5]);
+
+        // One point per frame rather than per sample
+        assert_eq!(
+            waveform.time_domain().collect::<Vec<_>>(),
+            &[(0.0, 0.0), (0.125, 0.25), (0.25, 0.5)]
+        );
+    }
+
+    #[test]
+    fn mix_pads_shorter_waveforms() -> color_eyre::Result<()> {
+        let long = Waveform::new_mono(vec![1.0, 1.0, 1.0, 1.0], 8);
+        let short = Waveform::new_mono(vec![0.5, 0.5], 8);
+
+        let mixed = Waveform::mix(&[&long, &short], &[1.0, 1.0])?;
+
+        assert_eq!(mixed.samples(), &[1.5, 1.5, 1.0, 1.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mix_gain() -> color_eyre::Result<()> {
+        let a = Waveform::new_mono(vec![1.0, -1.0], 8);
+        let b = Waveform::new_mono(vec![0.5, 0.5], 8);
+
+        let mixed = Waveform::mix(&[&a, &b], &[0.5, 2.0])?;
+
+        assert_eq!(mixed.samples(), &[1.5, 0.5]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mix_sample_rate_mismatch() {
+        let a = Waveform::new_mono(vec![0.0; 4], 8);
+        let b = Waveform::new_mono(vec![0.0; 4], 16);
+
+        assert!(Waveform::mix(&[&a, &b], &[1.0, 1.0]).is_err());
+    }
+
+    #[test]
+    fn normalize_peak() {
+        let waveform = Waveform::new_mono(vec![0.25, -0.5, 0.125], 8);
+
+        assert_eq!(waveform.normalize_peak(1.0).samples(), &[0.5, -1.0, 0.25]);
+    }
+
+    #[test]
+    fn normalize_rms() {
+        let waveform = Waveform::sine_wave(100.0, 1.0, Waveform::CD_SAMPLE_RATE);
+
+        let normalized = waveform.normalize_rms(0.1);
+
+        assert!((normalized.rms() - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn normalize_silence() {
+        let silence = Waveform::new_mono(vec![0.0; 16], 8);
+
+        assert_eq!(silence.normalize_peak(1.0).samples(), silence.samples());
+        assert_eq!(silence.normalize_rms(1.0).samples(), silence.samples());
+    }
+
+    #[test]
+    fn concat() -> color_eyre::Result<()> {
+        let a = Waveform::new_mono(vec![1.0, 2.0], 8);
+        let b = Waveform::new_mono(vec![3.0], 8);
+
+        assert_eq!(Waveform::concat(&[&a, &b])?.samples(), &[1.0, 2.0, 3.0]);
+
+        let c = Waveform::new_mono(vec![3.0], 16);
+        assert!(Waveform::concat(&[&a, &c]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn concat_crossfade() -> color_eyre::Result<()> {
+        let a = Waveform::new_mono(vec![1.0; 16], 8);
+        let b = Waveform::new_mono(vec![1.0; 16], 8);
+        let c = Waveform::new_mono(vec![1.0; 16], 8);
+
+        // Half a second at 8Hz overlaps 4 samples at each of the two boundaries
+        let joined = Waveform::concat_crossfade(&[&a, &b, &c], 0.5)?;
+        assert_eq!(joined.len(), 16 * 3 - 4 * 2);
+
+        // The overlap starts with only the outgoing waveform
+        assert_eq!(joined.samples()[12], 1.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn trim_silence() {
+        let sine = Waveform::sine_wave(100.0, 1.0, 8000);
+        let padding = vec![0.0; 4000];
+
+        let padded = Waveform::new_mono(
+            padding
+                .iter()
+                .chain(sine.samples())
+                .chain(&padding)
+                .copied()
+                .collect(),
+            8000,
+        );
+
+        let trimmed = padded.trim_silence(0.01, 0.1);
+
+        // Only the padding and the near zero ends of the sine are removed
+        assert!(trimmed.len() <= sine.len());
+        assert!(trimmed.len() > sine.len() - 4);
+        assert!(trimmed.samples()[0].abs() > 0.01);
+
+        // Quiet ends shorter than the minimum silence are left alone
+        assert_eq!(padded.trim_silence(0.01, 1.0).len(), padded.len());
+    }
+
+    #[test]
+    fn trim_all_silence() {
+        let silence = Waveform::new_mono(vec![0.0; 100], 8000);
+
+        assert!(silence.trim_silence(0.01, 0.0).is_empty());
+    }
+
+    #[test]
+    fn resample_short_waveforms() {
+        for quality in [ResampleQuality::Linear, ResampleQuality::Sinc(16)] {
+            let empty = Waveform::new_mono(vec![], 8).resample_with(16, quality);
+            assert!(empty.is_empty());
+            assert_eq!(empty.sample_rate(), 16);
+
+            let single = Waveform::new_mono(vec![0.5], 8).resample_with(16, quality);
+            assert_eq!(single.samples(), &[0.5]);
+            assert_eq!(single.sample_rate(), 16);
+
+            let double = Waveform::new_mono(vec![0.0, 1.0], 8).resample_with(16, quality);
+            assert_eq!(double.len(), 2);
+        }
+
+        let double = Waveform::new_mono(vec![0.0, 1.0], 8).resample(16);
+        assert_eq!(double.samples(), &[0.0, 0.5]);
+    }
+
+    #[test]
+    fn resample_boundaries() {
+        const RATES: [u32; 6] = [8_000, 11_025, 16_000, 22_050, 44_100, 48_000];
+
+        // Ratios that do not divide evenly can round the last virtual sample onto the end
+        for old_rate in RATES {
+            for new_rate in RATES {
+                for len in 1..64 {
+                    let waveform = Waveform::new_mono(vec![1.0; len], old_rate);
+
+                    let linear = waveform.resample(new_rate);
+                    let sinc = waveform.resample_with(new_rate, ResampleQuality::Sinc(4));
+
+                    assert_eq!(linear.len(), sinc.len());
+                    assert!(linear.samples_iter().all(|sample| sample == 1.0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sinc_downsample() {
+        // 7kHz is below the 8kHz nyquist frequency of the new sample rate, so is kept
+        let kept = Waveform::sine_wave(7_000.0, 1.0, 48_000)
+            .resample_with(16_000, ResampleQuality::Sinc(16));
+
+        assert_eq!(kept.sample_rate(), 16_000);
+        assert!(magnitude_at(&kept, 7_000.0) > 0.95);
+
+        // 10kHz is above it, and would alias down to 6kHz if not filtered out
+        let above_nyquist = Waveform::sine_wave(10_000.0, 1.0, 48_000);
+
+        let filtered = above_nyquist.resample_with(16_000, ResampleQuality::Sinc(16));
+        assert!(magnitude_at(&filtered, 6_000.0) < 0.01);
+
+        let aliased = above_nyquist.resample_with(16_000, ResampleQuality::Linear);
+        assert!(magnitude_at(&aliased, 6_000.0) > 0.1);
+    }
+
+    #[cfg(feature = "rodio")]
+    #[test]
+    fn from_rodio_buffer() {
+        use rodio::buffer::SamplesBuffer;
+
+        let waveform = Waveform::from(SamplesBuffer::new(2, 16_000, vec![i16::MAX; 8]));
+
+        assert_eq!(waveform.sample_rate(), 16_000);
+        assert_eq!(waveform.channels(), 2);
+        assert!(waveform
+            .samples_iter()
+            .all(|sample| (sample - 1.0).abs() < 1e-4));
+
+        let waveform = Waveform::from(SamplesBuffer::new(1, 8_000, vec![0.5f32, -0.5]));
+        assert_eq!(waveform.samples(), &[0.5, -0.5]);
+    }
+
+    #[test]
+    fn gain() {
+        let sine = Waveform::sine_wave(100.0, 0.1, Waveform::CD_SAMPLE_RATE);
+
+        assert_eq!(sine.gain(2.0).peak(), sine.peak() * 2.0);
+        assert_eq!(sine.gain_clipped(2.0).peak(), 1.0);
+    }
+
+    #[test]
+    fn reverse() {
+        let stereo = Waveform::new(vec![1.0, 2.0, 3.0, 4.0], 8, 2);
+
+        assert_eq!(stereo.reverse().samples(), &[3.0, 4.0, 1.0, 2.0]);
+        assert_eq!(stereo.reverse().reverse().samples(), stereo.samples());
+        assert_eq!(stereo.reverse().sample_rate(), 8);
+    }
+
+    #[test]
+    fn remove_dc() {
+        let sine = Waveform::sine_wave(100.0, 1.0, Waveform::CD_SAMPLE_RATE);
+        let biased = Waveform::new_mono(sine.samples_iter().map(|x| x + 0.3).collect(), 44_100);
+
+        let mean = |waveform: &Waveform| {
+            waveform.samples_iter().map(f64::from).sum::<f64>() / waveform.len() as f64
+        };
+        assert!((mean(&biased) - 0.3).abs() < 1e-3);
+
+        assert!(mean(&biased.remove_dc()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn remove_dc_high_pass() {
+        let offset = Waveform::new_mono(vec![0.3; 44_100], Waveform::CD_SAMPLE_RATE);
+
+        let filtered = offset.remove_dc_high_pass(20.0);
+
+        // The constant offset decays away
+        assert!(filtered.samples()[44_000].abs() < 1e-3);
+    }
+
+    #[test]
+    fn fades() {
+        let waveform = Waveform::new_mono(vec![1.0; 32], 8);
+
+        for faded in [
+            waveform.fade_in(0.5).fade_out(0.5),
+            waveform
+                .fade_in_with(0.5, FadeCurve::EqualPower)
+                .fade_out_with(0.5, FadeCurve::EqualPower),
+        ] {
+            let samples = faded.samples();
+
+            assert_eq!(samples[0], 0.0);
+            assert_eq!(samples[samples.len() - 1], 0.0);
+
+            // Only the first and last 4 samples are ramped
+            assert!(samples[3] < 1.0);
+            assert!(samples[4..28].iter().all(|&sample| sample == 1.0));
+            assert!(samples[28] < 1.0);
+        }
+    }
+
+    #[test]
+    fn fade_longer_than_waveform() {
+        let waveform = Waveform::new_mono(vec![1.0; 4], 8);
+
+        assert_eq!(waveform.fade_in(10.0).samples(), &[0.0, 0.25, 0.5, 0.75]);
+        assert_eq!(waveform.fade_out(10.0).samples(), &[0.75, 0.5, 0.25, 0.0]);
+    }
+
+    #[test]
+    fn pan_law() {
+        let waveform = Waveform::new_mono(vec![1.0, -0.5], 8);
+
+        for (position, (left, right)) in [
+            (-1.0, (1.0, 0.0)),
+            (0.0, (consts::FRAC_1_SQRT_2, consts::FRAC_1_SQRT_2)),
+            (1.0, (0.0, 1.0)),
+        ] {
+            let panned = waveform.pan(position);
+            assert_eq!(panned.channels(), 2);
+            assert_eq!(panned.frames(), 2);
+
+            for (channel, gain) in [(0, left), (1, right)] {
+                let samples = panned.channel(channel).into_samples();
+
+                assert!(
+                    (samples[0] - gain).abs() < 1e-6 && (samples[1] + 0.5 * gain).abs() < 1e-6,
+                    "{samples:?} at {position} should have a gain of {gain}"
+                );
+            }
+        }
+
+        // Equal power is 3dB down in each channel
+        let center = waveform.pan(0.0).channel(0).into_samples()[0];
+        assert!((20.0 * center.log10() + 3.01).abs() < 0.01);
+    }
+
+    #[test]
+    #[should_panic]
+    fn channel_out_of_range() {
+        let _ = Waveform::new(vec![0.0; 4], 8, 2).channel(2);
+    }
 }
 
 impl Waveform<'_> {
@@ -66,10 +936,20 @@ impl Waveform<'_> {
         self.samples.iter().copied()
     }
 
+    /// The total number of samples across all channels
     pub fn len(&self) -> usize {
         self.samples.len()
     }
 
+    /// The number of samples in each channel
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
     pub fn is_empty(&self) -> bool {
         self.samples.is_empty()
     }
@@ -79,64 +959,704 @@ impl Waveform<'_> {
     }
 
     pub fn duration(&self) -> f32 {
-        self.time_from_sample(self.len())
+        self.time_from_sample(self.frames())
     }
 
     pub fn time_from_sample(&self, sample: usize) -> f32 {
         sample as f32 / self.sample_rate as f32
     }
 
+    /// The time of each frame alongside its sample, downmixed the same way
+    /// as [`Self::to_mono`]
     pub fn time_domain(&self) -> impl ExactSizeIterator<Item = (f32, f32)> + '_ {
-        self.samples_iter()
+        self.samples
+            .chunks_exact(self.channels as usize)
             .enumerate()
-            .map(|(sample, x)| (self.time_from_sample(sample), x))
+            .map(|(frame, samples)| {
+                (
+                    self.time_from_sample(frame),
+                    samples.iter().sum::<f32>() / self.channels as f32,
+                )
+            })
+    }
+
+    /// Decimate the samples into at most `target_points` buckets of equal
+    /// width, keeping the smallest and largest sample of each so that a plot
+    /// of the pairs keeps the shape of the full waveform. The last bucket may
+    /// be cut short by the end of the samples.
+    pub fn thumbnail(&self, target_points: usize) -> Vec<(f32, f32)> {
+        if target_points == 0 {
+            return Vec::new();
+        }
+
+        let bucket = ((self.len() + target_points - 1) / target_points).max(1);
+
+        self.samples
+            .chunks(bucket)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &sample| {
+                        (min.min(sample), max.max(sample))
+                    })
+            })
+            .collect()
     }
 
     pub fn to_owned(&self) -> Waveform<'static> {
         Waveform {
             sample_rate: self.sample_rate,
+            channels: self.channels,
             samples: Cow::Owned(self.samples.clone().into_owned()),
         }
     }
 
+    /// Copy a single channel out of the interleaved samples
+    #[must_use = "Waveform::channel() creates a new waveform from the selected channel"]
+    pub fn channel(&self, channel: u16) -> Waveform<'static> {
+        assert!(
+            channel < self.channels,
+            "channel {channel} is out of range for a waveform with {} channels",
+            self.channels
+        );
+
+        Waveform::new_mono(
+            self.samples
+                .iter()
+                .skip(channel as usize)
+                .step_by(self.channels as usize)
+                .copied()
+                .collect(),
+            self.sample_rate,
+        )
+    }
+
+    /// Downmix all channels by averaging each frame
+    #[must_use = "Waveform::to_mono() does not modify the provided waveform"]
+    pub fn to_mono(&self) -> Waveform<'static> {
+        Waveform::new_mono(
+            self.samples
+                .chunks_exact(self.channels as usize)
+                .map(|frame| frame.iter().sum::<f32>() / self.channels as f32)
+                .collect(),
+            self.sample_rate,
+        )
+    }
+
+    /// Place the waveform between the left and right channels of a stereo
+    /// waveform, from `-1.0` hard left to `1.0` hard right, downmixing it to
+    /// mono first. The equal power pan law keeps the loudness the same across
+    /// the stereo field, so the center is 3dB down in each channel.
+    #[must_use = "Waveform::pan() creates a new stereo waveform"]
+    pub fn pan(&self, position: f32) -> Waveform<'static> {
+        let angle = (position.clamp(-1.0, 1.0) + 1.0) * consts::FRAC_PI_4;
+        let (left, right) = (angle.cos(), angle.sin());
+
+        let mono = self.to_mono();
+
+        Waveform::new(
+            mono.samples
+                .iter()
+                .flat_map(|&sample| [sample * left, sample * right])
+                .collect(),
+            self.sample_rate,
+            2,
+        )
+    }
+
+    /// Slice the underlying interleaved samples. For multichannel waveforms the
+    /// range should fall on frame boundaries.
     #[must_use = "Waveform::slice() creates a new waveform over the shortened range"]
     pub fn slice(&self, range: impl SliceIndex<[f32], Output = [f32]>) -> Waveform {
         Waveform {
             sample_rate: self.sample_rate,
+            channels: self.channels,
             samples: Cow::Borrowed(&self.samples[range]),
         }
     }
 
+    /// The largest absolute sample value
+    pub fn peak(&self) -> f32 {
+        self.samples_iter().map(f32::abs).fold(0.0, f32::max)
+    }
+
+    /// The root mean square of all samples
+    pub fn rms(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+
+        (self.energy() / self.len() as f64).sqrt() as f32
+    }
+
+    /// The sum of the squares of all samples
+    pub fn energy(&self) -> f64 {
+        self.samples_iter()
+            .map(|sample| sample as f64 * sample as f64)
+            .sum()
+    }
+
+    /// The root mean square of each `window_secs` long window across all
+    /// channels, starting every `hop_secs`. The last windows are cut short by
+    /// the end of the waveform rather than left out, so every frame is
+    /// covered.
+    pub fn envelope(&self, window_secs: f32, hop_secs: f32) -> Vec<f32> {
+        self.windowed(window_secs, hop_secs, |window| window.rms())
+    }
+
+    /// The largest absolute sample of each window, as with [`Waveform::envelope`]
+    pub fn peak_envelope(&self, window_secs: f32, hop_secs: f32) -> Vec<f32> {
+        self.windowed(window_secs, hop_secs, |window| window.peak())
+    }
+
+    /// The number of times the signal changes sign per frame in `window`, a
+    /// range of frames, which is high for noisy, unvoiced sounds and low for
+    /// tonal ones. Multichannel waveforms are downmixed as with
+    /// [`Waveform::to_mono`] first. Samples of exactly zero belong to neither
+    /// side, so a signal touching zero and returning does not cross.
+    pub fn zero_crossing_rate(&self, window: Range<usize>) -> f32 {
+        let frames = self.frames();
+        let window = window.start.min(frames)..window.end.min(frames);
+        if window.is_empty() {
+            return 0.0;
+        }
+
+        let channels = self.channels as usize;
+        let len = window.len();
+        let crossings = self.samples[window.start * channels..window.end * channels]
+            .chunks_exact(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .filter(|&sample| sample != 0.0)
+            .map(|sample| sample > 0.0)
+            .fold(
+                (None, 0),
+                |(previous, crossings), positive| match previous {
+                    Some(previous) if previous != positive => (Some(positive), crossings + 1),
+                    _ => (Some(positive), crossings),
+                },
+            )
+            .1;
+
+        crossings as f32 / len as f32
+    }
+
+    fn windowed(
+        &self,
+        window_secs: f32,
+        hop_secs: f32,
+        measure: impl Fn(&Waveform) -> f32,
+    ) -> Vec<f32> {
+        let window = ((window_secs * self.sample_rate as f32).round() as usize).max(1);
+        let hop = ((hop_secs * self.sample_rate as f32).round() as usize).max(1);
+        let channels = self.channels as usize;
+
+        (0..self.frames())
+            .step_by(hop)
+            .map(|start| {
+                let end = (start + window).min(self.frames());
+
+                measure(&self.slice(start * channels..end * channels))
+            })
+            .collect()
+    }
+
+    fn map_samples(&self, map: impl FnMut(f32) -> f32) -> Waveform<'static> {
+        Waveform {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            samples: Cow::Owned(self.samples_iter().map(map).collect()),
+        }
+    }
+
+    /// Multiply every sample by `factor`
+    #[must_use = "Waveform::gain() does not modify the provided waveform"]
+    pub fn gain(&self, factor: f32) -> Waveform<'static> {
+        self.map_samples(|sample| sample * factor)
+    }
+
+    /// Multiply every sample by `factor`, hard clipping the result to `-1.0..=1.0`
+    #[must_use = "Waveform::gain_clipped() does not modify the provided waveform"]
+    pub fn gain_clipped(&self, factor: f32) -> Waveform<'static> {
+        self.map_samples(|sample| (sample * factor).clamp(-1.0, 1.0))
+    }
+
+    /// Play the waveform backwards, keeping the channels of each frame in order
+    #[must_use = "Waveform::reverse() does not modify the provided waveform"]
+    pub fn reverse(&self) -> Waveform<'static> {
+        Waveform {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            samples: Cow::Owned(
+                self.samples
+                    .rchunks_exact(self.channels as usize)
+                    .flatten()
+                    .copied()
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Subtract the mean of each channel, centering it around zero
+    #[must_use = "Waveform::remove_dc() does not modify the provided waveform"]
+    pub fn remove_dc(&self) -> Waveform<'static> {
+        let channels = self.channels as usize;
+        let frames = self.frames().max(1) as f64;
+
+        // Sum in f64 so long clips do not lose precision
+        let mut means = vec![0.0f64; channels];
+        for frame in self.samples.chunks_exact(channels) {
+            for (mean, &sample) in means.iter_mut().zip(frame) {
+                *mean += sample as f64;
+            }
+        }
+        for mean in &mut means {
+            *mean /= frames;
+        }
+
+        self.map_samples({
+            let mut channel = 0;
+
+            move |sample| {
+                let centered = (sample as f64 - means[channel]) as f32;
+                channel = (channel + 1) % channels;
+
+                centered
+            }
+        })
+    }
+
+    /// Remove slowly varying offsets with a one pole high pass filter at `cutoff` Hz
+    #[must_use = "Waveform::remove_dc_high_pass() does not modify the provided waveform"]
+    pub fn remove_dc_high_pass(&self, cutoff: f32) -> Waveform<'static> {
+        let channels = self.channels as usize;
+        let pole = (-consts::TAU * cutoff / self.sample_rate as f32).exp();
+
+        let mut previous_input = vec![0.0; channels];
+        let mut previous_output = vec![0.0; channels];
+
+        self.map_samples({
+            let mut channel = 0;
+
+            move |sample| {
+                let output = sample - previous_input[channel] + pole * previous_output[channel];
+
+                previous_input[channel] = sample;
+                previous_output[channel] = output;
+                channel = (channel + 1) % channels;
+
+                output
+            }
+        })
+    }
+
+    /// Scale the waveform so its loudest sample reaches `target_peak`.
+    /// Silent waveforms are returned unchanged.
+    #[must_use = "Waveform::normalize_peak() does not modify the provided waveform"]
+    pub fn normalize_peak(&self, target_peak: f32) -> Waveform<'static> {
+        let peak = self.peak();
+
+        if peak == 0.0 {
+            return self.to_owned();
+        }
+
+        self.gain(target_peak / peak)
+    }
+
+    /// Scale the waveform so its root mean square reaches `target_rms`.
+    /// Silent waveforms are returned unchanged.
+    #[must_use = "Waveform::normalize_rms() does not modify the provided waveform"]
+    pub fn normalize_rms(&self, target_rms: f32) -> Waveform<'static> {
+        let rms = self.rms();
+
+        if rms == 0.0 {
+            return self.to_owned();
+        }
+
+        self.gain(target_rms / rms)
+    }
+
+    /// Linearly ramp the start of the waveform up from silence over `duration` seconds
+    #[must_use = "Waveform::fade_in() does not modify the provided waveform"]
+    pub fn fade_in(&self, duration: f32) -> Waveform<'static> {
+        self.fade_in_with(duration, FadeCurve::Linear)
+    }
+
+    /// Linearly ramp the end of the waveform down to silence over `duration` seconds
+    #[must_use = "Waveform::fade_out() does not modify the provided waveform"]
+    pub fn fade_out(&self, duration: f32) -> Waveform<'static> {
+        self.fade_out_with(duration, FadeCurve::Linear)
+    }
+
+    #[must_use = "Waveform::fade_in_with() does not modify the provided waveform"]
+    pub fn fade_in_with(&self, duration: f32, curve: FadeCurve) -> Waveform<'static> {
+        let ramp = self.ramp_frames(duration);
+
+        self.map_frames(|frame| {
+            if frame < ramp {
+                curve.gain(frame as f32 / ramp as f32)
+            } else {
+                1.0
+            }
+        })
+    }
+
+    #[must_use = "Waveform::fade_out_with() does not modify the provided waveform"]
+    pub fn fade_out_with(&self, duration: f32, curve: FadeCurve) -> Waveform<'static> {
+        let ramp = self.ramp_frames(duration);
+        let frames = self.frames();
+
+        self.map_frames(|frame| {
+            let remaining = frames - 1 - frame;
+
+            if remaining < ramp {
+                curve.gain(remaining as f32 / ramp as f32)
+            } else {
+                1.0
+            }
+        })
+    }
+
+    /// The length of a ramp in frames, clamped to the length of the waveform
+    fn ramp_frames(&self, duration: f32) -> usize {
+        ((duration * self.sample_rate as f32).round() as usize).min(self.frames())
+    }
+
+    /// Scale every frame by the gain returned for its index
+    fn map_frames(&self, mut gain: impl FnMut(usize) -> f32) -> Waveform<'static> {
+        let channels = self.channels as usize;
+
+        self.map_samples({
+            let mut sample = 0;
+
+            move |value| {
+                let gain = gain(sample / channels);
+                sample += 1;
+
+                value * gain
+            }
+        })
+    }
+
+    /// Remove the quiet start and end of the waveform, where every sample is at
+    /// or below `threshold`. Quiet runs shorter than `min_silence_secs` are kept.
+    ///
+    /// A waveform that is entirely quiet becomes an empty waveform.
+    #[must_use = "Waveform::trim_silence() creates a new waveform over the shortened range"]
+    pub fn trim_silence(&self, threshold: f32, min_silence_secs: f32) -> Waveform {
+        let channels = self.channels as usize;
+        let min_silence = (min_silence_secs * self.sample_rate as f32).round() as usize;
+
+        let is_loud = |frame: &[f32]| frame.iter().any(|sample| sample.abs() > threshold);
+
+        let first_loud = self.samples.chunks_exact(channels).position(is_loud);
+        let last_loud = self.samples.chunks_exact(channels).rposition(is_loud);
+
+        let (first_loud, last_loud) = match first_loud.zip(last_loud) {
+            Some(bounds) => bounds,
+            None => return self.slice(0..0),
+        };
+
+        let start = if first_loud >= min_silence {
+            first_loud
+        } else {
+            0
+        };
+        let end = if self.frames() - (last_loud + 1) >= min_silence {
+            last_loud + 1
+        } else {
+            self.frames()
+        };
+
+        self.slice(start * channels..end * channels)
+    }
+
+    /// Resample using linear interpolation, see [`Self::resample_with`]
     #[must_use = "Waveform::resample() does not modify the provided waveform"]
     pub fn resample(&self, new_sample_rate: u32) -> Waveform<'static> {
-        let new_sample_len =
-            (self.time_from_sample(self.len() - 1) * new_sample_rate as f32) as usize;
+        self.resample_with(new_sample_rate, ResampleQuality::Linear)
+    }
+
+    /// Waveforms with less than two frames have nothing to interpolate between,
+    /// so keep their samples as they are under the new sample rate.
+    #[must_use = "Waveform::resample_with() does not modify the provided waveform"]
+    pub fn resample_with(
+        &self,
+        new_sample_rate: u32,
+        quality: ResampleQuality,
+    ) -> Waveform<'static> {
+        let channels = self.channels as usize;
+        let frames = self.frames();
 
-        let mut resampled = vec![0.0; new_sample_len];
+        if frames <= 1 {
+            return Waveform {
+                sample_rate: new_sample_rate,
+                ..self.to_owned()
+            };
+        }
+
+        let new_frame_len = (self.time_from_sample(frames - 1) * new_sample_rate as f32) as usize;
+
+        let mut resampled = vec![0.0; new_frame_len * channels];
+
+        let ratio = new_sample_rate as f32 / self.sample_rate as f32;
 
         // Resample the waveform
-        for (n, sample) in resampled.iter_mut().enumerate() {
-            // Calculate where this sample lies
-            let virtual_sample = (n as f32 / new_sample_rate as f32) * self.sample_rate as f32;
-
-            // Get the sample before and after this fractional sample
-            let before_sample = virtual_sample.floor() as usize;
-            let after_sample = virtual_sample.ceil() as usize;
-
-            // Get the percentage between the two samples this sample is
-            let lerp_frac = virtual_sample.fract();
-
-            // Linearly interpolate between the two
-            *sample = Lerp::lerp(
-                self.samples[before_sample],
-                self.samples[after_sample],
-                lerp_frac,
-            );
+        for (n, frame) in resampled.chunks_exact_mut(channels).enumerate() {
+            // Calculate where this frame lies
+            let virtual_frame = n as f32 / ratio;
+
+            match quality {
+                ResampleQuality::Linear => {
+                    // Get the frame before and after this fractional frame
+                    // Clamp to the last frame, which rounding may otherwise step past
+                    let before_frame = (virtual_frame.floor() as usize).min(frames - 1);
+                    let after_frame = (virtual_frame.ceil() as usize).min(frames - 1);
+
+                    // Get the percentage between the two frames this frame is
+                    let lerp_frac = virtual_frame.fract();
+
+                    // Linearly interpolate between the two, for every channel
+                    for (channel, sample) in frame.iter_mut().enumerate() {
+                        *sample = Lerp::lerp(
+                            self.samples[before_frame * channels + channel],
+                            self.samples[after_frame * channels + channel],
+                            lerp_frac,
+                        );
+                    }
+                }
+                ResampleQuality::Sinc(zero_crossings) => {
+                    // Filter below the nyquist frequency of the lower sample rate
+                    let cutoff = ratio.min(1.0);
+
+                    // Widen the kernel as the cutoff drops to keep the same number of zero crossings
+                    let half_width = (zero_crossings as f32 / cutoff).ceil() as isize;
+
+                    let center = virtual_frame.floor() as isize;
+                    let first = (center - half_width + 1).max(0);
+                    let last = (center + half_width).min(frames as isize - 1);
+
+                    for source_frame in first..=last {
+                        let distance = virtual_frame - source_frame as f32;
+
+                        let weight = cutoff
+                            * sinc(distance * cutoff)
+                            * blackman(distance / half_width as f32);
+
+                        for (channel, sample) in frame.iter_mut().enumerate() {
+                            *sample +=
+                                self.samples[source_frame as usize * channels + channel] * weight;
+                        }
+                    }
+                }
+            }
         }
 
         Waveform {
             sample_rate: new_sample_rate,
+            channels: self.channels,
             samples: Cow::Owned(resampled),
         }
     }
 }
+
+/// Convert a rodio buffer into a waveform, normalizing integer samples into
+/// the `-1.0..=1.0` range
+#[cfg(feature = "rodio")]
+impl<S: rodio::Sample> From<rodio::buffer::SamplesBuffer<S>> for Waveform<'static> {
+    fn from(buffer: rodio::buffer::SamplesBuffer<S>) -> Self {
+        use rodio::Source;
+
+        let sample_rate = buffer.sample_rate();
+        let channels = buffer.channels();
+
+        Waveform::new(
+            buffer.map(|sample| sample.to_f32()).collect(),
+            sample_rate,
+            channels,
+        )
+    }
+}
+
+/// Samples are (de)serialized as little endian bytes, which is far more compact
+/// than a list of floats in most formats
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use std::fmt;
+
+    use serde::{
+        de::{self, SeqAccess, Visitor},
+        ser::SerializeStruct,
+        Deserialize, Deserializer, Serialize, Serializer,
+    };
+
+    use super::Waveform;
+
+    struct Bytes<'b>(&'b [u8]);
+
+    impl Serialize for Bytes<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+
+    struct ByteBuf(Vec<u8>);
+
+    impl<'de> Deserialize<'de> for ByteBuf {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct ByteBufVisitor;
+
+            impl<'de> Visitor<'de> for ByteBufVisitor {
+                type Value = ByteBuf;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    formatter.write_str("little endian f32 samples as bytes")
+                }
+
+                fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+                    Ok(ByteBuf(bytes.to_vec()))
+                }
+
+                fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Self::Value, E> {
+                    Ok(ByteBuf(bytes))
+                }
+
+                // Formats without a byte type write them as a sequence instead
+                fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                    let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+
+                    while let Some(byte) = seq.next_element()? {
+                        bytes.push(byte);
+                    }
+
+                    Ok(ByteBuf(bytes))
+                }
+            }
+
+            deserializer.deserialize_byte_buf(ByteBufVisitor)
+        }
+    }
+
+    impl Serialize for Waveform<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let bytes = self
+                .samples
+                .iter()
+                .flat_map(|sample| sample.to_le_bytes())
+                .collect::<Vec<_>>();
+
+            let mut waveform = serializer.serialize_struct("Waveform", 3)?;
+            waveform.serialize_field("samples", &Bytes(&bytes))?;
+            waveform.serialize_field("sample_rate", &self.sample_rate)?;
+            waveform.serialize_field("channels", &self.channels)?;
+            waveform.end()
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename = "Waveform")]
+    struct RawWaveform {
+        samples: ByteBuf,
+        sample_rate: u32,
+        channels: u16,
+    }
+
+    impl<'de> Deserialize<'de> for Waveform<'static> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let RawWaveform {
+                samples: ByteBuf(bytes),
+                sample_rate,
+                channels,
+            } = RawWaveform::deserialize(deserializer)?;
+
+            if bytes.len() % 4 != 0 {
+                return Err(de::Error::custom("samples are not a whole number of f32s"));
+            }
+
+            let samples = bytes
+                .chunks_exact(4)
+                .map(|sample| f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]))
+                .collect::<Vec<_>>();
+
+            if channels == 0 {
+                return Err(de::Error::custom(
+                    "a waveform must have at least one channel",
+                ));
+            }
+
+            if samples.len() % channels as usize != 0 {
+                return Err(de::Error::custom(
+                    "interleaved samples must contain whole frames",
+                ));
+            }
+
+            Ok(Waveform::new(samples, sample_rate, channels))
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use crate::waveform::Waveform;
+
+        #[test]
+        fn round_trip() -> color_eyre::Result<()> {
+            let waveform = Waveform::new(vec![0.5, -0.25, 1.0, f32::MIN_POSITIVE], 48_000, 2);
+
+            let serialized = ron::to_string(&waveform)?;
+            let deserialized: Waveform = ron::from_str(&serialized)?;
+
+            assert_eq!(deserialized.samples(), waveform.samples());
+            assert_eq!(deserialized.sample_rate(), 48_000);
+            assert_eq!(deserialized.channels(), 2);
+
+            assert!(
+                ron::from_str::<Waveform>(&serialized.replace("channels:2", "channels:3")).is_err()
+            );
+
+            Ok(())
+        }
+    }
+}
+
+/// A small, seedable pseudorandom number generator (splitmix64), so noise
+/// can be reproduced without pulling in a dependency
+#[derive(Debug, Clone, Copy)]
+struct NoiseSource {
+    state: u64,
+}
+
+impl NoiseSource {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// A uniformly distributed sample between `-1.0` and `1.0`
+    fn next_sample(&mut self) -> f32 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+
+        // The top 24 bits fill an f32 mantissa exactly
+        (z >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+}
+
+/// The normalized sinc function
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (consts::PI * x).sin() / (consts::PI * x)
+    }
+}
+
+/// A blackman window centered on zero, spanning `-1.0..=1.0`
+fn blackman(x: f32) -> f32 {
+    if x.abs() >= 1.0 {
+        0.0
+    } else {
+        0.42 + 0.5 * (consts::PI * x).cos() + 0.08 * (consts::TAU * x).cos()
+    }
+}
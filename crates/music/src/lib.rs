@@ -0,0 +1,918 @@
+#![forbid(unsafe_code)]
+#![warn(missing_copy_implementations, missing_debug_implementations)]
+
+//! Notes, keys and scales, and the piano keys and frequencies they fall on
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use serde::{
+    de::{self, Unexpected},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct MusicalNote {
+    letter: NoteLetter,
+    accidental: Option<Accidental>,
+    octave: i8,
+}
+
+impl Display for MusicalNote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.letter)?;
+
+        if let Some(accidental) = self.accidental {
+            write!(f, "{accidental}")?;
+        }
+
+        write!(f, "{}", self.octave)
+    }
+}
+
+impl MusicalNote {
+    pub fn new(letter: NoteLetter, accidental: impl Into<Option<Accidental>>, octave: i8) -> Self {
+        Self {
+            letter,
+            accidental: accidental.into(),
+            octave,
+        }
+    }
+
+    /// Get the musical note's letter.
+    pub fn letter(&self) -> NoteLetter {
+        self.letter
+    }
+
+    /// Get the musical note's accidental.
+    pub fn accidental(&self) -> Option<Accidental> {
+        self.accidental
+    }
+
+    /// Get the musical note's octave.
+    pub fn octave(&self) -> i8 {
+        self.octave
+    }
+
+    /// Check if two notes represent the same pitch note, even if they
+    /// are represented with different letters or accidentals
+    pub fn is_same_pitch_as(&self, other: &Self) -> bool {
+        self.octave == other.octave && self.semitone_offset() == other.semitone_offset()
+    }
+
+    /// Get the amount of semitones this note is off from its octave
+    pub fn semitone_offset(&self) -> i8 {
+        self.letter.semitone() as i8 + self.accidental.map_or(0, |a| a.semitone_delta())
+    }
+
+    /// Get the twelve tone equal temperament semitone from C0, which is
+    /// negative for notes below it
+    pub fn semitone(&self) -> i16 {
+        self.octave as i16 * 12 + self.semitone_offset() as i16
+    }
+
+    /// The frequency of this note in twelve tone equal temperament
+    pub fn frequency(&self, tuning: Tuning) -> f32 {
+        let a4 = MusicalNote::new(NoteLetter::A, None, 4).semitone();
+
+        tuning.a4_hz * 2.0f32.powf((self.semitone() - a4) as f32 / 12.0)
+    }
+
+    /// The key playing this note, if it is within the midi range
+    pub fn as_key(&self) -> Option<PianoKey> {
+        // Midi starts from C-1, an octave below C0
+        u8::try_from(self.semitone() + 12)
+            .ok()
+            .and_then(PianoKey::from_midi)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParseNoteError {
+    Empty,
+    InvalidLetter(char),
+    MissingOctave,
+    InvalidOctave(String),
+}
+
+impl Display for ParseNoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseNoteError::Empty => write!(f, "a note can not be empty"),
+            ParseNoteError::InvalidLetter(letter) => {
+                write!(f, "{letter:?} is not a note letter, expected A through G")
+            }
+            ParseNoteError::MissingOctave => write!(f, "the note is missing its octave"),
+            ParseNoteError::InvalidOctave(octave) => {
+                write!(f, "{octave:?} is not a valid octave")
+            }
+        }
+    }
+}
+
+impl Error for ParseNoteError {}
+
+impl FromStr for MusicalNote {
+    type Err = ParseNoteError;
+
+    /// Parse notes written like `C#4`, `Bb3` or `f♯2`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.trim().chars();
+
+        let letter = match chars.next().ok_or(ParseNoteError::Empty)? {
+            'A' | 'a' => NoteLetter::A,
+            'B' | 'b' => NoteLetter::B,
+            'C' | 'c' => NoteLetter::C,
+            'D' | 'd' => NoteLetter::D,
+            'E' | 'e' => NoteLetter::E,
+            'F' | 'f' => NoteLetter::F,
+            'G' | 'g' => NoteLetter::G,
+            letter => return Err(ParseNoteError::InvalidLetter(letter)),
+        };
+
+        let rest = chars.as_str();
+        let (accidental, octave) = match rest.chars().next() {
+            Some('#') => (Some(Accidental::Sharp), &rest[1..]),
+            Some('♯') => (Some(Accidental::Sharp), &rest['♯'.len_utf8()..]),
+            Some('b') => (Some(Accidental::Flat), &rest[1..]),
+            Some('♭') => (Some(Accidental::Flat), &rest['♭'.len_utf8()..]),
+            _ => (None, rest),
+        };
+
+        if octave.is_empty() {
+            return Err(ParseNoteError::MissingOctave);
+        }
+
+        let octave = octave
+            .parse()
+            .map_err(|_| ParseNoteError::InvalidOctave(octave.to_string()))?;
+
+        Ok(MusicalNote::new(letter, accidental, octave))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Accidental {
+    Sharp,
+    Flat,
+}
+
+impl Accidental {
+    /// The semitone change represented by this accidental
+    pub fn semitone_delta(&self) -> i8 {
+        match self {
+            Accidental::Sharp => 1,
+            Accidental::Flat => -1,
+        }
+    }
+}
+
+impl Display for Accidental {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match (self, f.alternate()) {
+                (Accidental::Sharp, false) => '#',
+                (Accidental::Sharp, true) => '♯',
+                (Accidental::Flat, false) => 'b',
+                (Accidental::Flat, true) => '♭',
+            }
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum NoteLetter {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+}
+
+impl NoteLetter {
+    /// The semitone in the octave that this note represents
+    pub fn semitone(&self) -> u8 {
+        // See the table on https://en.wikipedia.org/wiki/Piano_key_frequencies
+
+        match self {
+            NoteLetter::C => 0,
+            NoteLetter::D => 2,
+            NoteLetter::E => 4,
+            NoteLetter::F => 5,
+            NoteLetter::G => 7,
+            NoteLetter::A => 9,
+            NoteLetter::B => 11,
+        }
+    }
+}
+
+impl Display for NoteLetter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Just use the debug format for display
+        write!(f, "{:?}", self)
+    }
+}
+
+/// The interval from frequency `a` to frequency `b` in cents, positive when `b` is higher
+pub fn cents_between(a: f32, b: f32) -> f32 {
+    1200.0 * (b / a).log2()
+}
+
+/// The note nearest to `freq`, spelled with `preference`, and how many cents
+/// `freq` is above it, which is negative when it is flat
+pub fn nearest_note(
+    freq: f32,
+    tuning: Tuning,
+    preference: Accidental,
+) -> Option<(MusicalNote, f32)> {
+    let note = PianoKey::from_frequency(freq, tuning)?.as_note(preference);
+
+    Some((note, cents_between(note.frequency(tuning), freq)))
+}
+
+/// The reference pitch that all other notes are tuned relative to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Tuning {
+    /// The frequency of A4 in Hz
+    pub a4_hz: f32,
+}
+
+impl Tuning {
+    /// The modern standard of A4 at 440Hz
+    pub const CONCERT: Tuning = Tuning { a4_hz: 440.0 };
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self::CONCERT
+    }
+}
+
+/// A key in the full MIDI range, from C-1 up to G9. The 88 keys of a piano
+/// are numbered 1 - 88, with keys below A0 numbered from 0 downwards.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct PianoKey(u8);
+
+impl PianoKey {
+    /// The midi note of the piano's lowest key, A0
+    const MIDI_OFFSET: u8 = 21;
+
+    /// All the piano keys from highest to lowest
+    pub fn all() -> impl DoubleEndedIterator<Item = Self> + ExactSizeIterator<Item = Self> {
+        (1..=88).rev().map(|key| PianoKey::new(key).unwrap())
+    }
+
+    /// Every key in the midi range from highest to lowest
+    pub fn all_midi() -> impl DoubleEndedIterator<Item = Self> + ExactSizeIterator<Item = Self> {
+        (0..=127).rev().map(Self)
+    }
+
+    /// The key numbered `key` on a piano, from 1 to 88
+    pub fn new(key: u8) -> Option<Self> {
+        match key {
+            0 => None,
+            1..=88 => Some(Self(key - 1 + Self::MIDI_OFFSET)),
+            _ => None,
+        }
+    }
+
+    /// The key playing midi note `note`, from 0 to 127
+    pub fn from_midi(note: u8) -> Option<Self> {
+        (note <= 127).then_some(Self(note))
+    }
+
+    // TODO: Scales?
+    pub fn from_concert_pitch(freq: f32) -> Option<Self> {
+        Self::from_frequency(freq, Tuning::CONCERT)
+    }
+
+    /// The nearest key to `freq` with A4 tuned to `tuning`
+    pub fn from_frequency(freq: f32, tuning: Tuning) -> Option<Self> {
+        let a4 = Self::new(49)?.midi_number() as f32;
+        let note = (12.0 * (freq / tuning.a4_hz).log2()).round() + a4;
+
+        // NaN and infinite frequencies fail both comparisons
+        if (0.0..=127.0).contains(&note) {
+            Self::from_midi(note as u8)
+        } else {
+            None
+        }
+    }
+
+    pub fn concert_pitch(&self) -> f32 {
+        self.frequency(Tuning::CONCERT)
+    }
+
+    pub fn frequency(&self, tuning: Tuning) -> f32 {
+        let twelfth_root = 2.0f32.powf(1.0 / 12.0);
+
+        // Raise to the power of keys away from A4
+        tuning.a4_hz * twelfth_root.powi(self.number() as i32 - 49)
+    }
+
+    /// The number of this key on a piano, which falls outside of 1 - 88 for
+    /// keys beyond the piano's range
+    pub fn number(&self) -> i8 {
+        self.0 as i8 - Self::MIDI_OFFSET as i8 + 1
+    }
+
+    pub fn midi_number(&self) -> u8 {
+        self.0
+    }
+
+    /// Check if this key is one of the 88 on a piano
+    pub fn is_on_piano(&self) -> bool {
+        (1..=88).contains(&self.number())
+    }
+
+    // TODO: Scales?
+    pub fn as_note(&self, preference: Accidental) -> MusicalNote {
+        // Midi starts from C-1, an octave below C0
+        let note_offset = self.semitone_offset();
+        let octave = (self.0 / 12) as i8 - 1;
+
+        use self::{Accidental::*, NoteLetter::*};
+
+        match (note_offset, preference) {
+            (0, _) => MusicalNote::new(C, None, octave),
+            (1, Sharp) => MusicalNote::new(C, Sharp, octave),
+            (1, Flat) => MusicalNote::new(D, Flat, octave),
+            (2, _) => MusicalNote::new(D, None, octave),
+            (3, Sharp) => MusicalNote::new(D, Sharp, octave),
+            (3, Flat) => MusicalNote::new(E, Flat, octave),
+            (4, _) => MusicalNote::new(E, None, octave),
+            (5, _) => MusicalNote::new(F, None, octave),
+            (6, Sharp) => MusicalNote::new(F, Sharp, octave),
+            (6, Flat) => MusicalNote::new(G, Flat, octave),
+            (7, _) => MusicalNote::new(G, None, octave),
+            (8, Sharp) => MusicalNote::new(G, Sharp, octave),
+            (8, Flat) => MusicalNote::new(A, Flat, octave),
+            (9, _) => MusicalNote::new(A, None, octave),
+            (10, Sharp) => MusicalNote::new(A, Sharp, octave),
+            (10, Flat) => MusicalNote::new(B, Flat, octave),
+            (11, _) => MusicalNote::new(B, None, octave),
+            (12.., _) => unreachable!(),
+        }
+    }
+
+    /// Get the amount of semitones this key is above the C in its octave
+    pub fn semitone_offset(&self) -> u8 {
+        // Midi notes start at a C
+        self.0 % 12
+    }
+
+    pub fn is_white(&self) -> bool {
+        match self.semitone_offset() {
+            0 | 2 | 4 | 5 | 7 | 9 | 11 => true,
+            1 | 3 | 6 | 8 | 10 => false,
+            12.. => unreachable!(),
+        }
+    }
+
+    pub fn is_black(&self) -> bool {
+        !self.is_white()
+    }
+}
+
+/// Keys are stored as their MIDI note number
+impl Serialize for PianoKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PianoKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let note = u8::deserialize(deserializer)?;
+
+        Self::from_midi(note).ok_or_else(|| {
+            de::Error::invalid_value(Unexpected::Unsigned(note.into()), &"a MIDI note up to 127")
+        })
+    }
+}
+
+/// The set of notes reached by stepping up from a root note by a repeating
+/// pattern of intervals
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Scale {
+    root: MusicalNote,
+    /// Which of the twelve semitones above the root are in the scale
+    semitones: [bool; 12],
+}
+
+impl Scale {
+    /// Whole, whole, half, whole, whole, whole, half
+    pub const MAJOR_STEPS: [u8; 7] = [2, 2, 1, 2, 2, 2, 1];
+    /// Whole, half, whole, whole, half, whole, whole
+    pub const MINOR_STEPS: [u8; 7] = [2, 1, 2, 2, 1, 2, 2];
+
+    pub fn major(root: MusicalNote) -> Self {
+        Self::from_steps(root, &Self::MAJOR_STEPS)
+    }
+
+    /// The natural minor scale
+    pub fn minor(root: MusicalNote) -> Self {
+        Self::from_steps(root, &Self::MINOR_STEPS)
+    }
+
+    /// Build a scale from the semitones between each of its successive notes,
+    /// starting from the root. Steps past the octave wrap back around.
+    pub fn from_steps(root: MusicalNote, steps: &[u8]) -> Self {
+        let mut semitones = [false; 12];
+        semitones[0] = true;
+
+        let mut semitone = 0;
+        for &step in steps {
+            semitone = (semitone + step as usize) % 12;
+            semitones[semitone] = true;
+        }
+
+        Self { root, semitones }
+    }
+
+    /// Get the scale's root note.
+    pub fn root(&self) -> MusicalNote {
+        self.root
+    }
+
+    pub fn contains(&self, key: PianoKey) -> bool {
+        let root = self.root.semitone_offset().rem_euclid(12) as u8;
+
+        self.semitones[((key.semitone_offset() + 12 - root) % 12) as usize]
+    }
+
+    /// All of the piano keys in the scale from lowest to highest
+    pub fn notes(&self) -> impl Iterator<Item = PianoKey> {
+        let scale = *self;
+
+        PianoKey::all()
+            .rev()
+            .filter(move |&key| scale.contains(key))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Major => write!(f, "major"),
+            Mode::Minor => write!(f, "minor"),
+        }
+    }
+}
+
+/// A key estimated from the notes of a piece
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DetectedKey {
+    pub letter: NoteLetter,
+    pub accidental: Option<Accidental>,
+    pub mode: Mode,
+    /// The correlation between the piece and the key's profile, from -1 to 1
+    pub confidence: f32,
+}
+
+impl DetectedKey {
+    /// The scale of the key, rooted in the fourth octave
+    pub fn scale(&self) -> Scale {
+        let root = MusicalNote::new(self.letter, self.accidental, 4);
+
+        match self.mode {
+            Mode::Major => Scale::major(root),
+            Mode::Minor => Scale::minor(root),
+        }
+    }
+}
+
+impl Display for DetectedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.letter)?;
+
+        if let Some(accidental) = self.accidental {
+            write!(f, "{accidental}")?;
+        }
+
+        write!(f, " {}", self.mode)
+    }
+}
+
+/// Krumhansl and Kessler's ratings of how well each semitone above the tonic
+/// fits in a major key
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+/// Krumhansl and Kessler's ratings of how well each semitone above the tonic
+/// fits in a minor key
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+/// The conventional spelling of each major tonic, by semitones above C
+const MAJOR_TONICS: [(NoteLetter, Option<Accidental>); 12] = {
+    use self::{Accidental::*, NoteLetter::*};
+
+    [
+        (C, None),
+        (D, Some(Flat)),
+        (D, None),
+        (E, Some(Flat)),
+        (E, None),
+        (F, None),
+        (F, Some(Sharp)),
+        (G, None),
+        (A, Some(Flat)),
+        (A, None),
+        (B, Some(Flat)),
+        (B, None),
+    ]
+};
+/// The conventional spelling of each minor tonic, by semitones above C
+const MINOR_TONICS: [(NoteLetter, Option<Accidental>); 12] = {
+    use self::{Accidental::*, NoteLetter::*};
+
+    [
+        (C, None),
+        (C, Some(Sharp)),
+        (D, None),
+        (E, Some(Flat)),
+        (E, None),
+        (F, None),
+        (F, Some(Sharp)),
+        (G, None),
+        (G, Some(Sharp)),
+        (A, None),
+        (B, Some(Flat)),
+        (B, None),
+    ]
+};
+
+/// Correlate a chromagram, indexed by semitones above C, against the profile
+/// of every major and minor key, from the most to least likely key.
+///
+/// Returns nothing if every pitch class is equally present, since then no key
+/// stands out.
+pub fn key_candidates(chroma: &[f32; 12]) -> Vec<DetectedKey> {
+    let mut candidates = [
+        (Mode::Major, &MAJOR_PROFILE, &MAJOR_TONICS),
+        (Mode::Minor, &MINOR_PROFILE, &MINOR_TONICS),
+    ]
+    .into_iter()
+    .flat_map(|(mode, profile, tonics)| {
+        (0..12).filter_map(move |tonic| {
+            let (letter, accidental) = tonics[tonic];
+
+            // Rotate the profile so its tonic lines up with this pitch class
+            let profile = (0..12)
+                .map(|semitone| profile[(semitone + 12 - tonic) % 12])
+                .collect::<Vec<_>>();
+
+            correlation(chroma, &profile).map(|confidence| DetectedKey {
+                letter,
+                accidental,
+                mode,
+                confidence,
+            })
+        })
+    })
+    .collect::<Vec<_>>();
+
+    candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+    candidates
+}
+
+/// The most likely key of a chromagram, using the Krumhansl-Schmuckler algorithm
+pub fn detect_key(chroma: &[f32; 12]) -> Option<DetectedKey> {
+    key_candidates(chroma).into_iter().next()
+}
+
+/// The Pearson correlation coefficient between two series of the same length
+fn correlation(x: &[f32], y: &[f32]) -> Option<f32> {
+    let x_mean = x.iter().sum::<f32>() / x.len() as f32;
+    let y_mean = y.iter().sum::<f32>() / y.len() as f32;
+
+    let (covariance, x_variance, y_variance) = x.iter().zip(y).fold(
+        (0.0, 0.0, 0.0),
+        |(covariance, x_variance, y_variance), (x, y)| {
+            let (x, y) = (x - x_mean, y - y_mean);
+
+            (covariance + x * y, x_variance + x * x, y_variance + y * y)
+        },
+    );
+
+    let deviation = (x_variance * y_variance).sqrt();
+
+    (deviation > 0.0).then_some(covariance / deviation)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        cents_between, detect_key, key_candidates, nearest_note, Accidental::*, Mode, MusicalNote,
+        NoteLetter::*, ParseNoteError, PianoKey, Scale, Tuning,
+    };
+
+    // TODO: more test cases all around
+
+    #[test]
+    fn same_pitch() {
+        assert!(MusicalNote::new(A, Sharp, 0).is_same_pitch_as(&MusicalNote::new(B, Flat, 0)))
+    }
+
+    #[test]
+    fn as_note() {
+        // As0
+        assert_eq!(
+            PianoKey::new(2).unwrap().as_note(Sharp),
+            MusicalNote::new(A, Sharp, 0)
+        );
+
+        // Bb0
+        assert_eq!(
+            PianoKey::new(2).unwrap().as_note(Flat),
+            MusicalNote::new(B, Flat, 0)
+        );
+
+        // C4
+        assert_eq!(
+            PianoKey::new(40).unwrap().as_note(Sharp),
+            MusicalNote::new(C, None, 4)
+        );
+
+        // Make sure naturals return the same for both preferences
+        assert_eq!(
+            PianoKey::new(40).unwrap().as_note(Sharp),
+            PianoKey::new(40).unwrap().as_note(Flat),
+        );
+    }
+
+    #[test]
+    fn as_key() {
+        assert_eq!(MusicalNote::new(C, None, 4).as_key(), PianoKey::new(40));
+
+        assert_eq!(MusicalNote::new(A, None, 0).as_key(), PianoKey::new(1));
+
+        assert_eq!(MusicalNote::new(A, Sharp, 0).as_key(), PianoKey::new(2));
+        assert_eq!(MusicalNote::new(B, Flat, 0).as_key(), PianoKey::new(2));
+
+        assert_eq!(MusicalNote::new(A, None, 1).as_key(), PianoKey::new(13));
+
+        // Keys beyond the piano's range are still within midi's
+        assert_eq!(
+            MusicalNote::new(C, None, 0).as_key(),
+            PianoKey::from_midi(12)
+        );
+        assert_eq!(
+            MusicalNote::new(C, None, -1).as_key(),
+            PianoKey::from_midi(0)
+        );
+        assert_eq!(
+            MusicalNote::new(G, None, 9).as_key(),
+            PianoKey::from_midi(127)
+        );
+        assert_eq!(MusicalNote::new(C, Flat, -1).as_key(), None);
+        assert_eq!(MusicalNote::new(G, Sharp, 9).as_key(), None);
+    }
+
+    #[test]
+    fn midi_extremes() {
+        let lowest = PianoKey::from_midi(0).unwrap();
+        assert_eq!(lowest.number(), -20);
+        assert!(!lowest.is_on_piano());
+        assert_eq!(lowest.as_note(Sharp), MusicalNote::new(C, None, -1));
+        assert!((lowest.concert_pitch() - 8.176).abs() < 1e-3);
+        assert_eq!(PianoKey::from_concert_pitch(8.176), Some(lowest));
+
+        let highest = PianoKey::from_midi(127).unwrap();
+        assert_eq!(highest.number(), 107);
+        assert!(!highest.is_on_piano());
+        assert_eq!(highest.as_note(Sharp), MusicalNote::new(G, None, 9));
+        assert!((highest.concert_pitch() - 12543.854).abs() < 1e-1);
+        assert_eq!(PianoKey::from_concert_pitch(12543.854), Some(highest));
+
+        assert_eq!(PianoKey::from_midi(128), None);
+        assert_eq!(PianoKey::from_concert_pitch(7.0), None);
+        assert_eq!(PianoKey::from_concert_pitch(14_000.0), None);
+
+        // The piano's own range lines up with midi
+        assert_eq!(PianoKey::new(1), PianoKey::from_midi(21));
+        assert_eq!(PianoKey::new(88), PianoKey::from_midi(108));
+        assert!(PianoKey::all().all(|key| key.is_on_piano()));
+
+        // Every key agrees with its note, including flats across octaves
+        for key in PianoKey::all_midi() {
+            for preference in [Sharp, Flat] {
+                assert_eq!(key.as_note(preference).as_key(), Some(key));
+            }
+        }
+    }
+
+    #[test]
+    fn tuning() {
+        let a4 = PianoKey::new(49).unwrap();
+        let baroque = Tuning { a4_hz: 415.0 };
+
+        assert_eq!(a4.concert_pitch(), 440.0);
+        assert_eq!(a4.frequency(baroque), 415.0);
+
+        for key in PianoKey::all() {
+            assert_eq!(PianoKey::from_concert_pitch(key.concert_pitch()), Some(key));
+            assert_eq!(
+                PianoKey::from_frequency(key.frequency(baroque), baroque),
+                Some(key)
+            );
+        }
+
+        // G#4 at concert pitch, but closer to A4 when tuned down
+        assert_eq!(PianoKey::from_concert_pitch(425.0), PianoKey::new(48));
+        assert_eq!(
+            PianoKey::from_frequency(425.0, Tuning { a4_hz: 432.0 }),
+            PianoKey::new(49)
+        );
+    }
+
+    #[test]
+    fn note_frequency() {
+        assert_eq!(
+            MusicalNote::new(A, None, 4).frequency(Tuning::CONCERT),
+            440.0
+        );
+        assert_eq!(
+            MusicalNote::new(A, None, 4).frequency(Tuning { a4_hz: 432.0 }),
+            432.0
+        );
+
+        let c4 = MusicalNote::new(C, None, 4).frequency(Tuning::CONCERT);
+        let c5 = MusicalNote::new(C, None, 5).frequency(Tuning::CONCERT);
+        assert!((c5 / c4 - 2.0).abs() < 1e-5);
+        assert!((c4 - 261.626).abs() < 1e-3);
+
+        // Enharmonic notes, even across an octave boundary
+        assert_eq!(
+            MusicalNote::new(C, Flat, 5).frequency(Tuning::CONCERT),
+            MusicalNote::new(B, None, 4).frequency(Tuning::CONCERT)
+        );
+
+        // Agrees with the piano's keys
+        for key in PianoKey::all() {
+            let note = key.as_note(Sharp);
+
+            assert!((note.frequency(Tuning::CONCERT) - key.concert_pitch()).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn cents() {
+        assert!((cents_between(440.0, 880.0) - 1200.0).abs() < 1e-3);
+        assert!((cents_between(440.0, 220.0) + 1200.0).abs() < 1e-3);
+
+        let a4 = MusicalNote::new(A, None, 4).frequency(Tuning::CONCERT);
+        let a_sharp4 = MusicalNote::new(A, Sharp, 4).frequency(Tuning::CONCERT);
+        assert!((cents_between(a4, a_sharp4) - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn cents_off_nearest_note() {
+        let a4 = MusicalNote::new(A, None, 4);
+        let offset = |cents: f32| 440.0 * 2.0f32.powf(cents / 1200.0);
+
+        for cents in [0.0, 10.0, -30.0, 49.0] {
+            let (note, off) = nearest_note(offset(cents), Tuning::CONCERT, Sharp).unwrap();
+
+            assert_eq!(note, a4);
+            assert!(
+                (off - cents).abs() < 1e-2,
+                "{off} cents off instead of {cents}"
+            );
+        }
+
+        // Rounds to the next note past half a semitone
+        let (note, off) = nearest_note(offset(51.0), Tuning::CONCERT, Flat).unwrap();
+        assert_eq!(note, MusicalNote::new(B, Flat, 4));
+        assert!((off + 49.0).abs() < 1e-2);
+
+        // Relative to the tuning, not concert pitch
+        let (note, off) = nearest_note(440.0, Tuning { a4_hz: 432.0 }, Sharp).unwrap();
+        assert_eq!(note, a4);
+        assert!((off - cents_between(432.0, 440.0)).abs() < 1e-2);
+
+        assert_eq!(nearest_note(0.0, Tuning::CONCERT, Sharp), None);
+    }
+
+    #[test]
+    fn parse_note() {
+        assert_eq!("C#4".parse(), Ok(MusicalNote::new(C, Sharp, 4)));
+        assert_eq!("Bb3".parse(), Ok(MusicalNote::new(B, Flat, 3)));
+        assert_eq!("A0".parse(), Ok(MusicalNote::new(A, None, 0)));
+        assert_eq!("f♯2".parse(), Ok(MusicalNote::new(F, Sharp, 2)));
+        assert_eq!("e♭5".parse(), Ok(MusicalNote::new(E, Flat, 5)));
+        assert_eq!("bb1".parse(), Ok(MusicalNote::new(B, Flat, 1)));
+
+        // Displayed notes parse back to themselves
+        for key in PianoKey::all() {
+            let note = key.as_note(Flat);
+            assert_eq!(note.to_string().parse(), Ok(note));
+        }
+
+        assert_eq!(
+            "H2".parse::<MusicalNote>(),
+            Err(ParseNoteError::InvalidLetter('H'))
+        );
+        assert_eq!(
+            "C".parse::<MusicalNote>(),
+            Err(ParseNoteError::MissingOctave)
+        );
+        assert_eq!(
+            "C#".parse::<MusicalNote>(),
+            Err(ParseNoteError::MissingOctave)
+        );
+        assert_eq!(
+            "Cx4".parse::<MusicalNote>(),
+            Err(ParseNoteError::InvalidOctave("x4".to_string()))
+        );
+        assert_eq!("".parse::<MusicalNote>(), Err(ParseNoteError::Empty));
+    }
+
+    #[test]
+    fn major_scale() {
+        let c_major = Scale::major(MusicalNote::new(C, None, 4));
+
+        for key in PianoKey::all() {
+            assert_eq!(c_major.contains(key), key.is_white(), "{key:?}");
+        }
+
+        let notes = c_major
+            .notes()
+            .take(7)
+            .map(|key| key.as_note(Sharp))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            notes,
+            [
+                MusicalNote::new(A, None, 0),
+                MusicalNote::new(B, None, 0),
+                MusicalNote::new(C, None, 1),
+                MusicalNote::new(D, None, 1),
+                MusicalNote::new(E, None, 1),
+                MusicalNote::new(F, None, 1),
+                MusicalNote::new(G, None, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn minor_scale() {
+        // A minor shares its notes with C major
+        let a_minor = Scale::minor(MusicalNote::new(A, None, 4));
+        assert!(PianoKey::all().all(|key| a_minor.contains(key) == key.is_white()));
+
+        // E flat minor has six flats, so only F and C flat land on white keys
+        let e_flat_minor = Scale::minor(MusicalNote::new(E, Flat, 4));
+        let white = e_flat_minor
+            .notes()
+            .filter(PianoKey::is_white)
+            .map(|key| key.as_note(Flat).letter())
+            .collect::<Vec<_>>();
+        assert!(white.contains(&F) && white.contains(&B));
+        assert!(white.iter().all(|&letter| letter == F || letter == B));
+    }
+
+    #[test]
+    fn detect_c_major() {
+        let mut chroma = [0.0; 12];
+        for key in Scale::major(MusicalNote::new(C, None, 4)).notes().take(12) {
+            chroma[key.semitone_offset() as usize] = 1.0;
+        }
+
+        let candidates = key_candidates(&chroma);
+        let confidence = |letter, mode| {
+            candidates
+                .iter()
+                .find(|key| key.letter == letter && key.accidental.is_none() && key.mode == mode)
+                .map(|key| key.confidence)
+                .unwrap()
+        };
+
+        let detected = detect_key(&chroma).unwrap();
+        assert_eq!((detected.letter, detected.accidental), (C, None));
+        assert_eq!(detected.mode, Mode::Major);
+        assert_eq!(detected.to_string(), "C major");
+
+        // A minor shares every note, but C is the tonic
+        assert!(confidence(C, Mode::Major) > confidence(A, Mode::Minor));
+
+        // Nothing stands out in a flat chroma
+        assert_eq!(detect_key(&[1.0; 12]), None);
+    }
+}
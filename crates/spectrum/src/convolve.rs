@@ -0,0 +1,179 @@
+use std::iter;
+
+use audio::waveform::Waveform;
+use color_eyre::eyre::ensure;
+use num_complex::Complex;
+
+use crate::fft::{cfft, FftError};
+
+/// The largest block the impulse response is split into, leaving room for the
+/// fft to be twice as wide
+const MAX_BLOCK: usize = 8192;
+
+/// Convolve the two waveforms using uniformly partitioned overlap-add, so
+/// impulse responses longer than the widest fft can still be used.
+///
+/// A mono impulse is applied to every channel, otherwise the channel counts
+/// must match and each channel is convolved with its counterpart.
+pub(crate) fn convolve(
+    waveform: &Waveform,
+    impulse: &Waveform,
+) -> color_eyre::Result<Waveform<'static>> {
+    ensure!(
+        waveform.sample_rate() == impulse.sample_rate(),
+        "cannot convolve a waveform at {}Hz with an impulse at {}Hz",
+        waveform.sample_rate(),
+        impulse.sample_rate()
+    );
+    ensure!(
+        impulse.channels() == 1 || impulse.channels() == waveform.channels(),
+        "cannot convolve a waveform with {} channels with an impulse with {} channels",
+        waveform.channels(),
+        impulse.channels()
+    );
+
+    let channels = waveform.channels();
+
+    if waveform.is_empty() || impulse.is_empty() {
+        return Ok(Waveform::new(Vec::new(), waveform.sample_rate(), channels));
+    }
+
+    let frames = waveform.frames() + impulse.frames() - 1;
+    let mut samples = vec![0.0; frames * channels as usize];
+
+    for channel in 0..channels {
+        let impulse = impulse.channel(if impulse.channels() == 1 { 0 } else { channel });
+        let convolved = convolve_mono(waveform.channel(channel).samples(), impulse.samples())?;
+
+        for (frame, sample) in convolved.into_iter().enumerate() {
+            samples[frame * channels as usize + channel as usize] = sample;
+        }
+    }
+
+    Ok(Waveform::new(samples, waveform.sample_rate(), channels))
+}
+
+fn convolve_mono(signal: &[f32], impulse: &[f32]) -> Result<Vec<f32>, FftError> {
+    let block = impulse.len().next_power_of_two().min(MAX_BLOCK);
+    let width = block * 2;
+
+    let spectra = |samples: &[f32]| {
+        samples
+            .chunks(block)
+            .map(|chunk| {
+                let mut buckets = chunk
+                    .iter()
+                    .map(|&sample| Complex::new(sample, 0.0))
+                    .chain(iter::repeat(Complex::new(0.0, 0.0)))
+                    .take(width)
+                    .collect::<Vec<_>>();
+
+                cfft(&mut buckets)?;
+
+                Ok(buckets)
+            })
+            .collect::<Result<Vec<_>, FftError>>()
+    };
+
+    let impulse_spectra = spectra(impulse)?;
+    let signal_spectra = spectra(signal)?;
+
+    let mut output = vec![0.0; signal.len() + impulse.len() - 1];
+    let mut accumulator = vec![Complex::new(0.0, 0.0); width];
+
+    // Every pair of blocks whose offsets sum to the same output block can be
+    // accumulated in the frequency domain before a single inverse fft
+    for output_block in 0..signal_spectra.len() + impulse_spectra.len() - 1 {
+        accumulator.fill(Complex::new(0.0, 0.0));
+
+        let first = output_block.saturating_sub(impulse_spectra.len() - 1);
+        let last = output_block.min(signal_spectra.len() - 1);
+
+        for signal_block in first..=last {
+            let signal = &signal_spectra[signal_block];
+            let impulse = &impulse_spectra[output_block - signal_block];
+
+            for ((accumulator, signal), impulse) in accumulator.iter_mut().zip(signal).zip(impulse)
+            {
+                *accumulator += signal * impulse;
+            }
+        }
+
+        // Inverse fft by swapping the real and imaginary components either side
+        // of a forward fft
+        for complex in accumulator.iter_mut() {
+            *complex = Complex::new(complex.im, complex.re);
+        }
+
+        cfft(&mut accumulator)?;
+
+        for (output, complex) in output
+            .iter_mut()
+            .skip(output_block * block)
+            .zip(&accumulator)
+        {
+            *output += complex.im / width as f32;
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+
+    use crate::WaveformSpectrum;
+
+    #[test]
+    fn unit_impulse() -> color_eyre::Result<()> {
+        let sample_rate = Waveform::CD_SAMPLE_RATE;
+        let waveform = Waveform::sine_wave(440.0, 0.5, sample_rate);
+        let impulse = Waveform::new_mono(vec![1.0, 0.0, 0.0, 0.0], sample_rate);
+
+        let convolved = waveform.convolve(&impulse)?;
+
+        assert_eq!(convolved.len(), waveform.len() + 3);
+        for (convolved, original) in convolved.samples_iter().zip(waveform.samples_iter()) {
+            assert!((convolved - original).abs() < 1e-4);
+        }
+        assert!(convolved.samples()[waveform.len()..]
+            .iter()
+            .all(|padding| padding.abs() < 1e-4));
+
+        Ok(())
+    }
+
+    #[test]
+    fn long_delayed_impulse() -> color_eyre::Result<()> {
+        let sample_rate = Waveform::CD_SAMPLE_RATE;
+        let waveform = Waveform::new(vec![0.5, -0.25, 1.0, 0.75], sample_rate, 2);
+
+        // Longer than a single block, so the impulse is partitioned
+        let delay = 10_000;
+        let mut impulse = vec![0.0; delay + 1];
+        impulse[delay] = 1.0;
+        let impulse = Waveform::new_mono(impulse, sample_rate);
+
+        let convolved = waveform.convolve(&impulse)?;
+
+        assert_eq!(convolved.frames(), waveform.frames() + delay);
+        for (n, sample) in convolved.samples_iter().enumerate() {
+            let expected = n
+                .checked_sub(delay * 2)
+                .map_or(0.0, |n| waveform.samples()[n]);
+
+            assert!((sample - expected).abs() < 1e-4);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn sample_rate_mismatch() {
+        let waveform = Waveform::sine_wave(440.0, 0.1, Waveform::CD_SAMPLE_RATE);
+        let impulse = Waveform::new_mono(vec![1.0], 48_000);
+
+        assert!(waveform.convolve(&impulse).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use std::f32::consts;
+
+use num_complex::Complex;
+
+use crate::Window;
+
+/// Precomputed kernels for a constant-Q transform, with geometrically spaced
+/// bins starting at `f_min`.
+///
+/// With 12 bins per octave and `f_min` on a key's concert pitch, every bin lines
+/// up with exactly one semitone.
+#[derive(Debug, Clone)]
+pub struct ConstantQ {
+    sample_rate: u32,
+    bins_per_octave: u32,
+    f_min: f32,
+    kernels: Box<[Box<[Complex<f32>]>]>,
+}
+
+impl ConstantQ {
+    pub fn new(sample_rate: u32, bins_per_octave: u32, f_min: f32, f_max: f32) -> Self {
+        assert!(
+            bins_per_octave > 0,
+            "there must be at least one bin per octave"
+        );
+        assert!(
+            0.0 < f_min && f_min < f_max,
+            "frequency range must be positive and non-empty"
+        );
+        assert!(
+            f_max <= sample_rate as f32 / 2.0,
+            "f_max must be at or below the nyquist frequency"
+        );
+
+        let bins = (bins_per_octave as f32 * (f_max / f_min).log2()).floor() as usize + 1;
+
+        // Ratio of each bin's frequency to its bandwidth
+        let q = 1.0 / (2.0f32.powf(1.0 / bins_per_octave as f32) - 1.0);
+
+        let kernels = (0..bins)
+            .map(|bin| {
+                let frequency = f_min * 2.0f32.powf(bin as f32 / bins_per_octave as f32);
+                let width = (q * sample_rate as f32 / frequency).ceil() as usize;
+
+                Window::Hann
+                    .into_iter(width)
+                    .enumerate()
+                    .map(|(n, scale)| {
+                        let phase = -consts::TAU * q * n as f32 / width as f32;
+
+                        Complex::from_polar(scale / width as f32, phase)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            sample_rate,
+            bins_per_octave,
+            f_min,
+            kernels,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn bins(&self) -> usize {
+        self.kernels.len()
+    }
+
+    pub fn bins_per_octave(&self) -> u32 {
+        self.bins_per_octave
+    }
+
+    pub fn freq_from_bin(&self, bin: usize) -> f32 {
+        self.f_min * 2.0f32.powf(bin as f32 / self.bins_per_octave as f32)
+    }
+
+    pub fn bin_from_freq(&self, freq: f32) -> Option<usize> {
+        let bin = (self.bins_per_octave as f32 * (freq / self.f_min).log2()).round();
+
+        if bin >= 0.0 && (bin as usize) < self.bins() {
+            Some(bin as usize)
+        } else {
+            None
+        }
+    }
+
+    /// The magnitude of each bin, correlating each kernel with the start of
+    /// the samples. Samples shorter than a kernel are treated as zero padded.
+    pub fn transform(&self, samples: &[f32]) -> Vec<f32> {
+        self.kernels
+            .iter()
+            .map(|kernel| {
+                kernel
+                    .iter()
+                    .zip(samples)
+                    .map(|(&kernel, &sample)| kernel * sample)
+                    .sum::<Complex<f32>>()
+                    .norm()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+
+    use super::ConstantQ;
+    use crate::WaveformSpectrum;
+
+    #[test]
+    fn a4_lands_in_its_semitone() {
+        // A0 through C8, one bin per piano key
+        let cqt = ConstantQ::new(Waveform::CD_SAMPLE_RATE, 12, 27.5, 4200.0);
+        assert_eq!(cqt.bins(), 88);
+
+        let waveform = Waveform::sine_wave(440.0, 1.0, Waveform::CD_SAMPLE_RATE);
+        let bins = waveform.constant_q(&cqt);
+
+        let loudest = bins
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(bin, _)| bin);
+
+        // A4 is four octaves above A0
+        assert_eq!(loudest, Some(48));
+        assert_eq!(cqt.bin_from_freq(440.0), Some(48));
+        assert!((cqt.freq_from_bin(48) - 440.0).abs() < 0.01);
+    }
+}
@@ -1,3 +1,9 @@
+use std::{
+    error::Error,
+    f64::consts,
+    fmt::{self, Display},
+};
+
 use num_complex::Complex;
 
 macro_rules! variable_width_fft {
@@ -11,15 +17,70 @@ macro_rules! variable_width_fft {
         match $samples.len() {
             $(
                 $num => paste::paste! {{
-                    [<$algor _ $num>](TryFrom::<&mut [Complex<f32>]>::try_from($samples).expect(concat!("spectrum.len() != ", $num))) as &mut [Complex<f32>]
+                    [<$algor _ $num>](TryFrom::<&mut [Complex<f32>]>::try_from($samples).expect(concat!("spectrum.len() != ", $num)));
+
+                    Ok(())
                 }},
             )+
-            _ => unimplemented!("unsupported width"),
+            width => Err(FftError::UnsupportedWidth(width)),
         }
     };
 }
 
-pub fn cfft(samples: &mut [Complex<f32>]) {
+/// The widths [`cfft`] and [`rfft`] support
+pub const SUPPORTED_WIDTHS: [usize; 14] = [
+    2, 4, 8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 8192, 16384,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FftError {
+    /// The buffer was not a width the transform supports, such as one of the
+    /// [`SUPPORTED_WIDTHS`] for [`cfft`]
+    UnsupportedWidth(usize),
+}
+
+impl Display for FftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FftError::UnsupportedWidth(width) => {
+                write!(f, "a fft of width {width} is not supported")
+            }
+        }
+    }
+}
+
+impl Error for FftError {}
+
+/// Replace the complex samples with their discrete fourier transform, in
+/// place. The buckets are not normalized, so a unit sine over `N` samples
+/// peaks at `N / 2`.
+///
+/// The width of the buffer must be one of the [`SUPPORTED_WIDTHS`], any
+/// power of two from 2 to 16384, or it is left untouched and an error is
+/// returned.
+///
+/// There is no inverse transform, but swapping the real and imaginary parts
+/// before and after a forward transform gives one, scaled up by the width:
+///
+/// ```
+/// use spectrum::{fft::cfft, Complex};
+///
+/// let samples = [1.0, 2.0, 0.0, -1.0, 0.5, 0.25, -3.0, 4.0];
+/// let mut buffer = samples.map(|sample| Complex::new(sample, 0.0));
+///
+/// cfft(&mut buffer)?;
+/// // A constant signal is all in the first bucket
+/// assert!((buffer[0].re - samples.iter().sum::<f32>()).abs() < 1e-5);
+///
+/// let mut swapped = buffer.map(|bucket| Complex::new(bucket.im, bucket.re));
+/// cfft(&mut swapped)?;
+///
+/// for (sample, round_trip) in samples.iter().zip(swapped) {
+///     assert!((sample - round_trip.im / 8.0).abs() < 1e-5);
+/// }
+/// # Ok::<(), spectrum::fft::FftError>(())
+/// ```
+pub fn cfft(samples: &mut [Complex<f32>]) -> Result<(), FftError> {
     use microfft::complex::*;
 
     variable_width_fft! {
@@ -30,5 +91,112 @@ pub fn cfft(samples: &mut [Complex<f32>]) {
             128, 256, 512, 1024,
             2048, 4096, 8192, 16384
         ]
-    };
+    }
+}
+
+/// The discrete fourier transform of real samples, as the `width / 2 + 1`
+/// buckets from DC up to nyquist, since the rest mirror them. The width must
+/// be one of the [`SUPPORTED_WIDTHS`].
+pub fn rfft(samples: &[f32]) -> Result<Vec<Complex<f32>>, FftError> {
+    let mut buckets = samples
+        .iter()
+        .map(|&sample| Complex::new(sample, 0.0))
+        .collect::<Vec<_>>();
+
+    cfft(&mut buckets)?;
+    buckets.truncate(samples.len() / 2 + 1);
+
+    Ok(buckets)
+}
+
+/// An in place radix 2 fft in double precision, much slower than [`cfft`] but
+/// without the rounding error that builds up over its passes. Any power of two
+/// width is supported, any other is left untouched and an error is returned.
+pub fn cfft_f64(samples: &mut [Complex<f64>]) -> Result<(), FftError> {
+    let width = samples.len();
+    if !width.is_power_of_two() {
+        return Err(FftError::UnsupportedWidth(width));
+    }
+
+    if width == 1 {
+        return Ok(());
+    }
+
+    // Put the samples in bit reversed order, so every pass combines the
+    // halves of neighbouring chunks
+    let bits = width.trailing_zeros();
+    for i in 0..width {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+
+        if i < j {
+            samples.swap(i, j);
+        }
+    }
+
+    let mut size = 2;
+    while size <= width {
+        let half = size / 2;
+        let twiddles = (0..half)
+            .map(|k| Complex::from_polar(1.0, -consts::TAU * k as f64 / size as f64))
+            .collect::<Vec<_>>();
+
+        for chunk in samples.chunks_exact_mut(size) {
+            let (evens, odds) = chunk.split_at_mut(half);
+
+            for ((even, odd), twiddle) in evens.iter_mut().zip(odds).zip(&twiddles) {
+                let odd_twiddled = *odd * twiddle;
+
+                *odd = *even - odd_twiddled;
+                *even += odd_twiddled;
+            }
+        }
+
+        size *= 2;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use num_complex::Complex;
+
+    use super::{cfft, cfft_f64, rfft, FftError};
+
+    #[test]
+    fn unsupported_width() {
+        let mut buffer = [Complex::new(1.0, 0.0); 12];
+
+        assert_eq!(cfft(&mut buffer), Err(FftError::UnsupportedWidth(12)));
+        assert_eq!(buffer, [Complex::new(1.0, 0.0); 12]);
+
+        assert_eq!(rfft(&[]), Err(FftError::UnsupportedWidth(0)));
+        assert_eq!(
+            cfft_f64(&mut [Complex::new(1.0, 0.0); 3]),
+            Err(FftError::UnsupportedWidth(3))
+        );
+    }
+
+    #[test]
+    fn real_matches_double_precision() -> Result<(), FftError> {
+        let samples = (0..64)
+            .map(|n| (n as f32 * 0.3).sin() + (n % 5) as f32 * 0.1)
+            .collect::<Vec<_>>();
+
+        let buckets = rfft(&samples)?;
+        assert_eq!(buckets.len(), 33);
+
+        let mut precise = samples
+            .iter()
+            .map(|&sample| Complex::new(sample as f64, 0.0))
+            .collect::<Vec<_>>();
+        cfft_f64(&mut precise)?;
+
+        for (bucket, precise) in buckets.iter().zip(&precise) {
+            assert!((bucket.re as f64 - precise.re).abs() < 1e-4);
+            assert!((bucket.im as f64 - precise.im).abs() < 1e-4);
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,294 @@
+use std::{
+    error::Error,
+    f32::consts,
+    fmt::{self, Display},
+};
+
+use audio::waveform::Waveform;
+use num_complex::Complex;
+
+use crate::{
+    fft::{cfft, FftError, SUPPORTED_WIDTHS},
+    Window,
+};
+
+/// Enough iterations for the reconstruction to sound right, beyond which each
+/// one makes little difference
+pub const DEFAULT_ITERATIONS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GriffinLimError {
+    /// The frames do not hold the magnitudes of a supported fft width, see
+    /// [`crate::fft::SUPPORTED_WIDTHS`]
+    Fft(FftError),
+    /// A frame had a different number of magnitudes to the first one
+    MismatchedFrames { expected: usize, found: usize },
+    /// The frames were zero samples apart, or further apart than they are
+    /// wide, leaving nothing to overlap
+    UnsupportedHop { hop: usize, width: usize },
+}
+
+impl Display for GriffinLimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GriffinLimError::Fft(error) => write!(f, "{error}"),
+            GriffinLimError::MismatchedFrames { expected, found } => write!(
+                f,
+                "every frame must have {expected} magnitudes, but one had {found}"
+            ),
+            GriffinLimError::UnsupportedHop { hop, width } => write!(
+                f,
+                "a hop of {hop} samples does not overlap frames {width} samples wide"
+            ),
+        }
+    }
+}
+
+impl Error for GriffinLimError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GriffinLimError::Fft(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<FftError> for GriffinLimError {
+    fn from(error: FftError) -> Self {
+        GriffinLimError::Fft(error)
+    }
+}
+
+/// Reconstruct a waveform from only the magnitudes of its short time fourier
+/// transform, using the Griffin-Lim algorithm to estimate the phases.
+///
+/// Each frame holds the raw magnitudes from DC up to nyquist, as from
+/// [`crate::Spectrum::amplitudes_real_raw`], of a window of the signal with
+/// `window` applied. The frames start `hop` samples apart, which must be no
+/// further than they are wide.
+pub fn griffin_lim(
+    magnitudes: &[Vec<f32>],
+    window: Window,
+    hop: usize,
+    iterations: usize,
+    sample_rate: u32,
+) -> Result<Waveform<'static>, GriffinLimError> {
+    let first = match magnitudes.first() {
+        Some(first) => first,
+        None => return Ok(Waveform::new_mono(Vec::new(), sample_rate)),
+    };
+
+    let width = first.len().saturating_sub(1) * 2;
+    if !SUPPORTED_WIDTHS.contains(&width) {
+        return Err(FftError::UnsupportedWidth(width).into());
+    }
+    if let Some(frame) = magnitudes.iter().find(|frame| frame.len() != first.len()) {
+        return Err(GriffinLimError::MismatchedFrames {
+            expected: first.len(),
+            found: frame.len(),
+        });
+    }
+    if hop == 0 || hop > width {
+        return Err(GriffinLimError::UnsupportedHop { hop, width });
+    }
+
+    let window = window.into_iter(width).collect::<Vec<_>>();
+    let len = (magnitudes.len() - 1) * hop + width;
+
+    // Start from noise, since a constant phase tends to get stuck
+    let mut state = 0x2545_f491_u32;
+    let mut phases = magnitudes
+        .iter()
+        .map(|frame| {
+            frame
+                .iter()
+                .map(|_| {
+                    // xorshift32
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+
+                    (state as f32 / u32::MAX as f32) * consts::TAU - consts::PI
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    for _ in 0..iterations {
+        let samples = istft(magnitudes, &phases, &window, hop, len)?;
+
+        // Keep the phases of the consistent spectrogram closest to the estimate
+        for (start, phases) in (0..).step_by(hop).zip(phases.iter_mut()) {
+            let buckets = forward(&samples[start..start + width], &window)?;
+
+            for (phase, bucket) in phases.iter_mut().zip(buckets) {
+                *phase = bucket.arg();
+            }
+        }
+    }
+
+    Ok(Waveform::new_mono(
+        istft(magnitudes, &phases, &window, hop, len)?,
+        sample_rate,
+    ))
+}
+
+/// The fft of a window of samples, from DC up to nyquist
+pub(crate) fn forward(samples: &[f32], window: &[f32]) -> Result<Vec<Complex<f32>>, FftError> {
+    let mut buckets = samples
+        .iter()
+        .zip(window)
+        .map(|(sample, scale)| Complex::new(sample * scale, 0.0))
+        .collect::<Vec<_>>();
+
+    cfft(&mut buckets)?;
+    buckets.truncate(samples.len() / 2 + 1);
+
+    Ok(buckets)
+}
+
+/// Overlap-add the inverse ffts of each frame, undoing the window by weighting
+/// with its square
+pub(crate) fn istft(
+    magnitudes: &[Vec<f32>],
+    phases: &[Vec<f32>],
+    window: &[f32],
+    hop: usize,
+    len: usize,
+) -> Result<Vec<f32>, FftError> {
+    let width = window.len();
+
+    let mut samples = vec![0.0; len];
+    let mut weights = vec![0.0; len];
+    let mut buckets = Vec::with_capacity(width);
+
+    for ((magnitudes, phases), start) in magnitudes.iter().zip(phases).zip((0..).step_by(hop)) {
+        let half = magnitudes
+            .iter()
+            .zip(phases)
+            .map(|(&magnitude, &phase)| Complex::from_polar(magnitude, phase));
+
+        // Mirror the conjugates into the negative frequencies, so the signal is real
+        buckets.clear();
+        buckets.extend(half);
+
+        let mirror = buckets[1..width / 2]
+            .iter()
+            .rev()
+            .map(Complex::conj)
+            .collect::<Vec<_>>();
+        buckets.extend(mirror);
+
+        // Inverse fft by swapping the real and imaginary components either side
+        // of a forward fft
+        for complex in buckets.iter_mut() {
+            *complex = Complex::new(complex.im, complex.re);
+        }
+
+        cfft(&mut buckets)?;
+
+        for (n, complex) in buckets.iter().enumerate() {
+            samples[start + n] += complex.im / width as f32 * window[n];
+            weights[start + n] += window[n] * window[n];
+        }
+    }
+
+    for (sample, weight) in samples.iter_mut().zip(weights) {
+        if weight > 1e-6 {
+            *sample /= weight;
+        }
+    }
+
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+
+    use super::{forward, griffin_lim, GriffinLimError, DEFAULT_ITERATIONS};
+    use crate::{fft::FftError, WaveformSpectrum, Window};
+
+    #[test]
+    fn recovers_tone_frequency() -> Result<(), GriffinLimError> {
+        let sample_rate = 8000;
+        let (width, hop) = (512, 128);
+
+        // 500Hz lands exactly on bucket 32
+        let tone = Waveform::sine_wave(500.0, 0.5, sample_rate);
+        let window = Window::Hann.into_iter(width).collect::<Vec<_>>();
+
+        let magnitudes = (0..=tone.len() - width)
+            .step_by(hop)
+            .map(|start| {
+                Ok(forward(&tone.samples()[start..start + width], &window)?
+                    .into_iter()
+                    .map(|bucket| bucket.norm())
+                    .collect())
+            })
+            .collect::<Result<Vec<Vec<f32>>, FftError>>()?;
+
+        let reconstructed = griffin_lim(
+            &magnitudes,
+            Window::Hann,
+            hop,
+            DEFAULT_ITERATIONS,
+            sample_rate,
+        )?;
+
+        assert_eq!(reconstructed.len(), (magnitudes.len() - 1) * hop + width);
+        assert_eq!(reconstructed.sample_rate(), sample_rate);
+
+        let middle = reconstructed.len() / 2 - width / 2;
+        let window = reconstructed.slice(middle..middle + width);
+        let spectrum = window.spectrum(Window::Hann, width)?;
+
+        assert_eq!(
+            spectrum.main_frequency().map(|(bucket, _)| bucket),
+            Some(32)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_frames() -> Result<(), GriffinLimError> {
+        let reconstructed = griffin_lim(&[], Window::Hann, 128, DEFAULT_ITERATIONS, 8000)?;
+
+        assert!(reconstructed.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_frames() {
+        let reconstruct = |magnitudes: &[Vec<f32>], hop| {
+            griffin_lim(magnitudes, Window::Hann, hop, DEFAULT_ITERATIONS, 8000).err()
+        };
+
+        assert_eq!(
+            reconstruct(&[vec![0.0; 4]], 1),
+            Some(GriffinLimError::Fft(FftError::UnsupportedWidth(6)))
+        );
+        // Wider than the fft supports, although a power of two
+        assert_eq!(
+            reconstruct(&[vec![0.0; (1 << 14) + 1]], 1),
+            Some(GriffinLimError::Fft(FftError::UnsupportedWidth(1 << 15)))
+        );
+        assert_eq!(
+            reconstruct(&[vec![0.0; 5], vec![0.0; 3]], 1),
+            Some(GriffinLimError::MismatchedFrames {
+                expected: 5,
+                found: 3
+            })
+        );
+        assert_eq!(
+            reconstruct(&[vec![0.0; 5]], 0),
+            Some(GriffinLimError::UnsupportedHop { hop: 0, width: 8 })
+        );
+        assert_eq!(
+            reconstruct(&[vec![0.0; 5]], 9),
+            Some(GriffinLimError::UnsupportedHop { hop: 9, width: 8 })
+        );
+    }
+}
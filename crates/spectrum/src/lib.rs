@@ -4,18 +4,26 @@
 
 use std::{
     cmp::Ordering,
-    f32::consts,
+    f64::consts,
     fmt::{self, Display},
     iter,
     ops::Range,
 };
 
 use audio::waveform::Waveform;
+use cqt::ConstantQ;
 pub use num_complex::Complex;
 
-mod fft;
+mod convolve;
+pub mod cqt;
+pub mod fft;
+pub mod griffin_lim;
+pub mod mel;
+pub mod onset;
+pub mod pitch;
+mod pitch_shift;
 
-use fft::cfft;
+use fft::{cfft, cfft_f64, FftError};
 
 // pub fn pitch_change(samples: &[f32])
 
@@ -25,7 +33,7 @@ pub fn reconstruct_samples(
     work_buffer: &mut Vec<Complex<f32>>,
     samples: &mut Vec<f32>,
     width: usize,
-) {
+) -> Result<(), FftError> {
     debug_assert_eq!(
         full_spectrum.len(),
         width,
@@ -40,11 +48,13 @@ pub fn reconstruct_samples(
     );
     samples.shrink_to_fit();
 
-    cfft(work_buffer);
+    cfft(work_buffer)?;
 
     samples.clear();
     samples.extend(work_buffer.iter().map(|complex| complex.im / width as f32));
     samples.shrink_to_fit();
+
+    Ok(())
 }
 
 // TODO: signed shift?
@@ -138,6 +148,16 @@ pub fn scale_spectrum(
     }
 }
 
+/// The frequency content of a window of a waveform.
+///
+/// Amplitudes are normalized by the number of samples in the window, so they
+/// are the same whatever width the fft is zero padded to. A constant signal of
+/// `1.0` has a DC amplitude of `1.0`, and a unit sine landing on a bucket has
+/// an amplitude of `0.5` there, split evenly with its negative frequency. Any
+/// window function applied scales these further by its mean. The unscaled
+/// magnitudes are available from the `_raw` methods.
+///
+/// Phases are in radians, from `-π` to `π`.
 #[derive(Debug)]
 pub struct Spectrum<'waveform> {
     width: usize,
@@ -154,24 +174,78 @@ impl<'w> Spectrum<'w> {
         &self.buckets
     }
 
+    /// The amplitude of every bucket, normalized by the window length
     pub fn amplitudes(&self) -> impl Iterator<Item = f32> + '_ {
+        let window_length = self.waveform.frames().max(1) as f32;
+
+        self.amplitudes_raw()
+            .map(move |amplitude| amplitude / window_length)
+    }
+
+    /// The magnitude of every bucket, exactly as the fft produced it
+    pub fn amplitudes_raw(&self) -> impl Iterator<Item = f32> + '_ {
         self.buckets.iter().map(|complex| complex.norm())
     }
 
     pub fn phases(&self) -> impl Iterator<Item = f32> + '_ {
+        self.buckets.iter().map(|complex| complex.arg())
+    }
+
+    /// The sum of the squared raw magnitudes of every bucket.
+    ///
+    /// By Parseval's theorem this is the fft width times the
+    /// [`Waveform::energy`] of the window the spectrum was taken of, after the
+    /// window function is applied. Zero padding adds no energy, so a
+    /// rectangular window gives exactly the energy of the samples times the
+    /// width.
+    pub fn energy(&self) -> f64 {
         self.buckets
             .iter()
-            .map(|complex| complex.arg() / self.width as f32)
+            .map(|complex| complex.norm_sqr() as f64)
+            .sum()
     }
 
+    /// The normalized amplitudes of the buckets from DC up to nyquist
     pub fn amplitudes_real(&self) -> impl Iterator<Item = f32> + '_ {
         self.amplitudes().take(self.width / 2 + 1)
     }
 
+    /// The raw magnitudes of the buckets from DC up to nyquist
+    pub fn amplitudes_real_raw(&self) -> impl Iterator<Item = f32> + '_ {
+        self.amplitudes_raw().take(self.width / 2 + 1)
+    }
+
     pub fn phases_real(&self) -> impl Iterator<Item = f32> + '_ {
         self.phases().take(self.width / 2 + 1)
     }
 
+    /// The frequency and raw value of every bucket, where the frequencies past
+    /// nyquist are negative, as from [`Spectrum::freq_from_bucket`]
+    pub fn iter_bins(&self) -> impl Iterator<Item = (f64, Complex<f32>)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(bucket, &complex)| (self.freq_from_bucket(bucket), complex))
+    }
+
+    /// The frequency and raw value of the buckets from DC up to nyquist
+    pub fn iter_bins_real(&self) -> impl Iterator<Item = (f64, Complex<f32>)> + '_ {
+        self.iter_bins().take(self.width / 2 + 1)
+    }
+
+    /// The frequency, normalized amplitude and phase of every bucket, where
+    /// the frequencies past nyquist are negative
+    pub fn to_rows(&self) -> impl Iterator<Item = (f64, f32, f32)> + '_ {
+        self.iter_bins()
+            .zip(self.amplitudes())
+            .map(|((frequency, complex), amplitude)| (frequency, amplitude, complex.arg()))
+    }
+
+    /// The rows of the buckets from DC up to nyquist
+    pub fn to_rows_real(&self) -> impl Iterator<Item = (f64, f32, f32)> + '_ {
+        self.to_rows().take(self.width / 2 + 1)
+    }
+
     // TODO: rename?
     pub fn main_frequency(&self) -> Option<(usize, f32)> {
         self.amplitudes_real()
@@ -222,23 +296,25 @@ impl<'w> Spectrum<'w> {
         }
     }
 
-    #[must_use]
-    pub fn waveform(&self) -> Waveform<'static> {
+    /// Transform the spectrum back into a waveform. An error is returned if
+    /// the width is not one of the [`fft::SUPPORTED_WIDTHS`], as can happen
+    /// with spectra taken by [`WaveformSpectrum::spectrum_precise`].
+    pub fn waveform(&self) -> Result<Waveform<'static>, FftError> {
         let mut spectrum = self
             .buckets
             .iter()
             .map(|complex| Complex::new(complex.im, complex.re))
             .collect::<Vec<_>>();
 
-        cfft(&mut spectrum);
+        cfft(&mut spectrum)?;
 
-        Waveform::new(
+        Ok(Waveform::new_mono(
             spectrum
                 .into_iter()
                 .map(|complex| complex.im / self.width as f32)
                 .collect(),
             self.waveform.sample_rate(),
-        )
+        ))
     }
 }
 
@@ -249,29 +325,60 @@ mod sealed {
 impl<'w> sealed::Sealed for Waveform<'w> {}
 
 pub trait WaveformSpectrum: sealed::Sealed {
+    /// Take the spectrum of the windowed samples, downmixed to mono and zero
+    /// padded up to `fft_width`. An error is returned if the width is not one of the
+    /// [`fft::SUPPORTED_WIDTHS`], which is easy to run into with widths picked
+    /// by the user.
+    ///
+    /// # Panics
+    ///
+    /// If there are more frames than `fft_width`
+    fn spectrum(&self, window: Window, fft_width: usize) -> Result<Spectrum, FftError>;
+
+    /// Take the spectrum like [`WaveformSpectrum::spectrum`], but window and
+    /// transform the samples in double precision. The rounding error of the
+    /// single precision fft builds up over wide ffts into a noise floor which
+    /// can hide quiet partials; this lowers it at the cost of an fft several
+    /// times slower. The buckets are still stored as single precision. Any
+    /// power of two width is supported.
+    fn spectrum_precise(&self, window: Window, fft_width: usize) -> Result<Spectrum, FftError>;
+
+    /// The magnitude of every constant-Q bin over the waveform downmixed to
+    /// mono
     #[must_use]
-    fn spectrum(&self, window: Window, fft_width: usize) -> Spectrum;
+    fn constant_q(&self, cqt: &ConstantQ) -> Vec<f32>;
+
+    /// FFT based convolution with an impulse response, such as for reverb. The
+    /// output is extended by the length of the impulse minus one.
+    fn convolve(&self, impulse: &Waveform) -> color_eyre::Result<Waveform<'static>>;
+
+    /// Shift the pitch by `semitones` without changing the duration, using a
+    /// phase vocoder over ffts of `fft_width`. The formants move along with
+    /// the pitch. The result is mono. An error is returned if the width is
+    /// not one of the [`fft::SUPPORTED_WIDTHS`] from 4 up.
+    fn pitch_shift(&self, semitones: f32, fft_width: usize) -> Result<Waveform<'static>, FftError>;
+
+    /// Shift the pitch like [`WaveformSpectrum::pitch_shift`], but keep the
+    /// spectral envelope in place so shifted speech does not sound chipmunky.
+    /// The envelope keeps the lowest `lifter` quefrencies of the cepstrum,
+    /// which must be fewer than the samples in a period of the pitch.
+    fn pitch_shift_formant_preserved(
+        &self,
+        semitones: f32,
+        fft_width: usize,
+        lifter: usize,
+    ) -> Result<Waveform<'static>, FftError>;
 }
 
 impl<'w> WaveformSpectrum for Waveform<'w> {
     // TODO: see if rfft would be worth using unsafe for over cfft
-    #[must_use]
-    fn spectrum(&self, window: Window, fft_width: usize) -> Spectrum {
-        assert!(
-            self.len() <= fft_width,
-            "{} is too many samples for a fft of width {fft_width}",
-            self.len()
-        );
-        assert!(
-            fft_width.is_power_of_two(),
-            "fft width length must be a power of two"
-        );
+    fn spectrum(&self, window: Window, fft_width: usize) -> Result<Spectrum, FftError> {
+        assert_fft_width(self, fft_width);
 
-        let window = window.into_iter(self.len());
+        let window = window.into_iter(self.frames());
 
         // Copy samples into the spectrum, filling any extra space with zeros
-        let mut buckets = self
-            .samples_iter()
+        let mut buckets = mono_samples(self)
             .zip(window)
             .map(|(sample, scale)| Complex::new(sample * scale, 0.0))
             .chain(iter::repeat(Complex::new(0.0, 0.0)))
@@ -279,14 +386,89 @@ impl<'w> WaveformSpectrum for Waveform<'w> {
             .collect::<Box<_>>();
 
         // Perform the FFT based on the calculated width
-        cfft(&mut buckets);
+        cfft(&mut buckets)?;
 
-        Spectrum {
+        Ok(Spectrum {
             buckets,
             width: fft_width,
             waveform: self,
+        })
+    }
+
+    fn spectrum_precise(&self, window: Window, fft_width: usize) -> Result<Spectrum, FftError> {
+        assert_fft_width(self, fft_width);
+
+        let samples = self.frames();
+        let mut buckets = mono_samples(self)
+            .enumerate()
+            .map(|(n, sample)| Complex::new(sample as f64 * window.weight(n, samples), 0.0))
+            .chain(iter::repeat(Complex::new(0.0, 0.0)))
+            .take(fft_width)
+            .collect::<Vec<_>>();
+
+        cfft_f64(&mut buckets)?;
+
+        Ok(Spectrum {
+            buckets: buckets
+                .into_iter()
+                .map(|bucket| Complex::new(bucket.re as f32, bucket.im as f32))
+                .collect(),
+            width: fft_width,
+            waveform: self,
+        })
+    }
+
+    #[must_use]
+    fn constant_q(&self, cqt: &ConstantQ) -> Vec<f32> {
+        assert_eq!(
+            self.sample_rate(),
+            cqt.sample_rate(),
+            "waveform sample rate does not match the constant-q kernels"
+        );
+
+        if self.channels() == 1 {
+            cqt.transform(self.samples())
+        } else {
+            cqt.transform(self.to_mono().samples())
         }
     }
+
+    fn convolve(&self, impulse: &Waveform) -> color_eyre::Result<Waveform<'static>> {
+        convolve::convolve(self, impulse)
+    }
+
+    fn pitch_shift(&self, semitones: f32, fft_width: usize) -> Result<Waveform<'static>, FftError> {
+        pitch_shift::pitch_shift(self, semitones, fft_width)
+    }
+
+    fn pitch_shift_formant_preserved(
+        &self,
+        semitones: f32,
+        fft_width: usize,
+        lifter: usize,
+    ) -> Result<Waveform<'static>, FftError> {
+        pitch_shift::pitch_shift_formant_preserved(self, semitones, fft_width, lifter)
+    }
+}
+
+fn assert_fft_width(waveform: &Waveform, fft_width: usize) {
+    assert!(
+        waveform.frames() <= fft_width,
+        "{} is too many samples for a fft of width {fft_width}",
+        waveform.frames()
+    );
+}
+
+/// The samples of each frame averaged the same way as [`Waveform::to_mono`],
+/// so that interleaved channels are not transformed as one signal at twice
+/// the sample rate
+fn mono_samples<'w>(waveform: &'w Waveform) -> impl Iterator<Item = f32> + 'w {
+    let channels = waveform.channels() as usize;
+
+    waveform
+        .samples()
+        .chunks_exact(channels)
+        .map(move |frame| frame.iter().sum::<f32>() / channels as f32)
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -315,6 +497,21 @@ impl Window {
             window: self,
         }
     }
+
+    /// The weight of the `n`th of `width` samples
+    fn weight(self, n: usize, width: usize) -> f64 {
+        let n = n as f64;
+        let width = width as f64;
+
+        match self {
+            Window::Rectangular => 1.0,
+            Window::Bartlett => 1.0 - f64::abs((n - width / 2.0) / (width / 2.0)),
+            Window::Hann => 0.5 * (1.0 - f64::cos((consts::TAU * n) / width)),
+            Window::Hamming => {
+                (25.0 / 46.0) - ((21.0 / 46.0) * f64::cos((consts::TAU * n) / width))
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -328,20 +525,366 @@ impl Iterator for WindowIter {
     type Item = f32;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(n) = self.range.next() {
-            let n = n as f32;
-            let width = self.width as f32;
-
-            Some(match self.window {
-                Window::Rectangular => 1.0,
-                Window::Bartlett => 1.0 - f32::abs((n - width / 2.0) / (width / 2.0)),
-                Window::Hann => 0.5 * (1.0 - f32::cos((consts::TAU * n) / width)),
-                Window::Hamming => {
-                    (25.0 / 46.0) - ((21.0 / 46.0) * f32::cos((consts::TAU * n) / width))
-                }
-            })
-        } else {
-            None
+        self.range
+            .next()
+            .map(|n| self.window.weight(n, self.width) as f32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio::{
+        filter::Equalizer,
+        waveform::{ChirpCurve, Waveform},
+    };
+
+    use crate::{cqt::ConstantQ, fft::FftError, WaveformSpectrum, Window};
+
+    #[test]
+    fn precise_noise_floor() -> Result<(), FftError> {
+        // A tone landing exactly on bucket 1000, computed in double precision so
+        // the samples themselves add as little noise as they can
+        let width = 1 << 14;
+        let samples = (0..width)
+            .map(|n| (std::f64::consts::TAU * 1000.0 * n as f64 / width as f64).sin() as f32)
+            .collect();
+        let sine = Waveform::new_mono(samples, width as u32);
+
+        // The loudest bucket away from the tone, relative to the tone
+        let noise_floor = |amplitudes: Vec<f32>| {
+            let floor = amplitudes
+                .iter()
+                .enumerate()
+                .filter(|&(bucket, _)| (bucket as isize - 1000).abs() > 4)
+                .map(|(_, &amplitude)| amplitude)
+                .fold(0.0, f32::max);
+
+            (floor / amplitudes[1000], amplitudes[1000])
+        };
+
+        let (fast_floor, fast_peak) = noise_floor(
+            sine.spectrum(Window::Rectangular, width)?
+                .amplitudes_real()
+                .collect(),
+        );
+        let (precise_floor, precise_peak) = noise_floor(
+            sine.spectrum_precise(Window::Rectangular, width)?
+                .amplitudes_real()
+                .collect(),
+        );
+
+        assert!((fast_peak - 0.5).abs() < 1e-4, "{fast_peak}");
+        assert!((precise_peak - 0.5).abs() < 1e-6, "{precise_peak}");
+        assert!(
+            precise_floor < fast_floor / 2.0,
+            "{precise_floor} is not below {fast_floor}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_width() {
+        let waveform = Waveform::new_mono(vec![1.0, 0.0, -1.0], 8);
+
+        assert_eq!(
+            waveform.spectrum(Window::Hann, 3).err(),
+            Some(FftError::UnsupportedWidth(3))
+        );
+        assert_eq!(
+            waveform.spectrum_precise(Window::Hann, 3).err(),
+            Some(FftError::UnsupportedWidth(3))
+        );
+
+        // Past the widest single precision fft, but any power of two works in
+        // double precision
+        assert_eq!(
+            waveform.spectrum(Window::Hann, 1 << 15).err(),
+            Some(FftError::UnsupportedWidth(1 << 15))
+        );
+        assert!(waveform.spectrum_precise(Window::Hann, 1 << 15).is_ok());
+    }
+
+    #[test]
+    fn unsupported_inverse_width() -> Result<(), FftError> {
+        let waveform = Waveform::new_mono(vec![1.0, 0.0, -1.0, 0.0], 8);
+
+        let round_trip = waveform.spectrum(Window::Rectangular, 4)?.waveform()?;
+        assert!(round_trip
+            .samples_iter()
+            .zip(waveform.samples_iter())
+            .all(|(round_trip, original)| (round_trip - original).abs() < 1e-6));
+
+        // Only double precision spectra can be this wide
+        assert_eq!(
+            waveform
+                .spectrum_precise(Window::Rectangular, 1 << 15)?
+                .waveform()
+                .err(),
+            Some(FftError::UnsupportedWidth(1 << 15))
+        );
+
+        Ok(())
+    }
+
+    /// Every app shares these amplitudes, so pin down exactly what they mean
+    #[test]
+    fn amplitude_normalization() -> Result<(), FftError> {
+        // One second of a unit sine, landing exactly on bucket 64
+        let sine = Waveform::sine_wave(64.0, 1.0, 1024);
+        let spectrum = sine.spectrum(Window::Rectangular, 1024)?;
+        let amplitudes = spectrum.amplitudes_real().collect::<Vec<_>>();
+
+        assert_eq!(amplitudes.len(), 1024 / 2 + 1);
+        assert!((amplitudes[64] - 0.5).abs() < 1e-3);
+        assert!(amplitudes[63] < 1e-3 && amplitudes[65] < 1e-3);
+
+        // Raw magnitudes scale with the window length
+        let raw = spectrum.amplitudes_real_raw().collect::<Vec<_>>();
+        assert!((raw[64] - 1024.0 / 2.0).abs() < 0.1);
+
+        // A constant signal all lands in the DC bucket
+        let constant = Waveform::new_mono(vec![1.0; 1024], 1024);
+        let amplitudes = constant
+            .spectrum(Window::Rectangular, 1024)?
+            .amplitudes_real()
+            .collect::<Vec<_>>();
+
+        assert!((amplitudes[0] - 1.0).abs() < 1e-3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn equalizer_boost() -> Result<(), FftError> {
+        // 1000Hz lands on bucket 1000 with one second at 8192Hz
+        let sine = Waveform::sine_wave(1_000.0, 1.0, 8192);
+        let bucket = |equalizer: &Equalizer| {
+            Ok::<_, FftError>(
+                sine.equalize(equalizer)
+                    .spectrum(Window::Hann, 8192)?
+                    .amplitudes_real()
+                    .nth(1_000)
+                    .unwrap_or_default(),
+            )
+        };
+
+        let flat = Equalizer::graphic();
+        let mut boosted = flat.clone();
+        for band in &mut boosted.bands {
+            if band.frequency == 1_000.0 {
+                band.gain_db = 6.0;
+            }
+        }
+
+        // Close to twice the amplitude, short of it where the filter settles
+        let gain = bucket(&boosted)? / bucket(&flat)?;
+        assert!(gain > 1.8 && gain < 2.1, "gain was {gain}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn parseval() -> Result<(), FftError> {
+        let noise = Waveform::white_noise(0.01, 44_100, 3);
+
+        // Zero padded out past the window
+        for width in [512, 1024] {
+            let spectrum = noise.spectrum(Window::Rectangular, width)?;
+            let expected = noise.energy() * width as f64;
+
+            assert!(
+                (spectrum.energy() / expected - 1.0).abs() < 1e-4,
+                "{} against {expected}",
+                spectrum.energy()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn bins() -> Result<(), FftError> {
+        let sine = Waveform::sine_wave(100.0, 0.01, 800);
+        let spectrum = sine.spectrum(Window::Hann, 16)?;
+
+        let bins = spectrum.iter_bins().collect::<Vec<_>>();
+        assert_eq!(bins.len(), 16);
+
+        for (bucket, &(frequency, complex)) in bins.iter().enumerate() {
+            assert_eq!(frequency, spectrum.freq_from_bucket(bucket));
+            assert_eq!(complex, spectrum.buckets()[bucket]);
+        }
+
+        // Past nyquist the frequencies are negative
+        assert_eq!(bins[9].0, -350.0);
+
+        let real = spectrum.iter_bins_real().collect::<Vec<_>>();
+        assert_eq!(real, bins[..=8]);
+        assert_eq!(real.last().map(|&(frequency, _)| frequency), Some(400.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rows() -> Result<(), FftError> {
+        // 100Hz between each of the 8 buckets
+        let samples = [1.0, 0.5, -0.25, 0.0, 0.75, -1.0, 0.25, 0.5];
+        let waveform = Waveform::new_mono(samples.to_vec(), 800);
+        let spectrum = waveform.spectrum(Window::Rectangular, 8)?;
+
+        let rows = spectrum.to_rows().collect::<Vec<_>>();
+        assert_eq!(rows.len(), 8);
+
+        let frequencies = rows.iter().map(|&(freq, _, _)| freq).collect::<Vec<_>>();
+        assert_eq!(
+            frequencies,
+            (0..8)
+                .map(|bucket| spectrum.freq_from_bucket(bucket))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            frequencies,
+            [0.0, 100.0, 200.0, 300.0, 400.0, -300.0, -200.0, -100.0]
+        );
+
+        for ((row, amplitude), phase) in rows
+            .iter()
+            .zip(spectrum.amplitudes())
+            .zip(spectrum.phases())
+        {
+            assert_eq!((row.1, row.2), (amplitude, phase));
+        }
+
+        // Only up to nyquist, matching the other real methods
+        let real = spectrum.to_rows_real().collect::<Vec<_>>();
+        assert_eq!(real, rows[..5]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn normalized_across_widths() -> Result<(), FftError> {
+        // The same sine zero padded out to twice the width
+        let sine = Waveform::sine_wave(64.0, 1.0, 1024);
+        let narrow = sine
+            .spectrum(Window::Rectangular, 1024)?
+            .amplitudes_real()
+            .collect::<Vec<_>>();
+        let wide = sine
+            .spectrum(Window::Rectangular, 2048)?
+            .amplitudes_real()
+            .collect::<Vec<_>>();
+
+        assert!((narrow[64] - wide[128]).abs() < 1e-3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stereo_downmixed() -> Result<(), FftError> {
+        // The same sine in both channels, interleaved
+        let sine = Waveform::sine_wave(64.0, 1.0, 1024);
+        let stereo = Waveform::new(
+            sine.samples_iter()
+                .flat_map(|sample| [sample, sample])
+                .collect(),
+            1024,
+            2,
+        );
+
+        let mono = sine.spectrum(Window::Rectangular, 1024)?;
+        let downmixed = stereo.spectrum(Window::Rectangular, 1024)?;
+
+        assert_eq!(downmixed.main_frequency(), mono.main_frequency());
+        assert!(mono
+            .amplitudes()
+            .zip(downmixed.amplitudes())
+            .all(|(mono, downmixed)| (mono - downmixed).abs() < 1e-6));
+
+        let cqt = ConstantQ::new(1024, 12, 32.0, 256.0);
+        assert_eq!(stereo.constant_q(&cqt), sine.constant_q(&cqt));
+
+        Ok(())
+    }
+
+    #[test]
+    fn generator_fundamentals() -> Result<(), FftError> {
+        // One second at 1024Hz, so every bucket is 1Hz wide
+        for band_limited in [false, true] {
+            for wave in [
+                Waveform::square_wave,
+                Waveform::sawtooth_wave,
+                Waveform::triangle_wave,
+            ] {
+                let waveform = wave(64.0, 1.0, 1024, band_limited);
+                let spectrum = waveform.spectrum(Window::Hann, 1024)?;
+
+                assert_eq!(
+                    spectrum.main_frequency().map(|(bucket, _)| bucket),
+                    Some(64)
+                );
+            }
         }
+
+        Ok(())
+    }
+
+    #[test]
+    fn pink_noise_falls_off() -> Result<(), FftError> {
+        let white = Waveform::white_noise(16384.0 / 44_100.0, 44_100, 1);
+        let pink = Waveform::pink_noise(16384.0 / 44_100.0, 44_100, 1);
+
+        // The power in the octave from 16 to 32 buckets, relative to 512 to 1024
+        let tilt = |waveform: &Waveform| {
+            let power = waveform
+                .spectrum(Window::Hann, 16384)?
+                .amplitudes_real()
+                .map(|amplitude| amplitude * amplitude)
+                .collect::<Vec<_>>();
+
+            Ok::<_, FftError>(
+                power[16..32].iter().sum::<f32>() / power[512..1024].iter().sum::<f32>(),
+            )
+        };
+
+        // White noise has the same power in each bucket, so 32 times as much
+        // in the higher octave, while pink noise has the same in both
+        assert!(tilt(&white)? < 0.1);
+        let pink = tilt(&pink)?;
+        assert!(pink > 0.3 && pink < 3.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn chirp_sweeps_monotonically() -> Result<(), FftError> {
+        let sample_rate = 8192;
+        let width = 512;
+
+        for curve in [ChirpCurve::Linear, ChirpCurve::Exponential] {
+            let chirp = Waveform::chirp(100.0, 3000.0, 2.0, sample_rate, curve);
+
+            let loudest = (0..chirp.len() / width)
+                .map(|window| {
+                    let window = chirp.slice(window * width..(window + 1) * width);
+
+                    Ok(window
+                        .spectrum(Window::Hann, width)?
+                        .main_frequency()
+                        .map(|(bucket, _)| bucket))
+                })
+                .collect::<Result<Option<Vec<_>>, FftError>>()?;
+            let loudest = loudest.unwrap_or_default();
+
+            // Each bucket is 16Hz wide, and the ends of the sweep are smeared
+            // across the first and last windows
+            assert_eq!(loudest.len(), 32);
+            assert!(loudest.first().map_or(false, |&first| first <= 300 / 16));
+            assert!(loudest.last().map_or(false, |&last| last >= 2700 / 16));
+            assert!(loudest.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+
+        Ok(())
     }
 }
@@ -0,0 +1,184 @@
+use std::f32::consts;
+
+use crate::Spectrum;
+
+pub fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+pub fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10.0f32.powf(mel / 2595.0) - 1.0)
+}
+
+#[derive(Debug, Clone)]
+struct MelFilter {
+    /// The first fft bucket covered by this filter
+    start: usize,
+    weights: Box<[f32]>,
+}
+
+/// A bank of triangular filters spaced evenly on the mel scale
+#[derive(Debug, Clone)]
+pub struct MelFilterBank {
+    fft_width: usize,
+    sample_rate: u32,
+    filters: Box<[MelFilter]>,
+}
+
+impl MelFilterBank {
+    pub fn new(filters: usize, fft_width: usize, sample_rate: u32, f_min: f32, f_max: f32) -> Self {
+        assert!(filters > 0, "a mel filter bank needs at least one filter");
+        assert!(f_min < f_max, "f_min must be below f_max");
+
+        let mel_min = hz_to_mel(f_min);
+        let mel_max = hz_to_mel(f_max);
+
+        // The edges of every triangle, each filter spans three consecutive edges
+        let edges = (0..filters + 2)
+            .map(|n| mel_to_hz(mel_min + (mel_max - mel_min) * n as f32 / (filters + 1) as f32))
+            .collect::<Vec<_>>();
+
+        let freq_resolution = sample_rate as f32 / fft_width as f32;
+        let real_buckets = fft_width / 2 + 1;
+
+        let filters = edges
+            .windows(3)
+            .map(|edges| {
+                let (left, center, right) = (edges[0], edges[1], edges[2]);
+
+                let start = ((left / freq_resolution).ceil() as usize).min(real_buckets);
+                let end = ((right / freq_resolution).floor() as usize + 1).min(real_buckets);
+
+                let weights = (start..end)
+                    .map(|bucket| {
+                        let freq = bucket as f32 * freq_resolution;
+
+                        let weight = if freq <= center {
+                            (freq - left) / (center - left)
+                        } else {
+                            (right - freq) / (right - center)
+                        };
+
+                        weight.max(0.0)
+                    })
+                    .collect();
+
+                MelFilter { start, weights }
+            })
+            .collect();
+
+        Self {
+            fft_width,
+            sample_rate,
+            filters,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// The power in each of the filters for the given spectrum
+    pub fn energies(&self, spectrum: &Spectrum) -> Vec<f32> {
+        assert_eq!(
+            spectrum.width(),
+            self.fft_width,
+            "spectrum width does not match the filter bank"
+        );
+        assert_eq!(
+            spectrum.waveform.sample_rate(),
+            self.sample_rate,
+            "spectrum sample rate does not match the filter bank"
+        );
+
+        let power = spectrum
+            .amplitudes_real()
+            .map(|amplitude| amplitude * amplitude)
+            .collect::<Vec<_>>();
+
+        self.filters
+            .iter()
+            .map(|filter| {
+                power[filter.start..]
+                    .iter()
+                    .zip(filter.weights.iter())
+                    .map(|(power, weight)| power * weight)
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+/// Mel-frequency cepstral coefficients, the DCT-II of the log mel energies
+pub fn mfcc(mel_energies: &[f32], n_coeffs: usize) -> Vec<f32> {
+    // Avoid taking the log of silent filters
+    let log_energies = mel_energies
+        .iter()
+        .map(|energy| energy.max(f32::MIN_POSITIVE).ln())
+        .collect::<Vec<_>>();
+
+    let n = log_energies.len() as f32;
+
+    (0..n_coeffs)
+        .map(|k| {
+            log_energies
+                .iter()
+                .enumerate()
+                .map(|(i, log_energy)| {
+                    log_energy * f32::cos(consts::PI / n * (i as f32 + 0.5) * k as f32)
+                })
+                .sum()
+        })
+        .collect()
+}
+
+impl Spectrum<'_> {
+    pub fn mfcc(&self, bank: &MelFilterBank, n_coeffs: usize) -> Vec<f32> {
+        mfcc(&bank.energies(self), n_coeffs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+
+    use super::{mfcc, MelFilterBank};
+    use crate::{fft::FftError, WaveformSpectrum, Window};
+
+    #[test]
+    fn zeroth_coefficient_tracks_log_energy() {
+        let energies = [0.5, 2.0, 1.0, 4.0, 0.25];
+        let louder = energies.map(|energy| energy * 4.0);
+
+        let quiet = mfcc(&energies, 4);
+        let loud = mfcc(&louder, 4);
+
+        // Scaling every energy by 4 adds ln(4) per filter to the 0th coefficient
+        let expected = energies.len() as f32 * 4.0f32.ln();
+        assert!((loud[0] - quiet[0] - expected).abs() < 1e-4);
+
+        // The shape of the spectrum, and so the higher coefficients, stays the same
+        for (quiet, loud) in quiet.iter().zip(&loud).skip(1) {
+            assert!((quiet - loud).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn spectrum_mfcc() -> Result<(), FftError> {
+        let bank = MelFilterBank::new(26, 1024, Waveform::CD_SAMPLE_RATE, 0.0, 8000.0);
+
+        let waveform = Waveform::sine_wave(440.0, 0.02, Waveform::CD_SAMPLE_RATE);
+        let coefficients = waveform.spectrum(Window::Hann, 1024)?.mfcc(&bank, 13);
+
+        assert_eq!(coefficients.len(), 13);
+        assert!(coefficients
+            .iter()
+            .all(|coefficient| coefficient.is_finite()));
+
+        Ok(())
+    }
+}
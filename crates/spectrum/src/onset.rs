@@ -0,0 +1,151 @@
+use audio::waveform::Waveform;
+
+use crate::{fft::FftError, WaveformSpectrum, Window};
+
+/// The slowest tempo, in beats per minute, [`estimate_tempo`] will report
+pub const MIN_BPM: f32 = 40.0;
+/// The fastest tempo, in beats per minute, [`estimate_tempo`] will report
+pub const MAX_BPM: f32 = 240.0;
+
+/// The spectral flux of `waveform`, the amount each `width` wide window's
+/// spectrum grows over the window `hop` samples before it. Energy dying away
+/// is ignored, so the curve peaks only where notes start. There are
+/// `sample_rate / hop` values each second. An error is returned if `width` is
+/// not one of the [`crate::fft::SUPPORTED_WIDTHS`].
+pub fn spectral_flux(waveform: &Waveform, width: usize, hop: usize) -> Result<Vec<f32>, FftError> {
+    let mono = waveform.to_mono();
+    if mono.len() < width {
+        return Ok(Vec::new());
+    }
+
+    let mut previous = vec![0.0; width / 2 + 1];
+
+    (0..=mono.len() - width)
+        .step_by(hop.max(1))
+        .map(|start| {
+            let amplitudes = mono
+                .slice(start..start + width)
+                .spectrum(Window::Hann, width)?
+                .amplitudes_real()
+                .collect::<Vec<_>>();
+
+            let flux = amplitudes
+                .iter()
+                .zip(&previous)
+                .map(|(current, previous)| (current - previous).max(0.0))
+                .sum();
+
+            previous = amplitudes;
+
+            Ok(flux)
+        })
+        .collect()
+}
+
+/// Estimate the tempo in beats per minute of an onset envelope, such as from
+/// [`spectral_flux`], with `frame_rate` values each second. The envelope's
+/// autocorrelation is searched for its strongest period between [`MIN_BPM`]
+/// and [`MAX_BPM`], so a beat on every other onset is not mistaken for the
+/// tempo. Zero is returned when there are no onsets to go by, or the envelope
+/// is too short to hold two beats at the slowest tempo.
+pub fn estimate_tempo(onset_envelope: &[f32], frame_rate: f32) -> f32 {
+    let mean = onset_envelope.iter().sum::<f32>() / onset_envelope.len().max(1) as f32;
+    let centered = onset_envelope
+        .iter()
+        .map(|onset| onset - mean)
+        .collect::<Vec<_>>();
+
+    let shortest_lag = (60.0 * frame_rate / MAX_BPM).floor().max(1.0) as usize;
+    let longest_lag =
+        ((60.0 * frame_rate / MIN_BPM).ceil() as usize).min(centered.len().saturating_sub(1));
+
+    if shortest_lag > longest_lag {
+        return 0.0;
+    }
+
+    // Not normalized by the overlap, so that the shortest of the lags lining
+    // up with every beat wins over its multiples
+    let autocorrelation = |lag: usize| {
+        centered
+            .iter()
+            .zip(&centered[lag..])
+            .map(|(a, b)| a * b)
+            .sum::<f32>()
+    };
+
+    let (lag, strength) = (shortest_lag..=longest_lag)
+        .map(|lag| (lag, autocorrelation(lag)))
+        .fold((0, 0.0), |best, candidate| {
+            if candidate.1 > best.1 {
+                candidate
+            } else {
+                best
+            }
+        });
+
+    if strength <= 0.0 {
+        return 0.0;
+    }
+
+    // Fit a parabola through the neighbouring lags to find the true period
+    let (before, after) = (autocorrelation(lag - 1), autocorrelation(lag + 1));
+    let curvature = before - 2.0 * strength + after;
+    let period = if curvature < 0.0 {
+        lag as f32 + (before - after) / (2.0 * curvature)
+    } else {
+        lag as f32
+    };
+
+    (60.0 * frame_rate / period).clamp(MIN_BPM, MAX_BPM)
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+
+    use super::{estimate_tempo, spectral_flux};
+    use crate::fft::FftError;
+
+    #[test]
+    fn onset_train() {
+        // An onset every half second, and every 0.75 seconds
+        for (interval, bpm) in [(50, 120.0), (75, 80.0)] {
+            let envelope = (0..1000)
+                .map(|frame| if frame % interval == 0 { 1.0 } else { 0.0 })
+                .collect::<Vec<_>>();
+
+            let tempo = estimate_tempo(&envelope, 100.0);
+            assert!((tempo - bpm).abs() < 0.5, "{tempo} BPM instead of {bpm}");
+        }
+
+        assert_eq!(estimate_tempo(&[0.0; 1000], 100.0), 0.0);
+        assert_eq!(estimate_tempo(&[], 100.0), 0.0);
+    }
+
+    #[test]
+    fn clicks() -> Result<(), FftError> {
+        let sample_rate = 22_050;
+
+        // A short burst of a tone every half second
+        let samples = (0..sample_rate as usize * 8)
+            .map(|sample| {
+                let since_click = sample % (sample_rate as usize / 2);
+
+                if since_click < 441 {
+                    (since_click as f32 * 0.3).sin()
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let clicks = Waveform::new_mono(samples, sample_rate);
+
+        let hop = 256;
+        let flux = spectral_flux(&clicks, 1024, hop)?;
+        let tempo = estimate_tempo(&flux, sample_rate as f32 / hop as f32);
+
+        assert!((tempo - 120.0).abs() < 2.0, "{tempo} BPM instead of 120");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use audio::waveform::Waveform;
+
+/// The threshold suggested by the YIN paper, below which a dip in the
+/// difference function counts as a period
+pub const YIN_THRESHOLD: f32 = 0.1;
+
+/// Estimate the fundamental frequency of `waveform` in Hz using the YIN
+/// algorithm, or [`None`] if no period is clear enough to fall under
+/// `threshold`, as with silence and noise.
+///
+/// Periods up to half the length of the waveform can be detected, so the
+/// waveform must span at least two periods of the lowest expected pitch.
+pub fn detect_pitch_yin(waveform: &Waveform, threshold: f32) -> Option<f32> {
+    let mono = waveform.to_mono();
+    let samples = mono.samples();
+    let width = samples.len() / 2;
+
+    if width < 2 {
+        return None;
+    }
+
+    // The difference of the signal with itself delayed by each lag
+    let difference = (0..width)
+        .map(|lag| {
+            samples[..width]
+                .iter()
+                .zip(&samples[lag..lag + width])
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum::<f32>()
+        })
+        .collect::<Vec<_>>();
+
+    // Normalize by the running mean, so that small lags are not favoured
+    let mut running_sum = 0.0;
+    let normalized = difference
+        .iter()
+        .enumerate()
+        .map(|(lag, &difference)| {
+            running_sum += difference;
+
+            if lag == 0 || running_sum == 0.0 {
+                1.0
+            } else {
+                difference * lag as f32 / running_sum
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // The first dip under the threshold, followed down to its minimum
+    let mut lag = (2..width).find(|&lag| normalized[lag] < threshold)?;
+    while lag + 1 < width && normalized[lag + 1] < normalized[lag] {
+        lag += 1;
+    }
+
+    // Fit a parabola through the neighbouring lags to find the true minimum
+    let period = match (normalized.get(lag - 1), normalized.get(lag + 1)) {
+        (Some(&before), Some(&after)) => {
+            let curvature = before - 2.0 * normalized[lag] + after;
+
+            if curvature > 0.0 {
+                lag as f32 + (before - after) / (2.0 * curvature)
+            } else {
+                lag as f32
+            }
+        }
+        _ => lag as f32,
+    };
+
+    Some(mono.sample_rate() as f32 / period)
+}
+
+#[cfg(test)]
+mod test {
+    use audio::waveform::Waveform;
+
+    use super::{detect_pitch_yin, YIN_THRESHOLD};
+
+    #[test]
+    fn sine_pitch() {
+        // Within a tenth of a semitone
+        for frequency in [82.41, 220.0, 440.0, 1046.5] {
+            let sine = Waveform::sine_wave(frequency, 0.05, Waveform::CD_SAMPLE_RATE);
+            let detected = detect_pitch_yin(&sine, YIN_THRESHOLD);
+
+            assert!(
+                detected.map_or(false, |detected| (detected / frequency - 1.0).abs() < 0.005),
+                "detected {detected:?} for {frequency}Hz"
+            );
+        }
+    }
+
+    #[test]
+    fn no_pitch_in_silence() {
+        let silence = Waveform::new_mono(vec![0.0; 4096], Waveform::CD_SAMPLE_RATE);
+
+        assert_eq!(detect_pitch_yin(&silence, YIN_THRESHOLD), None);
+        assert_eq!(detect_pitch_yin(&silence.slice(..1), YIN_THRESHOLD), None);
+    }
+
+    #[test]
+    fn vocoder_keeps_carrier_pitch() -> color_eyre::Result<()> {
+        // A voice singing at a different pitch to the synth it is vocoded onto
+        let sample_rate = 22_050;
+        let voice = Waveform::sawtooth_wave(173.0, 0.3, sample_rate, true);
+        let synth = Waveform::sawtooth_wave(110.0, 0.3, sample_rate, true);
+
+        let vocoded = voice.vocode(&synth, 16)?;
+        assert_eq!(vocoded.len(), synth.len());
+
+        for start in [2_205, 4_410] {
+            let window = vocoded.slice(start..start + 1_102);
+            let detected = detect_pitch_yin(&window, YIN_THRESHOLD);
+
+            assert!(
+                detected.map_or(false, |detected| (detected / 110.0 - 1.0).abs() < 0.005),
+                "detected {detected:?}"
+            );
+        }
+
+        Ok(())
+    }
+}
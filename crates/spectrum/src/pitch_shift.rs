@@ -0,0 +1,261 @@
+use std::f32::consts;
+
+use audio::waveform::Waveform;
+use num_complex::Complex;
+
+use crate::{
+    fft::{cfft, FftError, SUPPORTED_WIDTHS},
+    griffin_lim::{forward, istft},
+    Window,
+};
+
+/// Keeps the magnitudes of the whole frame unchanged instead of following
+/// the spectral envelope
+const NO_LIFTER: usize = 0;
+
+/// Scale every frequency, formants included, by the ratio of `semitones`
+pub(crate) fn pitch_shift(
+    waveform: &Waveform,
+    semitones: f32,
+    fft_width: usize,
+) -> Result<Waveform<'static>, FftError> {
+    shift(waveform, semitones, fft_width, NO_LIFTER)
+}
+
+/// Scale the frequencies of the excitation by the ratio of `semitones`, then
+/// reapply the spectral envelope of each frame where it was.
+///
+/// The envelope is estimated by cepstral liftering. The harmonics of the pitch
+/// show up in the cepstrum at its period, so keeping fewer quefrencies than
+/// that leaves only the formants, which about a millisecond of samples manages
+/// for speech. Without any quefrencies there is no envelope to keep, so a
+/// `lifter` of zero shifts the formants along with [`pitch_shift`].
+pub(crate) fn pitch_shift_formant_preserved(
+    waveform: &Waveform,
+    semitones: f32,
+    fft_width: usize,
+    lifter: usize,
+) -> Result<Waveform<'static>, FftError> {
+    shift(waveform, semitones, fft_width, lifter)
+}
+
+/// A phase vocoder over frames overlapping by three quarters, which tracks the
+/// true frequency in each bucket so the shifted phases stay coherent
+fn shift(
+    waveform: &Waveform,
+    semitones: f32,
+    width: usize,
+    lifter: usize,
+) -> Result<Waveform<'static>, FftError> {
+    // The frames need to overlap by a whole number of samples
+    if width < 4 || !SUPPORTED_WIDTHS.contains(&width) {
+        return Err(FftError::UnsupportedWidth(width));
+    }
+
+    let ratio = 2f32.powf(semitones / 12.0);
+    let half = width / 2;
+    let hop = width / 4;
+    let window = Window::Hann.into_iter(width).collect::<Vec<_>>();
+
+    // Zero pad out to a whole number of frames
+    let mono = waveform.to_mono();
+    let frames = (mono.len().saturating_sub(width) + hop - 1) / hop + 1;
+    let mut samples = mono.samples().to_vec();
+    samples.resize((frames - 1) * hop + width, 0.0);
+
+    let bin_frequency = |bin: usize| consts::TAU * bin as f32 / width as f32;
+
+    let mut last_phases = vec![0.0; half + 1];
+    let mut shifted_phases = vec![0.0; half + 1];
+
+    let frames = (0..frames)
+        .map(|frame| {
+            let start = frame * hop;
+            let buckets = forward(&samples[start..start + width], &window)?;
+
+            let magnitudes = buckets
+                .iter()
+                .map(|bucket| bucket.norm())
+                .collect::<Vec<_>>();
+            let envelope = if lifter == NO_LIFTER {
+                vec![1.0; half + 1]
+            } else {
+                spectral_envelope(&magnitudes, lifter)?
+            };
+
+            // The frequency each bucket really holds, in radians per sample,
+            // from how far its phase moved past what its centre would have
+            let frequencies = buckets
+                .iter()
+                .zip(&mut last_phases)
+                .enumerate()
+                .map(|(bin, (bucket, last_phase))| {
+                    let phase = bucket.arg();
+                    let deviation = phase - *last_phase - hop as f32 * bin_frequency(bin);
+                    *last_phase = phase;
+
+                    let wrapped = (deviation + consts::PI).rem_euclid(consts::TAU) - consts::PI;
+
+                    bin_frequency(bin) + wrapped / hop as f32
+                })
+                .collect::<Vec<_>>();
+
+            // Move the excitation up or down, with each bucket taking the
+            // frequency of the loudest one landing on it
+            let mut excitation = vec![0.0; half + 1];
+            let mut loudest = vec![0.0; half + 1];
+            let mut shifted_frequencies = (0..=half).map(bin_frequency).collect::<Vec<_>>();
+
+            for (bin, (magnitude, envelope)) in magnitudes.iter().zip(&envelope).enumerate() {
+                let target = (bin as f32 * ratio).round() as usize;
+                if target > half {
+                    break;
+                }
+
+                let magnitude = magnitude / envelope;
+                excitation[target] += magnitude;
+
+                if magnitude > loudest[target] {
+                    loudest[target] = magnitude;
+                    shifted_frequencies[target] = frequencies[bin] * ratio;
+                }
+            }
+
+            for (phase, frequency) in shifted_phases.iter_mut().zip(shifted_frequencies) {
+                *phase += hop as f32 * frequency;
+            }
+
+            let magnitudes = excitation
+                .into_iter()
+                .zip(envelope)
+                .map(|(excitation, envelope)| excitation * envelope)
+                .collect::<Vec<_>>();
+
+            Ok((magnitudes, shifted_phases.clone()))
+        })
+        .collect::<Result<Vec<_>, FftError>>()?;
+    let (magnitudes, phases): (Vec<_>, Vec<_>) = frames.into_iter().unzip();
+
+    let mut shifted = istft(&magnitudes, &phases, &window, hop, samples.len())?;
+    shifted.truncate(mono.len());
+
+    Ok(Waveform::new_mono(shifted, mono.sample_rate()))
+}
+
+/// The smooth outline of the magnitudes from DC up to nyquist, found by
+/// keeping only the lowest `lifter` quefrencies of the real cepstrum, or all
+/// of them if there are fewer
+fn spectral_envelope(magnitudes: &[f32], lifter: usize) -> Result<Vec<f32>, FftError> {
+    // Small enough to not change anything audible, while keeping the log of
+    // silent buckets finite
+    const FLOOR: f32 = 1e-9;
+
+    let half = magnitudes.len() - 1;
+    let width = half * 2;
+    let lifter = lifter.min(half);
+
+    let log_magnitudes = magnitudes
+        .iter()
+        .chain(magnitudes[1..half].iter().rev())
+        .map(|magnitude| Complex::new((magnitude + FLOOR).ln(), 0.0));
+
+    // The log magnitudes are real and symmetric, so a forward fft inverts them
+    // just as well, besides the scaling
+    let mut cepstrum = log_magnitudes.collect::<Vec<_>>();
+    cfft(&mut cepstrum)?;
+
+    for (quefrency, coefficient) in cepstrum.iter_mut().enumerate() {
+        *coefficient = if quefrency < lifter || quefrency > width - lifter {
+            Complex::new(coefficient.re / width as f32, 0.0)
+        } else {
+            Complex::new(0.0, 0.0)
+        };
+    }
+
+    cfft(&mut cepstrum)?;
+
+    Ok(cepstrum
+        .iter()
+        .take(half + 1)
+        .map(|smoothed| smoothed.re.exp())
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use audio::{filter::Biquad, waveform::Waveform};
+
+    use crate::{
+        fft::FftError,
+        pitch::{detect_pitch_yin, YIN_THRESHOLD},
+        WaveformSpectrum, Window,
+    };
+
+    /// The amplitude weighted mean frequency
+    fn spectral_centroid(waveform: &Waveform) -> Result<f32, FftError> {
+        let slice = waveform.slice(..8192);
+        let spectrum = slice.spectrum(Window::Hann, 8192)?;
+
+        let (weighted, total) = spectrum.to_rows_real().fold(
+            (0.0, 0.0),
+            |(weighted, total), (frequency, amplitude, _)| {
+                (weighted + frequency as f32 * amplitude, total + amplitude)
+            },
+        );
+
+        Ok(weighted / total)
+    }
+
+    #[test]
+    fn formants_stay_put() -> color_eyre::Result<()> {
+        // A buzz at 150Hz through two formants, like a vowel
+        let sample_rate = 16_000;
+        let buzz = Waveform::sawtooth_wave(150.0, 1.0, sample_rate, true);
+        let vowel = Waveform::mix(
+            &[
+                &buzz.filter(&Biquad::band_pass(700.0, 4.0, sample_rate)),
+                &buzz.filter(&Biquad::band_pass(1_200.0, 4.0, sample_rate)),
+            ],
+            &[1.0, 1.0],
+        )?;
+
+        let naive = vowel.pitch_shift(5.0, 1024)?;
+        let preserved = vowel.pitch_shift_formant_preserved(5.0, 1024, 30)?;
+        assert_eq!(preserved.len(), vowel.len());
+
+        // Shifting naively drags the formants up with the pitch
+        let centroid = spectral_centroid(&vowel)?;
+        assert!(spectral_centroid(&naive)? / centroid > 1.15);
+        assert!((spectral_centroid(&preserved)? / centroid - 1.0).abs() < 0.1);
+
+        // while the pitch still goes up by a fourth
+        let expected = 150.0 * 2f32.powf(5.0 / 12.0);
+        for start in [2_000, 4_000] {
+            let detected = detect_pitch_yin(&preserved.slice(start..start + 800), YIN_THRESHOLD);
+
+            assert!(
+                detected.map_or(false, |detected| (detected / expected - 1.0).abs() < 0.01),
+                "detected {detected:?}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_width() {
+        let tone = Waveform::sine_wave(440.0, 0.1, 8000);
+
+        for width in [2, 1000, 1 << 15] {
+            assert_eq!(
+                tone.pitch_shift(1.0, width).err(),
+                Some(FftError::UnsupportedWidth(width))
+            );
+        }
+
+        // No quefrencies keeps no envelope, and more than there are keeps them all
+        for lifter in [0, 1024] {
+            assert!(tone.pitch_shift_formant_preserved(1.0, 256, lifter).is_ok());
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+};
+
+use audio::waveform::Waveform;
+use ttspico::Engine;
+
+use crate::synthesize;
+
+/// An engine which remembers the most recently synthesized utterances, so
+/// repeated phrases do not have to be synthesized again
+pub struct SynthesisCache {
+    engine: Engine,
+    capacity: usize,
+    utterances: HashMap<String, Waveform<'static>>,
+    /// Cached text from least to most recently used
+    recency: VecDeque<String>,
+    syntheses: usize,
+}
+
+impl fmt::Debug for SynthesisCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SynthesisCache")
+            .field("capacity", &self.capacity)
+            .field("recency", &self.recency)
+            .field("syntheses", &self.syntheses)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SynthesisCache {
+    /// Cache up to `capacity` utterances, evicting the least recently used
+    pub fn new(engine: Engine, capacity: usize) -> Self {
+        assert!(
+            capacity > 0,
+            "a synthesis cache must hold at least one utterance"
+        );
+
+        Self {
+            engine,
+            capacity,
+            utterances: HashMap::with_capacity(capacity),
+            recency: VecDeque::with_capacity(capacity),
+            syntheses: 0,
+        }
+    }
+
+    /// Synthesize `text`, or copy the cached waveform if it has been said recently
+    #[tracing::instrument(skip(self))]
+    pub fn say(&mut self, text: &str) -> color_eyre::Result<Waveform<'static>> {
+        if let Some(waveform) = self.utterances.get(text) {
+            let waveform = waveform.to_owned();
+            self.touch(text);
+
+            return Ok(waveform);
+        }
+
+        let waveform = synthesize(&mut self.engine, text)?;
+        self.syntheses += 1;
+
+        if self.recency.len() == self.capacity {
+            if let Some(evicted) = self.recency.pop_front() {
+                self.utterances.remove(&evicted);
+            }
+        }
+
+        self.utterances
+            .insert(text.to_string(), waveform.to_owned());
+        self.recency.push_back(text.to_string());
+
+        Ok(waveform)
+    }
+
+    /// Move `text` to the most recently used end
+    fn touch(&mut self, text: &str) {
+        if let Some(position) = self.recency.iter().position(|cached| cached == text) {
+            if let Some(text) = self.recency.remove(position) {
+                self.recency.push_back(text);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.utterances.clear();
+        self.recency.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.utterances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.utterances.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// How many times the engine has been used, rather than the cache
+    pub fn syntheses(&self) -> usize {
+        self.syntheses
+    }
+
+    pub fn into_engine(self) -> Engine {
+        self.engine
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use color_eyre::eyre::Report;
+
+    use super::SynthesisCache;
+    use crate::{load_language_from, setup_tts};
+
+    fn cache(capacity: usize) -> color_eyre::Result<SynthesisCache> {
+        let base = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../_lang");
+        let engine = setup_tts(load_language_from(&base, "en-US").map_err(Report::msg)?)?;
+
+        Ok(SynthesisCache::new(engine, capacity))
+    }
+
+    #[test]
+    fn repeated_text_is_cached() -> color_eyre::Result<()> {
+        let mut cache = cache(4)?;
+
+        let first = cache.say("Hello there")?;
+        let second = cache.say("Hello there")?;
+
+        assert_eq!(cache.syntheses(), 1);
+        assert_eq!(first.samples(), second.samples());
+
+        cache.clear();
+        cache.say("Hello there")?;
+        assert_eq!(cache.syntheses(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn least_recently_used_evicted() -> color_eyre::Result<()> {
+        let mut cache = cache(2)?;
+
+        cache.say("one")?;
+        cache.say("two")?;
+        cache.say("one")?;
+        cache.say("three")?;
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.syntheses(), 3);
+
+        // "two" was the least recently used, so it was evicted
+        cache.say("one")?;
+        assert_eq!(cache.syntheses(), 3);
+        cache.say("two")?;
+        assert_eq!(cache.syntheses(), 4);
+
+        Ok(())
+    }
+}
@@ -2,11 +2,39 @@
 #![warn(missing_copy_implementations, missing_debug_implementations)]
 
 use audio::{waveform::Waveform, Sample};
-use color_eyre::eyre::eyre;
-use std::{path::Path, rc::Rc};
-use tracing::info;
+use color_eyre::eyre::{eyre, WrapErr};
+use std::{
+    env,
+    ffi::OsStr,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    iter,
+    path::Path,
+    process,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tracing::{info, warn};
 use ttspico::{Engine, EngineStatus, System, Voice};
 
+pub mod cache;
+
+/// The rate pico renders speech at, for every voice
+pub const TTS_SAMPLE_RATE: u32 = 16_000;
+
+/// How many names to try for a temporary resource file before giving up
+const TEMPORARY_FILE_ATTEMPTS: usize = 16;
+
+/// The sample rate of the audio produced by `engine`.
+///
+/// ttspico does not expose the rate an engine was configured with, so this is
+/// always [`TTS_SAMPLE_RATE`] for now. Prefer it, or the rate of the returned
+/// [`Waveform`], over assuming a rate so other voices would not break callers.
+pub fn output_sample_rate(_engine: &Engine) -> u32 {
+    TTS_SAMPLE_RATE
+}
+
 // #[cfg(target_arch = "wasm32")]
 // compile_error!("`tts` can not be used on 'wasm32' platforms, yet");
 
@@ -25,11 +53,22 @@ pub fn setup_tts(
         .map_err(|err| eyre!("could not init ttspico system: {err}"))?;
 
     // 2. Load Text Analysis (TA) and Speech Generation (SG) resources for the voice you want to use
-    let ta_res = System::load_resource(Rc::clone(&sys), text_analysis)
+    // Pico can only load resources from files, so in-memory resources are written to a
+    // temporary file which is removed as soon as pico has read it
+    let text_analysis = text_analysis
+        .into_file()
+        .wrap_err("unable to write text analysis resource to a temporary file")?;
+    let speech_generation = speech_generation
+        .into_file()
+        .wrap_err("unable to write speech generation resource to a temporary file")?;
+
+    let ta_res = System::load_resource(Rc::clone(&sys), text_analysis.path.clone())
         .map_err(|err| eyre!("failed to load text analysis file: {err}"))?;
-    let sg_res = System::load_resource(Rc::clone(&sys), speech_generation)
+    let sg_res = System::load_resource(Rc::clone(&sys), speech_generation.path.clone())
         .map_err(|err| eyre!("Failed to load speech generation file: {err}"))?;
 
+    drop((text_analysis, speech_generation));
+
     info!(
         text_analysis = ta_res.borrow().name().unwrap_or("?"),
         speech_generation = sg_res.borrow().name().unwrap_or("?"),
@@ -56,15 +95,147 @@ pub fn setup_tts(
 
 #[derive(Debug)]
 pub struct TTSResources {
-    text_analysis: String,
-    speech_generation: String,
+    text_analysis: Resource,
+    speech_generation: Resource,
+}
+
+impl TTSResources {
+    /// Use resources already in memory, such as ones bundled into the binary with
+    /// [`include_bytes!`], instead of loading them from a languages directory.
+    ///
+    /// The bytes are owned until [`setup_tts`] copies them into the pico system's
+    /// own memory, after which they are dropped.
+    pub fn from_bytes(text_analysis: Vec<u8>, speech_generation: Vec<u8>) -> Self {
+        Self {
+            text_analysis: Resource::Bytes(text_analysis),
+            speech_generation: Resource::Bytes(speech_generation),
+        }
+    }
+}
+
+enum Resource {
+    Path(String),
+    Bytes(Vec<u8>),
+}
+
+impl fmt::Debug for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            Self::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+        }
+    }
+}
+
+impl Resource {
+    fn into_file(self) -> io::Result<ResourceFile> {
+        match self {
+            Self::Path(path) => Ok(ResourceFile {
+                path,
+                temporary: false,
+            }),
+            Self::Bytes(bytes) => {
+                static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+                for _ in 0..TEMPORARY_FILE_ATTEMPTS {
+                    let path = env::temp_dir().join(format!(
+                        "speaky-tts-{}-{}.bin",
+                        process::id(),
+                        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+                    ));
+
+                    // Only ever create a fresh file, so that nothing already at the
+                    // predictable path, such as a planted symlink, is written through
+                    let file = OpenOptions::new().write(true).create_new(true).open(&path);
+
+                    let mut file = match file {
+                        Ok(file) => file,
+                        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+                        Err(error) => return Err(error),
+                    };
+
+                    // Removed on drop, even if the bytes could not all be written
+                    let resource = ResourceFile {
+                        path: path.to_string_lossy().into_owned(),
+                        temporary: true,
+                    };
+                    file.write_all(&bytes)?;
+
+                    return Ok(resource);
+                }
+
+                Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "every temporary resource file name was already taken",
+                ))
+            }
+        }
+    }
+}
+
+/// A resource on disk, removed on drop if it was only written for loading
+struct ResourceFile {
+    path: String,
+    temporary: bool,
+}
+
+impl Drop for ResourceFile {
+    fn drop(&mut self) {
+        if self.temporary {
+            if let Err(error) = fs::remove_file(&self.path) {
+                warn!(%error, path = %self.path, "unable to remove temporary resource file");
+            }
+        }
+    }
 }
 
+/// The languages in `./_lang` that have both of their resource files installed
+pub fn list_languages() -> color_eyre::Result<Vec<String>> {
+    list_languages_from(Path::new("./_lang"))
+}
+
+/// The languages in `base` that have both of their resource files installed,
+/// sorted by name. A missing `base` directory has no languages.
 #[tracing::instrument]
+pub fn list_languages_from(base: &Path) -> color_eyre::Result<Vec<String>> {
+    if !base.exists() {
+        warn!("languages directory does not exist");
+
+        return Ok(Vec::new());
+    }
+
+    let mut languages = Vec::new();
+
+    for entry in base
+        .read_dir()
+        .wrap_err("unable to read languages directory")?
+    {
+        let path = entry.wrap_err("unable to read languages directory")?.path();
+
+        if !path.join("ta.bin").is_file() || !path.join("sg.bin").is_file() {
+            continue;
+        }
+
+        match path.file_name().and_then(OsStr::to_str) {
+            Some(lang) => languages.push(lang.to_string()),
+            None => warn!(?path, "skipping language with a non-unicode name"),
+        }
+    }
+
+    languages.sort();
+
+    Ok(languages)
+}
+
+/// Load a language from `./_lang`, relative to the working directory
 pub fn load_language(lang: &str) -> Result<TTSResources, String> {
-    let lang_dir = Path::new("./_lang");
+    load_language_from(Path::new("./_lang"), lang)
+}
 
-    if !lang_dir.exists() {
+/// Load a language from the `lang` subdirectory of `base`
+#[tracing::instrument]
+pub fn load_language_from(base: &Path, lang: &str) -> Result<TTSResources, String> {
+    if !base.exists() {
         return Err("languages directory does not exist".to_string());
     }
 
@@ -74,7 +245,7 @@ pub fn load_language(lang: &str) -> Result<TTSResources, String> {
         return Err("language name contains invalid characters".to_string());
     }
 
-    let lang_dir = lang_dir.join(lang);
+    let lang_dir = base.join(lang);
 
     if !lang_dir.exists() {
         return Err(format!("{:?} language directory does not exist", lang));
@@ -97,20 +268,46 @@ pub fn load_language(lang: &str) -> Result<TTSResources, String> {
     }
 
     Ok(TTSResources {
-        text_analysis: text_analysis.to_str().map(str::to_string).ok_or_else(|| {
-            "text analysis file path contained non-unicode characters".to_string()
-        })?,
-        speech_generation: speech_generation
-            .to_str()
-            .map(str::to_string)
-            .ok_or_else(|| {
-                "speech generation file path contained non-unicode characters".to_string()
-            })?,
+        text_analysis: Resource::Path(text_analysis.to_str().map(str::to_string).ok_or_else(
+            || "text analysis file path contained non-unicode characters".to_string(),
+        )?),
+        speech_generation: Resource::Path(
+            speech_generation
+                .to_str()
+                .map(str::to_string)
+                .ok_or_else(|| {
+                    "speech generation file path contained non-unicode characters".to_string()
+                })?,
+        ),
     })
 }
 
+/// Synthesize the whole of `text` at once as normalized mono samples at the
+/// engine's [`output_sample_rate`].
+/// See [`synthesize_streaming`] to start using the audio before synthesis finishes.
 #[tracing::instrument(skip(engine))]
 pub fn synthesize(engine: &mut Engine, text: &str) -> color_eyre::Result<Waveform<'static>> {
+    let sample_rate = output_sample_rate(engine);
+    let mut pcm_data = Vec::new();
+
+    for chunk in synthesize_streaming(engine, text)? {
+        pcm_data.extend_from_slice(chunk?.samples());
+    }
+
+    Ok(Waveform::new_mono(pcm_data, sample_rate))
+}
+
+/// Put `text` into the engine, returning an iterator over the chunks of audio
+/// as the engine renders them, each up to 1024 samples long.
+///
+/// The iterator borrows the engine for as long as it is alive, so the engine
+/// must outlive it. Dropping the iterator before it is exhausted leaves the
+/// rest of the utterance in the engine.
+#[tracing::instrument(skip(engine))]
+pub fn synthesize_streaming<'e>(
+    engine: &'e mut Engine,
+    text: &str,
+) -> color_eyre::Result<impl Iterator<Item = color_eyre::Result<Waveform<'static>>> + 'e> {
     // 5. Put (UTF-8) text to be spoken into the engine
     // See `Engine::put_text()` for more details.
     let mut text_bytes = text.as_bytes();
@@ -128,19 +325,166 @@ pub fn synthesize(engine: &mut Engine, text: &str) -> color_eyre::Result<Wavefor
 
     // 6. Do the actual text-to-speech, getting audio data (16-bit signed PCM @ 16kHz) from the input text
     // Speech audio is computed in small chunks, one "step" at a time; see `Engine::get_data()` for more details.
-    let mut pcm_data = Vec::new();
-    let mut pcm_buf = [0i16; 1024];
-    loop {
-        let (n_written, status) = engine
-            .get_data(&mut pcm_buf[..])
-            .map_err(|err| eyre!("failed to get pico pcm data: {err}"))?;
+    let sample_rate = output_sample_rate(engine);
+    let mut finished = false;
+
+    Ok(iter::from_fn(move || {
+        let mut pcm_buf = [0i16; 1024];
+
+        // Steps can produce no audio, so keep going until one does
+        while !finished {
+            let (n_written, status) = match engine.get_data(&mut pcm_buf[..]) {
+                Ok(data) => data,
+                Err(err) => {
+                    finished = true;
+
+                    return Some(Err(eyre!("failed to get pico pcm data: {err}")));
+                }
+            };
+
+            finished = status == EngineStatus::Idle;
+
+            if n_written > 0 {
+                return Some(Ok(Waveform::new_mono(
+                    pcm_buf[..n_written]
+                        .iter()
+                        .map(|sample| sample.to_f32())
+                        .collect(),
+                    sample_rate,
+                )));
+            }
+        }
+
+        None
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use color_eyre::eyre::Report;
+    use ttspico::Engine;
+
+    use crate::{
+        list_languages_from, load_language_from, output_sample_rate, setup_tts, synthesize,
+        synthesize_streaming, Resource, TTSResources, TTS_SAMPLE_RATE,
+    };
+
+    fn engine() -> color_eyre::Result<Engine> {
+        let base = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../_lang");
+
+        setup_tts(load_language_from(&base, "en-US").map_err(Report::msg)?)
+    }
+
+    #[test]
+    fn streaming_chunks() -> color_eyre::Result<()> {
+        let mut engine = engine()?;
+
+        let chunks = synthesize_streaming(&mut engine, "Hello there, general Kenobi")?
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+
+        assert!(chunks.len() > 1);
+        assert!(chunks
+            .iter()
+            .all(|chunk| !chunk.is_empty() && chunk.len() <= 1024));
+
+        Ok(())
+    }
 
-        pcm_data.extend(pcm_buf[..n_written].iter().map(|sample| sample.to_f32()));
+    #[test]
+    fn synthesize_waveform() -> color_eyre::Result<()> {
+        let mut engine = engine()?;
 
-        if status == EngineStatus::Idle {
-            break;
+        let waveform = synthesize(&mut engine, "Hello there")?;
+
+        assert_eq!(waveform.sample_rate(), TTS_SAMPLE_RATE);
+        assert_eq!(output_sample_rate(&engine), TTS_SAMPLE_RATE);
+        assert_eq!(waveform.channels(), 1);
+        assert!(!waveform.is_empty());
+        assert!(waveform.peak() <= 1.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn list_languages() -> color_eyre::Result<()> {
+        let base = std::env::temp_dir().join("speaky-list-languages");
+        let _ = fs::remove_dir_all(&base);
+
+        for (lang, files) in [
+            ("en-US", &["ta.bin", "sg.bin"][..]),
+            ("de-DE", &["ta.bin", "sg.bin"][..]),
+            ("fr-FR", &["ta.bin"][..]),
+            ("empty", &[][..]),
+        ] {
+            fs::create_dir_all(base.join(lang))?;
+
+            for file in files {
+                fs::write(base.join(lang).join(file), b"stub")?;
+            }
+        }
+        fs::write(base.join("README"), b"not a language")?;
+
+        assert_eq!(list_languages_from(&base)?, ["de-DE", "en-US"]);
+        assert!(list_languages_from(&base.join("missing"))?.is_empty());
+
+        fs::remove_dir_all(&base)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_language() -> color_eyre::Result<()> {
+        let base = std::env::temp_dir().join("speaky-load-language");
+        let _ = fs::remove_dir_all(&base);
+
+        fs::create_dir_all(base.join("en-US"))?;
+        fs::write(base.join("en-US/ta.bin"), b"stub")?;
+        fs::write(base.join("en-US/sg.bin"), b"stub")?;
+
+        let resources = load_language_from(&base, "en-US").map_err(Report::msg)?;
+        match (resources.text_analysis, resources.speech_generation) {
+            (Resource::Path(text_analysis), Resource::Path(speech_generation)) => {
+                assert_eq!(Path::new(&text_analysis), base.join("en-US/ta.bin"));
+                assert_eq!(Path::new(&speech_generation), base.join("en-US/sg.bin"));
+            }
+            resources => panic!("expected resource paths, found {resources:?}"),
         }
+
+        assert!(load_language_from(&base, "de-DE").is_err());
+        assert!(load_language_from(&base, "../en-US").is_err());
+        assert!(load_language_from(&base.join("missing"), "en-US").is_err());
+
+        fs::remove_dir_all(&base)?;
+
+        Ok(())
     }
 
-    Ok(Waveform::new(pcm_data, 16_000))
+    #[test]
+    fn temporary_resource_file() -> color_eyre::Result<()> {
+        let file = Resource::Bytes(vec![1, 2, 3]).into_file()?;
+        let path = file.path.clone();
+
+        assert_eq!(fs::read(&path)?, [1, 2, 3]);
+
+        drop(file);
+        assert!(!Path::new(&path).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn setup_from_bytes() -> color_eyre::Result<()> {
+        let resources = TTSResources::from_bytes(
+            include_bytes!("../../../_lang/en-US/ta.bin").to_vec(),
+            include_bytes!("../../../_lang/en-US/sg.bin").to_vec(),
+        );
+
+        let mut engine = setup_tts(resources)?;
+
+        assert!(!synthesize(&mut engine, "Hello")?.is_empty());
+
+        Ok(())
+    }
 }